@@ -0,0 +1,72 @@
+//! Stream from several boards at once and print how they line up.
+//!
+//! All boards need to see the same signal at the given frequency, e.g. a shared beacon or a splitter on one antenna.
+//!
+//! Usage: multi_board_capture <frequency_hz> <serial> <serial> [serial...]
+
+use num_complex::Complex;
+use rfnm::channel_settings::RxChannelSettings;
+use rfnm::discover_usb_boards;
+use rfnm::multi_device::{AlignmentSettings, MultiDevice};
+use rfnm_sys::rfnm_channel;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "Usage: {} <frequency_hz> <serial> <serial> [serial...]",
+            args[0]
+        );
+        eprintln!("Boards found:");
+        for board in discover_usb_boards() {
+            eprintln!("  {}", board.motherboard.serial_string());
+        }
+        return Ok(());
+    }
+    let frequency: i64 = args[1].parse()?;
+    let serials: Vec<&str> = args[2..].iter().map(|s| s.as_str()).collect();
+
+    let channel_settings = RxChannelSettings {
+        frequency,
+        ..Default::default()
+    };
+    let settings = AlignmentSettings {
+        auto_align: true,
+        ..Default::default()
+    };
+    let mut boards = MultiDevice::<Complex<f32>>::open(
+        &serials,
+        rfnm_channel::CH0,
+        &channel_settings,
+        settings,
+    )?;
+    eprintln!(
+        "Opened {} boards at {} Hz",
+        boards.board_count(),
+        boards.sample_rate()
+    );
+    boards.start()?;
+
+    let block = 1 << 16;
+    let mut scratch = vec![vec![Complex::new(0.0, 0.0); block]; boards.board_count()];
+    let mut buffers: Vec<&mut [Complex<f32>]> =
+        scratch.iter_mut().map(|b| b.as_mut_slice()).collect();
+    let mut last = Instant::now();
+    loop {
+        boards.read(&mut buffers, Duration::from_millis(100))?;
+        if last.elapsed() > Duration::from_secs(1) {
+            last = Instant::now();
+            for (serial, alignment) in boards.serials().zip(boards.alignment()) {
+                eprintln!(
+                    "{serial}: offset {:.2} samples ({:.0} ns), drift {:.3} ppm, correlation {:.2}",
+                    alignment.offset_samples,
+                    alignment.offset_ns,
+                    alignment.drift_ppm,
+                    alignment.correlation
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use num_complex::Complex;
+use rfnm::agc::{AgcSettings, SoftwareAgc};
+use rfnm::device::Device;
+use rfnm::stream::RxStream;
+use rfnm::tags::TagKind;
+use rfnm_sys::rfnm_channel;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Streams CH0 for ten seconds while the software AGC keeps the level around -20 dBFS.
+fn main() -> Result<(), Box<dyn Error>> {
+    let device = Device::connect_usb()?;
+    let stream = RxStream::<Complex<f32>>::new(device, rfnm_channel::CH0).map_err(|(e, _)| e)?;
+    let mut scratch = vec![Complex::new(0.0, 0.0); stream.suggested_buffer_size()];
+    let mut buffers = [scratch.as_mut_slice()];
+
+    let mut agc = SoftwareAgc::new(stream.device(), rfnm_channel::CH0, AgcSettings::default())?;
+    stream.start()?;
+
+    let mut tags = Vec::new();
+    let end = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < end {
+        let info =
+            stream.read_tagged(buffers.as_mut_slice(), Duration::from_millis(20), &mut tags)?;
+        for tag in &tags {
+            if let TagKind::Gain(gain) = tag.kind {
+                eprintln!(
+                    "Gain {} dB from sample {} of the block on",
+                    gain, tag.offset
+                );
+            }
+        }
+        if let Some(gain) = agc.process(stream.device(), &buffers[0][..info.elements_read])? {
+            let levels = agc.last_levels();
+            eprintln!(
+                "Peak {:.1} dBFS, RMS {:.1} dBFS, {} clipped: gain to {} dB",
+                levels.peak_dbfs(),
+                levels.rms_dbfs(),
+                levels.clipped,
+                gain
+            );
+        }
+    }
+    stream.stop()?;
+    Ok(())
+}
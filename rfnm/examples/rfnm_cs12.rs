@@ -0,0 +1,69 @@
+//! Record channel 0 of the first board repacked to 12 bits, and convert recordings.
+//!
+//! Usage: rfnm_cs12 record <file> <seconds>
+//!        rfnm_cs12 convert <file> <output> cs16|cf32
+//!
+//! Converted output is raw interleaved samples of channel 0, as most SDR tools read them.
+
+use num_complex::Complex;
+use rfnm::cs12::{Cs12, Cs12Reader, Cs12Writer, FromCs12};
+use rfnm::device::Device;
+use rfnm::stream::RxStream;
+use rfnm_sys::rfnm_channel;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    match (args.get(1).map(String::as_str), args.len()) {
+        (Some("record"), 4) => record(&args[2], Duration::from_secs_f64(args[3].parse()?)),
+        (Some("convert"), 5) if args[4] == "cs16" => convert::<Complex<i16>>(&args[2], &args[3]),
+        (Some("convert"), 5) if args[4] == "cf32" => convert::<Complex<f32>>(&args[2], &args[3]),
+        _ => {
+            eprintln!("Usage: {} record <file> <seconds>", args[0]);
+            eprintln!("       {} convert <file> <output> cs16|cf32", args[0]);
+            Ok(())
+        }
+    }
+}
+
+fn record(path: &str, duration: Duration) -> Result<(), Box<dyn Error>> {
+    let device = Device::connect_usb()?;
+    let stream = RxStream::<Cs12>::new(device, rfnm_channel::CH0).map_err(|(e, _)| e)?;
+    let mut writer = Cs12Writer::new(BufWriter::new(File::create(path)?), 1, stream.sample_rate())?;
+    let mut buffer = vec![Cs12::default(); stream.suggested_buffer_size()];
+
+    stream.start()?;
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        let info = stream.read(&mut [buffer.as_mut_slice()], Duration::from_millis(100))?;
+        writer.write_block(&[buffer.as_slice()], &info)?;
+    }
+    stream.stop()?;
+    writer.flush()?;
+    eprintln!(
+        "Recorded {} samples at {} S/s",
+        writer.samples_written(),
+        stream.sample_rate()
+    );
+    Ok(())
+}
+
+fn convert<T: FromCs12 + Default>(path: &str, output: &str) -> Result<(), Box<dyn Error>> {
+    let mut reader = Cs12Reader::new(BufReader::new(File::open(path)?))?;
+    let mut out = BufWriter::new(File::create(output)?);
+    let mut buffers = vec![vec![T::default(); 1 << 16]; reader.channel_count()];
+    let mut dst: Vec<&mut [T]> = buffers.iter_mut().map(|b| b.as_mut_slice()).collect();
+    while let Some(info) = reader.read(&mut dst)? {
+        let samples = &dst[0][..info.elements_read];
+        // both sample types are plain interleaved I and Q
+        let bytes = unsafe {
+            std::slice::from_raw_parts(samples.as_ptr() as *const u8, size_of_val(samples))
+        };
+        out.write_all(bytes)?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use rfnm::power::{PowerLogger, PowerSweepSettings};
+use std::error::Error;
+use std::time::Duration;
+
+fn usage() -> ! {
+    eprintln!("Usage: rfnm_power <start_hz> <stop_hz> [fft_size] [integration_seconds] [gain]");
+    eprintln!("Writes rtl_power compatible csv lines to stdout.");
+    std::process::exit(1);
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+    let mut settings = PowerSweepSettings {
+        freq_start: args[0].parse().unwrap_or_else(|_| usage()),
+        freq_stop: args[1].parse().unwrap_or_else(|_| usage()),
+        ..Default::default()
+    };
+    if let Some(fft_size) = args.get(2) {
+        settings.fft_size = fft_size.parse().unwrap_or_else(|_| usage());
+    }
+    if let Some(seconds) = args.get(3) {
+        settings.integration_interval =
+            Duration::from_secs_f64(seconds.parse().unwrap_or_else(|_| usage()));
+    }
+    if let Some(gain) = args.get(4) {
+        settings.gain = gain.parse().unwrap_or_else(|_| usage());
+    }
+
+    let mut logger = PowerLogger::new(settings)?;
+    eprintln!(
+        "Sweeping with {} hops at {} samples/s",
+        logger.hops().len(),
+        logger.sample_rate()
+    );
+    logger.run(&mut std::io::stdout().lock(), || true)?;
+
+    Ok(())
+}
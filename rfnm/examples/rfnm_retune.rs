@@ -0,0 +1,73 @@
+use num_complex::Complex;
+use rfnm::control::TicketState;
+use rfnm::device::Device;
+use rfnm::stream::RxStream;
+use rfnm::tags::TagKind;
+use rfnm_sys::rfnm_channel;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Hops CH0 between two frequencies while streaming, without ever blocking on the board.
+fn main() -> Result<(), Box<dyn Error>> {
+    let device = Device::connect_usb()?;
+    device.set_confirm_timeout(Duration::from_millis(200));
+    let stream = RxStream::<Complex<f32>>::new(device, rfnm_channel::CH0).map_err(|(e, _)| e)?;
+    let mut scratch = vec![Complex::new(0.0, 0.0); stream.suggested_buffer_size()];
+    let mut buffers = [scratch.as_mut_slice()];
+
+    let mut settings = stream
+        .device()
+        .get_rx_settings(rfnm_channel::CH0)?
+        .to_settings();
+    let frequencies = [settings.frequency, settings.frequency + 10_000_000];
+    stream.start()?;
+
+    let mut tags = Vec::new();
+    let mut pending = None;
+    for hop in 0..20 {
+        settings.frequency = frequencies[hop % frequencies.len()];
+        let ticket = stream
+            .device()
+            .submit_rx_settings(&[(rfnm_channel::CH0, settings.clone())])?;
+        pending = Some((ticket, Instant::now()));
+
+        let hop_end = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < hop_end {
+            let info =
+                stream.read_tagged(buffers.as_mut_slice(), Duration::from_millis(10), &mut tags)?;
+            for tag in &tags {
+                if let TagKind::Frequency(freq) = tag.kind {
+                    eprintln!(
+                        "Samples at {} Hz from {} ns on",
+                        freq,
+                        info.timestamp_ns as f64 + tag.offset as f64 * 1e9 / stream.sample_rate()
+                    );
+                }
+            }
+            let Some((ticket, submitted_at)) = pending else {
+                continue;
+            };
+            match stream.device().poll_ticket(&ticket)? {
+                TicketState::Pending => {}
+                TicketState::Executed(outcome) => {
+                    eprintln!(
+                        "{} Hz confirmed after {:?}: {:?}",
+                        settings.frequency,
+                        submitted_at.elapsed(),
+                        outcome.into_result()
+                    );
+                    pending = None;
+                }
+                TicketState::Superseded => pending = None,
+            }
+        }
+    }
+    if let Some((ticket, _)) = pending {
+        let outcome = stream
+            .device()
+            .wait_ticket(&ticket, Duration::from_secs(1))?;
+        eprintln!("Last hop: {:?}", outcome.into_result());
+    }
+    stream.stop()?;
+    Ok(())
+}
@@ -0,0 +1,65 @@
+//! Share channel 0 of the first board with other processes through a shared-memory ring.
+//!
+//! Usage: rfnm_shm_ring publish [name]
+//!        rfnm_shm_ring read [name]
+//!
+//! Start one publisher, then as many readers as you like. The ring is called `rfnm` if no name is given.
+
+use num_complex::Complex;
+use rfnm::device::Device;
+use rfnm::shm_ring::{ShmRingError, ShmRingPublisher, ShmRingReader, ShmRingSettings};
+use rfnm::stream::RxStream;
+use rfnm_sys::rfnm_channel;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let name = args.get(2).map(String::as_str).unwrap_or("rfnm");
+    match args.get(1).map(String::as_str) {
+        Some("publish") => publish(name),
+        Some("read") => read(name),
+        _ => {
+            eprintln!("Usage: {} publish|read [name]", args[0]);
+            Ok(())
+        }
+    }
+}
+
+fn publish(name: &str) -> Result<(), Box<dyn Error>> {
+    let device = Device::connect_usb()?;
+    let stream = RxStream::<Complex<i16>>::new(device, rfnm_channel::CH0).map_err(|(e, _)| e)?;
+    let mut ring = ShmRingPublisher::for_stream(name, &stream, &ShmRingSettings::default())?;
+    eprintln!(
+        "Publishing {} S/s to {}",
+        stream.sample_rate(),
+        ring.path().display()
+    );
+    stream.start()?;
+    loop {
+        ring.publish_from(&stream, Duration::from_millis(100))?;
+    }
+}
+
+fn read(name: &str) -> Result<(), Box<dyn Error>> {
+    let mut ring = ShmRingReader::<Complex<i16>>::attach(name)?;
+    let mut buffer = vec![Complex::new(0, 0); ring.block_size()];
+    let mut samples = 0;
+    let mut last_report = Instant::now();
+    loop {
+        match ring.read(&mut [buffer.as_mut_slice()], Duration::from_secs(1)) {
+            Ok(info) => samples += info.elements_read,
+            Err(ShmRingError::Closed) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        if last_report.elapsed() >= Duration::from_secs(1) {
+            println!(
+                "{:.0} samples/s, {} blocks lost so far",
+                samples as f64 / last_report.elapsed().as_secs_f64(),
+                ring.blocks_lost()
+            );
+            samples = 0;
+            last_report = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Serve channel 0 of the first board to SDR# or SDR++ over the SpyServer protocol.
+//!
+//! Usage: rfnm_spyserver [address]
+//!
+//! Listens on 0.0.0.0:5555 if no address is given.
+
+use num_complex::Complex;
+use rfnm::device::Device;
+use rfnm::spyserver::{SpyServer, SpyServerSettings};
+use rfnm::stream::RxStream;
+use rfnm_sys::rfnm_channel;
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut settings = SpyServerSettings::default();
+    if let Some(address) = std::env::args().nth(1) {
+        settings.address = address;
+    }
+
+    let device = Device::connect_usb()?;
+    let stream = RxStream::<Complex<i16>>::new(device, rfnm_channel::CH0).map_err(|(e, _)| e)?;
+    let mut server = SpyServer::bind(stream, settings)?;
+    eprintln!(
+        "Serving {} Hz at {} S/s on {}",
+        server
+            .stream()
+            .device()
+            .get_rx_settings(rfnm_channel::CH0)?
+            .to_settings()
+            .frequency,
+        server.stream().sample_rate(),
+        server.local_addr()?
+    );
+    server.run()?;
+    Ok(())
+}
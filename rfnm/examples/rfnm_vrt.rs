@@ -0,0 +1,45 @@
+//! Send channel 0 of the first board as VITA-49 packets over UDP.
+//!
+//! Usage: rfnm_vrt <destination>
+//!
+//! The destination is `host:port`, e.g. `192.168.1.10:4991`.
+//! Retuning the channel from elsewhere shows up as a new context packet.
+
+use num_complex::Complex;
+use rfnm::device::Device;
+use rfnm::stream::RxStream;
+use rfnm::vrt::{VrtContext, VrtSettings, VrtUdpSender};
+use rfnm_sys::rfnm_channel;
+use std::error::Error;
+use std::time::Duration;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("Usage: {} <destination>", args[0]);
+        return Ok(());
+    }
+    let mut sender = VrtUdpSender::connect(VrtSettings {
+        destination: args[1].clone(),
+        ..Default::default()
+    })?;
+
+    let device = Device::connect_usb()?;
+    let dcs_clk = device.hwinfo().clock_info.dcs_clk;
+    let stream = RxStream::<Complex<i16>>::new(device, rfnm_channel::CH0).map_err(|(e, _)| e)?;
+    let mut scratch = vec![Complex::new(0, 0); stream.suggested_buffer_size()];
+    stream.start()?;
+    eprintln!("Sending to {}", sender.settings().destination);
+    loop {
+        let info = stream.read(&mut [scratch.as_mut_slice()], Duration::from_millis(100))?;
+        let settings = stream
+            .device()
+            .get_rx_settings(rfnm_channel::CH0)?
+            .to_settings();
+        sender.send(
+            &scratch[..info.elements_read],
+            info.timestamp_ns,
+            &VrtContext::from_settings(&settings, dcs_clk),
+        )?;
+    }
+}
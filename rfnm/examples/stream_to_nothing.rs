@@ -1,6 +1,6 @@
 use num_complex::Complex;
 use rfnm::device::Device;
-use rfnm::stream::RxStream;
+use rfnm::stream::{RxStream, RxStreamSettings};
 use rfnm_sys::rfnm_channel;
 use std::error::Error;
 use std::time::{Duration, Instant};
@@ -9,14 +9,20 @@ fn main() -> Result<(), Box<dyn Error>> {
     eprintln!("Setting up device etc");
     let device = Device::connect_usb()?;
     eprintln!("Device created successfully");
-    let stream =
-        RxStream::<Complex<f32>>::new(device, rfnm_channel::CH0).expect("Could not create stream");
+    // pass low-latency or robust to compare the latency of the buffering presets
+    let settings = match std::env::args().nth(1).as_deref() {
+        Some("low-latency") => RxStreamSettings::low_latency(),
+        Some("robust") => RxStreamSettings::robust(),
+        _ => RxStreamSettings::default(),
+    };
+    let stream = RxStream::<Complex<f32>>::with_settings(device, rfnm_channel::CH0, &settings)
+        .expect("Could not create stream");
     eprintln!(
         "Connected and ready to stream. Device suggests a buffer with {} elements in it.",
         stream.suggested_buffer_size()
     );
     let mut scratch = vec![Complex::new(0.0, 0.0); stream.suggested_buffer_size()];
-    let buffers = [scratch.as_mut_slice()];
+    let mut buffers = [scratch.as_mut_slice()];
     // before we start, lets make sure we are somewhat sanely setup though
     let ch_settings = stream
         .device()
@@ -34,33 +40,34 @@ fn main() -> Result<(), Box<dyn Error>> {
         return Err(e.into());
     }
 
-    let mut total_samples = 0;
-    let mut since_last = 0;
-    let started = Instant::now();
-    let mut last = started;
+    let mut last = Instant::now();
+    let mut last_stats = stream.stats();
     loop {
-        match stream.read(buffers.as_slice(), Duration::from_millis(10)) {
-            Ok(info) => {
-                total_samples += info.elements_read;
-                since_last += info.elements_read;
-                let now = Instant::now();
-                if now.duration_since(last) > Duration::from_secs(5) {
-                    let rate_since_start =
-                        total_samples as f64 / now.duration_since(started).as_secs_f64();
-                    let rate_since_last =
-                        since_last as f64 / now.duration_since(last).as_secs_f64();
-                    eprintln!(
-                        "Samples since last status: {since_last}, that is {rate_since_last}/s"
-                    );
-                    eprintln!("Total samples: {total_samples}, that is {rate_since_start}/s");
-                    last = now;
-                    since_last = 0;
-                }
-            }
-            Err(e) => {
-                eprintln!("Error while streaming: {e}");
-                return Err(e.into());
-            }
+        if let Err(e) = stream.read(buffers.as_mut_slice(), Duration::from_millis(10)) {
+            eprintln!("Error while streaming: {e}");
+            return Err(e.into());
+        }
+        let now = Instant::now();
+        if now.duration_since(last) > Duration::from_secs(5) {
+            let stats = stream.stats();
+            let since_last = stats.samples - last_stats.samples;
+            let rate_since_last = since_last as f64 / now.duration_since(last).as_secs_f64();
+            eprintln!("Samples since last status: {since_last}, that is {rate_since_last}/s");
+            eprintln!(
+                "Total samples: {}, that is {}/s of the expected {}/s. {} timeouts, {} errors, {} discontinuities",
+                stats.samples,
+                stats.measured_sample_rate,
+                stats.expected_sample_rate,
+                stats.timeouts,
+                stats.errors,
+                stats.discontinuities
+            );
+            eprintln!(
+                "Latency {:?}, at most {:?}",
+                stats.latency, stats.max_latency
+            );
+            last = now;
+            last_stats = stats;
         }
     }
 }
@@ -0,0 +1,331 @@
+//! Gain control on the host, for signals the hardware AGC does not handle well.
+//!
+//! `rfnm_agc_type` only has off and librfnm's default. `SoftwareAgc` instead looks at the samples of one channel
+//! block by block, measures peak, RMS and clipped samples, and moves `RxChannelSettings::gain` within the
+//! channel's gain range: down right away when the signal is too loud, up slowly and only after a hold time when
+//! it is too quiet, so bursts do not make it pump. Gains go out with `Device::submit_rx_settings`, so the stream
+//! keeps running, and the stream tags where the new gain starts with `crate::tags::TagKind::Gain`.
+
+use crate::RfnmApiError;
+use crate::control::{SetTicket, TicketState};
+use crate::device::Device;
+use crate::stream::ComplexSample;
+use rfnm_sys::rfnm_channel;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+/// How `SoftwareAgc` reacts.
+#[derive(Debug, Clone)]
+pub struct AgcSettings {
+    /// RMS level to aim for, in dB relative to full scale, -20 dBFS by default.
+    pub target_rms_dbfs: f32,
+    /// No change while the RMS is within this many dB of the target, 3 by default.
+    pub hysteresis_db: f32,
+    /// Magnitude from which on a sample counts as clipped, 1.0 is full scale. 0.98 by default.
+    pub clip_level: f32,
+    /// Clipped samples a block may have before the gain goes down, whatever the RMS. 0 by default.
+    pub max_clipped: usize,
+    /// Most the gain goes down in one step, in dB, 6 by default.
+    pub attack_db: i8,
+    /// Most the gain goes up in one step, in dB, 1 by default.
+    pub decay_db: i8,
+    /// Time after any change before the gain may go up again. Gives bursts time to come back. 200 ms by default.
+    pub decay_hold: Duration,
+    /// Time after the board executed a new gain before blocks are measured again, so the samples taken with
+    /// the old one that are still buffered are not. 50 ms by default.
+    pub settle_time: Duration,
+    /// Limits inside the channel's gain range, `None` for the channel's own, which is the default.
+    pub gain_limits: Option<RangeInclusive<i8>>,
+}
+
+impl Default for AgcSettings {
+    fn default() -> Self {
+        Self {
+            target_rms_dbfs: -20.0,
+            hysteresis_db: 3.0,
+            clip_level: 0.98,
+            max_clipped: 0,
+            attack_db: 6,
+            decay_db: 1,
+            decay_hold: Duration::from_millis(200),
+            settle_time: Duration::from_millis(50),
+            gain_limits: None,
+        }
+    }
+}
+
+/// What one block looked like, scaled so that 1.0 is full scale.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BlockLevels {
+    pub peak: f32,
+    pub rms: f32,
+    /// Samples with a magnitude of at least `AgcSettings::clip_level`.
+    pub clipped: usize,
+}
+
+impl BlockLevels {
+    pub fn measure<T: ComplexSample>(samples: &[T], clip_level: f32) -> Self {
+        let mut peak_sqr = 0.0f32;
+        let mut sum_sqr = 0.0f64;
+        let mut clipped = 0;
+        let clip_sqr = clip_level * clip_level;
+        for sample in samples {
+            let power = sample.to_complex_f32().norm_sqr();
+            peak_sqr = peak_sqr.max(power);
+            sum_sqr += power as f64;
+            if power >= clip_sqr {
+                clipped += 1;
+            }
+        }
+        let rms = if samples.is_empty() {
+            0.0
+        } else {
+            (sum_sqr / samples.len() as f64).sqrt() as f32
+        };
+        Self {
+            peak: peak_sqr.sqrt(),
+            rms,
+            clipped,
+        }
+    }
+
+    pub fn peak_dbfs(&self) -> f32 {
+        20.0 * self.peak.max(f32::MIN_POSITIVE).log10()
+    }
+
+    pub fn rms_dbfs(&self) -> f32 {
+        20.0 * self.rms.max(f32::MIN_POSITIVE).log10()
+    }
+}
+
+/// Software AGC for one rx channel of a stream.
+#[derive(Debug)]
+pub struct SoftwareAgc {
+    settings: AgcSettings,
+    channel: rfnm_channel,
+    gain_limits: RangeInclusive<i8>,
+    gain: i8,
+    last_change: Instant,
+    // a gain that went out, with the one before it, until the board has executed it
+    pending: Option<(SetTicket, i8)>,
+    // blocks are not measured before this, see `AgcSettings::settle_time`
+    settle_until: Option<Instant>,
+    last_levels: BlockLevels,
+}
+
+impl SoftwareAgc {
+    /// Start from the gain `channel` has right now.
+    pub fn new(
+        device: &Device,
+        channel: rfnm_channel,
+        settings: AgcSettings,
+    ) -> Result<Self, RfnmApiError> {
+        let info = device.get_rx_settings(channel)?;
+        let range = info.gain_range();
+        let gain_limits = match &settings.gain_limits {
+            Some(limits) => *limits.start().max(range.start())..=*limits.end().min(range.end()),
+            None => range,
+        };
+        Ok(Self {
+            settings,
+            channel,
+            gain_limits,
+            gain: info.gain(),
+            last_change: Instant::now(),
+            pending: None,
+            settle_until: None,
+            last_levels: BlockLevels::default(),
+        })
+    }
+
+    pub fn channel(&self) -> rfnm_channel {
+        self.channel
+    }
+
+    /// The gain last set, in dB. It may not be in the samples yet.
+    /// Goes back to the one before when the board rejects it.
+    pub fn gain(&self) -> i8 {
+        self.gain
+    }
+
+    /// Levels of the last block that was measured.
+    pub fn last_levels(&self) -> BlockLevels {
+        self.last_levels
+    }
+
+    /// Look at the next block of the channel and change the gain if needed, returning the new one.
+    ///
+    /// Never waits for the board. While a new gain is not executed yet, and for `AgcSettings::settle_time`
+    /// after, blocks are not measured, as they were taken with the old one.
+    pub fn process<T: ComplexSample>(
+        &mut self,
+        device: &Device,
+        samples: &[T],
+    ) -> Result<Option<i8>, RfnmApiError> {
+        if let Some((ticket, previous_gain)) = &self.pending {
+            let previous_gain = *previous_gain;
+            let state = device.poll_ticket(ticket)?;
+            if !self.ticket_done(state, previous_gain)? {
+                return Ok(None);
+            }
+        }
+        let settling = self
+            .settle_until
+            .is_some_and(|until| Instant::now() < until);
+        if settling || samples.is_empty() {
+            return Ok(None);
+        }
+        self.settle_until = None;
+
+        let levels = BlockLevels::measure(samples, self.settings.clip_level);
+        self.last_levels = levels;
+        let Some(gain) = self.next_gain(&levels) else {
+            return Ok(None);
+        };
+
+        let mut channel_settings = device.get_rx_settings(self.channel)?.to_settings();
+        channel_settings.gain = gain;
+        let ticket = device.submit_rx_settings(&[(self.channel, channel_settings)])?;
+        self.pending = Some((ticket, self.gain));
+        self.gain = gain;
+        self.last_change = Instant::now();
+        Ok(Some(gain))
+    }
+
+    /// Whether the pending gain is through, the error of the board if it rejected it.
+    fn ticket_done(&mut self, state: TicketState, previous_gain: i8) -> Result<bool, RfnmApiError> {
+        match state {
+            TicketState::Pending => return Ok(false),
+            TicketState::Executed(outcome) => {
+                self.settled();
+                // a gain the board did not take is an error for the caller, and the old one is still set
+                if let Err(e) = outcome.into_result() {
+                    self.gain = previous_gain;
+                    return Err(e);
+                }
+            }
+            // other settings were executed after it, so it was as well
+            TicketState::Superseded => self.settled(),
+        }
+        Ok(true)
+    }
+
+    fn settled(&mut self) {
+        self.pending = None;
+        self.settle_until = Some(Instant::now() + self.settings.settle_time);
+    }
+
+    fn next_gain(&self, levels: &BlockLevels) -> Option<i8> {
+        let s = &self.settings;
+        let rms_error = levels.rms_dbfs() - s.target_rms_dbfs;
+        let step = if levels.clipped > s.max_clipped {
+            -(s.attack_db as f32)
+        } else if rms_error > s.hysteresis_db {
+            -rms_error.min(s.attack_db as f32)
+        } else if rms_error < -s.hysteresis_db && self.last_change.elapsed() >= s.decay_hold {
+            // never raise the gain into clipping
+            let headroom = 20.0 * s.clip_level.log10() - levels.peak_dbfs();
+            (-rms_error).min(s.decay_db as f32).min(headroom).max(0.0)
+        } else {
+            0.0
+        };
+        let gain = (self.gain as f32 + step.round()).clamp(
+            *self.gain_limits.start() as f32,
+            *self.gain_limits.end() as f32,
+        ) as i8;
+        (gain != self.gain).then_some(gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::SetOutcome;
+    use rfnm_sys::rfnm_api_failcode;
+
+    /// An AGC at `gain` dB within 0 to 30 dB, last changed `since` ago.
+    fn agc(gain: i8, since: Duration) -> SoftwareAgc {
+        SoftwareAgc {
+            settings: AgcSettings::default(),
+            channel: rfnm_channel(1),
+            gain_limits: 0..=30,
+            gain,
+            last_change: Instant::now() - since,
+            pending: None,
+            settle_until: None,
+            last_levels: BlockLevels::default(),
+        }
+    }
+
+    fn levels(rms_dbfs: f32, peak_dbfs: f32, clipped: usize) -> BlockLevels {
+        BlockLevels {
+            peak: 10f32.powf(peak_dbfs / 20.0),
+            rms: 10f32.powf(rms_dbfs / 20.0),
+            clipped,
+        }
+    }
+
+    const HELD: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn clipping_attacks_whatever_the_rms() {
+        assert_eq!(agc(20, HELD).next_gain(&levels(-40.0, 0.0, 1)), Some(14));
+    }
+
+    #[test]
+    fn too_loud_goes_down_by_the_error_up_to_the_attack() {
+        assert_eq!(agc(20, HELD).next_gain(&levels(-16.0, -6.0, 0)), Some(16));
+        assert_eq!(agc(20, HELD).next_gain(&levels(-5.0, -1.0, 0)), Some(14));
+    }
+
+    #[test]
+    fn within_the_hysteresis_nothing_changes() {
+        assert_eq!(agc(20, HELD).next_gain(&levels(-18.0, -6.0, 0)), None);
+        assert_eq!(agc(20, HELD).next_gain(&levels(-22.5, -6.0, 0)), None);
+    }
+
+    #[test]
+    fn too_quiet_decays_after_the_hold() {
+        assert_eq!(agc(20, HELD).next_gain(&levels(-40.0, -30.0, 0)), Some(21));
+        assert_eq!(
+            agc(20, Duration::ZERO).next_gain(&levels(-40.0, -30.0, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn decay_keeps_the_peak_below_the_clip_level() {
+        // a burst right under the clip level leaves no headroom
+        assert_eq!(agc(20, HELD).next_gain(&levels(-40.0, -0.5, 0)), None);
+    }
+
+    #[test]
+    fn gain_stays_within_the_limits() {
+        assert_eq!(agc(30, HELD).next_gain(&levels(-40.0, -30.0, 0)), None);
+        assert_eq!(agc(3, HELD).next_gain(&levels(-5.0, -1.0, 0)), Some(0));
+        assert_eq!(agc(0, HELD).next_gain(&levels(-5.0, -1.0, 0)), None);
+    }
+
+    #[test]
+    fn a_rejected_gain_is_taken_back() {
+        let mut agc = agc(14, HELD);
+        let mut codes = [0; 8];
+        codes[0] = rfnm_api_failcode::RFNM_API_GAIN_FAIL.0 as i32;
+        let outcome = SetOutcome::new(rfnm_channel(1), &codes);
+        assert!(matches!(
+            agc.ticket_done(TicketState::Executed(outcome), 20),
+            Err(RfnmApiError::GainFail)
+        ));
+        assert_eq!(agc.gain(), 20);
+    }
+
+    #[test]
+    fn an_executed_gain_stays() {
+        let mut agc = agc(14, HELD);
+        assert!(!agc.ticket_done(TicketState::Pending, 20).unwrap());
+        assert_eq!(agc.gain(), 14);
+        let outcome = SetOutcome::new(rfnm_channel(1), &[0; 8]);
+        assert!(agc.ticket_done(TicketState::Executed(outcome), 20).unwrap());
+        assert_eq!(agc.gain(), 14);
+    }
+}
@@ -0,0 +1,136 @@
+//! Stream one channel to GNU Radio over ZeroMQ, set up from a profile file.
+//!
+//! Usage: rfnm_zmq <profile>
+//!
+//! The profile has one `key = value` per line, `#` starts a comment. Everything is optional:
+//!
+//! ```text
+//! serial = ABCD1234        # board to use, the first one found if not given
+//! channel = 0
+//! frequency = 100000000
+//! gain = 0
+//! m = 1                    # kept on the board, does not change the rate
+//! n = 2                    # sample rate is dcs_clk / n
+//! tuning_offset = 0
+//! format = cf32            # cf32, cs16 or cs8
+//! endpoint = tcp://*:5555
+//! pass_tags = true
+//! ```
+//!
+//! Build with `cargo build -p rfnm --features zmq --bin rfnm_zmq`.
+
+use num_complex::Complex;
+use rfnm::RfnmApiError;
+use rfnm::channel_settings::RxChannelSettings;
+use rfnm::device::Device;
+use rfnm::stream::{ComplexSample, RxStream};
+use rfnm::zmq_sink::{BlockInfo, ZmqPubSink, ZmqSinkSettings};
+use rfnm_sys::rfnm_channel;
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+
+struct Profile {
+    values: HashMap<String, String>,
+}
+
+impl Profile {
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut values = HashMap::new();
+        for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{path}:{}: expected `key = value`", number + 1))?;
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(Self { values })
+    }
+
+    fn get<T: FromStr>(&self, key: &str, default: T) -> Result<T, Box<dyn Error>> {
+        match self.values.get(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("invalid value for {key}: {value}").into()),
+            None => Ok(default),
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 2 {
+        eprintln!("Usage: {} <profile>", args[0]);
+        return Ok(());
+    }
+    let profile = Profile::load(&args[1])?;
+
+    let device = match profile.values.get("serial") {
+        Some(serial) => Device::connect_usb_serial(serial)?,
+        None => Device::connect_usb()?,
+    };
+    let channel = rfnm_channel(1 << profile.get::<u32>("channel", 0)?);
+    let defaults = RxChannelSettings::default();
+    let mut settings = device.get_rx_settings(channel)?.to_settings();
+    settings.frequency = profile.get("frequency", defaults.frequency)?;
+    settings.gain = profile.get("gain", defaults.gain)?;
+    settings.rate_divider_settings.m = profile.get("m", settings.rate_divider_settings.m)?;
+    settings.rate_divider_settings.n = profile.get("n", settings.rate_divider_settings.n)?;
+    settings.tuning_offset = profile.get("tuning_offset", 0)?;
+    device.set_rx_settings(channel, &settings)?;
+
+    let sink_settings = ZmqSinkSettings {
+        endpoint: profile.get("endpoint", ZmqSinkSettings::default().endpoint)?,
+        pass_tags: profile.get("pass_tags", true)?,
+        ..Default::default()
+    };
+    let sink = ZmqPubSink::bind(sink_settings)?;
+    eprintln!(
+        "Publishing {} Hz on {}",
+        settings.frequency,
+        sink.settings().endpoint
+    );
+
+    match profile.get("format", "cf32".to_string())?.as_str() {
+        "cf32" => run::<Complex<f32>>(device, channel, sink),
+        "cs16" => run::<Complex<i16>>(device, channel, sink),
+        "cs8" => run::<Complex<i8>>(device, channel, sink),
+        other => Err(format!("unknown format {other}, use cf32, cs16 or cs8").into()),
+    }
+}
+
+fn run<T: ComplexSample>(
+    device: Device,
+    channel: rfnm_channel,
+    mut sink: ZmqPubSink,
+) -> Result<(), Box<dyn Error>> {
+    let stream = RxStream::<T>::new(device, channel).map_err(|(e, _)| e)?;
+    let mut scratch =
+        vec![T::from_complex_f32(Complex::new(0.0, 0.0)); stream.suggested_buffer_size()];
+    stream.start()?;
+    loop {
+        let info = stream.read(&mut [scratch.as_mut_slice()], Duration::from_millis(100))?;
+        let frequency = stream
+            .device()
+            .get_rx_settings(channel)?
+            .to_settings()
+            .frequency;
+        sink.send(
+            &scratch[..info.elements_read],
+            &BlockInfo {
+                timestamp_ns: info.timestamp_ns,
+                frequency: frequency as f64,
+                sample_rate: stream.sample_rate(),
+            },
+        )?;
+        // the samples before a timeout are fine, anything else ends the bridge once they are out
+        match info.error {
+            None | Some(RfnmApiError::Timeout) => {}
+            Some(e) => return Err(e.into()),
+        }
+    }
+}
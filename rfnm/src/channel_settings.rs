@@ -1,16 +1,28 @@
+use crate::iq_balance::IqBalance;
 use crate::{RfnmApiError, check_code};
+use num_complex::Complex;
 use rfnm_sys::{
     DeviceWrapper,
     device_get_rx_channel,
+    device_get_tx_channel,
+    device_set,
     device_set_rx_channel_freq,
     device_set_rx_channel_gain,
     device_set_rx_channel_path,
     device_set_rx_channel_samp_freq_div,
+    rfnm_agc_type,
     rfnm_api_rx_ch,
+    rfnm_api_tx_ch,
+    rfnm_bias_tee,
+    rfnm_ch_data_type,
+    rfnm_ch_enable,
+    rfnm_ch_stream,
+    rfnm_fm_notch,
     rfnm_rf_path,
 };
 use std::fmt::{Display, Formatter};
 use std::mem::MaybeUninit;
+use std::ops::RangeInclusive;
 
 /// This struct represents the full range of possible *everything* a rx channel can be, as well as its current state.
 /// Only a subset of this can actually be set during runtime.
@@ -18,19 +30,29 @@ use std::mem::MaybeUninit;
 #[derive(Debug, Clone)]
 pub struct RxChannelInfo {
     raw: rfnm_api_rx_ch,
+    host: HostChannelState,
+}
+
+/// The part of the channel settings that only lives on the rust side. The device knows nothing about these.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct HostChannelState {
+    pub iq_balance: IqBalance,
+    pub tuning_offset: i64,
 }
 
 impl RxChannelInfo {
     /// Extract the part of the `RxChannelInfo` that can be updated at runtime.
     pub fn to_settings(&self) -> RxChannelSettings {
         RxChannelSettings {
-            frequency: self.raw.freq,
+            frequency: self.raw.freq + self.host.tuning_offset,
             gain: self.raw.gain,
             rate_divider_settings: SampleRateDividerSettings {
                 m: self.raw.samp_freq_div_m,
                 n: self.raw.samp_freq_div_n,
             },
             path: self.path(),
+            iq_balance: self.host.iq_balance,
+            tuning_offset: self.host.tuning_offset,
         }
     }
 
@@ -48,13 +70,105 @@ impl RxChannelInfo {
             raw.assume_init()
         };
 
-        Ok(Self { raw })
+        Ok(Self {
+            raw,
+            host: HostChannelState::default(),
+        })
     }
 
+    pub(crate) fn with_host_state(mut self, host: HostChannelState) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// The frequency the LO is tuned to. With offset tuning, this is not the one you asked for.
     pub fn freq(&self) -> i64 {
         self.raw.freq
     }
 
+    /// Frequencies the channel can be tuned to, in Hz.
+    pub fn freq_range(&self) -> RangeInclusive<i64> {
+        self.raw.freq_min..=self.raw.freq_max
+    }
+
+    pub fn freq_min(&self) -> i64 {
+        self.raw.freq_min
+    }
+
+    pub fn freq_max(&self) -> i64 {
+        self.raw.freq_max
+    }
+
+    /// Current gain, in dB.
+    pub fn gain(&self) -> i8 {
+        self.raw.gain
+    }
+
+    /// Gains the channel accepts, in dB.
+    pub fn gain_range(&self) -> RangeInclusive<i8> {
+        let range = self.raw.gain_range;
+        range.min..=range.max.min(i8::MAX as u8) as i8
+    }
+
+    /// Bandwidth of the low pass filter in the RF IC, in MHz.
+    pub fn rfic_lpf_bw(&self) -> i16 {
+        self.raw.rfic_lpf_bw
+    }
+
+    /// Number of the channel across the whole board, bit `abs_id` in a `crate::rfnm_channel`.
+    pub fn abs_id(&self) -> i8 {
+        self.raw.abs_id
+    }
+
+    /// Daughterboard slot the channel is on.
+    pub fn dgb_id(&self) -> i8 {
+        self.raw.dgb_id
+    }
+
+    /// Number of the channel on its daughterboard.
+    pub fn dgb_ch_id(&self) -> i8 {
+        self.raw.dgb_ch_id
+    }
+
+    /// ADC the channel is sampled with. Channels sharing one cannot stream together.
+    pub fn adc_id(&self) -> i8 {
+        self.raw.adc_id
+    }
+
+    /// False if the daughterboard has the channel, but it cannot be used right now.
+    pub fn avail(&self) -> bool {
+        self.raw.avail != 0
+    }
+
+    pub fn enable(&self) -> rfnm_ch_enable {
+        self.raw.enable
+    }
+
+    pub fn stream(&self) -> rfnm_ch_stream {
+        self.raw.stream
+    }
+
+    pub fn agc(&self) -> rfnm_agc_type {
+        self.raw.agc
+    }
+
+    pub fn bias_tee(&self) -> rfnm_bias_tee {
+        self.raw.bias_tee
+    }
+
+    pub fn fm_notch(&self) -> rfnm_fm_notch {
+        self.raw.fm_notch
+    }
+
+    pub fn data_type(&self) -> rfnm_ch_data_type {
+        self.raw.data_type
+    }
+
+    /// DC offset the RF IC corrects by itself, I in `re` and Q in `im`. librfnm has no setter for it.
+    pub fn rfic_dc_offset(&self) -> Complex<i16> {
+        Complex::new(self.raw.rfic_dc_i, self.raw.rfic_dc_q)
+    }
+
     pub fn available_paths(&self) -> impl IntoIterator<Item = RfPath> {
         let paths = self.raw.path_possible;
         paths.into_iter().filter_map(|raw_path| {
@@ -73,14 +187,158 @@ impl RxChannelInfo {
     pub fn preferred_path(&self) -> RfPath {
         RfPath(self.raw.path_preferred)
     }
+
+    /// The IQ imbalance last stored for this channel. This is kept on the rust side, not on the device.
+    pub fn iq_balance(&self) -> IqBalance {
+        self.host.iq_balance
+    }
+
+    /// Distance of the LO from the requested frequency, see `RxChannelSettings::tuning_offset`.
+    pub fn tuning_offset(&self) -> i64 {
+        self.host.tuning_offset
+    }
+}
+
+impl Display for RxChannelInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let gain_range = self.gain_range();
+        write!(
+            f,
+            "ch {} (dgb {} ch {}, adc {}){}: {} Hz ({}..={}), gain {} dB ({}..={}), path {}",
+            self.abs_id(),
+            self.dgb_id(),
+            self.dgb_ch_id(),
+            self.adc_id(),
+            if self.avail() { "" } else { " unavailable" },
+            self.freq(),
+            self.freq_min(),
+            self.freq_max(),
+            self.gain(),
+            gain_range.start(),
+            gain_range.end(),
+            self.path(),
+        )?;
+        if self.tuning_offset() != 0 {
+            write!(f, ", tuning offset {} Hz", self.tuning_offset())?;
+        }
+        let dc = self.rfic_dc_offset();
+        write!(
+            f,
+            ", lpf {} MHz, {}, stream {}, agc {}, bias tee {}, fm notch {}, {}, rfic dc {}/{}",
+            self.rfic_lpf_bw(),
+            match self.enable() {
+                rfnm_ch_enable::RFNM_CH_OFF => "off",
+                rfnm_ch_enable::RFNM_CH_ON => "on",
+                rfnm_ch_enable::RFNM_CH_ON_TDD => "on (tdd)",
+                _ => "unknown",
+            },
+            match self.stream() {
+                rfnm_ch_stream::RFNM_CH_STREAM_AUTO => "auto",
+                rfnm_ch_stream::RFNM_CH_STREAM_OFF => "off",
+                rfnm_ch_stream::RFNM_CH_STREAM_ON => "on",
+                _ => "unknown",
+            },
+            match self.agc() {
+                rfnm_agc_type::RFNM_AGC_OFF => "off",
+                rfnm_agc_type::RFNM_AGC_DEFAULT => "on",
+                _ => "unknown",
+            },
+            match self.bias_tee() {
+                rfnm_bias_tee::RFNM_BIAS_TEE_OFF => "off",
+                rfnm_bias_tee::RFNM_BIAS_TEE_ON => "on",
+                _ => "unknown",
+            },
+            match self.fm_notch() {
+                rfnm_fm_notch::RFNM_FM_NOTCH_AUTO => "auto",
+                rfnm_fm_notch::RFNM_FM_NOTCH_ON => "on",
+                rfnm_fm_notch::RFNM_FM_NOTCH_OFF => "off",
+                _ => "unknown",
+            },
+            match self.data_type() {
+                rfnm_ch_data_type::RFNM_CH_DATA_TYPE_COMPLEX => "complex",
+                rfnm_ch_data_type::RFNM_CH_DATA_TYPE_REAL => "real",
+                _ => "unknown",
+            },
+            dc.re,
+            dc.im,
+        )
+    }
+}
+
+/// Where a tx channel sits and what it can do. Nothing here can be changed yet.
+#[derive(Debug, Clone)]
+pub struct TxChannelInfo {
+    raw: rfnm_api_tx_ch,
+}
+
+impl TxChannelInfo {
+    pub(crate) unsafe fn from_device(
+        wrapper: *mut DeviceWrapper,
+        channel_num: u32,
+    ) -> Result<Self, RfnmApiError> {
+        let mut raw: MaybeUninit<rfnm_api_tx_ch> = MaybeUninit::uninit();
+        let raw = unsafe {
+            check_code(device_get_tx_channel(
+                wrapper,
+                channel_num,
+                raw.as_mut_ptr(),
+            ))?;
+            raw.assume_init()
+        };
+        Ok(Self { raw })
+    }
+
+    /// Number of the channel across the whole board.
+    pub fn abs_id(&self) -> i8 {
+        self.raw.abs_id
+    }
+
+    /// Daughterboard slot the channel is on.
+    pub fn dgb_id(&self) -> i8 {
+        self.raw.dgb_id
+    }
+
+    /// Number of the channel on its daughterboard.
+    pub fn dgb_ch_id(&self) -> i8 {
+        self.raw.dgb_ch_id
+    }
+
+    /// DAC the channel is fed from.
+    pub fn dac_id(&self) -> i8 {
+        self.raw.dac_id
+    }
+
+    /// Frequencies the channel can be tuned to, in Hz.
+    pub fn freq_range(&self) -> RangeInclusive<i64> {
+        self.raw.freq_min..=self.raw.freq_max
+    }
+
+    /// Output powers the channel accepts, in dBm.
+    pub fn power_range(&self) -> RangeInclusive<i8> {
+        let range = self.raw.power_range;
+        range.min..=range.max.min(i8::MAX as u8) as i8
+    }
+
+    pub fn avail(&self) -> bool {
+        self.raw.avail != 0
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct SampleRateDividerSettings {
+    /// Stored on the board but not used for the rate, librfnm only checks it matches between channels.
     pub m: i16,
     pub n: i16,
 }
 
+impl SampleRateDividerSettings {
+    /// The resulting sample rate in Hz, given the `dcs_clk` from `crate::hwinfo::ClockInfo`.
+    /// This is `dcs_clk / n`, the same librfnm derives its timestamps from.
+    pub fn sample_rate(&self, dcs_clk: u64) -> f64 {
+        dcs_clk as f64 / self.n as f64
+    }
+}
+
 impl Default for SampleRateDividerSettings {
     fn default() -> Self {
         Self { m: 1, n: 1 }
@@ -88,11 +346,18 @@ impl Default for SampleRateDividerSettings {
 }
 
 /// The settable portion of the RxChannelInfo. All members are public to ease editing.
+#[derive(Debug, Clone)]
 pub struct RxChannelSettings {
     pub frequency: i64,
     pub gain: i8,
     pub rate_divider_settings: SampleRateDividerSettings,
     pub path: RfPath,
+    /// Correction for `crate::iq_balance::IqCorrector`. Not sent to the device, only remembered for the channel.
+    pub iq_balance: IqBalance,
+    /// Offset tuning: the LO is set this far below `frequency`, and a `crate::stream::RxStream`
+    /// shifts its output back, so `frequency` still ends up at 0 Hz but the DC spike does not.
+    /// 0 disables it.
+    pub tuning_offset: i64,
 }
 
 impl Default for RxChannelSettings {
@@ -102,22 +367,43 @@ impl Default for RxChannelSettings {
             gain: 0,
             rate_divider_settings: Default::default(),
             path: RfPath::default(),
+            iq_balance: IqBalance::default(),
+            tuning_offset: 0,
         }
     }
 }
 
 impl RxChannelSettings {
+    /// Apply the settings and wait up to `timeout_us` for the board to confirm them.
     pub(crate) unsafe fn apply_to_device(
         &self,
         wrapper: *mut DeviceWrapper,
         channel_num: u32,
+        timeout_us: u32,
+    ) -> Result<(), RfnmApiError> {
+        unsafe {
+            self.stage_on_device(wrapper, channel_num)?;
+            check_code(device_set(
+                wrapper,
+                rx_apply_flag(channel_num),
+                true,
+                timeout_us,
+            ))
+        }
+    }
+
+    /// Store the settings in librfnm without sending them to the board.
+    pub(crate) unsafe fn stage_on_device(
+        &self,
+        wrapper: *mut DeviceWrapper,
+        channel_num: u32,
     ) -> Result<(), RfnmApiError> {
         unsafe {
             check_code(device_set_rx_channel_samp_freq_div(
                 wrapper,
                 channel_num,
                 self.rate_divider_settings.m,
-                self.rate_divider_settings.m,
+                self.rate_divider_settings.n,
                 false,
             ))?;
             check_code(device_set_rx_channel_gain(
@@ -132,18 +418,79 @@ impl RxChannelSettings {
                 self.path.0,
                 false,
             ))?;
-            // Do frequency last, as it is the most likely to affect things across the board.
             check_code(device_set_rx_channel_freq(
                 wrapper,
                 channel_num,
                 self.frequency,
-                true,
+                false,
             ))?;
         }
         Ok(())
     }
 }
 
+/// `RxChannelSettings` as plain numbers, for bindings and wire formats that cannot carry the nested types.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlatRxChannelSettings {
+    pub frequency: i64,
+    pub gain: i8,
+    /// Sample rate is dcs_clk / n, m does not change it
+    pub m: i16,
+    pub n: i16,
+    /// Index of the rf path, 0 is SMA A
+    pub path: u32,
+    pub iq_gain: f32,
+    pub iq_phase: f32,
+    pub tuning_offset: i64,
+}
+
+impl Default for FlatRxChannelSettings {
+    fn default() -> Self {
+        RxChannelSettings::default().into()
+    }
+}
+
+impl From<RxChannelSettings> for FlatRxChannelSettings {
+    fn from(value: RxChannelSettings) -> Self {
+        Self {
+            frequency: value.frequency,
+            gain: value.gain,
+            m: value.rate_divider_settings.m,
+            n: value.rate_divider_settings.n,
+            path: value.path.0.0,
+            iq_gain: value.iq_balance.gain,
+            iq_phase: value.iq_balance.phase,
+            tuning_offset: value.tuning_offset,
+        }
+    }
+}
+
+impl From<FlatRxChannelSettings> for RxChannelSettings {
+    fn from(value: FlatRxChannelSettings) -> Self {
+        Self {
+            frequency: value.frequency,
+            gain: value.gain,
+            rate_divider_settings: SampleRateDividerSettings {
+                m: value.m,
+                n: value.n,
+            },
+            path: RfPath(rfnm_rf_path(value.path)),
+            iq_balance: IqBalance {
+                gain: value.iq_gain,
+                phase: value.iq_phase,
+            },
+            tuning_offset: value.tuning_offset,
+        }
+    }
+}
+
+/// librfnm's `APPLY_CHx_RX`, the rx channels sit in the upper byte of the apply mask.
+pub(crate) fn rx_apply_flag(channel_num: u32) -> u16 {
+    0x100 << channel_num
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RfPath(pub rfnm_rf_path);
 
@@ -173,3 +520,16 @@ impl Default for RfPath {
         Self(rfnm_rf_path::RFNM_PATH_SMA_A)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_rate_ignores_m_like_librfnm() {
+        let dcs_clk = 122_880_000;
+        let rate = |m, n| SampleRateDividerSettings { m, n }.sample_rate(dcs_clk);
+        assert_eq!(rate(1, 2), 61_440_000.0);
+        assert_eq!(rate(3, 2), rate(1, 2));
+    }
+}
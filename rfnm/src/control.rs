@@ -0,0 +1,166 @@
+//! Settings without the wait.
+//!
+//! `Device::submit_rx_settings` sends channel settings to the board and returns right away with a `SetTicket`.
+//! The board works through them while the caller keeps reading samples, and `Device::poll_ticket`
+//! or `Device::wait_ticket` later tell whether it is done and what every channel reported.
+//!
+//! The board only remembers the outcome of the last settings it executed, so the outcome of a ticket
+//! is gone once anything else has been applied after it. Such a ticket reads as superseded.
+
+use crate::{RfnmApiError, check_code, split_channel_flags};
+use rfnm_sys::{rfnm_api_failcode, rfnm_channel, rfnm_dev_get_set_result};
+use std::time::Duration;
+
+/// How long applying settings may take before giving up, unless changed with `Device::set_confirm_timeout`.
+/// The same as librfnm's own default.
+pub const DEFAULT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Settings that were sent to the board, but are not confirmed yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetTicket {
+    pub(crate) cc_rx: u32,
+    pub(crate) rx_channels: rfnm_channel,
+}
+
+impl SetTicket {
+    /// The rx channels the settings were submitted for.
+    pub fn rx_channels(&self) -> rfnm_channel {
+        self.rx_channels
+    }
+
+    pub(crate) fn state(&self, result: &rfnm_dev_get_set_result) -> TicketState {
+        // the change counter wraps, so compare by distance
+        match result.cc_rx.wrapping_sub(self.cc_rx) as i32 {
+            d if d < 0 => TicketState::Pending,
+            0 => TicketState::Executed(SetOutcome::new(self.rx_channels, &result.rx_ecodes)),
+            _ => TicketState::Superseded,
+        }
+    }
+}
+
+/// Where the board is with a `SetTicket`.
+#[derive(Debug)]
+pub enum TicketState {
+    /// Not executed yet.
+    Pending,
+    /// Executed, with the result of every channel.
+    Executed(SetOutcome),
+    /// Executed, but later settings were executed after it and the per channel results are lost.
+    Superseded,
+}
+
+/// What the board reported for each channel of a `SetTicket`.
+#[derive(Debug)]
+pub struct SetOutcome {
+    channels: Vec<(rfnm_channel, Result<(), RfnmApiError>)>,
+}
+
+impl SetOutcome {
+    pub(crate) fn new(rx_channels: rfnm_channel, rx_ecodes: &[i32; 8]) -> Self {
+        let channels = split_channel_flags(rx_channels)
+            .map(|ch| {
+                let code = rx_ecodes[ch.0.trailing_zeros() as usize];
+                (ch, check_code(rfnm_api_failcode(code as _)))
+            })
+            .collect();
+        Self { channels }
+    }
+
+    /// Every channel of the ticket with its result, lowest first.
+    pub fn channels(&self) -> impl Iterator<Item = (rfnm_channel, &Result<(), RfnmApiError>)> {
+        self.channels.iter().map(|(ch, result)| (*ch, result))
+    }
+
+    /// The result of one channel, `None` if it was not part of the ticket.
+    pub fn channel(&self, channel: rfnm_channel) -> Option<&Result<(), RfnmApiError>> {
+        self.channels
+            .iter()
+            .find(|(ch, _)| *ch == channel)
+            .map(|(_, result)| result)
+    }
+
+    /// True if every channel took its settings.
+    pub fn is_ok(&self) -> bool {
+        self.channels.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// The first failure, the way a confirmed `Device::set_rx_settings` would have returned it.
+    pub fn into_result(self) -> Result<(), RfnmApiError> {
+        self.channels
+            .into_iter()
+            .map(|(_, result)| result)
+            .find(Result::is_err)
+            .unwrap_or(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(cc_rx: u32, rx_ecodes: [i32; 8]) -> rfnm_dev_get_set_result {
+        rfnm_dev_get_set_result {
+            cc_rx,
+            rx_ecodes,
+            ..Default::default()
+        }
+    }
+
+    fn ticket(cc_rx: u32) -> SetTicket {
+        SetTicket {
+            cc_rx,
+            rx_channels: rfnm_channel(0b101),
+        }
+    }
+
+    #[test]
+    fn a_ticket_is_pending_until_its_counter_comes_back() {
+        assert!(matches!(
+            ticket(5).state(&result(4, [0; 8])),
+            TicketState::Pending
+        ));
+        assert!(matches!(
+            ticket(5).state(&result(5, [0; 8])),
+            TicketState::Executed(_)
+        ));
+        assert!(matches!(
+            ticket(5).state(&result(6, [0; 8])),
+            TicketState::Superseded
+        ));
+    }
+
+    #[test]
+    fn counters_compare_across_the_wrap() {
+        assert!(matches!(
+            ticket(1).state(&result(u32::MAX, [0; 8])),
+            TicketState::Pending
+        ));
+        assert!(matches!(
+            ticket(u32::MAX).state(&result(0, [0; 8])),
+            TicketState::Superseded
+        ));
+        assert!(matches!(
+            ticket(0).state(&result(0, [0; 8])),
+            TicketState::Executed(_)
+        ));
+    }
+
+    #[test]
+    fn the_outcome_has_the_codes_of_the_ticket_channels() {
+        let mut codes = [0; 8];
+        codes[1] = rfnm_api_failcode::RFNM_API_GAIN_FAIL.0 as i32;
+        codes[2] = rfnm_api_failcode::RFNM_API_TUNE_FAIL.0 as i32;
+        let TicketState::Executed(outcome) = ticket(3).state(&result(3, codes)) else {
+            panic!("not executed");
+        };
+        assert!(matches!(outcome.channel(rfnm_channel(1)), Some(Ok(()))));
+        assert!(matches!(
+            outcome.channel(rfnm_channel(1 << 2)),
+            Some(Err(RfnmApiError::TuneFail))
+        ));
+        // channel 1 was not part of the ticket
+        assert!(outcome.channel(rfnm_channel(1 << 1)).is_none());
+        assert!(!outcome.is_ok());
+        assert!(matches!(outcome.into_result(), Err(RfnmApiError::TuneFail)));
+    }
+}
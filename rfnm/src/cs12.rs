@@ -0,0 +1,531 @@
+//! Repacking CS16 into 12 bit samples, and recording them.
+//!
+//! The board sends every complex sample as 3 bytes: 12 bit I, then 12 bit Q, little endian
+//! (`LA_RX_BASE_BUFSIZE_12`), but librfnm always unpacks them to CS16 on the way in and has no way
+//! to hand them out packed. `RxStream<Cs12>` therefore reads CS16 and packs it again on the host,
+//! which costs a pass over the samples but stores them at 3/4 of the size of CS16. As librfnm's
+//! unpacking only shifts the 12 bits up, the repacked samples are the ones the board sent,
+//! unless its DC offset correction is on.
+//!
+//! `Cs12Writer` stores blocks of them in a simple file format, `Cs12Reader` reads them back,
+//! as they were or converted to CS16 or CF32. A file is a header:
+//!
+//! | bytes | content                         |
+//! |-------|---------------------------------|
+//! | 8     | `RFNMCS12`                      |
+//! | 4     | version, 1                      |
+//! | 4     | channel count                   |
+//! | 8     | sample rate in Hz, `f64`        |
+//!
+//! followed by blocks of a `u64` timestamp in ns (`StreamReadInfo::timestamp_ns`), a `u32` sample count,
+//! and that many samples of every channel in turn. Everything is little endian. A block holds at most
+//! `MAX_BLOCK_SAMPLES` samples over all channels, longer reads are written as several.
+
+use crate::stream::{ComplexSample, Cs16Repack, StreamDataFormat, StreamReadInfo};
+use num_complex::Complex;
+use rfnm_sys::rfnm_stream_format;
+use std::io::{ErrorKind, Read, Write};
+use thiserror::Error;
+
+const MAGIC: &[u8; 8] = b"RFNMCS12";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 24;
+const BLOCK_HEADER_SIZE: usize = 12;
+
+/// Most samples, over all channels, one block of a recording holds.
+/// Keeps what a reader allocates for a block bounded, whatever the file says.
+pub const MAX_BLOCK_SAMPLES: usize = 1 << 22;
+
+/// One complex sample packed into 3 bytes, the way the board packs it on USB.
+///
+/// Bits 0 to 11 are I, bits 12 to 23 are Q, both two's complement.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Cs12(pub [u8; 3]);
+
+impl Cs12 {
+    pub const MIN: i16 = -2048;
+    pub const MAX: i16 = 2047;
+
+    /// Pack `re` and `im`, both in `Cs12::MIN..=Cs12::MAX`. Only their lower 12 bits are kept.
+    pub fn new(re: i16, im: i16) -> Self {
+        let re = re as u16 & 0xfff;
+        let im = im as u16 & 0xfff;
+        Self([re as u8, (re >> 8) as u8 | (im << 4) as u8, (im >> 4) as u8])
+    }
+
+    pub fn re(self) -> i16 {
+        let raw = self.0[0] as u16 | (self.0[1] as u16 & 0xf) << 8;
+        // move the sign bit to the top and back to extend it
+        (raw << 4) as i16 >> 4
+    }
+
+    pub fn im(self) -> i16 {
+        let raw = (self.0[1] as u16) >> 4 | (self.0[2] as u16) << 4;
+        (raw << 4) as i16 >> 4
+    }
+
+    /// The sample as librfnm's CS16 has it, the 12 bits in the upper bits.
+    pub fn to_cs16(self) -> Complex<i16> {
+        Complex::new(self.re() << 4, self.im() << 4)
+    }
+
+    /// Round CS16 to 12 bits. Exact for samples straight from librfnm, unless its DC offset correction is on.
+    pub fn from_cs16(value: Complex<i16>) -> Self {
+        let round = |v: i16| ((v as i32 + 8) >> 4).min(Self::MAX as i32) as i16;
+        Self::new(round(value.re), round(value.im))
+    }
+}
+
+/// Read as CS16 from librfnm and repacked with `cs16_to_cs12`.
+impl StreamDataFormat for Cs12 {
+    fn api_format() -> rfnm_stream_format {
+        rfnm_stream_format::STREAM_FORMAT_CS16
+    }
+    const FROM_CS16: Option<Cs16Repack<Self>> = Some(cs16_to_cs12);
+}
+
+impl ComplexSample for Cs12 {
+    fn to_complex_f32(self) -> Complex<f32> {
+        self.to_cs16().to_complex_f32()
+    }
+    fn from_complex_f32(value: Complex<f32>) -> Self {
+        Self::from_cs16(Complex::<i16>::from_complex_f32(value))
+    }
+}
+
+/// Pack as many samples as both slices hold.
+pub fn cs16_to_cs12(src: &[Complex<i16>], dst: &mut [Cs12]) {
+    for (out, sample) in dst.iter_mut().zip(src) {
+        *out = Cs12::from_cs16(*sample);
+    }
+}
+
+/// Unpack as many samples as both slices hold, like librfnm does for CS16.
+pub fn cs12_to_cs16(src: &[Cs12], dst: &mut [Complex<i16>]) {
+    for (out, sample) in dst.iter_mut().zip(src) {
+        *out = sample.to_cs16();
+    }
+}
+
+/// Unpack as many samples as both slices hold, like librfnm does for CF32.
+pub fn cs12_to_cf32(src: &[Cs12], dst: &mut [Complex<f32>]) {
+    for (out, sample) in dst.iter_mut().zip(src) {
+        *out = sample.to_complex_f32();
+    }
+}
+
+/// Sample formats `Cs12Reader` converts to.
+pub trait FromCs12: Copy {
+    fn from_cs12(src: &[Cs12], dst: &mut [Self]);
+}
+
+impl FromCs12 for Cs12 {
+    fn from_cs12(src: &[Cs12], dst: &mut [Self]) {
+        let count = src.len().min(dst.len());
+        dst[..count].copy_from_slice(&src[..count]);
+    }
+}
+
+impl FromCs12 for Complex<i16> {
+    fn from_cs12(src: &[Cs12], dst: &mut [Self]) {
+        cs12_to_cs16(src, dst);
+    }
+}
+
+impl FromCs12 for Complex<f32> {
+    fn from_cs12(src: &[Cs12], dst: &mut [Self]) {
+        cs12_to_cf32(src, dst);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Cs12Error {
+    #[error("Could not access the recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not a CS12 recording, or one of another version")]
+    NotARecording,
+    #[error("A recording needs between 1 and {MAX_BLOCK_SAMPLES} channels, got {0}")]
+    InvalidChannelCount(usize),
+    #[error("Block of {0} samples, recordings have at most {MAX_BLOCK_SAMPLES}")]
+    BlockTooLarge(u64),
+    #[error("Buffer count {0} does not match recording channel count of {1}")]
+    BufferCountMismatch(usize, usize),
+    #[error("Buffer sizes do not match. They must all be the same")]
+    BufferSizeMismatch,
+}
+
+/// Writes blocks of `Cs12` samples in the recording format.
+pub struct Cs12Writer<W: Write> {
+    out: W,
+    channels: usize,
+    sample_rate: f64,
+    samples_written: u64,
+}
+
+impl<W: Write> Cs12Writer<W> {
+    /// Start a recording of `channels` at `sample_rate`, writing the header right away.
+    ///
+    /// Writes go straight to `out`, wrap files in a `BufWriter`.
+    pub fn new(mut out: W, channels: usize, sample_rate: f64) -> Result<Self, Cs12Error> {
+        if channels == 0 || channels > MAX_BLOCK_SAMPLES {
+            return Err(Cs12Error::InvalidChannelCount(channels));
+        }
+        let mut header = [0; HEADER_SIZE];
+        header[0..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&(channels as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&sample_rate.to_le_bytes());
+        out.write_all(&header)?;
+        Ok(Self {
+            out,
+            channels,
+            sample_rate,
+            samples_written: 0,
+        })
+    }
+
+    /// Samples per channel written so far.
+    pub fn samples_written(&self) -> u64 {
+        self.samples_written
+    }
+
+    /// Write the first `info.elements_read` samples of every buffer in `src` as one block.
+    pub fn write_block(&mut self, src: &[&[Cs12]], info: &StreamReadInfo) -> Result<(), Cs12Error> {
+        if src.len() != self.channels {
+            return Err(Cs12Error::BufferCountMismatch(src.len(), self.channels));
+        }
+        if src.iter().any(|buffer| buffer.len() < info.elements_read) {
+            return Err(Cs12Error::BufferSizeMismatch);
+        }
+        // longer reads become several blocks
+        let max_count = MAX_BLOCK_SAMPLES / self.channels;
+        let mut done = 0;
+        while done < info.elements_read {
+            let count = (info.elements_read - done).min(max_count);
+            let mut header = [0; BLOCK_HEADER_SIZE];
+            let timestamp_ns = info.timestamp_ns + (done as f64 * 1e9 / self.sample_rate) as u64;
+            header[0..8].copy_from_slice(&timestamp_ns.to_le_bytes());
+            header[8..12].copy_from_slice(&(count as u32).to_le_bytes());
+            self.out.write_all(&header)?;
+            for buffer in src {
+                let samples = &buffer[done..done + count];
+                // `Cs12` is just its bytes
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(samples.as_ptr() as *const u8, samples.len() * 3)
+                };
+                self.out.write_all(bytes)?;
+            }
+            done += count;
+        }
+        self.samples_written += info.elements_read as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Cs12Error> {
+        Ok(self.out.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Reads a recording `Cs12Writer` made.
+///
+/// A block cut short, e.g. because the capture was killed, ends the recording.
+pub struct Cs12Reader<R: Read> {
+    input: R,
+    channels: usize,
+    sample_rate: f64,
+    // the current block, channel after channel
+    block: Vec<Cs12>,
+    block_len: usize,
+    block_timestamp_ns: u64,
+    // samples of the current block already handed out
+    offset: usize,
+}
+
+impl<R: Read> Cs12Reader<R> {
+    /// Read the header of the recording in `input`.
+    pub fn new(mut input: R) -> Result<Self, Cs12Error> {
+        let mut header = [0; HEADER_SIZE];
+        input.read_exact(&mut header).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => Cs12Error::NotARecording,
+            _ => e.into(),
+        })?;
+        let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let channels = word(12) as usize;
+        if &header[0..8] != MAGIC
+            || word(8) != VERSION
+            || channels == 0
+            || channels > MAX_BLOCK_SAMPLES
+        {
+            return Err(Cs12Error::NotARecording);
+        }
+        Ok(Self {
+            input,
+            channels,
+            sample_rate: f64::from_le_bytes(header[16..24].try_into().unwrap()),
+            block: Vec::new(),
+            block_len: 0,
+            block_timestamp_ns: 0,
+            offset: 0,
+        })
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Read the same number of samples for every channel, converted to `T`.
+    ///
+    /// Returns at most the rest of one block, `None` at the end of the recording.
+    pub fn read<T: FromCs12>(
+        &mut self,
+        dst: &mut [&mut [T]],
+    ) -> Result<Option<StreamReadInfo>, Cs12Error> {
+        if dst.len() != self.channels {
+            return Err(Cs12Error::BufferCountMismatch(dst.len(), self.channels));
+        }
+        if dst.iter().any(|buffer| buffer.len() != dst[0].len()) {
+            return Err(Cs12Error::BufferSizeMismatch);
+        }
+        if self.offset == self.block_len && !self.next_block()? {
+            return Ok(None);
+        }
+
+        let count = dst[0].len().min(self.block_len - self.offset);
+        for (channel, buffer) in dst.iter_mut().enumerate() {
+            let start = channel * self.block_len + self.offset;
+            T::from_cs12(&self.block[start..start + count], buffer);
+        }
+        let info = StreamReadInfo {
+            elements_read: count,
+            timestamp_ns: self.block_timestamp_ns
+                + (self.offset as f64 * 1e9 / self.sample_rate) as u64,
+            error: None,
+        };
+        self.offset += count;
+        Ok(Some(info))
+    }
+
+    /// Load the next non-empty block, `false` at the end of the recording.
+    fn next_block(&mut self) -> Result<bool, Cs12Error> {
+        loop {
+            let mut header = [0; BLOCK_HEADER_SIZE];
+            if !self.fill(&mut header)? {
+                return Ok(false);
+            }
+            let len = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+            let samples = len as u64 * self.channels as u64;
+            if samples > MAX_BLOCK_SAMPLES as u64 {
+                return Err(Cs12Error::BlockTooLarge(samples));
+            }
+            self.block.resize(len * self.channels, Cs12::default());
+            let bytes = unsafe {
+                std::slice::from_raw_parts_mut(
+                    self.block.as_mut_ptr() as *mut u8,
+                    self.block.len() * 3,
+                )
+            };
+            if !self.fill(bytes)? {
+                return Ok(false);
+            }
+            self.block_len = len;
+            self.block_timestamp_ns = u64::from_le_bytes(header[0..8].try_into().unwrap());
+            self.offset = 0;
+            if len > 0 {
+                return Ok(true);
+            }
+        }
+    }
+
+    // `read_exact`, but running out of input is the end of the recording rather than an error
+    fn fill(&mut self, buf: &mut [u8]) -> Result<bool, Cs12Error> {
+        match self.input.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn packing_matches_the_board() {
+        // I 0x123, Q 0xabc
+        let sample = Cs12::new(0x123, 0xabc_u16 as i16);
+        assert_eq!(sample.0, [0x23, 0xc1, 0xab]);
+        assert_eq!(Cs12([0x23, 0xc1, 0xab]).re(), 0x123);
+        assert_eq!(Cs12([0x23, 0xc1, 0xab]).im(), 0xabc - 0x1000);
+    }
+
+    #[test]
+    fn every_value_survives_packing() {
+        for re in Cs12::MIN..=Cs12::MAX {
+            let im = Cs12::MAX - (re - Cs12::MIN);
+            let sample = Cs12::new(re, im);
+            assert_eq!((sample.re(), sample.im()), (re, im));
+            assert_eq!(sample.to_cs16(), Complex::new(re << 4, im << 4));
+            assert_eq!(Cs12::from_cs16(sample.to_cs16()), sample);
+        }
+    }
+
+    #[test]
+    fn cs16_is_rounded_and_clamped() {
+        assert_eq!(Cs12::from_cs16(Complex::new(7, 8)), Cs12::new(0, 1));
+        assert_eq!(Cs12::from_cs16(Complex::new(-8, -9)), Cs12::new(0, -1));
+        assert_eq!(
+            Cs12::from_cs16(Complex::new(i16::MAX, i16::MIN)),
+            Cs12::new(Cs12::MAX, Cs12::MIN)
+        );
+    }
+
+    #[test]
+    fn slices_convert() {
+        let cs16 = [Complex::new(16, -32), Complex::new(i16::MIN, 2047 << 4)];
+        let mut cs12 = [Cs12::default(); 2];
+        cs16_to_cs12(&cs16, &mut cs12);
+        let mut back = [Complex::new(0, 0); 2];
+        cs12_to_cs16(&cs12, &mut back);
+        assert_eq!(back, cs16);
+        let mut cf32 = [Complex::new(0.0, 0.0); 2];
+        cs12_to_cf32(&cs12, &mut cf32);
+        assert_eq!(cf32[0], Complex::new(16.0, -32.0) / i16::MAX as f32);
+    }
+
+    fn ramp(start: i16, len: usize) -> Vec<Cs12> {
+        (0..len as i16)
+            .map(|i| Cs12::new(start + i, -(start + i)))
+            .collect()
+    }
+
+    fn info(elements_read: usize, timestamp_ns: u64) -> StreamReadInfo {
+        StreamReadInfo {
+            elements_read,
+            timestamp_ns,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn recordings_round_trip() {
+        let mut writer = Cs12Writer::new(Vec::new(), 2, 1e6).unwrap();
+        let (a, b) = (ramp(0, 100), ramp(500, 100));
+        writer.write_block(&[&a, &b], &info(100, 5_000)).unwrap();
+        // only the samples read count
+        writer.write_block(&[&a, &b], &info(0, 0)).unwrap();
+        writer.write_block(&[&b, &a], &info(40, 200_000)).unwrap();
+        assert_eq!(writer.samples_written(), 140);
+        let file = writer.into_inner();
+        assert_eq!(
+            file.len(),
+            HEADER_SIZE + 2 * BLOCK_HEADER_SIZE + 140 * 2 * 3
+        );
+
+        let mut reader = Cs12Reader::new(Cursor::new(file)).unwrap();
+        assert_eq!(reader.channel_count(), 2);
+        assert_eq!(reader.sample_rate(), 1e6);
+        let mut first = vec![Cs12::default(); 60];
+        let mut second = vec![Cs12::default(); 60];
+
+        let got = reader
+            .read(&mut [&mut first, &mut second])
+            .unwrap()
+            .unwrap();
+        assert_eq!((got.elements_read, got.timestamp_ns), (60, 5_000));
+        assert_eq!((&first[..], &second[..]), (&a[..60], &b[..60]));
+        // the rest of the block, 60 samples at 1 MHz later
+        let got = reader
+            .read(&mut [&mut first, &mut second])
+            .unwrap()
+            .unwrap();
+        assert_eq!((got.elements_read, got.timestamp_ns), (40, 65_000));
+        assert_eq!((&first[..40], &second[..40]), (&a[60..], &b[60..]));
+
+        let mut cs16 = vec![Complex::new(0i16, 0); 60];
+        let mut other = vec![Complex::new(0i16, 0); 60];
+        let got = reader.read(&mut [&mut cs16, &mut other]).unwrap().unwrap();
+        assert_eq!((got.elements_read, got.timestamp_ns), (40, 200_000));
+        assert_eq!(cs16[0], b[0].to_cs16());
+        assert!(reader.read(&mut [&mut cs16, &mut other]).unwrap().is_none());
+    }
+
+    #[test]
+    fn long_reads_become_several_blocks() {
+        let channels = 4;
+        let max = MAX_BLOCK_SAMPLES / channels;
+        let samples = vec![Cs12::new(1, 2); max + 10];
+        let src: Vec<&[Cs12]> = vec![&samples; channels];
+        let mut writer = Cs12Writer::new(Vec::new(), channels, 1e9).unwrap();
+        writer.write_block(&src, &info(samples.len(), 0)).unwrap();
+
+        let mut reader = Cs12Reader::new(Cursor::new(writer.into_inner())).unwrap();
+        let mut buffers = vec![vec![Cs12::default(); max + 10]; channels];
+        let mut dst: Vec<&mut [Cs12]> = buffers.iter_mut().map(|b| b.as_mut_slice()).collect();
+        let got = reader.read(&mut dst).unwrap().unwrap();
+        assert_eq!((got.elements_read, got.timestamp_ns), (max, 0));
+        let got = reader.read(&mut dst).unwrap().unwrap();
+        // one sample per ns
+        assert_eq!((got.elements_read, got.timestamp_ns), (10, max as u64));
+    }
+
+    #[test]
+    fn a_cut_block_ends_the_recording() {
+        let mut writer = Cs12Writer::new(Vec::new(), 1, 1e6).unwrap();
+        let a = ramp(0, 10);
+        writer.write_block(&[&a], &info(10, 0)).unwrap();
+        writer.write_block(&[&a], &info(10, 10_000)).unwrap();
+        let mut file = writer.into_inner();
+        file.truncate(file.len() - 1);
+
+        let mut reader = Cs12Reader::new(Cursor::new(file)).unwrap();
+        let mut dst = vec![Cs12::default(); 10];
+        assert_eq!(
+            reader.read(&mut [&mut dst]).unwrap().unwrap().elements_read,
+            10
+        );
+        assert!(reader.read(&mut [&mut dst]).unwrap().is_none());
+    }
+
+    #[test]
+    fn oversized_blocks_are_refused() {
+        let mut file = Cs12Writer::new(Vec::new(), 2, 1e6).unwrap().into_inner();
+        file.extend_from_slice(&0u64.to_le_bytes());
+        file.extend_from_slice(&u32::MAX.to_le_bytes());
+        let mut reader = Cs12Reader::new(Cursor::new(file)).unwrap();
+        let mut dst = vec![Cs12::default(); 10];
+        let mut other = vec![Cs12::default(); 10];
+        assert!(matches!(
+            reader.read(&mut [&mut dst, &mut other]),
+            Err(Cs12Error::BlockTooLarge(n)) if n == 2 * u32::MAX as u64
+        ));
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        assert!(matches!(
+            Cs12Reader::new(Cursor::new(b"RFNMCS12".to_vec())),
+            Err(Cs12Error::NotARecording)
+        ));
+        let mut file = Cs12Writer::new(Vec::new(), 1, 1e6).unwrap().into_inner();
+        file[0] = b'X';
+        assert!(matches!(
+            Cs12Reader::new(Cursor::new(file)),
+            Err(Cs12Error::NotARecording)
+        ));
+        assert!(matches!(
+            Cs12Writer::new(Vec::new(), 0, 1e6),
+            Err(Cs12Error::InvalidChannelCount(0))
+        ));
+    }
+}
@@ -0,0 +1,251 @@
+//! Interleaving aware DC offset correction.
+//!
+//! The RFNM ADCs are interleaved: every fourth complex sample comes from the same converter core,
+//! and every core has its own offset on I and on Q. That makes 8 phases, each with a DC offset of its own.
+//! This is the correction librfnm does internally when `RxStream::set_auto_dc_offset` is enabled,
+//! but with the filter coefficient exposed, the offsets readable and writable,
+//! and usable on recorded data just as well as on live streams.
+
+use crate::stream::ComplexSample;
+use num_complex::Complex;
+
+/// Number of offsets tracked: I and Q for each of the 4 interleaved converter cores.
+pub const DC_OFFSET_PHASES: usize = 8;
+const CORES: usize = DC_OFFSET_PHASES / 2;
+
+/// Filter coefficient librfnm uses for its own correction.
+pub const DEFAULT_FILTER_COEFF: f32 = 0.1;
+
+/// Per-phase DC offset estimator and remover for one channel.
+///
+/// Offsets are stored in the librfnm order, `[I0, Q0, I1, Q1, I2, Q2, I3, Q3]`,
+/// scaled so that 1.0 is full scale regardless of the sample format.
+///
+/// The corrector keeps track of which core the next sample belongs to,
+/// so buffers of any length can be fed one after another.
+#[derive(Debug, Clone)]
+pub struct DcOffsetCorrector {
+    offsets: [f32; DC_OFFSET_PHASES],
+    filter_coeff: f32,
+    frozen: bool,
+    measured: bool,
+    phase: usize,
+}
+
+impl Default for DcOffsetCorrector {
+    fn default() -> Self {
+        Self::new(DEFAULT_FILTER_COEFF)
+    }
+}
+
+impl DcOffsetCorrector {
+    /// `filter_coeff` is the weight a new measurement gets, between 0 and 1.
+    /// The very first measurement is always taken as is.
+    pub fn new(filter_coeff: f32) -> Self {
+        Self {
+            offsets: [0.0; DC_OFFSET_PHASES],
+            filter_coeff: filter_coeff.clamp(0.0, 1.0),
+            frozen: false,
+            measured: false,
+            phase: 0,
+        }
+    }
+
+    pub fn filter_coeff(&self) -> f32 {
+        self.filter_coeff
+    }
+
+    pub fn set_filter_coeff(&mut self, filter_coeff: f32) {
+        self.filter_coeff = filter_coeff.clamp(0.0, 1.0);
+    }
+
+    /// While frozen, `process` keeps applying the current offsets but stops measuring new ones.
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub fn offsets(&self) -> [f32; DC_OFFSET_PHASES] {
+        self.offsets
+    }
+
+    /// Overwrite the offsets, for example with values saved from an earlier run.
+    /// Further measurements are filtered against them instead of replacing them.
+    pub fn set_offsets(&mut self, offsets: [f32; DC_OFFSET_PHASES]) {
+        self.offsets = offsets;
+        self.measured = true;
+    }
+
+    /// The converter core (0..4) the next sample passed in is assumed to come from.
+    pub fn phase(&self) -> usize {
+        self.phase
+    }
+
+    /// Realign to the interleaving pattern, e.g. at the start of a recording.
+    pub fn set_phase(&mut self, phase: usize) {
+        self.phase = phase % CORES;
+    }
+
+    /// Account for samples that were never passed in, for example blocks a reader dropped.
+    pub fn skip(&mut self, samples: usize) {
+        self.phase = (self.phase + samples) % CORES;
+    }
+
+    /// Forget everything measured so far.
+    pub fn reset(&mut self) {
+        self.offsets = [0.0; DC_OFFSET_PHASES];
+        self.measured = false;
+        self.phase = 0;
+    }
+
+    /// Update the offset estimate from `buf`, without modifying it or advancing the phase.
+    pub fn measure<T: ComplexSample>(&mut self, buf: &[T]) {
+        let mut sums = [Complex::new(0.0f64, 0.0); CORES];
+        let mut counts = [0usize; CORES];
+        for (i, sample) in buf.iter().enumerate() {
+            let core = (self.phase + i) % CORES;
+            let value = sample.to_complex_f32();
+            sums[core] += Complex::new(value.re as f64, value.im as f64);
+            counts[core] += 1;
+        }
+
+        let coeff = if self.measured {
+            self.filter_coeff
+        } else {
+            1.0
+        };
+        for ((offset, sum), count) in self.offsets.chunks_exact_mut(2).zip(sums).zip(counts) {
+            if count == 0 {
+                continue;
+            }
+            let mean = sum / count as f64;
+            offset[0] = mean.re as f32 * coeff + offset[0] * (1.0 - coeff);
+            offset[1] = mean.im as f32 * coeff + offset[1] * (1.0 - coeff);
+            self.measured = true;
+        }
+    }
+
+    /// Subtract the current offsets from `buf` and advance the phase past it.
+    pub fn apply<T: ComplexSample>(&mut self, buf: &mut [T]) {
+        let offsets: [Complex<f32>; CORES] = std::array::from_fn(|core| {
+            Complex::new(self.offsets[core * 2], self.offsets[core * 2 + 1])
+        });
+        for (i, sample) in buf.iter_mut().enumerate() {
+            let core = (self.phase + i) % CORES;
+            *sample = T::from_complex_f32(sample.to_complex_f32() - offsets[core]);
+        }
+        self.skip(buf.len());
+    }
+
+    /// Measure (unless frozen) and apply in one go. This is what you want for streaming.
+    pub fn process<T: ComplexSample>(&mut self, buf: &mut [T]) {
+        if !self.frozen {
+            self.measure(buf);
+        }
+        self.apply(buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const OFFSETS: [f32; DC_OFFSET_PHASES] = [0.01, -0.02, 0.03, -0.04, 0.05, -0.06, 0.07, -0.08];
+
+    fn core_offset(core: usize) -> Complex<f32> {
+        Complex::new(OFFSETS[core * 2], OFFSETS[core * 2 + 1])
+    }
+
+    /// A tone at 1/8 of the sample rate, which averages out on every core, on top of `OFFSETS`.
+    fn signal(len: usize) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|n| {
+                0.5 * Complex::from_polar(1.0, 2.0 * PI * n as f32 / 8.0) + core_offset(n % CORES)
+            })
+            .collect()
+    }
+
+    fn assert_offsets(actual: [f32; DC_OFFSET_PHASES], expected: [f32; DC_OFFSET_PHASES]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn every_phase_gets_its_own_offset() {
+        let mut corrector = DcOffsetCorrector::default();
+        let mut samples = signal(1024);
+        corrector.process(&mut samples);
+        assert_offsets(corrector.offsets(), OFFSETS);
+
+        for core in 0..CORES {
+            let mean: Complex<f32> = samples
+                .iter()
+                .skip(core)
+                .step_by(CORES)
+                .sum::<Complex<f32>>()
+                / (samples.len() / CORES) as f32;
+            assert!(mean.norm() < 1e-5, "core {core} still at {mean}");
+        }
+    }
+
+    #[test]
+    fn the_phase_carries_over_between_buffers() {
+        let whole = {
+            let mut corrector = DcOffsetCorrector::default();
+            corrector.set_offsets(OFFSETS);
+            let mut samples = signal(99);
+            corrector.apply(&mut samples);
+            samples
+        };
+
+        let mut corrector = DcOffsetCorrector::default();
+        corrector.set_offsets(OFFSETS);
+        let mut samples = signal(99);
+        let mut start = 0;
+        for len in [3, 5, 7, 9, 11, 13, 15, 17, 19] {
+            corrector.apply(&mut samples[start..start + len]);
+            start += len;
+        }
+        assert_eq!(corrector.phase(), 99 % CORES);
+        assert_eq!(samples, whole);
+    }
+
+    #[test]
+    fn frozen_offsets_are_applied_but_not_measured() {
+        let mut corrector = DcOffsetCorrector::default();
+        corrector.set_offsets(OFFSETS);
+        corrector.set_frozen(true);
+        let mut samples = vec![Complex::new(0.5f32, 0.5); 16];
+        corrector.process(&mut samples);
+        assert_eq!(corrector.offsets(), OFFSETS);
+        assert_eq!(samples[1], Complex::new(0.5, 0.5) - core_offset(1));
+
+        corrector.set_frozen(false);
+        corrector.process(&mut samples);
+        assert_ne!(corrector.offsets(), OFFSETS);
+    }
+
+    #[test]
+    fn measurements_are_filtered_against_set_offsets() {
+        let mut corrector = DcOffsetCorrector::new(0.25);
+        corrector.set_offsets([0.0; DC_OFFSET_PHASES]);
+        corrector.measure(&[Complex::new(1.0f32, -1.0); 8]);
+        assert_offsets(
+            corrector.offsets(),
+            [0.25, -0.25, 0.25, -0.25, 0.25, -0.25, 0.25, -0.25],
+        );
+
+        // without offsets set, the first measurement is taken as is
+        let mut corrector = DcOffsetCorrector::new(0.25);
+        corrector.measure(&[Complex::new(1.0f32, -1.0); 8]);
+        assert_offsets(
+            corrector.offsets(),
+            [1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0],
+        );
+    }
+}
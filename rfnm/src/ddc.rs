@@ -0,0 +1,403 @@
+//! Digital downconversion.
+//!
+//! The sample rates the `SampleRateDividerSettings` offer are a lot wider than most signals.
+//! `Ddc` moves a signal at some offset from the LO down to 0 Hz with an NCO,
+//! then decimates with a CIC (for large ratios) followed by a FIR filter.
+//! It knows the resulting rate and center frequency, so narrowband recordings stay cheap and correctly labeled.
+
+use crate::RfnmApiError;
+use crate::device::Device;
+use crate::stream::ComplexSample;
+use num_complex::Complex;
+use rfnm_sys::rfnm_channel;
+use std::f64::consts::PI;
+use thiserror::Error;
+
+/// `CicDecimator` works with 64 bit integers and 20 bits of input resolution, which leaves this for its gain.
+const MAX_CIC_GAIN_BITS: f64 = 40.0;
+
+#[derive(Debug, Error)]
+pub enum DdcError {
+    #[error("Device error: {0}")]
+    Api(#[from] RfnmApiError),
+    #[error(
+        "A CIC decimating by {decimation} with {stages} stages has too much gain, decimation^stages must stay below 2^40"
+    )]
+    CicGainTooLarge { decimation: usize, stages: usize },
+    #[error("The FIR filter needs at least {MIN_FIR_TAPS} taps, got {0}")]
+    TooFewFirTaps(usize),
+}
+
+/// Below this the blackman window leaves no tap that is not 0.
+pub const MIN_FIR_TAPS: usize = 3;
+
+/// Numerically controlled oscillator, mixing a given frequency down to 0 Hz.
+#[derive(Debug, Clone)]
+pub struct Nco {
+    phase: f64,
+    phase_inc: f64,
+}
+
+impl Nco {
+    /// `frequency` is the offset to move down to 0 Hz, both in Hz.
+    pub fn new(frequency: f64, sample_rate: f64) -> Self {
+        Self {
+            phase: 0.0,
+            phase_inc: -2.0 * PI * frequency / sample_rate,
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f64, sample_rate: f64) {
+        self.phase_inc = -2.0 * PI * frequency / sample_rate;
+    }
+
+    pub fn next_rotation(&mut self) -> Complex<f32> {
+        let rotation = Complex::from_polar(1.0, self.phase as f32);
+        self.phase = (self.phase + self.phase_inc) % (2.0 * PI);
+        rotation
+    }
+
+    pub fn mix<T: ComplexSample>(&mut self, buf: &mut [T]) {
+        for sample in buf {
+            *sample = T::from_complex_f32(sample.to_complex_f32() * self.next_rotation());
+        }
+    }
+}
+
+/// Design a linear phase lowpass filter as blackman windowed sinc, with unity gain at DC.
+/// `cutoff` is relative to the sample rate, so 0.5 is nyquist.
+/// Fewer than `MIN_FIR_TAPS` taps come out as NaN.
+pub fn design_lowpass(cutoff: f64, taps: usize) -> Vec<f32> {
+    let taps = taps.max(1);
+    let center = (taps - 1) as f64 / 2.0;
+    let raw: Vec<f64> = (0..taps)
+        .map(|i| {
+            let x = i as f64 - center;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * x).sin() / (PI * x)
+            };
+            let w = 2.0 * PI * i as f64 / (taps - 1).max(1) as f64;
+            let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f64 = raw.iter().sum();
+    raw.iter().map(|t| (t / sum) as f32).collect()
+}
+
+/// FIR filter that only computes the outputs it keeps.
+#[derive(Debug, Clone)]
+pub struct FirDecimator {
+    reversed_taps: Vec<f32>,
+    decimation: usize,
+    buffer: Vec<Complex<f32>>,
+    next_start: usize,
+}
+
+impl FirDecimator {
+    pub fn new(taps: &[f32], decimation: usize) -> Self {
+        let reversed_taps: Vec<f32> = taps.iter().rev().copied().collect();
+        Self {
+            buffer: vec![Complex::new(0.0, 0.0); reversed_taps.len().saturating_sub(1)],
+            reversed_taps,
+            decimation: decimation.max(1),
+            next_start: 0,
+        }
+    }
+
+    pub fn decimation(&self) -> usize {
+        self.decimation
+    }
+
+    /// Filter `input`, appending the decimated result to `output`.
+    pub fn process(&mut self, input: &[Complex<f32>], output: &mut Vec<Complex<f32>>) {
+        self.buffer.extend_from_slice(input);
+        let taps = self.reversed_taps.len();
+        let mut start = self.next_start;
+        while start + taps <= self.buffer.len() {
+            let window = &self.buffer[start..start + taps];
+            let sum = window
+                .iter()
+                .zip(&self.reversed_taps)
+                .fold(Complex::new(0.0, 0.0), |acc, (x, t)| acc + x * t);
+            output.push(sum);
+            start += self.decimation;
+        }
+        let drained = start.min(self.buffer.len());
+        self.buffer.drain(..drained);
+        self.next_start = start - drained;
+    }
+}
+
+/// Cascaded integrator comb decimator.
+///
+/// Works on fixed point internally, so the integrators can wrap around without losing precision
+/// no matter how long it runs. The output is normalized back to the input scale.
+#[derive(Debug, Clone)]
+pub struct CicDecimator {
+    decimation: usize,
+    integrators: Vec<Complex<i64>>,
+    combs: Vec<Complex<i64>>,
+    counter: usize,
+    scale: f64,
+}
+
+impl CicDecimator {
+    const INPUT_SCALE: f64 = (1 << 20) as f64;
+
+    /// The gain grows with `decimation^stages`, so together they must stay below 2^40.
+    pub fn new(decimation: usize, stages: usize) -> Result<Self, DdcError> {
+        let decimation = decimation.max(1);
+        if stages as f64 * (decimation as f64).log2() >= MAX_CIC_GAIN_BITS {
+            return Err(DdcError::CicGainTooLarge { decimation, stages });
+        }
+        let gain = (decimation as f64).powi(stages as i32);
+        Ok(Self {
+            decimation,
+            integrators: vec![Complex::new(0, 0); stages],
+            combs: vec![Complex::new(0, 0); stages],
+            counter: 0,
+            scale: 1.0 / (gain * Self::INPUT_SCALE),
+        })
+    }
+
+    pub fn decimation(&self) -> usize {
+        self.decimation
+    }
+
+    pub fn process(&mut self, input: &[Complex<f32>], output: &mut Vec<Complex<f32>>) {
+        for sample in input {
+            let mut acc = Complex::new(
+                (sample.re as f64 * Self::INPUT_SCALE) as i64,
+                (sample.im as f64 * Self::INPUT_SCALE) as i64,
+            );
+            for integrator in &mut self.integrators {
+                integrator.re = integrator.re.wrapping_add(acc.re);
+                integrator.im = integrator.im.wrapping_add(acc.im);
+                acc = *integrator;
+            }
+            self.counter += 1;
+            if self.counter < self.decimation {
+                continue;
+            }
+            self.counter = 0;
+            for comb in &mut self.combs {
+                let delayed = *comb;
+                *comb = acc;
+                acc.re = acc.re.wrapping_sub(delayed.re);
+                acc.im = acc.im.wrapping_sub(delayed.im);
+            }
+            output.push(Complex::new(
+                (acc.re as f64 * self.scale) as f32,
+                (acc.im as f64 * self.scale) as f32,
+            ));
+        }
+    }
+}
+
+/// How a `Ddc` is set up. Use `DdcSettings::new` to get a sensible split of the decimation.
+#[derive(Debug, Clone)]
+pub struct DdcSettings {
+    /// Offset from the LO (or whatever the input center is) to shift down to 0 Hz, in Hz.
+    pub offset_frequency: f64,
+    /// Decimation done by the CIC stage. 1 disables it.
+    pub cic_decimation: usize,
+    pub cic_stages: usize,
+    /// Decimation done by the FIR stage after the CIC.
+    pub fir_decimation: usize,
+    /// Passband of the FIR filter as a fraction of the output sample rate.
+    pub bandwidth: f64,
+    /// At least `MIN_FIR_TAPS`.
+    pub fir_taps: usize,
+}
+
+impl DdcSettings {
+    /// Decimate by `decimation` in total. Large ratios are handed mostly to the CIC, the rest to the FIR.
+    pub fn new(offset_frequency: f64, decimation: usize) -> Self {
+        let decimation = decimation.max(1);
+        let (cic_decimation, fir_decimation) = if decimation <= 16 {
+            (1, decimation)
+        } else {
+            match (2..=8).rev().find(|d| decimation % d == 0) {
+                Some(fir) => (decimation / fir, fir),
+                None => (decimation, 1),
+            }
+        };
+        // as many stages as the gain `CicDecimator` can handle allows, up to 4
+        let cic_bits = (cic_decimation as f64).log2();
+        let cic_stages = (1..=4)
+            .rev()
+            .find(|stages| *stages as f64 * cic_bits < MAX_CIC_GAIN_BITS)
+            .unwrap_or(1);
+        Self {
+            offset_frequency,
+            cic_decimation,
+            cic_stages,
+            fir_decimation,
+            bandwidth: 0.8,
+            fir_taps: (fir_decimation * 16 + 1).max(31),
+        }
+    }
+
+    pub fn decimation(&self) -> usize {
+        self.cic_decimation.max(1) * self.fir_decimation.max(1)
+    }
+}
+
+/// NCO, optional CIC and FIR, in that order.
+#[derive(Debug, Clone)]
+pub struct Ddc {
+    nco: Nco,
+    cic: Option<CicDecimator>,
+    fir: FirDecimator,
+    input_rate: f64,
+    input_center: f64,
+    settings: DdcSettings,
+    mixed: Vec<Complex<f32>>,
+    cic_out: Vec<Complex<f32>>,
+}
+
+impl Ddc {
+    /// `input_rate` and `input_center` describe the samples going in, both in Hz.
+    /// Fails for a CIC stage `CicDecimator::new` refuses, or fewer than `MIN_FIR_TAPS` FIR taps.
+    pub fn new(
+        settings: DdcSettings,
+        input_rate: f64,
+        input_center: f64,
+    ) -> Result<Self, DdcError> {
+        if settings.fir_taps < MIN_FIR_TAPS {
+            return Err(DdcError::TooFewFirTaps(settings.fir_taps));
+        }
+        let cic = (settings.cic_decimation > 1)
+            .then(|| CicDecimator::new(settings.cic_decimation, settings.cic_stages))
+            .transpose()?;
+        let fir_rate = input_rate / settings.cic_decimation.max(1) as f64;
+        let output_rate = fir_rate / settings.fir_decimation.max(1) as f64;
+        let cutoff = settings.bandwidth * output_rate / 2.0 / fir_rate;
+        let fir = FirDecimator::new(
+            &design_lowpass(cutoff, settings.fir_taps),
+            settings.fir_decimation,
+        );
+        Ok(Self {
+            nco: Nco::new(settings.offset_frequency, input_rate),
+            cic,
+            fir,
+            input_rate,
+            input_center,
+            settings,
+            mixed: Vec::new(),
+            cic_out: Vec::new(),
+        })
+    }
+
+    /// Set up for the samples a `RxStream` delivers for `channel` with the device's current settings.
+    pub fn for_channel(
+        device: &Device,
+        channel: rfnm_channel,
+        settings: DdcSettings,
+    ) -> Result<Self, DdcError> {
+        let channel_settings = device.get_rx_settings(channel)?.to_settings();
+        let input_rate = channel_settings
+            .rate_divider_settings
+            .sample_rate(device.hwinfo().clock_info.dcs_clk);
+        Self::new(settings, input_rate, channel_settings.frequency as f64)
+    }
+
+    pub fn settings(&self) -> &DdcSettings {
+        &self.settings
+    }
+
+    pub fn input_rate(&self) -> f64 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> f64 {
+        self.input_rate / self.settings.decimation() as f64
+    }
+
+    /// The RF frequency that ends up at 0 Hz in the output.
+    pub fn center_frequency(&self) -> f64 {
+        self.input_center + self.settings.offset_frequency
+    }
+
+    /// Change the offset without resetting the filters.
+    pub fn set_offset_frequency(&mut self, offset_frequency: f64) {
+        self.settings.offset_frequency = offset_frequency;
+        self.nco.set_frequency(offset_frequency, self.input_rate);
+    }
+
+    /// Downconvert `input`, appending the result to `output`.
+    pub fn process<T: ComplexSample>(&mut self, input: &[T], output: &mut Vec<Complex<f32>>) {
+        self.mixed.clear();
+        self.mixed.extend(
+            input
+                .iter()
+                .map(|s| s.to_complex_f32() * self.nco.next_rotation()),
+        );
+        match &mut self.cic {
+            Some(cic) => {
+                self.cic_out.clear();
+                cic.process(&self.mixed, &mut self.cic_out);
+                self.fir.process(&self.cic_out, output);
+            }
+            None => self.fir.process(&self.mixed, output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_keep_cic_gain_in_range() {
+        for decimation in [1, 2, 17, 64, 1000, 8192, 1 << 20, 1 << 30] {
+            let settings = DdcSettings::new(0.0, decimation);
+            assert_eq!(settings.decimation(), decimation);
+            assert!(
+                Ddc::new(settings, 1e6, 0.0).is_ok(),
+                "decimation {decimation}"
+            );
+        }
+    }
+
+    #[test]
+    fn cic_gain_too_large_is_an_error() {
+        assert!(matches!(
+            CicDecimator::new(1024, 4),
+            Err(DdcError::CicGainTooLarge {
+                decimation: 1024,
+                stages: 4
+            })
+        ));
+        assert!(CicDecimator::new(1024, 3).is_ok());
+    }
+
+    #[test]
+    fn too_few_fir_taps_are_an_error() {
+        for taps in [0, 1, 2] {
+            let settings = DdcSettings {
+                fir_taps: taps,
+                ..DdcSettings::new(0.0, 4)
+            };
+            assert!(matches!(
+                Ddc::new(settings, 1e6, 0.0),
+                Err(DdcError::TooFewFirTaps(t)) if t == taps
+            ));
+        }
+        let taps = design_lowpass(0.1, MIN_FIR_TAPS);
+        assert!(taps.iter().all(|t| t.is_finite()));
+    }
+
+    #[test]
+    fn cic_passes_dc_at_unity_gain() {
+        let mut cic = CicDecimator::new(8, 3).unwrap();
+        let mut output = Vec::new();
+        cic.process(&vec![Complex::new(0.5, -0.25); 8 * 10], &mut output);
+        assert_eq!(output.len(), 10);
+        let last = output.last().unwrap();
+        assert!((last.re - 0.5).abs() < 1e-4 && (last.im + 0.25).abs() < 1e-4);
+    }
+}
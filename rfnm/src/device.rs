@@ -1,31 +1,82 @@
-use crate::channel_settings::{RxChannelInfo, RxChannelSettings};
-use crate::{RfnmApiError, channel_flag_to_number, check_code};
+use crate::channel_settings::{
+    HostChannelState,
+    RxChannelInfo,
+    RxChannelSettings,
+    TxChannelInfo,
+    rx_apply_flag,
+};
+use crate::control::{DEFAULT_CONFIRM_TIMEOUT, SetOutcome, SetTicket, TicketState};
+use crate::hwinfo::HwInfo;
+use crate::logging::DebugLevel;
+use crate::tags::ChannelChange;
+use crate::topology::Topology;
+use crate::{MAX_RX_CHANNELS, RfnmApiError, channel_flag_to_number, check_code};
 use rfnm_sys::{
     DeviceWrapper,
     WrappedThrownError,
     device_connect_usb,
     device_free,
+    device_get_hwinfo,
     device_get_rx_channel,
     device_get_rx_channel_count,
+    device_get_set_result,
+    device_get_tx_channel_count,
     device_rx_work_stop,
     device_set_rx_channel_active,
+    device_submit,
     device_tx_work_stop,
     rfnm_api_rx_ch,
     rfnm_ch_enable,
     rfnm_ch_stream,
     rfnm_channel,
+    rfnm_dev_get_set_result,
+    rfnm_dev_hwinfo,
 };
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[derive(Debug)]
 pub struct Device {
     device_wrapper: *mut DeviceWrapper,
+    // boxed to keep the device cheap to move around, it is returned in a few error paths
+    rx_host_state: Box<[Cell<HostChannelState>; MAX_RX_CHANNELS]>,
+    confirm_timeout: Cell<Duration>,
+    // what went out per channel since a stream last looked, for the tags of `crate::stream::RxStream::read_tagged`
+    rx_changes: Box<[RefCell<VecDeque<ChannelChange>>; MAX_RX_CHANNELS]>,
 }
 
 impl Device {
+    /// Connect to the first board found.
     pub fn connect_usb() -> Result<Self, RfnmApiError> {
+        Self::connect_usb_address(c"", DebugLevel::None)
+    }
+
+    /// Connect to the board with the given usb serial number, see `BoardInfo::serial_string`.
+    pub fn connect_usb_serial(serial: &str) -> Result<Self, RfnmApiError> {
+        let address =
+            CString::new(serial).map_err(|_| RfnmApiError::InvalidSerial(serial.to_string()))?;
+        Self::connect_usb_address(&address, DebugLevel::None)
+    }
+
+    /// Connect to the board with the given usb serial number, or the first one found for `None`,
+    /// telling librfnm how much to report about it.
+    pub fn connect_usb_with_debug_level(
+        serial: Option<&str>,
+        debug_level: DebugLevel,
+    ) -> Result<Self, RfnmApiError> {
+        let address = CString::new(serial.unwrap_or(""))
+            .map_err(|_| RfnmApiError::InvalidSerial(serial.unwrap_or("").to_string()))?;
+        Self::connect_usb_address(&address, debug_level)
+    }
+
+    fn connect_usb_address(address: &CStr, debug_level: DebugLevel) -> Result<Self, RfnmApiError> {
         let mut throw_error = WrappedThrownError::empty();
-        let device_wrapper = unsafe { device_connect_usb(&mut throw_error) };
+        let device_wrapper =
+            unsafe { device_connect_usb(address.as_ptr(), debug_level.into(), &mut throw_error) };
         if device_wrapper.is_null() {
             Err(throw_error.into())
         } else {
@@ -44,7 +95,11 @@ impl Device {
                         frequency: valid_center,
                         ..Default::default()
                     };
-                    settings.apply_to_device(device_wrapper, i)?;
+                    settings.apply_to_device(
+                        device_wrapper,
+                        i,
+                        timeout_us(DEFAULT_CONFIRM_TIMEOUT),
+                    )?;
                     check_code(device_set_rx_channel_active(
                         device_wrapper,
                         i,
@@ -57,7 +112,12 @@ impl Device {
                 //check_code(device_set_tx_channel_active(device_wrapper,i,rfnm_ch_enable::RFNM_CH_OFF, rfnm_ch_stream::RFNM_CH_STREAM_OFF, true))?;
                 //}
             }
-            Ok(Self { device_wrapper })
+            Ok(Self {
+                device_wrapper,
+                rx_host_state: Box::default(),
+                confirm_timeout: Cell::new(DEFAULT_CONFIRM_TIMEOUT),
+                rx_changes: Box::default(),
+            })
         }
     }
 
@@ -65,29 +125,185 @@ impl Device {
         self.device_wrapper
     }
 
+    /// Hardware info of the connected board, as reported on connect.
+    pub fn hwinfo(&self) -> HwInfo {
+        let mut raw = rfnm_dev_hwinfo::default();
+        unsafe { device_get_hwinfo(self.device_wrapper, &mut raw) };
+        raw.into()
+    }
+
     pub fn set_rx_settings(
         &self,
         channel: rfnm_channel,
         settings: &RxChannelSettings,
     ) -> Result<(), RfnmApiError> {
+        let channel_num = channel_flag_to_number(channel).unwrap_or(0);
+        let lo_settings = RxChannelSettings {
+            frequency: settings.frequency - settings.tuning_offset,
+            ..settings.clone()
+        };
+        let timeout = timeout_us(self.confirm_timeout.get());
+        unsafe { lo_settings.apply_to_device(self.device_wrapper, channel_num, timeout)? };
+        self.set_host_state(channel_num, settings);
+        self.record_change(channel_num, settings);
+        Ok(())
+    }
+
+    /// Send settings for one or more channels without waiting for the board to execute them.
+    /// Check on them later with `poll_ticket` or `wait_ticket`.
+    /// The parts that only live on the rust side, like the tuning offset, take effect right away.
+    pub fn submit_rx_settings(
+        &self,
+        settings: &[(rfnm_channel, RxChannelSettings)],
+    ) -> Result<SetTicket, RfnmApiError> {
+        let mut rx_channels = rfnm_channel(0);
+        let mut applies = 0;
+        for (channel, settings) in settings {
+            let channel_num = channel_flag_to_number(*channel)
+                .ok_or(RfnmApiError::NotSingleChannel(channel.0))?;
+            let lo_settings = RxChannelSettings {
+                frequency: settings.frequency - settings.tuning_offset,
+                ..settings.clone()
+            };
+            unsafe { lo_settings.stage_on_device(self.device_wrapper, channel_num)? };
+            rx_channels.0 |= channel.0;
+            applies |= rx_apply_flag(channel_num);
+        }
+        let (mut cc_tx, mut cc_rx) = (0, 0);
         unsafe {
-            settings.apply_to_device(
+            check_code(device_submit(
                 self.device_wrapper,
-                channel_flag_to_number(channel).unwrap_or(0),
-            )
+                applies,
+                &mut cc_tx,
+                &mut cc_rx,
+            ))?;
+        }
+        for (channel, settings) in settings {
+            // checked above
+            let channel_num = channel_flag_to_number(*channel).unwrap_or(0);
+            self.set_host_state(channel_num, settings);
+            self.record_change(channel_num, settings);
         }
+        Ok(SetTicket { cc_rx, rx_channels })
     }
 
-    pub fn get_rx_settings(&self, channel: rfnm_channel) -> Result<RxChannelInfo, RfnmApiError> {
-        unsafe {
-            RxChannelInfo::from_device(
-                self.device_wrapper,
-                channel_flag_to_number(channel).unwrap_or(0),
-            )
+    /// Ask the board once how far it is with `ticket`.
+    pub fn poll_ticket(&self, ticket: &SetTicket) -> Result<TicketState, RfnmApiError> {
+        let mut result = rfnm_dev_get_set_result::default();
+        unsafe { check_code(device_get_set_result(self.device_wrapper, &mut result))? };
+        Ok(ticket.state(&result))
+    }
+
+    /// Poll until the board has executed `ticket`, for at most `timeout`.
+    pub fn wait_ticket(
+        &self,
+        ticket: &SetTicket,
+        timeout: Duration,
+    ) -> Result<SetOutcome, RfnmApiError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.poll_ticket(ticket)? {
+                TicketState::Executed(outcome) => return Ok(outcome),
+                TicketState::Superseded => return Err(RfnmApiError::SetResultSuperseded),
+                TicketState::Pending if Instant::now() >= deadline => {
+                    return Err(RfnmApiError::Timeout);
+                }
+                TicketState::Pending => thread::sleep(TICKET_POLL_INTERVAL),
+            }
+        }
+    }
+
+    /// How long `set_rx_settings` waits for the board to confirm.
+    pub fn confirm_timeout(&self) -> Duration {
+        self.confirm_timeout.get()
+    }
+
+    pub fn set_confirm_timeout(&self, timeout: Duration) {
+        self.confirm_timeout.set(timeout);
+    }
+
+    /// Hand every change sent for `channel` since the last call to `f`, oldest first.
+    pub(crate) fn drain_rx_changes(&self, channel: rfnm_channel, f: impl FnMut(ChannelChange)) {
+        let channel_num = channel_flag_to_number(channel).unwrap_or(0);
+        self.rx_changes[channel_num as usize]
+            .borrow_mut()
+            .drain(..)
+            .for_each(f);
+    }
+
+    fn record_change(&self, channel_num: u32, settings: &RxChannelSettings) {
+        let mut changes = self.rx_changes[channel_num as usize].borrow_mut();
+        // nothing drains the queue while no stream reads, and then only the latest change counts
+        if changes.len() == MAX_QUEUED_CHANGES {
+            changes.pop_front();
         }
+        changes.push_back(ChannelChange {
+            frequency: settings.frequency,
+            gain: settings.gain,
+            path: settings.path,
+            at: Instant::now(),
+        });
+    }
+
+    fn set_host_state(&self, channel_num: u32, settings: &RxChannelSettings) {
+        self.rx_host_state[channel_num as usize].set(HostChannelState {
+            iq_balance: settings.iq_balance,
+            tuning_offset: settings.tuning_offset,
+        });
+    }
+
+    pub fn get_rx_settings(&self, channel: rfnm_channel) -> Result<RxChannelInfo, RfnmApiError> {
+        let channel_num = channel_flag_to_number(channel).unwrap_or(0);
+        self.rx_channel_info(channel_num)
     }
+
+    fn rx_channel_info(&self, channel_num: u32) -> Result<RxChannelInfo, RfnmApiError> {
+        let info = unsafe { RxChannelInfo::from_device(self.device_wrapper, channel_num)? };
+        Ok(info.with_host_state(self.rx_host_state[channel_num as usize].get()))
+    }
+
+    /// Rx channels the board has, daughterboards included.
+    pub fn rx_channel_count(&self) -> u32 {
+        unsafe { device_get_rx_channel_count(self.device_wrapper) }
+    }
+
+    pub fn tx_channel_count(&self) -> u32 {
+        unsafe { device_get_tx_channel_count(self.device_wrapper) }
+    }
+
+    /// Info on tx channel number `channel_num`, counted across the board like `rx_channel_count`.
+    pub fn get_tx_channel_info(&self, channel_num: u32) -> Result<TxChannelInfo, RfnmApiError> {
+        unsafe { TxChannelInfo::from_device(self.device_wrapper, channel_num) }
+    }
+
+    /// Which channel sits on which daughterboard and converter, see `Topology`.
+    pub fn topology(&self) -> Result<Topology, RfnmApiError> {
+        let rx = (0..self.rx_channel_count())
+            .map(|i| self.rx_channel_info(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tx = (0..self.tx_channel_count())
+            .map(|i| self.get_tx_channel_info(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Topology::new(&self.hwinfo(), &rx, &tx))
+    }
+
+    /// The offset tuning currently in effect for `channel`, see `RxChannelSettings::tuning_offset`.
+    pub fn tuning_offset(&self, channel: rfnm_channel) -> i64 {
+        let channel_num = channel_flag_to_number(channel).unwrap_or(0);
+        self.rx_host_state[channel_num as usize].get().tuning_offset
+    }
+}
+
+const TICKET_POLL_INTERVAL: Duration = Duration::from_micros(200);
+const MAX_QUEUED_CHANGES: usize = 1024;
+
+fn timeout_us(timeout: Duration) -> u32 {
+    timeout.as_micros().min(u32::MAX as u128) as u32
 }
 
+// librfnm does not tie a device to the thread that opened it
+unsafe impl Send for Device {}
+
 impl Drop for Device {
     fn drop(&mut self) {
         unsafe { device_free(self.device_wrapper) };
@@ -0,0 +1,143 @@
+//! Noticing boards being plugged in and removed.
+//!
+//! `HotplugWatcher` compares the connected serial numbers (`crate::list_usb_serials`) between polls.
+//! That does not claim the boards, so a board that is streaming does not look like it vanished.
+//! Use `HotplugWatcher::spawn` to have it poll on a background thread.
+
+use crate::hwinfo::HwInfo;
+use crate::{find_usb_board, list_usb_serials};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub enum HotplugEvent {
+    /// A board showed up. Reported once its hardware info could be read,
+    /// so a board that is held by another process arrives as soon as it is released.
+    Arrived { serial: String, hwinfo: HwInfo },
+    /// A board that arrived earlier is gone, `hwinfo` is what was read when it arrived.
+    Departed { serial: String, hwinfo: HwInfo },
+}
+
+impl HotplugEvent {
+    pub fn serial(&self) -> &str {
+        match self {
+            HotplugEvent::Arrived { serial, .. } | HotplugEvent::Departed { serial, .. } => serial,
+        }
+    }
+
+    pub fn hwinfo(&self) -> &HwInfo {
+        match self {
+            HotplugEvent::Arrived { hwinfo, .. } | HotplugEvent::Departed { hwinfo, .. } => hwinfo,
+        }
+    }
+}
+
+/// Tracks which boards are connected and reports the changes.
+#[derive(Debug, Default)]
+pub struct HotplugWatcher {
+    known: HashMap<String, HwInfo>,
+}
+
+impl HotplugWatcher {
+    /// Starts out knowing no boards, so the first `poll` reports every connected board as arrived.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Boards that have arrived and not departed since.
+    pub fn boards(&self) -> impl Iterator<Item = (&str, &HwInfo)> {
+        self.known
+            .iter()
+            .map(|(serial, info)| (serial.as_str(), info))
+    }
+
+    /// Look at the connected boards once, returning what changed since the last call.
+    pub fn poll(&mut self) -> Vec<HotplugEvent> {
+        let present: HashSet<String> = list_usb_serials().into_iter().collect();
+        let mut events = Vec::new();
+
+        let departed: Vec<String> = self
+            .known
+            .keys()
+            .filter(|serial| !present.contains(*serial))
+            .cloned()
+            .collect();
+        for serial in departed {
+            if let Some(hwinfo) = self.known.remove(&serial) {
+                events.push(HotplugEvent::Departed { serial, hwinfo });
+            }
+        }
+
+        for serial in present {
+            if self.known.contains_key(&serial) {
+                continue;
+            }
+            // a board that is still booting or in use is tried again on the next poll
+            if let Some(hwinfo) = find_usb_board(&serial) {
+                self.known.insert(serial.clone(), hwinfo.clone());
+                events.push(HotplugEvent::Arrived { serial, hwinfo });
+            }
+        }
+        events
+    }
+
+    /// Move the watcher to a thread that polls every `interval`.
+    pub fn spawn(self, interval: Duration) -> HotplugThread {
+        let (sender, receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let mut watcher = self;
+        let handle = thread::spawn(move || {
+            while thread_running.load(Ordering::Relaxed) {
+                for event in watcher.poll() {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+                thread::sleep(interval);
+            }
+        });
+        HotplugThread {
+            receiver,
+            running,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// A `HotplugWatcher` polling in the background. Stops the thread when dropped.
+pub struct HotplugThread {
+    receiver: Receiver<HotplugEvent>,
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HotplugThread {
+    /// Wait up to `timeout` for the next event.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<HotplugEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// The next event, if there is one already.
+    pub fn try_recv(&self) -> Option<HotplugEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            // the thread only ever panics if libusb does
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for HotplugThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -19,6 +19,18 @@ pub struct BoardInfo {
     pub channel_counts: ChannelCounts,
 }
 
+impl BoardInfo {
+    /// The serial number as text, as used by `crate::device::Device::connect_usb_serial`.
+    pub fn serial_string(&self) -> String {
+        let len = self
+            .serial
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.serial.len());
+        String::from_utf8_lossy(&self.serial[..len]).to_string()
+    }
+}
+
 impl From<rfnm_dev_hwinfo> for HwInfo {
     fn from(value: rfnm_dev_hwinfo) -> Self {
         let db1 = if value.daughterboard[0].board_id == 0 {
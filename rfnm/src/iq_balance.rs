@@ -0,0 +1,253 @@
+//! IQ imbalance estimation and correction.
+//!
+//! Gain and phase mismatch between the I and Q paths of the RFIC shows up as a mirror image of every
+//! signal at the negative frequency. `IqImbalanceEstimator` measures the mismatch blindly from the
+//! second order statistics of the received samples, `IqCorrector` removes it again.
+//!
+//! Estimation assumes the DC offset is already gone, see `crate::dc_offset`.
+//!
+//! The result of an estimation is an `IqBalance`, which is kept per channel as part of the
+//! `crate::channel_settings::RxChannelSettings`, so it survives a round trip through the device.
+
+use crate::stream::ComplexSample;
+
+/// Mismatch of the Q path relative to the I path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IqBalance {
+    /// Amplitude of Q relative to I. 1.0 means balanced.
+    pub gain: f32,
+    /// Phase error of Q relative to I in radians. 0.0 means the paths are exactly 90° apart.
+    pub phase: f32,
+}
+
+impl Default for IqBalance {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl IqBalance {
+    /// How far below a signal its mirror image ends up with this imbalance, in dB.
+    pub fn image_rejection_db(&self) -> f32 {
+        let g = self.gain;
+        let cross = 2.0 * g * self.phase.cos();
+        let wanted = 1.0 + cross + g * g;
+        let image = (1.0 - cross + g * g).max(f32::MIN_POSITIVE);
+        10.0 * (wanted / image).log10()
+    }
+}
+
+/// Blind estimator for the IQ imbalance of one channel.
+///
+/// Keeps exponentially filtered averages of `E[I²]`, `E[Q²]` and `E[IQ]`.
+/// For any signal that is on average symmetric around the LO, those alone give the imbalance.
+#[derive(Debug, Clone)]
+pub struct IqImbalanceEstimator {
+    filter_coeff: f64,
+    power_i: f64,
+    power_q: f64,
+    cross: f64,
+    initialized: bool,
+}
+
+impl Default for IqImbalanceEstimator {
+    fn default() -> Self {
+        Self::new(0.05)
+    }
+}
+
+impl IqImbalanceEstimator {
+    /// `filter_coeff` is the weight each new block gets, between 0 and 1.
+    pub fn new(filter_coeff: f64) -> Self {
+        Self {
+            filter_coeff: filter_coeff.clamp(0.0, 1.0),
+            power_i: 0.0,
+            power_q: 0.0,
+            cross: 0.0,
+            initialized: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.power_i = 0.0;
+        self.power_q = 0.0;
+        self.cross = 0.0;
+        self.initialized = false;
+    }
+
+    /// Feed one block of samples, e.g. the result of a `RxStream::read`.
+    pub fn update<T: ComplexSample>(&mut self, buf: &[T]) {
+        if buf.is_empty() {
+            return;
+        }
+        let (mut power_i, mut power_q, mut cross) = (0.0, 0.0, 0.0);
+        for sample in buf {
+            let value = sample.to_complex_f32();
+            let (i, q) = (value.re as f64, value.im as f64);
+            power_i += i * i;
+            power_q += q * q;
+            cross += i * q;
+        }
+        let count = buf.len() as f64;
+        let coeff = if self.initialized {
+            self.filter_coeff
+        } else {
+            1.0
+        };
+        self.power_i = power_i / count * coeff + self.power_i * (1.0 - coeff);
+        self.power_q = power_q / count * coeff + self.power_q * (1.0 - coeff);
+        self.cross = cross / count * coeff + self.cross * (1.0 - coeff);
+        self.initialized = true;
+    }
+
+    /// The current estimate. `None` until some signal has been seen.
+    pub fn estimate(&self) -> Option<IqBalance> {
+        if !self.initialized || self.power_i <= 0.0 || self.power_q <= 0.0 {
+            return None;
+        }
+        let gain = (self.power_q / self.power_i).sqrt();
+        let sin_phase = (self.cross / (self.power_i * self.power_q).sqrt()).clamp(-1.0, 1.0);
+        Some(IqBalance {
+            gain: gain as f32,
+            phase: sin_phase.asin() as f32,
+        })
+    }
+}
+
+/// Removes a known IQ imbalance from sample buffers.
+///
+/// I is kept as is, Q is rescaled and made orthogonal to I again.
+#[derive(Debug, Clone)]
+pub struct IqCorrector {
+    balance: IqBalance,
+    i_to_q: f32,
+    q_scale: f32,
+}
+
+impl Default for IqCorrector {
+    fn default() -> Self {
+        Self::new(IqBalance::default())
+    }
+}
+
+impl IqCorrector {
+    pub fn new(balance: IqBalance) -> Self {
+        let mut corrector = Self {
+            balance,
+            i_to_q: 0.0,
+            q_scale: 1.0,
+        };
+        corrector.set_balance(balance);
+        corrector
+    }
+
+    pub fn balance(&self) -> IqBalance {
+        self.balance
+    }
+
+    pub fn set_balance(&mut self, balance: IqBalance) {
+        let cos_phase = balance.phase.cos().max(f32::EPSILON);
+        self.balance = balance;
+        self.i_to_q = -balance.phase.tan();
+        self.q_scale = 1.0 / (balance.gain.max(f32::EPSILON) * cos_phase);
+    }
+
+    pub fn apply<T: ComplexSample>(&self, buf: &mut [T]) {
+        for sample in buf {
+            let mut value = sample.to_complex_f32();
+            value.im = value.re * self.i_to_q + value.im * self.q_scale;
+            *sample = T::from_complex_f32(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_complex::Complex;
+    use std::f64::consts::PI;
+
+    // a whole number of periods in every block, so the averages come out exact
+    const TONE: f64 = 0.05;
+    const LEN: usize = 1000;
+
+    /// A tone at `TONE` cycles per sample whose Q path has the given imbalance.
+    fn imbalanced_tone(balance: IqBalance) -> Vec<Complex<f32>> {
+        (0..LEN)
+            .map(|n| {
+                let angle = 2.0 * PI * TONE * n as f64;
+                let i = angle.cos();
+                let q = balance.gain as f64 * (angle + balance.phase as f64).sin();
+                Complex::new(i as f32, q as f32)
+            })
+            .collect()
+    }
+
+    /// Power of `samples` at `freq` cycles per sample.
+    fn power_at(samples: &[Complex<f32>], freq: f64) -> f64 {
+        let sum: Complex<f64> = samples
+            .iter()
+            .enumerate()
+            .map(|(n, s)| {
+                let angle = -2.0 * PI * freq * n as f64;
+                Complex::new(s.re as f64, s.im as f64) * Complex::new(angle.cos(), angle.sin())
+            })
+            .sum();
+        sum.norm_sqr()
+    }
+
+    fn image_rejection(samples: &[Complex<f32>]) -> f64 {
+        10.0 * (power_at(samples, TONE) / power_at(samples, -TONE)).log10()
+    }
+
+    const BALANCE: IqBalance = IqBalance {
+        gain: 1.1,
+        phase: 0.1,
+    };
+
+    #[test]
+    fn the_estimate_recovers_the_imbalance() {
+        let mut estimator = IqImbalanceEstimator::default();
+        assert_eq!(estimator.estimate(), None);
+        estimator.update(&imbalanced_tone(BALANCE));
+        let estimate = estimator.estimate().unwrap();
+        assert!((estimate.gain - BALANCE.gain).abs() < 1e-3, "{estimate:?}");
+        assert!(
+            (estimate.phase - BALANCE.phase).abs() < 1e-3,
+            "{estimate:?}"
+        );
+    }
+
+    #[test]
+    fn the_image_rejection_matches_the_tone() {
+        let measured = image_rejection(&imbalanced_tone(BALANCE));
+        assert!(
+            (measured - BALANCE.image_rejection_db() as f64).abs() < 0.1,
+            "{measured} dB"
+        );
+        assert!(IqBalance::default().image_rejection_db() > 60.0);
+    }
+
+    #[test]
+    fn correction_suppresses_the_image() {
+        let mut samples = imbalanced_tone(BALANCE);
+        assert!(image_rejection(&samples) < 30.0);
+
+        let mut estimator = IqImbalanceEstimator::default();
+        estimator.update(&samples);
+        IqCorrector::new(estimator.estimate().unwrap()).apply(&mut samples);
+        let rejection = image_rejection(&samples);
+        assert!(rejection > 60.0, "{rejection} dB");
+    }
+
+    #[test]
+    fn a_balanced_corrector_changes_nothing() {
+        let tone = imbalanced_tone(IqBalance::default());
+        let mut samples = tone.clone();
+        IqCorrector::default().apply(&mut samples);
+        assert_eq!(samples, tone);
+    }
+}
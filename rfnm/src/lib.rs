@@ -1,7 +1,25 @@
+pub mod agc;
 pub mod channel_settings;
+pub mod control;
+pub mod cs12;
+pub mod dc_offset;
+pub mod ddc;
 pub mod device;
+pub mod hotplug;
 pub mod hwinfo;
+pub mod iq_balance;
+pub mod logging;
+pub mod multi_device;
+pub mod power;
+pub mod shm_ring;
+pub mod spyserver;
 pub mod stream;
+pub mod supervised;
+pub mod tags;
+pub mod topology;
+pub mod vrt;
+#[cfg(feature = "zmq")]
+pub mod zmq_sink;
 
 pub use rfnm_sys;
 
@@ -9,10 +27,13 @@ pub use rfnm_sys::rfnm_channel;
 
 use crate::hwinfo::HwInfo;
 use rfnm_sys::{WrappedThrownError, rfnm_api_failcode, rfnm_dev_hwinfo};
-use std::ffi::CStr;
+use std::ffi::{CStr, CString, c_char};
 use std::mem::MaybeUninit;
 use thiserror::Error;
 
+/// Maximum number of rx channels a board can have, see librfnm's `MAX_RX_CHANNELS`.
+pub const MAX_RX_CHANNELS: usize = 8;
+
 /// Discover all connected rfnm devices.
 pub fn discover_usb_boards() -> Vec<HwInfo> {
     let mut dst = Vec::new();
@@ -30,6 +51,33 @@ pub fn discover_usb_boards() -> Vec<HwInfo> {
     dst
 }
 
+/// USB serial numbers of all connected rfnm devices.
+///
+/// Unlike `discover_usb_boards` this does not claim the boards, so it also lists boards that are in use.
+pub fn list_usb_serials() -> Vec<String> {
+    const SERIAL_LEN: usize = 9;
+    unsafe {
+        let board_count = rfnm_sys::list_usb_serials(std::ptr::null_mut(), 0);
+        let mut raw = vec![0 as c_char; board_count * SERIAL_LEN];
+        let actual_count = rfnm_sys::list_usb_serials(raw.as_mut_ptr(), board_count);
+        raw.chunks_exact(SERIAL_LEN)
+            .take(actual_count)
+            .map(|serial| {
+                let c_str = CStr::from_ptr(serial.as_ptr());
+                String::from_utf8_lossy(c_str.to_bytes()).to_string()
+            })
+            .collect()
+    }
+}
+
+/// Hardware info of the board with the given usb serial number.
+/// `None` if there is no such board, or it is in use.
+pub fn find_usb_board(serial: &str) -> Option<HwInfo> {
+    let serial = CString::new(serial).ok()?;
+    let mut raw = rfnm_dev_hwinfo::default();
+    unsafe { rfnm_sys::find_usb_device_by_serial(serial.as_ptr(), &mut raw) }.then(|| raw.into())
+}
+
 #[derive(Debug, Error)]
 pub enum RfnmApiError {
     #[error("The RFNM Api threw an exception: {0}")]
@@ -60,10 +108,50 @@ pub enum RfnmApiError {
     MinQbufCountNotSatisfied,
     #[error("RFNM_API_MIN_QBUF_QUEUE_FULL")]
     MinQbufQueueFull,
+    #[error("Rx channel {0} does not exist on this board")]
+    NoSuchChannel(u32),
+    #[error("Channel flags {0:#x} do not name exactly one channel")]
+    NotSingleChannel(u32),
+    #[error("Rx channel {0} is not available")]
+    ChannelUnavailable(u32),
+    #[error("Rx channel {0} is already enabled, maybe by another stream")]
+    ChannelAlreadyEnabled(u32),
+    #[error(
+        "Rx channels {first} and {other} have different sample rate dividers ({first_m}/{first_n} vs {other_m}/{other_n}), a stream needs the same for all"
+    )]
+    SampleRateMismatch {
+        first: u32,
+        first_m: i16,
+        first_n: i16,
+        other: u32,
+        other_m: i16,
+        other_n: i16,
+    },
+    #[error(
+        "Rx channels {first} and {other} are both sampled by ADC {adc_id}, only one of them can stream"
+    )]
+    AdcConflict { adc_id: i8, first: u32, other: u32 },
+    #[error("Later settings were executed before the result of these was read")]
+    SetResultSuperseded,
+    #[error("Invalid serial number: {0:?}")]
+    InvalidSerial(String),
     #[error("Encounterd an unkwon error code: {0}")]
     Unknown(u32),
 }
 
+impl RfnmApiError {
+    /// Errors that mean the board is not talking to us anymore, and reconnecting is the way out.
+    pub fn is_transport_error(&self) -> bool {
+        matches!(
+            self,
+            RfnmApiError::UsbFail
+                | RfnmApiError::Timeout
+                | RfnmApiError::DqbufNoData
+                | RfnmApiError::ApiException(_)
+        )
+    }
+}
+
 impl From<WrappedThrownError> for RfnmApiError {
     fn from(value: WrappedThrownError) -> Self {
         let c_str = unsafe { CStr::from_ptr(value.message.as_ptr()) };
@@ -95,8 +183,15 @@ pub fn check_code(code: rfnm_api_failcode) -> Result<(), RfnmApiError> {
     }
 }
 
+/// Split a combination of channel flags into single channels, lowest first.
+pub fn split_channel_flags(channels: rfnm_channel) -> impl Iterator<Item = rfnm_channel> {
+    (0..MAX_RX_CHANNELS)
+        .map(|i| rfnm_channel(1 << i))
+        .filter(move |ch| channels.0 & ch.0 != 0)
+}
+
 pub fn channel_flag_to_number(channel: rfnm_channel) -> Option<u32> {
-    for i in 0..7 {
+    for i in 0..8 {
         if channel.0 == (1 << i) {
             return Some(i);
         }
@@ -104,3 +199,18 @@ pub fn channel_flag_to_number(channel: rfnm_channel) -> Option<u32> {
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_channel_flag_has_a_number() {
+        for i in 0..8 {
+            assert_eq!(channel_flag_to_number(rfnm_channel(1 << i)), Some(i));
+        }
+        assert_eq!(channel_flag_to_number(rfnm_channel(0)), None);
+        assert_eq!(channel_flag_to_number(rfnm_channel(0b11)), None);
+        assert_eq!(channel_flag_to_number(rfnm_channel(1 << 8)), None);
+    }
+}
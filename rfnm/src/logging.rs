@@ -0,0 +1,84 @@
+//! librfnm's log output.
+//!
+//! librfnm logs through spdlog, straight to stdout. `forward_to_log` hands its records to the `log`
+//! crate instead, under the target `librfnm`, so they end up wherever the application's logger puts them.
+//! `tracing` subscribers get them through `tracing-log`.
+
+use log::{Level, LevelFilter};
+use rfnm_sys::{log_set_callback, log_set_level, rfnm_debug_level};
+use std::ffi::{c_char, c_int};
+
+pub const LOG_TARGET: &str = "librfnm";
+
+// spdlog's levels
+const SPDLOG_TRACE: c_int = 0;
+const SPDLOG_DEBUG: c_int = 1;
+const SPDLOG_INFO: c_int = 2;
+const SPDLOG_WARN: c_int = 3;
+const SPDLOG_ERROR: c_int = 4;
+const SPDLOG_OFF: c_int = 6;
+
+/// What librfnm is told to report about a board when connecting, see `Device::connect_usb_with_debug_level`.
+///
+/// librfnm only takes it for now. Its spdlog output is filtered with `set_level`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugLevel {
+    #[default]
+    None,
+    Error,
+    Info,
+    Verbose,
+}
+
+impl From<DebugLevel> for rfnm_debug_level {
+    fn from(value: DebugLevel) -> Self {
+        match value {
+            DebugLevel::None => rfnm_debug_level::DEBUG_NONE,
+            DebugLevel::Error => rfnm_debug_level::DEBUG_ERROR,
+            DebugLevel::Info => rfnm_debug_level::DEBUG_INFO,
+            DebugLevel::Verbose => rfnm_debug_level::DEBUG_VERBOSE,
+        }
+    }
+}
+
+/// Send librfnm's log records to the `log` crate, at most as detailed as `log::max_level` is now.
+///
+/// Set up the logger first and call this before connecting, spdlog does not expect its output to change while it logs.
+pub fn forward_to_log() {
+    unsafe { log_set_callback(Some(forward_record)) };
+    set_level(log::max_level());
+}
+
+/// Back to spdlog writing to stdout.
+pub fn log_to_stdout() {
+    unsafe { log_set_callback(None) };
+}
+
+/// Drop librfnm's records below `level` before they are even formatted.
+pub fn set_level(level: LevelFilter) {
+    let level = match level {
+        LevelFilter::Off => SPDLOG_OFF,
+        LevelFilter::Error => SPDLOG_ERROR,
+        LevelFilter::Warn => SPDLOG_WARN,
+        LevelFilter::Info => SPDLOG_INFO,
+        LevelFilter::Debug => SPDLOG_DEBUG,
+        LevelFilter::Trace => SPDLOG_TRACE,
+    };
+    unsafe { log_set_level(level) };
+}
+
+unsafe extern "C" fn forward_record(level: c_int, msg: *const c_char, msg_len: usize) {
+    let level = match level {
+        SPDLOG_TRACE => Level::Trace,
+        SPDLOG_DEBUG => Level::Debug,
+        SPDLOG_INFO => Level::Info,
+        SPDLOG_WARN => Level::Warn,
+        // error and critical
+        _ => Level::Error,
+    };
+    if !log::log_enabled!(target: LOG_TARGET, level) {
+        return;
+    }
+    let msg = unsafe { std::slice::from_raw_parts(msg.cast::<u8>(), msg_len) };
+    log::log!(target: LOG_TARGET, level, "{}", String::from_utf8_lossy(msg));
+}
@@ -0,0 +1,689 @@
+//! Synchronized capture from several boards.
+//!
+//! Every board runs from its own clock and starts streaming whenever its `RxStream::start` gets through,
+//! so sample `n` of one board is not sample `n` of another. `MultiDevice` estimates the offset between
+//! boards coarsely from `StreamReadInfo::timestamp_ns` and the host clock, refines it by cross-correlating
+//! a signal all boards receive, and holds back the boards that are ahead so the buffers `read` hands out line up.
+//! Offsets are tracked over time, which also gives the clock drift of each board relative to the first one.
+
+use crate::channel_settings::RxChannelSettings;
+use crate::device::Device;
+use crate::stream::{ComplexSample, RxStream, StreamReadInfo};
+use crate::{RfnmApiError, channel_flag_to_number};
+use num_complex::Complex;
+use rfnm_sys::rfnm_channel;
+use rustfft::FftPlanner;
+use std::collections::VecDeque;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Number of offset measurements kept per board to estimate the drift from.
+const DRIFT_HISTORY: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum MultiDeviceError {
+    #[error("Board {serial}: {source}")]
+    Board {
+        serial: String,
+        #[source]
+        source: RfnmApiError,
+    },
+    #[error("At least one board is needed")]
+    NoBoards,
+    #[error("Exactly one channel per board is supported, got {0:#x}")]
+    InvalidChannel(u32),
+    #[error("Board {0} runs at {1} Hz, but the first board at {2} Hz")]
+    SampleRateMismatch(String, f64, f64),
+    #[error("Read buffer count {0} does not match board count of {1}")]
+    BufferCountMismatch(usize, usize),
+    #[error("Buffer sizes in read buffers do not match. They must all be the same")]
+    BufferSizeMismatch,
+}
+
+/// How the boards get lined up.
+#[derive(Debug, Clone)]
+pub struct AlignmentSettings {
+    /// Largest offset in samples the cross-correlation looks for around the current estimate.
+    /// `None` searches as far as the block passed to `MultiDevice::align` allows.
+    pub max_lag: Option<usize>,
+    /// Correlation peaks below this (between 0 and 1) are ignored, as there is no usable reference signal.
+    pub min_correlation: f32,
+    /// Run `MultiDevice::align` on every block `MultiDevice::read` returns.
+    /// Only useful if the reference signal is present all the time.
+    pub auto_align: bool,
+}
+
+impl Default for AlignmentSettings {
+    fn default() -> Self {
+        Self {
+            max_lag: None,
+            min_correlation: 0.5,
+            auto_align: false,
+        }
+    }
+}
+
+/// Where one board stands relative to the first one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoardAlignment {
+    /// How many samples later something shows up on this board than on the first one.
+    pub offset_samples: f64,
+    /// The same offset in nanoseconds.
+    pub offset_ns: f64,
+    /// How fast the offset changes, in parts per million of the sample rate.
+    pub drift_ppm: f64,
+    /// Normalized peak of the last cross-correlation, between 0 and 1.
+    /// 0 while the offset is only known from the timestamps.
+    pub correlation: f32,
+}
+
+/// Result of `CrossCorrelator::correlate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CorrelationPeak {
+    /// Samples `other` is delayed relative to `reference`, with sub-sample precision.
+    pub lag: f64,
+    /// Normalized magnitude of the peak, between 0 and 1.
+    pub magnitude: f32,
+}
+
+/// FFT based cross-correlation, keeping plans and buffers around between calls.
+pub struct CrossCorrelator {
+    planner: FftPlanner<f32>,
+    reference: Vec<Complex<f32>>,
+    other: Vec<Complex<f32>>,
+}
+
+impl Default for CrossCorrelator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrossCorrelator {
+    pub fn new() -> Self {
+        Self {
+            planner: FftPlanner::new(),
+            reference: Vec::new(),
+            other: Vec::new(),
+        }
+    }
+
+    /// Find the lag between two recordings of the same signal, looking at most `max_lag` samples in both directions.
+    /// `None` if either input is empty or silent.
+    pub fn correlate<T: ComplexSample>(
+        &mut self,
+        reference: &[T],
+        other: &[T],
+        max_lag: Option<usize>,
+    ) -> Option<CorrelationPeak> {
+        let len = reference.len().min(other.len());
+        if len == 0 {
+            return None;
+        }
+        let max_lag = max_lag.unwrap_or(len / 2).min(len - 1) as i64;
+        // zero padding to twice the length turns the circular correlation into a linear one
+        let fft_len = (2 * len).next_power_of_two();
+        let zero = Complex::new(0.0, 0.0);
+        self.reference.clear();
+        self.reference
+            .extend(reference[..len].iter().map(|s| s.to_complex_f32()));
+        self.reference.resize(fft_len, zero);
+        self.other.clear();
+        self.other
+            .extend(other[..len].iter().map(|s| s.to_complex_f32()));
+        self.other.resize(fft_len, zero);
+
+        let energy = |buf: &[Complex<f32>]| buf.iter().map(|s| s.norm_sqr() as f64).sum::<f64>();
+        let norm = (energy(&self.reference) * energy(&self.other)).sqrt();
+        if norm <= 0.0 {
+            return None;
+        }
+
+        self.planner
+            .plan_fft_forward(fft_len)
+            .process(&mut self.reference);
+        self.planner
+            .plan_fft_forward(fft_len)
+            .process(&mut self.other);
+        for (r, o) in self.reference.iter_mut().zip(&self.other) {
+            *r = r.conj() * o;
+        }
+        self.planner
+            .plan_fft_inverse(fft_len)
+            .process(&mut self.reference);
+
+        // the inverse fft is not normalized
+        let scale = 1.0 / (fft_len as f64 * norm);
+        let magnitude_at = |lag: i64| {
+            let index = lag.rem_euclid(fft_len as i64) as usize;
+            self.reference[index].norm() as f64 * scale
+        };
+        let (peak_lag, peak) = (-max_lag..=max_lag)
+            .map(|lag| (lag, magnitude_at(lag)))
+            .fold(
+                (0, f64::MIN),
+                |best, cur| if cur.1 > best.1 { cur } else { best },
+            );
+
+        // parabolic interpolation around the peak for the sub-sample part
+        let mut fraction = 0.0;
+        if peak_lag.abs() < max_lag {
+            let (before, after) = (magnitude_at(peak_lag - 1), magnitude_at(peak_lag + 1));
+            let denominator = before - 2.0 * peak + after;
+            if denominator < 0.0 {
+                fraction = (0.5 * (before - after) / denominator).clamp(-0.5, 0.5);
+            }
+        }
+        Some(CorrelationPeak {
+            lag: peak_lag as f64 + fraction,
+            magnitude: peak.min(1.0) as f32,
+        })
+    }
+}
+
+struct Board<T> {
+    serial: String,
+    stream: RxStream<T>,
+    raw: Vec<T>,
+    pending: VecDeque<T>,
+    // earliest host time the first sample could have been taken at
+    origin: Option<Instant>,
+    // samples dropped (or padded, if negative) so far to line this board up
+    applied_delay: i64,
+    offset_samples: f64,
+    correlation: f32,
+    history: VecDeque<(Instant, f64)>,
+    drift_ppm: f64,
+}
+
+impl<T: ComplexSample> Board<T> {
+    fn reset(&mut self) {
+        self.pending.clear();
+        self.origin = None;
+        self.applied_delay = 0;
+        self.offset_samples = 0.0;
+        self.correlation = 0.0;
+        self.history.clear();
+        self.drift_ppm = 0.0;
+    }
+
+    /// The samples up to the end of this block were all taken before `now`,
+    /// so `now` minus their duration is an upper bound for when the stream started.
+    fn update_origin(&mut self, now: Instant, info: &StreamReadInfo, sample_rate: f64) {
+        let block_ns = info.elements_read as f64 * 1e9 / sample_rate;
+        let streamed = Duration::from_nanos(info.timestamp_ns + block_ns as u64);
+        if let Some(origin) = now.checked_sub(streamed) {
+            self.origin = Some(self.origin.map_or(origin, |o| o.min(origin)));
+        }
+    }
+
+    fn record_offset(&mut self, now: Instant, sample_rate: f64) {
+        if self.history.len() == DRIFT_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((now, self.offset_samples));
+        // least squares slope of the offset over time
+        let first = self.history[0].0;
+        let points: Vec<(f64, f64)> = self
+            .history
+            .iter()
+            .map(|(t, offset)| (t.duration_since(first).as_secs_f64(), *offset))
+            .collect();
+        let count = points.len() as f64;
+        let mean_t = points.iter().map(|p| p.0).sum::<f64>() / count;
+        let mean_offset = points.iter().map(|p| p.1).sum::<f64>() / count;
+        let covariance: f64 = points
+            .iter()
+            .map(|(t, o)| (t - mean_t) * (o - mean_offset))
+            .sum();
+        let variance: f64 = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+        if variance > 0.0 {
+            self.drift_ppm = covariance / variance / sample_rate * 1e6;
+        }
+    }
+}
+
+/// Several boards streaming one channel each, with their outputs lined up.
+///
+/// All boards use the same `RxChannelSettings`, so they run at the same rate.
+pub struct MultiDevice<T> {
+    boards: Vec<Board<T>>,
+    sample_rate: f64,
+    settings: AlignmentSettings,
+    correlator: CrossCorrelator,
+}
+
+impl<T: ComplexSample + Send> MultiDevice<T> {
+    /// Connect to the boards with the given serial numbers and set up `channel` on each of them.
+    /// The first board is the one all others are aligned to.
+    pub fn open(
+        serials: &[&str],
+        channel: rfnm_channel,
+        channel_settings: &RxChannelSettings,
+        settings: AlignmentSettings,
+    ) -> Result<Self, MultiDeviceError> {
+        if serials.is_empty() {
+            return Err(MultiDeviceError::NoBoards);
+        }
+        if channel_flag_to_number(channel).is_none() {
+            return Err(MultiDeviceError::InvalidChannel(channel.0));
+        }
+        let mut boards = Vec::with_capacity(serials.len());
+        for serial in serials {
+            let board_err = |source| MultiDeviceError::Board {
+                serial: serial.to_string(),
+                source,
+            };
+            let device = Device::connect_usb_serial(serial).map_err(board_err)?;
+            device
+                .set_rx_settings(channel, channel_settings)
+                .map_err(board_err)?;
+            let stream = RxStream::new(device, channel).map_err(|(e, _)| board_err(e))?;
+            boards.push(Board {
+                serial: serial.to_string(),
+                stream,
+                raw: Vec::new(),
+                pending: VecDeque::new(),
+                origin: None,
+                applied_delay: 0,
+                offset_samples: 0.0,
+                correlation: 0.0,
+                history: VecDeque::new(),
+                drift_ppm: 0.0,
+            });
+        }
+
+        let sample_rate = boards[0].stream.sample_rate();
+        if let Some(board) = boards
+            .iter()
+            .find(|b| b.stream.sample_rate() != sample_rate)
+        {
+            return Err(MultiDeviceError::SampleRateMismatch(
+                board.serial.clone(),
+                board.stream.sample_rate(),
+                sample_rate,
+            ));
+        }
+        Ok(Self {
+            boards,
+            sample_rate,
+            settings,
+            correlator: CrossCorrelator::new(),
+        })
+    }
+
+    pub fn board_count(&self) -> usize {
+        self.boards.len()
+    }
+
+    /// Serial numbers of the boards, in the order their buffers are passed to `read`.
+    pub fn serials(&self) -> impl Iterator<Item = &str> {
+        self.boards.iter().map(|b| b.serial.as_str())
+    }
+
+    /// The stream of one board, e.g. to change its settings through `RxStream::device`.
+    pub fn stream(&self, board: usize) -> &RxStream<T> {
+        &self.boards[board].stream
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn settings(&self) -> &AlignmentSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: AlignmentSettings) {
+        self.settings = settings;
+    }
+
+    /// Current alignment of every board, the first one always being at 0.
+    pub fn alignment(&self) -> Vec<BoardAlignment> {
+        self.boards
+            .iter()
+            .map(|b| BoardAlignment {
+                offset_samples: b.offset_samples,
+                offset_ns: b.offset_samples * 1e9 / self.sample_rate,
+                drift_ppm: b.drift_ppm,
+                correlation: b.correlation,
+            })
+            .collect()
+    }
+
+    /// Start all streams at once, one thread per board released at the same moment.
+    /// Alignment starts over, as the boards start with a new offset.
+    pub fn start(&mut self) -> Result<(), MultiDeviceError> {
+        for board in &mut self.boards {
+            board.reset();
+        }
+        let barrier = Barrier::new(self.boards.len());
+        let results: Vec<Result<(), RfnmApiError>> = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .boards
+                .iter_mut()
+                .map(|board| {
+                    let barrier = &barrier;
+                    s.spawn(move || {
+                        barrier.wait();
+                        board.stream.start()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("stream start panicked"))
+                .collect()
+        });
+        self.collect_errors(results)
+    }
+
+    /// Stop all streams, even if some of them fail to.
+    pub fn stop(&mut self) -> Result<(), MultiDeviceError> {
+        let results: Vec<_> = self.boards.iter().map(|b| b.stream.stop()).collect();
+        self.collect_errors(results)
+    }
+
+    /// Read the same stretch of time from every board, one buffer per board.
+    ///
+    /// Returns how many samples of each buffer were filled. That can be less than the buffer size
+    /// while boards are being held back to line them up.
+    pub fn read(
+        &mut self,
+        dst: &mut [&mut [T]],
+        timeout: Duration,
+    ) -> Result<usize, MultiDeviceError> {
+        if dst.len() != self.boards.len() {
+            return Err(MultiDeviceError::BufferCountMismatch(
+                dst.len(),
+                self.boards.len(),
+            ));
+        }
+        if dst.iter().any(|buf| buf.len() != dst[0].len()) {
+            return Err(MultiDeviceError::BufferSizeMismatch);
+        }
+
+        let wanted = dst[0].len();
+        for board in &mut self.boards {
+            board
+                .raw
+                .resize(wanted, T::from_complex_f32(Complex::new(0.0, 0.0)));
+            let info = board
+                .stream
+                .read(&mut [board.raw.as_mut_slice()], timeout)
+                .map_err(|source| MultiDeviceError::Board {
+                    serial: board.serial.clone(),
+                    source,
+                })?;
+            board.update_origin(Instant::now(), &info, self.sample_rate);
+            board
+                .pending
+                .extend(board.raw[..info.elements_read].iter().copied());
+        }
+        self.apply_alignment();
+
+        let available = self
+            .boards
+            .iter()
+            .map(|b| b.pending.len())
+            .min()
+            .unwrap_or(0)
+            .min(wanted);
+        for (board, buf) in self.boards.iter_mut().zip(dst.iter_mut()) {
+            for (d, s) in buf.iter_mut().zip(board.pending.drain(..available)) {
+                *d = s;
+            }
+        }
+        if self.settings.auto_align && available > 0 {
+            let aligned: Vec<&[T]> = dst.iter().map(|buf| &buf[..available]).collect();
+            self.align(&aligned);
+        }
+        Ok(available)
+    }
+
+    /// Refine the offsets by cross-correlating a block `read` returned, one buffer per board.
+    /// The block must contain a signal all boards receive, any strong enough signal will do.
+    ///
+    /// Returns whether every board correlated well enough to be updated.
+    pub fn align<B: AsRef<[T]>>(&mut self, aligned: &[B]) -> bool {
+        if aligned.len() != self.boards.len() {
+            return false;
+        }
+        let now = Instant::now();
+        let reference_delay = self.boards[0].applied_delay;
+        let mut all_updated = true;
+        for (board, buf) in self.boards.iter_mut().zip(aligned).skip(1) {
+            let peak =
+                self.correlator
+                    .correlate(aligned[0].as_ref(), buf.as_ref(), self.settings.max_lag);
+            match peak {
+                Some(peak) if peak.magnitude >= self.settings.min_correlation => {
+                    // the block was already shifted by the delays applied so far
+                    board.offset_samples =
+                        (board.applied_delay - reference_delay) as f64 + peak.lag;
+                    board.correlation = peak.magnitude;
+                    board.record_offset(now, self.sample_rate);
+                }
+                _ => all_updated = false,
+            }
+        }
+        all_updated
+    }
+}
+
+impl<T: ComplexSample> MultiDevice<T> {
+    fn collect_errors(
+        &self,
+        results: Vec<Result<(), RfnmApiError>>,
+    ) -> Result<(), MultiDeviceError> {
+        for (board, result) in self.boards.iter().zip(results) {
+            result.map_err(|source| MultiDeviceError::Board {
+                serial: board.serial.clone(),
+                source,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Drop samples from boards that are ahead, or pad the ones that fell behind an updated estimate.
+    fn apply_alignment(&mut self) {
+        let (first, rest) = self.boards.split_first_mut().expect("at least one board");
+        for board in rest.iter_mut() {
+            // until a correlation came through, the start times are all there is
+            if board.correlation == 0.0 {
+                if let (Some(reference), Some(origin)) = (first.origin, board.origin) {
+                    let seconds = if reference >= origin {
+                        reference.duration_since(origin).as_secs_f64()
+                    } else {
+                        -origin.duration_since(reference).as_secs_f64()
+                    };
+                    board.offset_samples = seconds * self.sample_rate;
+                }
+            }
+        }
+
+        let targets: Vec<i64> = self
+            .boards
+            .iter()
+            .map(|b| b.offset_samples.round() as i64)
+            .collect();
+        let base = targets.iter().copied().min().unwrap_or(0);
+        let zero = T::from_complex_f32(Complex::new(0.0, 0.0));
+        for (board, target) in self.boards.iter_mut().zip(targets) {
+            let delta = target - base - board.applied_delay;
+            if delta > 0 {
+                let dropped = (delta as usize).min(board.pending.len());
+                board.pending.drain(..dropped);
+                board.applied_delay += dropped as i64;
+            } else if delta < 0 {
+                for _ in 0..-delta {
+                    board.pending.push_front(zero);
+                }
+                board.applied_delay += delta;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic white noise.
+    fn noise(len: usize, seed: u32) -> Vec<Complex<f32>> {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1 << 24) as f32 - 0.5
+        };
+        (0..len).map(|_| Complex::new(next(), next())).collect()
+    }
+
+    /// `samples` arriving `delay` samples later, zeros where nothing arrived yet.
+    fn delayed(samples: &[Complex<f32>], delay: i64) -> Vec<Complex<f32>> {
+        (0..samples.len() as i64)
+            .map(|n| {
+                usize::try_from(n - delay)
+                    .ok()
+                    .and_then(|i| samples.get(i))
+                    .copied()
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// A gaussian pulse of `width` samples, centered at `center`, which may fall between samples.
+    fn pulse(len: usize, center: f64, width: f64) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|n| {
+                let t = (n as f64 - center) / width;
+                Complex::new((-t * t).exp() as f32, 0.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn integer_lags_are_found() {
+        let mut correlator = CrossCorrelator::new();
+        let reference = noise(1024, 1);
+        for delay in [0, 5, -7, 100] {
+            let peak = correlator
+                .correlate(&reference, &delayed(&reference, delay), None)
+                .unwrap();
+            assert!((peak.lag - delay as f64).abs() < 0.05, "{delay}: {peak:?}");
+            assert!(peak.magnitude > 0.85, "{delay}: {peak:?}");
+        }
+    }
+
+    #[test]
+    fn sub_sample_lags_are_interpolated() {
+        let mut correlator = CrossCorrelator::new();
+        let reference = pulse(256, 100.0, 8.0);
+        for delay in [3.4, -2.25] {
+            let other = pulse(256, 100.0 + delay, 8.0);
+            let peak = correlator.correlate(&reference, &other, None).unwrap();
+            assert!((peak.lag - delay).abs() < 0.05, "{delay}: {peak:?}");
+        }
+    }
+
+    #[test]
+    fn lags_beyond_max_lag_are_not_found() {
+        let mut correlator = CrossCorrelator::new();
+        let reference = noise(1024, 2);
+        let peak = correlator
+            .correlate(&reference, &delayed(&reference, 40), Some(10))
+            .unwrap();
+        assert!(peak.lag.abs() <= 10.0);
+        assert!(peak.magnitude < 0.5, "{peak:?}");
+    }
+
+    #[test]
+    fn silence_does_not_correlate() {
+        let mut correlator = CrossCorrelator::new();
+        let silence = vec![Complex::new(0.0f32, 0.0); 64];
+        assert_eq!(correlator.correlate(&noise(64, 3), &silence, None), None);
+        assert_eq!(correlator.correlate::<Complex<f32>>(&[], &[], None), None);
+    }
+
+    /// Two connections to the simulated board, standing in for two boards.
+    #[cfg(feature = "mock")]
+    mod boards {
+        use super::*;
+
+        fn two_boards() -> MultiDevice<Complex<f32>> {
+            let device = Device::connect_usb().unwrap();
+            let channel_settings = device
+                .get_rx_settings(rfnm_channel::CH0)
+                .unwrap()
+                .to_settings();
+            drop(device);
+            MultiDevice::open(
+                &["MOCK0001", "MOCK0001"],
+                rfnm_channel::CH0,
+                &channel_settings,
+                AlignmentSettings::default(),
+            )
+            .unwrap()
+        }
+
+        fn pending(multi: &MultiDevice<Complex<f32>>, board: usize) -> Vec<f32> {
+            multi.boards[board].pending.iter().map(|s| s.re).collect()
+        }
+
+        /// Give both boards the samples 0 to 9 and the second one an offset of `offset` samples.
+        fn align_to(multi: &mut MultiDevice<Complex<f32>>, offset: f64) {
+            for board in &mut multi.boards {
+                board.pending = (0..10).map(|n| Complex::new(n as f32, 0.0)).collect();
+            }
+            multi.boards[1].offset_samples = offset;
+            // a correlation came through, the start times no longer count
+            multi.boards[1].correlation = 1.0;
+            multi.apply_alignment();
+        }
+
+        #[test]
+        fn the_later_board_is_held_back() {
+            let mut multi = two_boards();
+            align_to(&mut multi, 3.0);
+            assert_eq!(pending(&multi, 0)[0], 0.0);
+            assert_eq!(pending(&multi, 1)[0], 3.0);
+            assert_eq!(multi.boards[1].applied_delay, 3);
+        }
+
+        #[test]
+        fn the_first_board_is_held_back_for_negative_offsets() {
+            let mut multi = two_boards();
+            align_to(&mut multi, -4.0);
+            assert_eq!(pending(&multi, 0)[0], 4.0);
+            assert_eq!(pending(&multi, 1)[0], 0.0);
+        }
+
+        #[test]
+        fn a_smaller_estimate_pads_the_board_again() {
+            let mut multi = two_boards();
+            align_to(&mut multi, 3.0);
+            multi.boards[1].offset_samples = 1.0;
+            multi.apply_alignment();
+            assert_eq!(pending(&multi, 1)[..3], [0.0, 0.0, 3.0]);
+            assert_eq!(multi.boards[1].applied_delay, 1);
+        }
+
+        #[test]
+        fn weak_correlations_are_ignored() {
+            let mut multi = two_boards();
+            let reference = noise(1024, 4);
+            assert!(!multi.align(&[reference.clone(), noise(1024, 5)]));
+            assert_eq!(multi.alignment()[1].correlation, 0.0);
+
+            assert!(multi.align(&[reference.clone(), delayed(&reference, 6)]));
+            let alignment = multi.alignment()[1];
+            assert!(
+                (alignment.offset_samples - 6.0).abs() < 0.05,
+                "{alignment:?}"
+            );
+            assert!(alignment.correlation > 0.5);
+        }
+    }
+}
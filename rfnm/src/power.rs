@@ -0,0 +1,476 @@
+//! rtl_power style spectrum logging.
+//!
+//! `PowerLogger` sweeps a frequency range hop by hop, averages the power spectrum of every hop over
+//! its share of the integration interval and writes one rtl_power compatible CSV line per hop:
+//! `date, time, Hz low, Hz high, Hz step, samples, dB, dB, ...`.
+//! If the board goes away on the way, it is reconnected and the sweep continues.
+
+use crate::RfnmApiError;
+use crate::channel_settings::{RfPath, RxChannelSettings, SampleRateDividerSettings};
+use crate::device::Device;
+use crate::stream::RxStream;
+use chrono::Local;
+use num_complex::Complex;
+use rfnm_sys::rfnm_channel;
+use rustfft::{Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PowerLogError {
+    #[error("Device error: {0}")]
+    Api(#[from] RfnmApiError),
+    #[error("Could not write output: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid sweep range: {0} Hz to {1} Hz")]
+    InvalidRange(i64, i64),
+    #[error("FFT size must be a power of two and at least 8, got {0}")]
+    InvalidFftSize(usize),
+}
+
+/// Averages windowed power spectra over any number of frames.
+pub struct PsdAccumulator {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    normalization: f64,
+    scratch: Vec<Complex<f32>>,
+    sums: Vec<f64>,
+    frames: usize,
+}
+
+impl PsdAccumulator {
+    pub fn new(fft_size: usize) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(fft_size);
+        // hann window
+        let window: Vec<f32> = (0..fft_size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / fft_size as f32).cos())
+            .collect();
+        let window_sum: f64 = window.iter().map(|w| *w as f64).sum();
+        Self {
+            fft,
+            window,
+            // scaled so that a full scale tone ends up at 0 dB
+            normalization: 1.0 / (window_sum * window_sum),
+            scratch: vec![Complex::new(0.0, 0.0); fft_size],
+            sums: vec![0.0; fft_size],
+            frames: 0,
+        }
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Number of frames accumulated since the last reset.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Add all complete frames in `samples`. A trailing partial frame is ignored.
+    pub fn push(&mut self, samples: &[Complex<f32>]) {
+        for frame in samples.chunks_exact(self.fft_size()) {
+            for ((dst, src), w) in self.scratch.iter_mut().zip(frame).zip(&self.window) {
+                *dst = src * w;
+            }
+            self.fft.process(&mut self.scratch);
+            for (sum, bin) in self.sums.iter_mut().zip(&self.scratch) {
+                *sum += bin.norm_sqr() as f64;
+            }
+            self.frames += 1;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.sums.fill(0.0);
+        self.frames = 0;
+    }
+
+    /// The averaged power per bin in dBFS, ordered from the lowest to the highest frequency.
+    pub fn power_db(&self) -> Vec<f64> {
+        let size = self.fft_size();
+        let scale = self.normalization / self.frames.max(1) as f64;
+        (0..size)
+            .map(|i| {
+                let power = self.sums[(i + size / 2) % size] * scale;
+                10.0 * power.max(1e-20).log10()
+            })
+            .collect()
+    }
+}
+
+/// Everything that describes a sweep.
+#[derive(Debug, Clone)]
+pub struct PowerSweepSettings {
+    /// Lowest frequency of the sweep in Hz, 88 MHz by default.
+    pub freq_start: i64,
+    /// Highest frequency of the sweep in Hz, 108 MHz by default.
+    pub freq_stop: i64,
+    /// Bins per FFT, 1024 by default. Together with the sample rate this sets the `Hz step` column.
+    pub fft_size: usize,
+    /// Time for one full sweep over all hops, 10 s by default. One line per hop is written per interval.
+    pub integration_interval: Duration,
+    /// Fraction of each FFT that is discarded at the band edges, where the filters roll off. 0.2 by default.
+    pub crop: f64,
+    /// CH0 by default.
+    pub channel: rfnm_channel,
+    /// 0 dB by default.
+    pub gain: i8,
+    /// SMA A by default.
+    pub path: RfPath,
+    /// 1/1, the full rate, by default.
+    pub rate_divider_settings: SampleRateDividerSettings,
+    /// Samples read right after retuning are thrown away for this long, 5 ms by default.
+    pub settle_time: Duration,
+    /// 100 ms by default.
+    pub read_timeout: Duration,
+    /// Failed reads in a row after which the board is considered gone and is reconnected, 10 by default.
+    pub max_consecutive_errors: u32,
+    /// Wait before reconnecting, 1 s by default.
+    pub reconnect_delay: Duration,
+}
+
+impl Default for PowerSweepSettings {
+    fn default() -> Self {
+        Self {
+            freq_start: 88_000_000,
+            freq_stop: 108_000_000,
+            fft_size: 1024,
+            integration_interval: Duration::from_secs(10),
+            crop: 0.2,
+            channel: rfnm_channel::CH0,
+            gain: 0,
+            path: RfPath::default(),
+            rate_divider_settings: SampleRateDividerSettings::default(),
+            settle_time: Duration::from_millis(5),
+            read_timeout: Duration::from_millis(100),
+            max_consecutive_errors: 10,
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// One tuning step of a sweep.
+#[derive(Debug, Clone)]
+pub struct Hop {
+    /// The LO frequency for this hop
+    pub center: i64,
+    pub freq_low: f64,
+    pub freq_high: f64,
+    first_bin: usize,
+    bin_count: usize,
+}
+
+/// Split the sweep range into hops, so that only the uncropped part of each FFT is used.
+pub fn plan_hops(settings: &PowerSweepSettings, sample_rate: f64) -> Vec<Hop> {
+    let fft_size = settings.fft_size;
+    let bin_hz = sample_rate / fft_size as f64;
+    let bin_count = ((fft_size as f64 * (1.0 - settings.crop.clamp(0.0, 0.9))) as usize).max(1);
+    let first_bin = (fft_size - bin_count) / 2;
+    let usable = bin_count as f64 * bin_hz;
+    let span = (settings.freq_stop - settings.freq_start) as f64;
+    let hop_count = (span / usable).ceil().max(1.0) as usize;
+
+    // the first used bin is this far below the center, which is not half of `usable` if the crop is odd
+    let below_center = ((fft_size / 2) as f64 - first_bin as f64) * bin_hz;
+    (0..hop_count)
+        .map(|i| {
+            let center =
+                (settings.freq_start as f64 + usable * i as f64 + below_center).round() as i64;
+            let freq_low = center as f64 - below_center;
+            Hop {
+                center,
+                freq_low,
+                freq_high: freq_low + usable,
+                first_bin,
+                bin_count,
+            }
+        })
+        .collect()
+}
+
+/// Sweeps and logs the spectrum in the rtl_power csv format.
+///
+/// Owns the device for as long as it lives.
+pub struct PowerLogger {
+    settings: PowerSweepSettings,
+    stream: Option<RxStream<Complex<f32>>>,
+    sample_rate: f64,
+    hops: Vec<Hop>,
+    tuned_hop: Option<usize>,
+    accumulator: PsdAccumulator,
+    buffer: Vec<Complex<f32>>,
+}
+
+impl PowerLogger {
+    /// Connect to the board and get it ready for sweeping.
+    pub fn new(settings: PowerSweepSettings) -> Result<Self, PowerLogError> {
+        if settings.freq_stop <= settings.freq_start {
+            return Err(PowerLogError::InvalidRange(
+                settings.freq_start,
+                settings.freq_stop,
+            ));
+        }
+        if !settings.fft_size.is_power_of_two() || settings.fft_size < 8 {
+            return Err(PowerLogError::InvalidFftSize(settings.fft_size));
+        }
+
+        let device = Device::connect_usb()?;
+        let sample_rate = settings
+            .rate_divider_settings
+            .sample_rate(device.hwinfo().clock_info.dcs_clk);
+        let hops = plan_hops(&settings, sample_rate);
+        let stream = Self::open_stream(&settings, device, hops[0].center)?;
+
+        Ok(Self {
+            accumulator: PsdAccumulator::new(settings.fft_size),
+            buffer: vec![Complex::new(0.0, 0.0); settings.fft_size],
+            settings,
+            stream: Some(stream),
+            sample_rate,
+            hops,
+            tuned_hop: Some(0),
+        })
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn hops(&self) -> &[Hop] {
+        &self.hops
+    }
+
+    /// Sweep until `keep_running` returns false, reconnecting to the board whenever it fails us.
+    pub fn run<W: Write>(
+        &mut self,
+        out: &mut W,
+        mut keep_running: impl FnMut() -> bool,
+    ) -> Result<(), PowerLogError> {
+        while keep_running() {
+            match self.sweep(out) {
+                Ok(()) => {}
+                Err(PowerLogError::Api(e)) if e.is_transport_error() => {
+                    self.reconnect(&mut keep_running);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Do a single sweep over all hops, writing one line per hop.
+    pub fn sweep<W: Write>(&mut self, out: &mut W) -> Result<(), PowerLogError> {
+        let hop_dwell = self.settings.integration_interval / self.hops.len() as u32;
+        let fft_size = self.settings.fft_size;
+        let frames = ((hop_dwell.as_secs_f64() * self.sample_rate) as usize / fft_size).max(1);
+        let settle_frames =
+            (self.settings.settle_time.as_secs_f64() * self.sample_rate) as usize / fft_size;
+
+        for hop_num in 0..self.hops.len() {
+            let now = Local::now();
+            if self.tuned_hop != Some(hop_num) {
+                self.tuned_hop = None;
+                let settings = channel_settings(&self.settings, self.hops[hop_num].center);
+                self.stream()?
+                    .device()
+                    .set_rx_settings(self.settings.channel, &settings)?;
+                self.tuned_hop = Some(hop_num);
+                for _ in 0..settle_frames {
+                    self.read_frame()?;
+                }
+            }
+
+            self.accumulator.reset();
+            for _ in 0..frames {
+                self.read_frame()?;
+                self.accumulator.push(&self.buffer);
+            }
+
+            let hop = &self.hops[hop_num];
+            let power = self.accumulator.power_db();
+            write!(
+                out,
+                "{}, {:.0}, {:.0}, {:.2}, {}",
+                now.format("%Y-%m-%d, %H:%M:%S"),
+                hop.freq_low,
+                hop.freq_high,
+                self.sample_rate / fft_size as f64,
+                frames * fft_size,
+            )?;
+            for db in &power[hop.first_bin..hop.first_bin + hop.bin_count] {
+                write!(out, ", {db:.2}")?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()?;
+
+        Ok(())
+    }
+
+    fn stream(&self) -> Result<&RxStream<Complex<f32>>, RfnmApiError> {
+        self.stream.as_ref().ok_or(RfnmApiError::UsbFail)
+    }
+
+    /// Read exactly one FFT worth of samples into the buffer, retrying failed reads a few times.
+    ///
+    /// Reads that stop short are continued until the buffer is full, so every frame is contiguous.
+    fn read_frame(&mut self) -> Result<(), RfnmApiError> {
+        let stream = self.stream.as_ref().ok_or(RfnmApiError::UsbFail)?;
+        let mut errors = 0;
+        let mut filled = 0;
+        while filled < self.buffer.len() {
+            let result = stream.read(
+                &mut [&mut self.buffer[filled..]],
+                self.settings.read_timeout,
+            );
+            let result = match result {
+                Ok(info) => {
+                    filled += info.elements_read;
+                    match info.error {
+                        Some(e) if filled < self.buffer.len() => Err(e),
+                        _ => Ok(()),
+                    }
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => errors = 0,
+                Err(e) if e.is_transport_error() => {
+                    errors += 1;
+                    if errors >= self.settings.max_consecutive_errors {
+                        return Err(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the board and try to get it back, until it works or we are told to stop.
+    fn reconnect(&mut self, keep_running: &mut impl FnMut() -> bool) {
+        // dropping the stream drops the device with it
+        self.stream = None;
+        self.tuned_hop = None;
+        while keep_running() {
+            std::thread::sleep(self.settings.reconnect_delay);
+            let Ok(device) = Device::connect_usb() else {
+                continue;
+            };
+            if let Ok(stream) = Self::open_stream(&self.settings, device, self.hops[0].center) {
+                self.stream = Some(stream);
+                self.tuned_hop = Some(0);
+                return;
+            }
+        }
+    }
+
+    fn open_stream(
+        settings: &PowerSweepSettings,
+        device: Device,
+        center: i64,
+    ) -> Result<RxStream<Complex<f32>>, RfnmApiError> {
+        device.set_rx_settings(settings.channel, &channel_settings(settings, center))?;
+        let stream = RxStream::new(device, settings.channel).map_err(|(e, _)| e)?;
+        stream.set_auto_dc_offset(true, settings.channel);
+        stream.start()?;
+        Ok(stream)
+    }
+}
+
+fn channel_settings(settings: &PowerSweepSettings, frequency: i64) -> RxChannelSettings {
+    RxChannelSettings {
+        frequency,
+        gain: settings.gain,
+        rate_divider_settings: settings.rate_divider_settings,
+        path: settings.path,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(len: usize, cycles_per_sample: f32) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|n| Complex::from_polar(1.0, 2.0 * PI * cycles_per_sample * n as f32))
+            .collect()
+    }
+
+    #[test]
+    fn a_full_scale_tone_is_at_0_db_in_its_bin() {
+        let mut accumulator = PsdAccumulator::new(1024);
+        for bin in [100i32, -50] {
+            accumulator.reset();
+            accumulator.push(&tone(4 * 1024, bin as f32 / 1024.0));
+            assert_eq!(accumulator.frames(), 4);
+            let power = accumulator.power_db();
+            // the lowest frequency comes first, 0 Hz is in the middle
+            let index = (512 + bin) as usize;
+            assert!(power[index].abs() < 0.01, "{bin}: {} dB", power[index]);
+            // the hann window spreads it over the neighbours, and nowhere else
+            assert!((power[index + 1] + 6.02).abs() < 0.01);
+            assert!((power[index - 1] + 6.02).abs() < 0.01);
+            assert!(power[index + 3] < -100.0);
+        }
+    }
+
+    #[test]
+    fn partial_frames_are_not_counted() {
+        let mut accumulator = PsdAccumulator::new(64);
+        accumulator.push(&tone(100, 0.25));
+        assert_eq!(accumulator.frames(), 1);
+    }
+
+    #[test]
+    fn hops_tile_the_range() {
+        let cases = [
+            (PowerSweepSettings::default(), 61.44e6),
+            (
+                PowerSweepSettings {
+                    freq_start: 433_050_000,
+                    freq_stop: 434_790_000,
+                    fft_size: 256,
+                    crop: 0.3,
+                    ..Default::default()
+                },
+                1e6 / 3.0,
+            ),
+            (
+                PowerSweepSettings {
+                    freq_start: 100_000_000,
+                    freq_stop: 100_001_000,
+                    ..Default::default()
+                },
+                61.44e6,
+            ),
+        ];
+        for (settings, sample_rate) in cases {
+            let hops = plan_hops(&settings, sample_rate);
+            let bin_hz = sample_rate / settings.fft_size as f64;
+            // centers are whole Hz, the edges move with them
+            assert!((hops[0].freq_low - settings.freq_start as f64).abs() <= 1.0);
+            let last = hops.last().unwrap();
+            assert!(last.freq_high >= settings.freq_stop as f64 - 1.0);
+            assert!(last.freq_low < settings.freq_stop as f64);
+            for hop in &hops {
+                let width = hop.freq_high - hop.freq_low;
+                assert!((width - hop.bin_count as f64 * bin_hz).abs() < 1e-3);
+                assert!(hop.first_bin + hop.bin_count <= settings.fft_size);
+            }
+            for pair in hops.windows(2) {
+                assert!(
+                    (pair[1].freq_low - pair[0].freq_high).abs() <= 1.0,
+                    "{:?} then {:?}",
+                    pair[0],
+                    pair[1]
+                );
+            }
+        }
+    }
+}
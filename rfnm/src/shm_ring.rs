@@ -0,0 +1,793 @@
+//! Handing samples to other processes on the same machine through shared memory.
+//!
+//! `ShmRingPublisher` creates a named ring of sample blocks and writes `RxStream` output into it,
+//! `ShmRingReader` attaches to it by name from any number of processes. Nothing is copied
+//! through the kernel, and the publisher never waits for a reader: every reader keeps its own
+//! position and finds out from each block's sequence counter when the publisher lapped it.
+//! Blocks carry the `StreamReadInfo` they were read with.
+//!
+//! On Linux rings live in `/dev/shm`, elsewhere in the temporary directory, see `ring_path`.
+
+use crate::RfnmApiError;
+use crate::stream::{RxStream, StreamDataFormat, StreamReadInfo};
+use memmap2::{Mmap, MmapMut};
+use std::fs::{File, OpenOptions};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering, fence};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"RFNMRING");
+const VERSION: u32 = 1;
+// header and slot headers each get a cache line of their own
+const HEADER_SIZE: usize = 64;
+const SLOT_HEADER_SIZE: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum ShmRingError {
+    #[error("Device error: {0}")]
+    Api(#[from] RfnmApiError),
+    #[error("Shared memory failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid ring name {0:?}, it must be non-empty and must not contain '/'")]
+    InvalidName(String),
+    #[error("{0} is not a sample ring, or one of another version")]
+    NotARing(PathBuf),
+    #[error(
+        "The ring holds format {found} in {found_size} bytes, but format {expected} in {expected_size} bytes was asked for"
+    )]
+    FormatMismatch {
+        expected: u32,
+        expected_size: usize,
+        found: u32,
+        found_size: usize,
+    },
+    #[error("A ring needs at least one channel, one slot and a block size above zero")]
+    InvalidLayout,
+    #[error("Read buffer count {0} does not match ring channel count of {1}")]
+    BufferCountMismatch(usize, usize),
+    #[error("Buffer sizes in read buffers do not match. They must all be the same")]
+    BufferSizeMismatch,
+    #[error("Timeout")]
+    Timeout,
+    #[error("The publisher closed the ring")]
+    Closed,
+}
+
+/// How the ring is laid out.
+#[derive(Debug, Clone)]
+pub struct ShmRingSettings {
+    /// Blocks the ring holds, 64 by default. Readers falling further behind than this lose blocks.
+    pub slot_count: usize,
+}
+
+impl Default for ShmRingSettings {
+    fn default() -> Self {
+        Self { slot_count: 64 }
+    }
+}
+
+/// Where the ring called `name` lives.
+pub fn ring_path(name: &str) -> PathBuf {
+    let shm = Path::new("/dev/shm");
+    if cfg!(target_os = "linux") && shm.is_dir() {
+        shm.join(name)
+    } else {
+        std::env::temp_dir().join(name)
+    }
+}
+
+fn checked_path(name: &str) -> Result<PathBuf, ShmRingError> {
+    if name.is_empty() || name.contains('/') {
+        return Err(ShmRingError::InvalidName(name.to_string()));
+    }
+    Ok(ring_path(name))
+}
+
+#[repr(C)]
+struct RingHeader {
+    // written last by the publisher, so a reader seeing it sees the rest
+    magic: AtomicU64,
+    version: u32,
+    format: u32,
+    element_size: u32,
+    channels: u32,
+    slot_count: u32,
+    /// Samples per channel a slot holds.
+    block_size: u32,
+    sample_rate: f64,
+    /// Blocks published so far, block `n` is in slot `n % slot_count`.
+    published: AtomicU64,
+    closed: AtomicU32,
+}
+
+#[repr(C)]
+struct SlotHeader {
+    /// `2 * n + 1` while block `n` is written, `2 * n + 2` once it is complete,
+    /// 0 while the slot holds no block.
+    sequence: AtomicU64,
+    elements: AtomicU64,
+    timestamp_ns: AtomicU64,
+}
+
+const _: () = assert!(size_of::<RingHeader>() <= HEADER_SIZE);
+const _: () = assert!(size_of::<SlotHeader>() <= SLOT_HEADER_SIZE);
+
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    channels: usize,
+    slot_count: usize,
+    block_size: usize,
+    element_size: usize,
+}
+
+impl Layout {
+    fn channel_bytes(&self) -> usize {
+        self.block_size * self.element_size
+    }
+
+    fn slot_bytes(&self) -> usize {
+        SLOT_HEADER_SIZE + (self.channels * self.channel_bytes()).next_multiple_of(64)
+    }
+
+    fn total_bytes(&self) -> usize {
+        HEADER_SIZE + self.slot_count * self.slot_bytes()
+    }
+
+    fn slot_offset(&self, block: u64) -> usize {
+        HEADER_SIZE + (block % self.slot_count as u64) as usize * self.slot_bytes()
+    }
+
+    fn data_offset(&self, block: u64, channel: usize) -> usize {
+        self.slot_offset(block) + SLOT_HEADER_SIZE + channel * self.channel_bytes()
+    }
+}
+
+/// Writes sample blocks into a named shared-memory ring.
+///
+/// The ring is removed again when the publisher is dropped. Readers still attached see `Closed`
+/// once they read everything that was published.
+pub struct ShmRingPublisher<T> {
+    _p: PhantomData<T>,
+    path: PathBuf,
+    map: MmapMut,
+    layout: Layout,
+    sample_rate: f64,
+}
+
+impl<T: StreamDataFormat> ShmRingPublisher<T> {
+    /// Create the ring `name` for blocks of up to `block_size` samples on each of `channels`.
+    ///
+    /// A ring left over under the same name, e.g. by a publisher that crashed, is replaced.
+    pub fn create(
+        name: &str,
+        channels: usize,
+        sample_rate: f64,
+        block_size: usize,
+        settings: &ShmRingSettings,
+    ) -> Result<Self, ShmRingError> {
+        let path = checked_path(name)?;
+        let layout = Layout {
+            channels,
+            slot_count: settings.slot_count,
+            block_size,
+            element_size: size_of::<T>(),
+        };
+        if channels == 0
+            || layout.slot_count == 0
+            || block_size == 0
+            || u32::try_from(channels.max(layout.slot_count).max(block_size)).is_err()
+        {
+            return Err(ShmRingError::InvalidLayout);
+        }
+
+        // readers of a stale ring keep their mapping, new readers get the new one
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        file.set_len(layout.total_bytes() as u64)?;
+        let map = unsafe { MmapMut::map_mut(&file)? };
+
+        let publisher = Self {
+            _p: PhantomData,
+            path,
+            map,
+            layout,
+            sample_rate,
+        };
+        unsafe {
+            let header = publisher.map.as_ptr() as *mut RingHeader;
+            (&raw mut (*header).version).write(VERSION);
+            (&raw mut (*header).format).write(T::api_format().0);
+            (&raw mut (*header).element_size).write(layout.element_size as u32);
+            (&raw mut (*header).channels).write(channels as u32);
+            (&raw mut (*header).slot_count).write(layout.slot_count as u32);
+            (&raw mut (*header).block_size).write(block_size as u32);
+            (&raw mut (*header).sample_rate).write(sample_rate);
+        }
+        publisher.header().magic.store(MAGIC, Ordering::Release);
+        Ok(publisher)
+    }
+
+    /// Create the ring `name` matching the channels, sample rate and buffer size of `stream`.
+    pub fn for_stream(
+        name: &str,
+        stream: &RxStream<T>,
+        settings: &ShmRingSettings,
+    ) -> Result<Self, ShmRingError> {
+        Self::create(
+            name,
+            stream.channel_count(),
+            stream.sample_rate(),
+            stream.suggested_buffer_size(),
+            settings,
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.layout.channels
+    }
+
+    /// Samples per channel one block holds at most.
+    pub fn block_size(&self) -> usize {
+        self.layout.block_size
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn blocks_published(&self) -> u64 {
+        self.header().published.load(Ordering::Relaxed)
+    }
+
+    /// Publish the first `info.elements_read` samples of every buffer in `src`.
+    ///
+    /// More samples than fit one block are split over several, with their timestamps advanced to match.
+    pub fn publish(&mut self, src: &[&[T]], info: &StreamReadInfo) -> Result<(), ShmRingError> {
+        if src.len() != self.layout.channels {
+            return Err(ShmRingError::BufferCountMismatch(
+                src.len(),
+                self.layout.channels,
+            ));
+        }
+        if src.iter().any(|buffer| buffer.len() < info.elements_read) {
+            return Err(ShmRingError::BufferSizeMismatch);
+        }
+
+        let mut done = 0;
+        while done < info.elements_read {
+            let elements = (info.elements_read - done).min(self.layout.block_size);
+            let timestamp_ns = info.timestamp_ns + self.duration_ns(done);
+            let block = self.begin_block();
+            for (channel, buffer) in src.iter().enumerate() {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        buffer[done..].as_ptr(),
+                        self.data_ptr(block, channel),
+                        elements,
+                    );
+                }
+            }
+            self.finish_block(block, elements, timestamp_ns);
+            done += elements;
+        }
+        Ok(())
+    }
+
+    /// Read one block from `stream` straight into the ring and publish it.
+    ///
+    /// The stream must have as many channels as the ring. A read that stops part way, e.g. on a timeout,
+    /// publishes the samples it got. A read that fails publishes nothing, and the block that was in the
+    /// slot is lost to readers that had not got to it yet.
+    pub fn publish_from(
+        &mut self,
+        stream: &RxStream<T>,
+        timeout: Duration,
+    ) -> Result<StreamReadInfo, ShmRingError> {
+        if stream.channel_count() != self.layout.channels {
+            return Err(ShmRingError::BufferCountMismatch(
+                self.layout.channels,
+                stream.channel_count(),
+            ));
+        }
+
+        let block = self.begin_block();
+        let result = {
+            // the slot is marked as being written, readers do not trust what they copy from it now
+            let mut buffers: Vec<&mut [T]> = (0..self.layout.channels)
+                .map(|channel| unsafe {
+                    std::slice::from_raw_parts_mut(
+                        self.data_ptr(block, channel),
+                        self.layout.block_size,
+                    )
+                })
+                .collect();
+            stream.read(&mut buffers, timeout)
+        };
+        match result {
+            Ok(info) if info.elements_read > 0 => {
+                self.finish_block(block, info.elements_read, info.timestamp_ns);
+                Ok(info)
+            }
+            result => {
+                self.abandon_block(block);
+                Ok(result?)
+            }
+        }
+    }
+
+    fn header(&self) -> &RingHeader {
+        unsafe { &*(self.map.as_ptr() as *const RingHeader) }
+    }
+
+    fn slot(&self, block: u64) -> &SlotHeader {
+        unsafe { &*(self.map.as_ptr().add(self.layout.slot_offset(block)) as *const SlotHeader) }
+    }
+
+    fn data_ptr(&self, block: u64, channel: usize) -> *mut T {
+        unsafe {
+            self.map
+                .as_ptr()
+                .add(self.layout.data_offset(block, channel)) as *mut T
+        }
+    }
+
+    fn duration_ns(&self, elements: usize) -> u64 {
+        (elements as f64 * 1e9 / self.sample_rate) as u64
+    }
+
+    /// Mark the slot of the next block as being written and return the block number.
+    fn begin_block(&self) -> u64 {
+        let block = self.header().published.load(Ordering::Relaxed);
+        self.slot(block)
+            .sequence
+            .store(2 * block + 1, Ordering::Relaxed);
+        // readers must not see new samples before the mark
+        fence(Ordering::Release);
+        block
+    }
+
+    fn finish_block(&self, block: u64, elements: usize, timestamp_ns: u64) {
+        let slot = self.slot(block);
+        slot.elements.store(elements as u64, Ordering::Relaxed);
+        slot.timestamp_ns.store(timestamp_ns, Ordering::Relaxed);
+        slot.sequence.store(2 * block + 2, Ordering::Release);
+        self.header().published.store(block + 1, Ordering::Release);
+    }
+
+    /// Give up on a block begun but not finished. What was in the slot may be partly
+    /// overwritten already, so it does not hold any block any more.
+    fn abandon_block(&self, block: u64) {
+        self.slot(block).sequence.store(0, Ordering::Release);
+    }
+}
+
+impl<T> Drop for ShmRingPublisher<T> {
+    fn drop(&mut self) {
+        let header = unsafe { &*(self.map.as_ptr() as *const RingHeader) };
+        header.closed.store(1, Ordering::Release);
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Reads the blocks of a ring some `ShmRingPublisher` writes, possibly in another process.
+///
+/// Starts with the next block published after attaching. A reader that falls more than the
+/// ring's slot count behind skips ahead to the oldest block still there, see `blocks_lost`.
+pub struct ShmRingReader<T> {
+    _p: PhantomData<T>,
+    map: Mmap,
+    layout: Layout,
+    sample_rate: f64,
+    next_block: u64,
+    // samples of `next_block` already handed out
+    offset: usize,
+    blocks_lost: u64,
+    poll_interval: Duration,
+}
+
+impl<T: StreamDataFormat> ShmRingReader<T> {
+    pub fn attach(name: &str) -> Result<Self, ShmRingError> {
+        let path = checked_path(name)?;
+        let file = File::open(&path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_SIZE {
+            return Err(ShmRingError::NotARing(path));
+        }
+
+        let header = unsafe { &*(map.as_ptr() as *const RingHeader) };
+        if header.magic.load(Ordering::Acquire) != MAGIC
+            || header.version != VERSION
+            || header.slot_count == 0
+        {
+            return Err(ShmRingError::NotARing(path));
+        }
+        if header.format != T::api_format().0 || header.element_size as usize != size_of::<T>() {
+            return Err(ShmRingError::FormatMismatch {
+                expected: T::api_format().0,
+                expected_size: size_of::<T>(),
+                found: header.format,
+                found_size: header.element_size as usize,
+            });
+        }
+        let layout = Layout {
+            channels: header.channels as usize,
+            slot_count: header.slot_count as usize,
+            block_size: header.block_size as usize,
+            element_size: size_of::<T>(),
+        };
+        if map.len() < layout.total_bytes() {
+            return Err(ShmRingError::NotARing(path));
+        }
+        let sample_rate = header.sample_rate;
+        let next_block = header.published.load(Ordering::Acquire);
+
+        // a few looks per block, without spinning on small blocks
+        let block_duration = Duration::try_from_secs_f64(layout.block_size as f64 / sample_rate)
+            .unwrap_or(Duration::ZERO);
+        let poll_interval =
+            (block_duration / 4).clamp(Duration::from_micros(50), Duration::from_millis(10));
+
+        Ok(Self {
+            _p: PhantomData,
+            map,
+            layout,
+            sample_rate,
+            next_block,
+            offset: 0,
+            blocks_lost: 0,
+            poll_interval,
+        })
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.layout.channels
+    }
+
+    /// Samples per channel one block holds at most, a good size for read buffers.
+    pub fn block_size(&self) -> usize {
+        self.layout.block_size
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.layout.slot_count
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Blocks this reader missed so far because the publisher overwrote them before they were read.
+    pub fn blocks_lost(&self) -> u64 {
+        self.blocks_lost
+    }
+
+    /// Blocks published but not read yet.
+    pub fn backlog(&self) -> u64 {
+        self.header()
+            .published
+            .load(Ordering::Acquire)
+            .saturating_sub(self.next_block)
+    }
+
+    /// Drop the backlog and continue with the next block published.
+    pub fn skip_to_newest(&mut self) {
+        self.next_block = self.header().published.load(Ordering::Acquire);
+        self.offset = 0;
+    }
+
+    /// Read the same number of samples for every channel of the ring, waiting up to `timeout` for them.
+    ///
+    /// Returns at most the rest of one block. A jump in `timestamp_ns` goes along with lost blocks.
+    pub fn read(
+        &mut self,
+        dst: &mut [&mut [T]],
+        timeout: Duration,
+    ) -> Result<StreamReadInfo, ShmRingError> {
+        if dst.len() != self.layout.channels {
+            return Err(ShmRingError::BufferCountMismatch(
+                dst.len(),
+                self.layout.channels,
+            ));
+        }
+        if dst.iter().any(|buffer| buffer.len() != dst[0].len()) {
+            return Err(ShmRingError::BufferSizeMismatch);
+        }
+
+        let started = Instant::now();
+        loop {
+            let published = self.header().published.load(Ordering::Acquire);
+            // once the block a slot further on is out, ours is gone
+            if self.next_block + (self.layout.slot_count as u64) < published {
+                let oldest = published - self.layout.slot_count as u64;
+                self.lose_blocks(oldest - self.next_block);
+            }
+            if self.next_block < published {
+                if let Some(info) = self.copy_block(dst) {
+                    return Ok(info);
+                }
+                // overwritten while we looked, the lap check above sorts out where to continue
+                self.lose_blocks(1);
+                continue;
+            }
+
+            if self.header().closed.load(Ordering::Acquire) != 0 {
+                return Err(ShmRingError::Closed);
+            }
+            let elapsed = started.elapsed();
+            if elapsed >= timeout {
+                return Err(ShmRingError::Timeout);
+            }
+            std::thread::sleep(self.poll_interval.min(timeout - elapsed));
+        }
+    }
+
+    fn header(&self) -> &RingHeader {
+        unsafe { &*(self.map.as_ptr() as *const RingHeader) }
+    }
+
+    fn lose_blocks(&mut self, count: u64) {
+        self.blocks_lost += count;
+        self.next_block += count;
+        self.offset = 0;
+    }
+
+    /// Copy from `next_block`, `None` if the publisher got to its slot first.
+    fn copy_block(&mut self, dst: &mut [&mut [T]]) -> Option<StreamReadInfo> {
+        let block = self.next_block;
+        let slot = unsafe {
+            &*(self.map.as_ptr().add(self.layout.slot_offset(block)) as *const SlotHeader)
+        };
+        let sequence = slot.sequence.load(Ordering::Acquire);
+        if sequence != 2 * block + 2 {
+            return None;
+        }
+        let elements = slot.elements.load(Ordering::Relaxed) as usize;
+        let timestamp_ns = slot.timestamp_ns.load(Ordering::Relaxed);
+        let count = dst[0].len().min(elements.saturating_sub(self.offset));
+        for (channel, buffer) in dst.iter_mut().enumerate() {
+            unsafe {
+                let src = self
+                    .map
+                    .as_ptr()
+                    .add(self.layout.data_offset(block, channel))
+                    as *const T;
+                std::ptr::copy_nonoverlapping(src.add(self.offset), buffer.as_mut_ptr(), count);
+            }
+        }
+        // the copy may have raced the publisher, only the unchanged sequence says it did not
+        fence(Ordering::Acquire);
+        if slot.sequence.load(Ordering::Relaxed) != sequence {
+            return None;
+        }
+
+        let info = StreamReadInfo {
+            elements_read: count,
+            timestamp_ns: timestamp_ns + (self.offset as f64 * 1e9 / self.sample_rate) as u64,
+            error: None,
+        };
+        self.offset += count;
+        if self.offset >= elements {
+            self.next_block += 1;
+            self.offset = 0;
+        }
+        Some(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_complex::Complex;
+    use std::io::{Seek, SeekFrom, Write};
+
+    type Sample = Complex<i16>;
+
+    const RATE: f64 = 1e6;
+    const NO_WAIT: Duration = Duration::ZERO;
+
+    fn ring(name: &str, slot_count: usize, block_size: usize) -> ShmRingPublisher<Sample> {
+        let name = format!("rfnm-test-{}-{name}", std::process::id());
+        ShmRingPublisher::create(&name, 1, RATE, block_size, &ShmRingSettings { slot_count })
+            .unwrap()
+    }
+
+    fn attach(publisher: &ShmRingPublisher<Sample>) -> ShmRingReader<Sample> {
+        let name = publisher.path().file_name().unwrap().to_str().unwrap();
+        ShmRingReader::attach(name).unwrap()
+    }
+
+    /// Publish a block whose samples are all `value`, at `value` ms.
+    fn publish(publisher: &mut ShmRingPublisher<Sample>, value: i16, elements: usize) {
+        let block = vec![Complex::new(value, -value); elements];
+        let info = StreamReadInfo {
+            elements_read: elements,
+            timestamp_ns: value as u64 * 1_000_000,
+            error: None,
+        };
+        publisher.publish(&[&block], &info).unwrap();
+    }
+
+    fn read(
+        reader: &mut ShmRingReader<Sample>,
+        len: usize,
+    ) -> Result<(Vec<Sample>, u64), ShmRingError> {
+        let mut buffer = vec![Complex::new(0, 0); len];
+        let info = reader.read(&mut [&mut buffer], NO_WAIT)?;
+        buffer.truncate(info.elements_read);
+        Ok((buffer, info.timestamp_ns))
+    }
+
+    #[test]
+    fn blocks_arrive_in_order() {
+        let mut publisher = ring("order", 4, 100);
+        let mut reader = attach(&publisher);
+        assert!(matches!(read(&mut reader, 100), Err(ShmRingError::Timeout)));
+
+        publish(&mut publisher, 1, 100);
+        publish(&mut publisher, 2, 50);
+        assert_eq!(reader.backlog(), 2);
+        assert_eq!(
+            read(&mut reader, 100).unwrap(),
+            (vec![Complex::new(1, -1); 100], 1_000_000)
+        );
+        assert_eq!(
+            read(&mut reader, 100).unwrap(),
+            (vec![Complex::new(2, -2); 50], 2_000_000)
+        );
+        assert_eq!(reader.blocks_lost(), 0);
+    }
+
+    #[test]
+    fn partial_reads_advance_the_timestamp() {
+        let mut publisher = ring("partial", 4, 100);
+        let mut reader = attach(&publisher);
+        publish(&mut publisher, 1, 100);
+        assert_eq!(read(&mut reader, 30).unwrap().1, 1_000_000);
+        // 30 samples at 1 MHz later
+        assert_eq!(
+            read(&mut reader, 100).unwrap(),
+            (vec![Complex::new(1, -1); 70], 1_030_000)
+        );
+    }
+
+    #[test]
+    fn long_blocks_are_split() {
+        let mut publisher = ring("split", 4, 100);
+        let mut reader = attach(&publisher);
+        publish(&mut publisher, 1, 250);
+        assert_eq!(publisher.blocks_published(), 3);
+        let timestamps: Vec<(usize, u64)> = (0..3)
+            .map(|_| {
+                read(&mut reader, 100)
+                    .map(|(samples, ts)| (samples.len(), ts))
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            timestamps,
+            [(100, 1_000_000), (100, 1_100_000), (50, 1_200_000)]
+        );
+    }
+
+    #[test]
+    fn a_lapped_reader_skips_to_the_oldest_block() {
+        let mut publisher = ring("lap", 4, 10);
+        let mut reader = attach(&publisher);
+        for value in 1..=7 {
+            publish(&mut publisher, value, 10);
+        }
+        // blocks 1 to 3 were overwritten by 5 to 7
+        assert_eq!(read(&mut reader, 10).unwrap().0[0], Complex::new(4, -4));
+        assert_eq!(reader.blocks_lost(), 3);
+        for value in 5..=7 {
+            assert_eq!(
+                read(&mut reader, 10).unwrap().0[0],
+                Complex::new(value, -value)
+            );
+        }
+        assert_eq!(reader.blocks_lost(), 3);
+    }
+
+    #[test]
+    fn a_lapped_reader_drops_the_rest_of_its_block() {
+        let mut publisher = ring("lap-partial", 2, 10);
+        let mut reader = attach(&publisher);
+        publish(&mut publisher, 1, 10);
+        read(&mut reader, 4).unwrap();
+        for value in 2..=4 {
+            publish(&mut publisher, value, 10);
+        }
+        // the rest of block 1 and block 2 are gone, block 3 is read from its start
+        assert_eq!(
+            read(&mut reader, 10).unwrap(),
+            (vec![Complex::new(3, -3); 10], 3_000_000)
+        );
+        assert_eq!(reader.blocks_lost(), 2);
+    }
+
+    #[test]
+    fn an_abandoned_slot_holds_no_block() {
+        let mut publisher = ring("abandon", 2, 10);
+        let mut reader = attach(&publisher);
+        publish(&mut publisher, 1, 10);
+        publish(&mut publisher, 2, 10);
+
+        // a failed read started to write over block 1
+        let block = publisher.begin_block();
+        publisher.abandon_block(block);
+        assert_eq!(read(&mut reader, 10).unwrap().0[0], Complex::new(2, -2));
+        assert_eq!(reader.blocks_lost(), 1);
+
+        // the block number is used again
+        publish(&mut publisher, 3, 10);
+        assert_eq!(read(&mut reader, 10).unwrap().0[0], Complex::new(3, -3));
+    }
+
+    #[test]
+    fn readers_see_the_close_after_the_last_block() {
+        let mut publisher = ring("close", 4, 10);
+        let path = publisher.path().to_path_buf();
+        let mut reader = attach(&publisher);
+        publish(&mut publisher, 1, 10);
+        drop(publisher);
+        assert!(!path.exists());
+        assert!(read(&mut reader, 10).is_ok());
+        assert!(matches!(read(&mut reader, 10), Err(ShmRingError::Closed)));
+    }
+
+    #[test]
+    fn attach_checks_the_ring() {
+        let publisher = ring("check", 4, 10);
+        let name = publisher
+            .path()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(matches!(
+            ShmRingReader::<Complex<f32>>::attach(&name),
+            Err(ShmRingError::FormatMismatch { .. })
+        ));
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(publisher.path())
+            .unwrap();
+        file.seek(SeekFrom::Start(
+            std::mem::offset_of!(RingHeader, slot_count) as u64,
+        ))
+        .unwrap();
+        file.write_all(&0u32.to_ne_bytes()).unwrap();
+        assert!(matches!(
+            ShmRingReader::<Sample>::attach(&name),
+            Err(ShmRingError::NotARing(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_layouts_are_refused() {
+        let settings = ShmRingSettings { slot_count: 0 };
+        assert!(matches!(
+            ShmRingPublisher::<Sample>::create("rfnm-test-invalid", 1, RATE, 10, &settings),
+            Err(ShmRingError::InvalidLayout)
+        ));
+        assert!(matches!(
+            ShmRingPublisher::<Sample>::create("a/b", 1, RATE, 10, &ShmRingSettings::default()),
+            Err(ShmRingError::InvalidName(_))
+        ));
+    }
+}
@@ -0,0 +1,1116 @@
+//! A SpyServer compatible server, for SDR# and SDR++.
+//!
+//! `SpyServer` drives the first channel of a `RxStream` and serves it to any number of SpyServer clients.
+//! Every client gets its own decimation and FFT, computed on the host, and may pick its own frequency
+//! within the band the board currently receives.
+//!
+//! The first client to say hello controls the board: its frequency and gain commands become the
+//! `RxChannelSettings` of the channel, so the band follows it. Everybody else shares that band read only.
+//! When the controlling client leaves, the one connected the longest takes over.
+//!
+//! The protocol carries frequencies as 32 bit Hz, so clients cannot tune above 4.29 GHz.
+//! Clients only know Airspy and RTL-SDR hardware, the board shows up as an Airspy One.
+
+use crate::RfnmApiError;
+use crate::ddc::{Ddc, DdcError, DdcSettings};
+use crate::power::PsdAccumulator;
+use crate::stream::{ComplexSample, RxStream};
+use num_complex::Complex;
+use rfnm_sys::rfnm_channel;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+// see `spyserver_protocol.h` of the SpyServer distribution
+const PROTOCOL_VERSION: u32 = (2 << 24) | 1700;
+const MAX_COMMAND_BODY_SIZE: u32 = 256;
+const MAX_MESSAGE_BODY_SIZE: usize = 1 << 20;
+const MIN_DISPLAY_PIXELS: u32 = 100;
+const MAX_DISPLAY_PIXELS: u32 = 1 << 15;
+const MIN_FFT_DB_RANGE: u32 = 10;
+const MAX_FFT_DB_RANGE: u32 = 150;
+
+const DEVICE_AIRSPY_ONE: u32 = 1;
+const ADC_RESOLUTION: u32 = 12;
+
+const CMD_HELLO: u32 = 0;
+const CMD_SET_SETTING: u32 = 2;
+const CMD_PING: u32 = 3;
+
+const SETTING_STREAMING_MODE: u32 = 0;
+const SETTING_STREAMING_ENABLED: u32 = 1;
+const SETTING_GAIN: u32 = 2;
+const SETTING_IQ_FORMAT: u32 = 100;
+const SETTING_IQ_FREQUENCY: u32 = 101;
+const SETTING_IQ_DECIMATION: u32 = 102;
+const SETTING_FFT_FORMAT: u32 = 200;
+const SETTING_FFT_FREQUENCY: u32 = 201;
+const SETTING_FFT_DECIMATION: u32 = 202;
+const SETTING_FFT_DB_OFFSET: u32 = 203;
+const SETTING_FFT_DB_RANGE: u32 = 204;
+const SETTING_FFT_DISPLAY_PIXELS: u32 = 205;
+
+const STREAM_TYPE_STATUS: u32 = 0;
+const STREAM_TYPE_IQ: u32 = 1;
+const STREAM_TYPE_FFT: u32 = 4;
+
+const FORMAT_UINT8: u32 = 1;
+const FORMAT_INT16: u32 = 2;
+const FORMAT_FLOAT: u32 = 4;
+
+const MSG_DEVICE_INFO: u32 = 0;
+const MSG_CLIENT_SYNC: u32 = 1;
+const MSG_PONG: u32 = 2;
+const MSG_UINT8_IQ: u32 = 100;
+const MSG_INT16_IQ: u32 = 101;
+const MSG_FLOAT_IQ: u32 = 103;
+const MSG_UINT8_FFT: u32 = 301;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const IDLE_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Error)]
+pub enum SpyServerError {
+    #[error("Device error: {0}")]
+    Api(#[from] RfnmApiError),
+    #[error("Network error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Cannot decimate that far: {0}")]
+    Ddc(#[from] DdcError),
+}
+
+/// How to serve.
+#[derive(Debug, Clone)]
+pub struct SpyServerSettings {
+    /// Address to listen on, `0.0.0.0:5555` by default. 5555 is the port clients try by default.
+    pub address: String,
+    /// Connections beyond this many are closed right away, 8 by default.
+    pub max_clients: usize,
+    /// Let the first client retune the board and change its gain, on by default. Without it, every client is
+    /// read only.
+    pub allow_control: bool,
+    /// Clients can decimate by up to `2^decimation_stages`, 10 by default.
+    pub decimation_stages: u32,
+    /// Messages queued per client before new ones are dropped for it, so a slow client cannot hold up the others.
+    /// 64 by default.
+    pub client_queue_len: usize,
+    /// Most FFT frames sent to one client per second, 15 by default.
+    pub fft_frame_rate: f64,
+}
+
+impl Default for SpyServerSettings {
+    fn default() -> Self {
+        Self {
+            address: "0.0.0.0:5555".to_string(),
+            max_clients: 8,
+            allow_control: true,
+            decimation_stages: 10,
+            client_queue_len: 64,
+            fft_frame_rate: 15.0,
+        }
+    }
+}
+
+/// Ends `SpyServer::run` from another thread.
+#[derive(Debug, Clone)]
+pub struct SpyServerStopHandle(Arc<AtomicBool>);
+
+impl SpyServerStopHandle {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// What the board currently receives.
+#[derive(Debug, Clone, Copy)]
+struct Band {
+    center: i64,
+    gain: i8,
+    sample_rate: f64,
+    freq_min: i64,
+    freq_max: i64,
+    gain_min: i8,
+    gain_max: i8,
+}
+
+impl Band {
+    /// Center frequencies a stream of `rate` can have without leaving the band.
+    fn range_for(&self, rate: f64) -> (i64, i64) {
+        let half = ((self.sample_rate - rate) / 2.0).max(0.0) as i64;
+        (self.center - half, self.center + half)
+    }
+}
+
+enum Command {
+    Hello { name: String },
+    SetSetting { setting: u32, value: u32 },
+    Ping,
+    Other,
+}
+
+enum Event {
+    Command(u64, Command),
+    Gone(u64),
+}
+
+struct Client {
+    id: u64,
+    name: String,
+    peer: SocketAddr,
+    socket: TcpStream,
+    outgoing: SyncSender<Vec<u8>>,
+    connected: bool,
+    greeted: bool,
+    streaming_mode: u32,
+    streaming_enabled: bool,
+
+    iq_format: u32,
+    iq_frequency: i64,
+    iq_decimation: u32,
+    iq_sequence: u32,
+    iq_ddc: Option<Ddc>,
+    iq: Vec<Complex<f32>>,
+    body: Vec<u8>,
+
+    fft_frequency: i64,
+    fft_decimation: u32,
+    fft_db_offset: i32,
+    fft_db_range: u32,
+    fft_pixels: u32,
+    fft_sequence: u32,
+    fft_ddc: Option<Ddc>,
+    fft: Option<PsdAccumulator>,
+    fft_pending: Vec<Complex<f32>>,
+    last_fft: Option<Instant>,
+}
+
+impl Client {
+    fn new(
+        id: u64,
+        peer: SocketAddr,
+        socket: TcpStream,
+        outgoing: SyncSender<Vec<u8>>,
+        band: &Band,
+    ) -> Self {
+        Self {
+            id,
+            name: String::new(),
+            peer,
+            socket,
+            outgoing,
+            connected: true,
+            greeted: false,
+            streaming_mode: STREAM_TYPE_IQ,
+            streaming_enabled: false,
+            iq_format: FORMAT_INT16,
+            iq_frequency: band.center,
+            iq_decimation: 0,
+            iq_sequence: 0,
+            iq_ddc: None,
+            iq: Vec::new(),
+            body: Vec::new(),
+            fft_frequency: band.center,
+            fft_decimation: 0,
+            fft_db_offset: 0,
+            fft_db_range: 127,
+            fft_pixels: 1024,
+            fft_sequence: 0,
+            fft_ddc: None,
+            fft: None,
+            fft_pending: Vec::new(),
+            last_fft: None,
+        }
+    }
+
+    fn wants(&self, stream_type: u32) -> bool {
+        self.greeted && self.streaming_enabled && self.streaming_mode & stream_type != 0
+    }
+
+    /// Queue a message, dropping it if the client is too far behind.
+    fn send(&mut self, message: Vec<u8>) {
+        if let Err(TrySendError::Disconnected(_)) = self.outgoing.try_send(message) {
+            self.connected = false;
+        }
+    }
+
+    /// Move the client's own frequencies back into the band and start the DDCs over.
+    fn fit_into(&mut self, band: &Band) {
+        let (min, max) = band.range_for(decimated(band.sample_rate, self.iq_decimation));
+        self.iq_frequency = self.iq_frequency.clamp(min, max);
+        let (min, max) = band.range_for(decimated(band.sample_rate, self.fft_decimation));
+        self.fft_frequency = self.fft_frequency.clamp(min, max);
+        self.iq_ddc = None;
+        self.fft_ddc = None;
+    }
+
+    fn forward_iq<T: ComplexSample>(&mut self, samples: &[T], band: &Band) {
+        self.iq.clear();
+        downconvert(
+            &mut self.iq_ddc,
+            self.iq_frequency,
+            self.iq_decimation,
+            band,
+            samples,
+            &mut self.iq,
+        );
+        let (message_type, bytes_per_sample) = match self.iq_format {
+            FORMAT_UINT8 => (MSG_UINT8_IQ, 2),
+            FORMAT_FLOAT => (MSG_FLOAT_IQ, 8),
+            _ => (MSG_INT16_IQ, 4),
+        };
+        let mut iq = std::mem::take(&mut self.iq);
+        for chunk in iq.chunks(MAX_MESSAGE_BODY_SIZE / bytes_per_sample) {
+            self.body.clear();
+            encode_iq(self.iq_format, chunk, &mut self.body);
+            let message = message(message_type, STREAM_TYPE_IQ, self.iq_sequence, &self.body);
+            self.iq_sequence = self.iq_sequence.wrapping_add(1);
+            self.send(message);
+        }
+        iq.clear();
+        self.iq = iq;
+    }
+
+    fn forward_fft<T: ComplexSample>(
+        &mut self,
+        samples: &[T],
+        band: &Band,
+        frame_interval: Duration,
+    ) {
+        if self
+            .last_fft
+            .is_some_and(|last| last.elapsed() < frame_interval)
+        {
+            return;
+        }
+        downconvert(
+            &mut self.fft_ddc,
+            self.fft_frequency,
+            self.fft_decimation,
+            band,
+            samples,
+            &mut self.fft_pending,
+        );
+        let pixels = self.fft_pixels as usize;
+        if self.fft_pending.len() < pixels {
+            return;
+        }
+        let fft = self.fft.get_or_insert_with(|| PsdAccumulator::new(pixels));
+        fft.reset();
+        fft.push(&self.fft_pending[..pixels]);
+        // 0 is `db_offset - db_range`, 255 is `db_offset`
+        let floor = (self.fft_db_offset - self.fft_db_range as i32) as f64;
+        let range = self.fft_db_range as f64;
+        let body: Vec<u8> = fft
+            .power_db()
+            .iter()
+            .map(|db| ((db - floor) / range * 255.0).clamp(0.0, 255.0) as u8)
+            .collect();
+        let message = message(MSG_UINT8_FFT, STREAM_TYPE_FFT, self.fft_sequence, &body);
+        self.fft_sequence = self.fft_sequence.wrapping_add(1);
+        self.send(message);
+        self.fft_pending.clear();
+        self.last_fft = Some(Instant::now());
+    }
+}
+
+/// Serves one channel of a `RxStream` to SpyServer clients.
+pub struct SpyServer<T> {
+    stream: RxStream<T>,
+    channel: rfnm_channel,
+    settings: SpyServerSettings,
+    listener: TcpListener,
+    events_tx: Sender<Event>,
+    events: Receiver<Event>,
+    clients: Vec<Client>,
+    controller: Option<u64>,
+    next_id: u64,
+    band: Band,
+    serial: u32,
+    running: bool,
+    stop: Arc<AtomicBool>,
+    buffers: Vec<Vec<T>>,
+}
+
+impl<T: ComplexSample> SpyServer<T> {
+    /// Start listening for clients of the first channel of `stream`. Nothing is served before `run`.
+    pub fn bind(stream: RxStream<T>, settings: SpyServerSettings) -> Result<Self, SpyServerError> {
+        let channel = stream.channels()[0];
+        let info = stream.device().get_rx_settings(channel)?;
+        let current = info.to_settings();
+        let band = Band {
+            center: current.frequency,
+            gain: current.gain,
+            sample_rate: stream.sample_rate(),
+            freq_min: *info.freq_range().start(),
+            freq_max: *info.freq_range().end(),
+            gain_min: *info.gain_range().start(),
+            gain_max: *info.gain_range().end(),
+        };
+        // the largest decimation works if any does, the CIC only gets smaller below it
+        Ddc::new(
+            DdcSettings::new(0.0, max_decimation(settings.decimation_stages)),
+            band.sample_rate,
+            0.0,
+        )?;
+        let serial = stream.device().hwinfo().motherboard.serial_string();
+        let listener = TcpListener::bind(&settings.address)?;
+        // clients are accepted between reads
+        listener.set_nonblocking(true)?;
+        let (events_tx, events) = mpsc::channel();
+        let zero = T::from_complex_f32(Complex::new(0.0, 0.0));
+        let buffers = vec![vec![zero; stream.suggested_buffer_size()]; stream.channel_count()];
+        Ok(Self {
+            stream,
+            channel,
+            settings,
+            listener,
+            events_tx,
+            events,
+            clients: Vec::new(),
+            controller: None,
+            next_id: 0,
+            band,
+            serial: u32::from_str_radix(&serial, 16).unwrap_or(0),
+            running: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            buffers,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn stop_handle(&self) -> SpyServerStopHandle {
+        SpyServerStopHandle(self.stop.clone())
+    }
+
+    /// Take back a stop, so the next `run` serves again.
+    pub fn reset_stop(&self) {
+        self.stop.store(false, Ordering::Relaxed);
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Name and address of every client, the controlling one first if there is one.
+    pub fn clients(&self) -> Vec<(String, SocketAddr)> {
+        let mut clients: Vec<&Client> = self.clients.iter().collect();
+        clients.sort_by_key(|c| Some(c.id) != self.controller);
+        clients.iter().map(|c| (c.name.clone(), c.peer)).collect()
+    }
+
+    pub fn stream(&self) -> &RxStream<T> {
+        &self.stream
+    }
+
+    pub fn into_stream(self) -> RxStream<T> {
+        self.stream
+    }
+
+    /// Serve until stopped through a `SpyServerStopHandle` or the board fails.
+    ///
+    /// The stream only runs while at least one client has streaming enabled. A stop that came before the call
+    /// ends it right away, and stays in place afterwards until `reset_stop`.
+    pub fn run(&mut self) -> Result<(), SpyServerError> {
+        let result = self.serve();
+        for client in &self.clients {
+            // ends the client's reader thread, the writer follows once its queue is gone
+            let _ = client.socket.shutdown(Shutdown::Both);
+        }
+        self.clients.clear();
+        self.controller = None;
+        if self.running {
+            self.running = false;
+            self.stream.stop()?;
+        }
+        result
+    }
+
+    fn serve(&mut self) -> Result<(), SpyServerError> {
+        while !self.stop.load(Ordering::Relaxed) {
+            self.accept_clients()?;
+            self.handle_events()?;
+            let wanted = self
+                .clients
+                .iter()
+                .any(|c| c.wants(STREAM_TYPE_IQ | STREAM_TYPE_FFT));
+            if wanted && !self.running {
+                self.stream.start()?;
+                self.running = true;
+            } else if !wanted && self.running {
+                self.stream.stop()?;
+                self.running = false;
+            }
+            if self.running {
+                self.forward_samples()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn accept_clients(&mut self) -> Result<(), SpyServerError> {
+        loop {
+            let (socket, peer) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            if self.clients.len() >= self.settings.max_clients {
+                continue;
+            }
+            socket.set_nonblocking(false)?;
+            socket.set_nodelay(true)?;
+            let id = self.next_id;
+            self.next_id += 1;
+
+            let reader = socket.try_clone()?;
+            let events = self.events_tx.clone();
+            thread::spawn(move || read_commands(id, reader, events));
+            let writer = socket.try_clone()?;
+            let (outgoing, queue) = mpsc::sync_channel(self.settings.client_queue_len);
+            thread::spawn(move || write_messages(writer, queue));
+
+            self.clients
+                .push(Client::new(id, peer, socket, outgoing, &self.band));
+        }
+    }
+
+    fn handle_events(&mut self) -> Result<(), SpyServerError> {
+        // nothing else to do while idle, so wait for the clients here
+        if !self.running {
+            match self.events.recv_timeout(IDLE_POLL) {
+                Ok(event) => self.handle_event(event)?,
+                Err(_) => return Ok(()),
+            }
+        }
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => self.handle_event(event)?,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) -> Result<(), SpyServerError> {
+        match event {
+            Event::Gone(id) => self.remove_client(id),
+            Event::Command(id, Command::Hello { name }) => {
+                let Some(client) = self.clients.iter_mut().find(|c| c.id == id) else {
+                    return Ok(());
+                };
+                client.name = name;
+                client.greeted = true;
+                if self.settings.allow_control && self.controller.is_none() {
+                    self.controller = Some(id);
+                }
+                let device_info = self.device_info();
+                self.send_to(id, device_info);
+                self.sync(id);
+            }
+            Event::Command(id, Command::Ping) => {
+                self.send_to(id, message(MSG_PONG, STREAM_TYPE_STATUS, 0, &[]));
+            }
+            Event::Command(id, Command::SetSetting { setting, value }) => {
+                self.set_setting(id, setting, value)?;
+            }
+            Event::Command(_, Command::Other) => {}
+        }
+        Ok(())
+    }
+
+    fn set_setting(&mut self, id: u64, setting: u32, value: u32) -> Result<(), SpyServerError> {
+        let can_control = self.controller == Some(id);
+        let band = self.band;
+        let stages = self.settings.decimation_stages;
+        let Some(client) = self.clients.iter_mut().find(|c| c.id == id) else {
+            return Ok(());
+        };
+        match setting {
+            SETTING_STREAMING_MODE => client.streaming_mode = value,
+            SETTING_STREAMING_ENABLED => client.streaming_enabled = value != 0,
+            SETTING_GAIN if can_control => {
+                let gain = (band.gain_min as i64 + value as i64).min(band.gain_max as i64);
+                self.retune(None, Some(gain as i8))?;
+            }
+            SETTING_IQ_FORMAT if matches!(value, FORMAT_UINT8 | FORMAT_INT16 | FORMAT_FLOAT) => {
+                client.iq_format = value;
+            }
+            SETTING_IQ_FREQUENCY if can_control => {
+                client.iq_frequency = value as i64;
+                self.retune(Some(value as i64), None)?;
+            }
+            SETTING_IQ_FREQUENCY => {
+                client.iq_frequency = value as i64;
+                client.fit_into(&band);
+                self.sync(id);
+            }
+            SETTING_IQ_DECIMATION => {
+                client.iq_decimation = value.min(stages);
+                client.fit_into(&band);
+                self.sync(id);
+            }
+            SETTING_FFT_FORMAT => {
+                // only 8 bit FFTs are supported, the sync tells the client nothing about it
+            }
+            SETTING_FFT_FREQUENCY => {
+                client.fft_frequency = value as i64;
+                client.fit_into(&band);
+                self.sync(id);
+            }
+            SETTING_FFT_DECIMATION => {
+                client.fft_decimation = value.min(stages);
+                client.fit_into(&band);
+                self.sync(id);
+            }
+            SETTING_FFT_DB_OFFSET => client.fft_db_offset = value as i32,
+            SETTING_FFT_DB_RANGE => {
+                client.fft_db_range = value.clamp(MIN_FFT_DB_RANGE, MAX_FFT_DB_RANGE);
+            }
+            SETTING_FFT_DISPLAY_PIXELS => {
+                client.fft_pixels = value.clamp(MIN_DISPLAY_PIXELS, MAX_DISPLAY_PIXELS);
+                client.fft = None;
+                client.fft_pending.clear();
+            }
+            // digital gain, and control commands of read only clients
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Apply a new frequency or gain to the board and tell every client about it.
+    ///
+    /// Settings the board refuses are skipped, the clients then see the unchanged state in the sync.
+    fn retune(&mut self, frequency: Option<i64>, gain: Option<i8>) -> Result<(), SpyServerError> {
+        let device = self.stream.device();
+        let mut settings = device.get_rx_settings(self.channel)?.to_settings();
+        if let Some(frequency) = frequency {
+            settings.frequency = frequency.clamp(self.band.freq_min, self.band.freq_max);
+        }
+        if let Some(gain) = gain {
+            settings.gain = gain;
+        }
+        match device.set_rx_settings(self.channel, &settings) {
+            Ok(()) => {}
+            Err(e) if e.is_transport_error() => return Err(e.into()),
+            Err(_) => {}
+        }
+        let current = device.get_rx_settings(self.channel)?.to_settings();
+        self.band.center = current.frequency;
+        self.band.gain = current.gain;
+
+        let ids: Vec<u64> = self.clients.iter().map(|c| c.id).collect();
+        for id in ids {
+            if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+                client.fit_into(&self.band);
+            }
+            self.sync(id);
+        }
+        Ok(())
+    }
+
+    fn forward_samples(&mut self) -> Result<(), SpyServerError> {
+        let mut dst: Vec<&mut [T]> = self.buffers.iter_mut().map(|b| b.as_mut_slice()).collect();
+        let info = match self.stream.read(&mut dst, READ_TIMEOUT) {
+            Ok(info) => info,
+            Err(RfnmApiError::Timeout | RfnmApiError::DqbufNoData) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let samples = &self.buffers[0][..info.elements_read];
+        let frame_interval = Duration::try_from_secs_f64(1.0 / self.settings.fft_frame_rate)
+            .unwrap_or(Duration::MAX);
+        for client in &mut self.clients {
+            if client.wants(STREAM_TYPE_IQ) {
+                client.forward_iq(samples, &self.band);
+            }
+            if client.wants(STREAM_TYPE_FFT) {
+                client.forward_fft(samples, &self.band, frame_interval);
+            }
+        }
+
+        let gone: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|c| !c.connected)
+            .map(|c| c.id)
+            .collect();
+        for id in gone {
+            self.remove_client(id);
+        }
+        Ok(())
+    }
+
+    fn remove_client(&mut self, id: u64) {
+        let Some(index) = self.clients.iter().position(|c| c.id == id) else {
+            return;
+        };
+        let client = self.clients.remove(index);
+        let _ = client.socket.shutdown(Shutdown::Both);
+        if self.controller == Some(id) {
+            // the clients are in connection order
+            self.controller = self.clients.iter().find(|c| c.greeted).map(|c| c.id);
+            if let Some(next) = self.controller {
+                self.sync(next);
+            }
+        }
+    }
+
+    fn send_to(&mut self, id: u64, message: Vec<u8>) {
+        if let Some(client) = self.clients.iter_mut().find(|c| c.id == id) {
+            client.send(message);
+        }
+    }
+
+    fn device_info(&self) -> Vec<u8> {
+        let body = words(&[
+            DEVICE_AIRSPY_ONE,
+            self.serial,
+            self.band.sample_rate as u32,
+            self.band.sample_rate as u32,
+            self.settings.decimation_stages,
+            // there is only one gain, as far as the clients are concerned
+            1,
+            (self.band.gain_max as i32 - self.band.gain_min as i32).max(0) as u32,
+            hz(self.band.freq_min),
+            hz(self.band.freq_max),
+            ADC_RESOLUTION,
+            // minimum decimation, and no format forced
+            0,
+            0,
+        ]);
+        message(MSG_DEVICE_INFO, STREAM_TYPE_STATUS, 0, &body)
+    }
+
+    /// Tell a client the state of the board and what it may tune to.
+    fn sync(&mut self, id: u64) {
+        let band = self.band;
+        let can_control = self.controller == Some(id);
+        let Some(client) = self.clients.iter_mut().find(|c| c.id == id) else {
+            return;
+        };
+        if !client.greeted {
+            return;
+        }
+        let (iq_min, iq_max) = if can_control {
+            (band.freq_min, band.freq_max)
+        } else {
+            band.range_for(decimated(band.sample_rate, client.iq_decimation))
+        };
+        let (fft_min, fft_max) = band.range_for(decimated(band.sample_rate, client.fft_decimation));
+        let body = words(&[
+            can_control as u32,
+            (band.gain as i32 - band.gain_min as i32).max(0) as u32,
+            hz(band.center),
+            hz(client.iq_frequency),
+            hz(client.fft_frequency),
+            hz(iq_min),
+            hz(iq_max),
+            hz(fft_min),
+            hz(fft_max),
+        ]);
+        client.send(message(MSG_CLIENT_SYNC, STREAM_TYPE_STATUS, 0, &body));
+    }
+}
+
+fn max_decimation(stages: u32) -> usize {
+    1usize.checked_shl(stages).unwrap_or(usize::MAX)
+}
+
+fn decimated(sample_rate: f64, stage: u32) -> f64 {
+    sample_rate / (1u64 << stage) as f64
+}
+
+/// Frequencies travel as 32 bit Hz.
+fn hz(frequency: i64) -> u32 {
+    frequency.clamp(0, u32::MAX as i64) as u32
+}
+
+/// Shift `frequency` down to 0 Hz and decimate by `2^stage`, appending to `output`.
+/// The DDC is set up on first use, and skipped entirely when there is nothing to do.
+fn downconvert<T: ComplexSample>(
+    ddc: &mut Option<Ddc>,
+    frequency: i64,
+    stage: u32,
+    band: &Band,
+    samples: &[T],
+    output: &mut Vec<Complex<f32>>,
+) {
+    let offset = (frequency - band.center) as f64;
+    if stage == 0 && offset == 0.0 {
+        output.extend(samples.iter().map(|s| s.to_complex_f32()));
+        return;
+    }
+    ddc.get_or_insert_with(|| {
+        // expect: `SpyServer::bind` tried the largest decimation clients can ask for
+        Ddc::new(
+            DdcSettings::new(offset, 1 << stage),
+            band.sample_rate,
+            band.center as f64,
+        )
+        .expect("checked on bind")
+    })
+    .process(samples, output);
+}
+
+fn encode_iq(format: u32, samples: &[Complex<f32>], body: &mut Vec<u8>) {
+    match format {
+        FORMAT_UINT8 => {
+            for sample in samples {
+                for value in [sample.re, sample.im] {
+                    body.push((value * 127.5 + 127.5).round().clamp(0.0, 255.0) as u8);
+                }
+            }
+        }
+        FORMAT_FLOAT => {
+            for sample in samples {
+                body.extend_from_slice(&sample.re.to_le_bytes());
+                body.extend_from_slice(&sample.im.to_le_bytes());
+            }
+        }
+        _ => {
+            for sample in samples {
+                for value in [sample.re, sample.im] {
+                    // `as` saturates
+                    body.extend_from_slice(
+                        &((value * i16::MAX as f32).round() as i16).to_le_bytes(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+fn message(message_type: u32, stream_type: u32, sequence: u32, body: &[u8]) -> Vec<u8> {
+    let mut out = words(&[
+        PROTOCOL_VERSION,
+        message_type,
+        stream_type,
+        sequence,
+        body.len() as u32,
+    ]);
+    out.extend_from_slice(body);
+    out
+}
+
+fn read_commands(id: u64, mut socket: TcpStream, events: Sender<Event>) {
+    while let Ok(command) = read_command(&mut socket) {
+        if events.send(Event::Command(id, command)).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(Event::Gone(id));
+}
+
+fn read_command(socket: &mut impl Read) -> io::Result<Command> {
+    let mut header = [0u8; 8];
+    socket.read_exact(&mut header)?;
+    let command_type = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let body_size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if body_size > MAX_COMMAND_BODY_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "command body too large",
+        ));
+    }
+    let mut body = vec![0; body_size as usize];
+    socket.read_exact(&mut body)?;
+    let word = |i: usize| {
+        body.get(i * 4..i * 4 + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    Ok(match (command_type, word(0), word(1)) {
+        (CMD_HELLO, Some(_), length) => {
+            // the protocol version, then the name. Some clients put the length of the name in between
+            let name_start = match length {
+                Some(length) if length as usize == body.len() - 8 => 8,
+                _ => 4,
+            };
+            Command::Hello {
+                name: String::from_utf8_lossy(&body[name_start..]).into_owned(),
+            }
+        }
+        (CMD_SET_SETTING, Some(setting), Some(value)) => Command::SetSetting { setting, value },
+        (CMD_PING, _, _) => Command::Ping,
+        _ => Command::Other,
+    })
+}
+
+fn write_messages(mut socket: TcpStream, messages: Receiver<Vec<u8>>) {
+    for message in messages {
+        if socket.write_all(&message).is_err() {
+            break;
+        }
+    }
+    // lets the reader thread notice, if it did not already
+    let _ = socket.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Both ends of a loopback connection.
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    fn command(command_type: u32, body: &[u8]) -> Vec<u8> {
+        let mut out = words(&[command_type, body.len() as u32]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn band() -> Band {
+        Band {
+            center: 100_000_000,
+            gain: 10,
+            sample_rate: 10e6,
+            freq_min: 10_000_000,
+            freq_max: 6_000_000_000,
+            gain_min: -12,
+            gain_max: 30,
+        }
+    }
+
+    #[test]
+    fn hello_with_and_without_the_name_length() {
+        let mut body = words(&[PROTOCOL_VERSION]);
+        body.extend_from_slice(b"SDR#");
+        let Command::Hello { name } = read_command(&mut &command(CMD_HELLO, &body)[..]).unwrap()
+        else {
+            panic!("not a hello");
+        };
+        assert_eq!(name, "SDR#");
+
+        let mut body = words(&[PROTOCOL_VERSION, 6]);
+        body.extend_from_slice(b"SDR++!");
+        let Command::Hello { name } = read_command(&mut &command(CMD_HELLO, &body)[..]).unwrap()
+        else {
+            panic!("not a hello");
+        };
+        assert_eq!(name, "SDR++!");
+    }
+
+    #[test]
+    fn settings_and_pings_are_read() {
+        let bytes = [
+            command(CMD_SET_SETTING, &words(&[SETTING_GAIN, 7])),
+            command(CMD_PING, &[]),
+            command(99, &[1, 2, 3]),
+        ]
+        .concat();
+        let mut reader = &bytes[..];
+        assert!(matches!(
+            read_command(&mut reader).unwrap(),
+            Command::SetSetting {
+                setting: SETTING_GAIN,
+                value: 7
+            }
+        ));
+        assert!(matches!(read_command(&mut reader).unwrap(), Command::Ping));
+        assert!(matches!(read_command(&mut reader).unwrap(), Command::Other));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn oversized_commands_end_the_connection() {
+        let bytes = words(&[CMD_SET_SETTING, MAX_COMMAND_BODY_SIZE + 1]);
+        let e = read_command(&mut &bytes[..]).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn messages_are_little_endian_words_with_a_header() {
+        assert_eq!(words(&[1, 0x01020304]), [1, 0, 0, 0, 4, 3, 2, 1]);
+        let out = message(MSG_PONG, STREAM_TYPE_STATUS, 7, &[9, 9]);
+        assert_eq!(
+            out[..20],
+            words(&[PROTOCOL_VERSION, MSG_PONG, STREAM_TYPE_STATUS, 7, 2])[..]
+        );
+        assert_eq!(out[20..], [9, 9]);
+    }
+
+    #[test]
+    fn uint8_iq_is_offset_binary() {
+        let mut body = Vec::new();
+        let samples = [Complex::new(-1.0, 1.0), Complex::new(0.0, 2.0)];
+        encode_iq(FORMAT_UINT8, &samples, &mut body);
+        assert_eq!(body, [0, 255, 128, 255]);
+    }
+
+    #[test]
+    fn int16_iq_saturates() {
+        let mut body = Vec::new();
+        let samples = [Complex::new(0.5, -0.5), Complex::new(2.0, -2.0)];
+        encode_iq(FORMAT_INT16, &samples, &mut body);
+        let values: Vec<i16> = body
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(values, [16384, -16384, i16::MAX, i16::MIN]);
+    }
+
+    #[test]
+    fn decimated_streams_move_within_the_band() {
+        let band = band();
+        assert_eq!(band.range_for(10e6), (100_000_000, 100_000_000));
+        assert_eq!(band.range_for(2.5e6), (96_250_000, 103_750_000));
+        // wider than the band, it can only sit in the middle
+        assert_eq!(band.range_for(20e6), (100_000_000, 100_000_000));
+    }
+
+    #[test]
+    fn clients_are_fit_into_the_band() {
+        let (_peer, socket) = socket_pair();
+        let peer = socket.peer_addr().unwrap();
+        let (outgoing, _queue) = mpsc::sync_channel(4);
+        let mut client = Client::new(0, peer, socket, outgoing, &band());
+        client.iq_frequency = 120_000_000;
+        client.iq_decimation = 2;
+        client.fft_frequency = 90_000_000;
+        client.fit_into(&band());
+        assert_eq!(client.iq_frequency, 103_750_000);
+        assert_eq!(client.fft_frequency, 100_000_000);
+    }
+
+    /// Against the simulated board, with a client that already said hello.
+    #[cfg(feature = "mock")]
+    mod served {
+        use super::*;
+        use crate::device::Device;
+
+        fn server() -> SpyServer<Complex<f32>> {
+            let device = Device::connect_usb().unwrap();
+            let stream = RxStream::new(device, rfnm_channel::CH0)
+                .map_err(|(e, _)| e)
+                .unwrap();
+            SpyServer::bind(
+                stream,
+                SpyServerSettings {
+                    address: "127.0.0.1:0".to_string(),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        }
+
+        /// Connect, say hello as `name` and take the device info and sync that come back.
+        fn greet(
+            server: &mut SpyServer<Complex<f32>>,
+            name: &str,
+        ) -> (TcpStream, Vec<u8>, Vec<u8>) {
+            let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            server.accept_clients().unwrap();
+            let mut body = words(&[PROTOCOL_VERSION]);
+            body.extend_from_slice(name.as_bytes());
+            client.write_all(&command(CMD_HELLO, &body)).unwrap();
+            next_event(server);
+
+            let (message_type, device_info) = receive(&mut client);
+            assert_eq!(message_type, MSG_DEVICE_INFO);
+            let (message_type, sync) = receive(&mut client);
+            assert_eq!(message_type, MSG_CLIENT_SYNC);
+            (client, device_info, sync)
+        }
+
+        fn next_event(server: &mut SpyServer<Complex<f32>>) {
+            let event = server.events.recv_timeout(Duration::from_secs(5)).unwrap();
+            server.handle_event(event).unwrap();
+        }
+
+        /// Type and body of the next message.
+        fn receive(client: &mut TcpStream) -> (u32, Vec<u8>) {
+            let mut header = [0; 20];
+            client.read_exact(&mut header).unwrap();
+            let header = unwords(&header);
+            assert_eq!(header[0], PROTOCOL_VERSION);
+            let mut body = vec![0; header[4] as usize];
+            client.read_exact(&mut body).unwrap();
+            (header[1], body)
+        }
+
+        fn unwords(bytes: &[u8]) -> Vec<u32> {
+            bytes
+                .chunks(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect()
+        }
+
+        #[test]
+        fn device_info_describes_the_band() {
+            let mut server = server();
+            let band = server.band;
+            let (_client, device_info, _) = greet(&mut server, "first");
+            assert_eq!(
+                unwords(&device_info),
+                [
+                    DEVICE_AIRSPY_ONE,
+                    server.serial,
+                    band.sample_rate as u32,
+                    band.sample_rate as u32,
+                    10,
+                    1,
+                    (band.gain_max - band.gain_min) as u32,
+                    hz(band.freq_min),
+                    hz(band.freq_max),
+                    ADC_RESOLUTION,
+                    0,
+                    0,
+                ]
+            );
+        }
+
+        #[test]
+        fn only_the_first_client_controls_the_board() {
+            let mut server = server();
+            let band = server.band;
+            let (_first, _, sync) = greet(&mut server, "first");
+            let sync = unwords(&sync);
+            assert_eq!(sync[0], 1);
+            assert_eq!(sync[1], (band.gain - band.gain_min) as u32);
+            assert_eq!(sync[2], hz(band.center));
+            // the controller may tune the whole board
+            assert_eq!(sync[5..7], [hz(band.freq_min), hz(band.freq_max)]);
+
+            let (_second, _, sync) = greet(&mut server, "second");
+            let sync = unwords(&sync);
+            assert_eq!(sync[0], 0);
+            // everybody else stays within the band
+            assert_eq!(sync[5..7], [hz(band.center), hz(band.center)]);
+            assert_eq!(server.clients()[0].0, "first");
+        }
+
+        #[test]
+        fn control_goes_to_the_longest_connected_client() {
+            let mut server = server();
+            let (_first, _, _) = greet(&mut server, "first");
+            let (mut second, _, _) = greet(&mut server, "second");
+            let (_third, _, _) = greet(&mut server, "third");
+
+            let first_id = server.controller.unwrap();
+            server.remove_client(first_id);
+            let (message_type, sync) = receive(&mut second);
+            assert_eq!(message_type, MSG_CLIENT_SYNC);
+            assert_eq!(unwords(&sync)[0], 1);
+            let names: Vec<String> = server.clients().into_iter().map(|(name, _)| name).collect();
+            assert_eq!(names, ["second", "third"]);
+        }
+    }
+}
@@ -1,27 +1,41 @@
 use crate::RfnmApiError::BufferCountMismatch;
+use crate::ddc::Nco;
 use crate::device::Device;
-use crate::{RfnmApiError, check_code};
+use crate::tags::{Block, StreamTag, TagState};
+use crate::{RfnmApiError, check_code, split_channel_flags};
 use rfnm_sys::{
+    StreamDiscontinuity,
     StreamWrapper,
     WrappedThrownError,
+    device_reserve_rx_buffers,
     device_set_stream_format,
+    rfnm_ch_enable,
     rfnm_channel,
     rfnm_stream_format,
     stream_create,
     stream_free,
+    stream_get_discontinuities,
     stream_read,
     stream_set_auto_dc_offset,
     stream_start,
     stream_stop,
 };
+use std::cell::RefCell;
 use std::ffi::c_void;
 use std::marker::PhantomData;
-use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use num_complex::Complex;
 
-pub trait StreamDataFormat {
+/// Converts the CS16 samples librfnm delivers to a format it does not have.
+pub type Cs16Repack<T> = fn(&[Complex<i16>], &mut [T]);
+
+pub trait StreamDataFormat: Sized {
     fn api_format() -> rfnm_stream_format;
+    /// For formats librfnm does not have, `api_format` is CS16 and `RxStream` repacks with this.
+    const FROM_CS16: Option<Cs16Repack<Self>> = None;
 }
 
 impl StreamDataFormat for Complex<i8> {
@@ -40,24 +54,281 @@ impl StreamDataFormat for Complex<f32> {
     }
 }
 
+/// Formats the processing on top of a stream can work with, through a common floating point view.
+pub trait ComplexSample: StreamDataFormat + Copy {
+    /// Convert to floating point, scaled so that full scale is 1.0 for every format.
+    fn to_complex_f32(self) -> Complex<f32>;
+    /// Convert back from the 1.0 full scale representation, saturating integer formats.
+    fn from_complex_f32(value: Complex<f32>) -> Self;
+}
+
+impl ComplexSample for Complex<i8> {
+    fn to_complex_f32(self) -> Complex<f32> {
+        Complex::new(self.re as f32, self.im as f32) / i8::MAX as f32
+    }
+    fn from_complex_f32(value: Complex<f32>) -> Self {
+        let scaled = value * i8::MAX as f32;
+        // `as` saturates on float to int conversions
+        Complex::new(scaled.re.round() as i8, scaled.im.round() as i8)
+    }
+}
+impl ComplexSample for Complex<i16> {
+    fn to_complex_f32(self) -> Complex<f32> {
+        Complex::new(self.re as f32, self.im as f32) / i16::MAX as f32
+    }
+    fn from_complex_f32(value: Complex<f32>) -> Self {
+        let scaled = value * i16::MAX as f32;
+        Complex::new(scaled.re.round() as i16, scaled.im.round() as i16)
+    }
+}
+impl ComplexSample for Complex<f32> {
+    fn to_complex_f32(self) -> Complex<f32> {
+        self
+    }
+    fn from_complex_f32(value: Complex<f32>) -> Self {
+        value
+    }
+}
+
+// more in one read than this are counted, but not tagged
+const MAX_DISCONTINUITIES: usize = 64;
+
+/// librfnm's `MIN_RX_BUFCNT`, it does not stream with fewer usb buffers than this.
+pub const MIN_RX_BUFFER_COUNT: usize = 1000;
+
+/// How an `RxStream` buffers samples, trading latency for robustness.
+///
+/// `StreamStats::latency` shows what a choice costs. Blocks add their own duration to it,
+/// and a reader that falls behind adds its backlog, up to what the usb buffers hold.
+#[derive(Debug, Clone)]
+pub struct RxStreamSettings {
+    /// Usb buffers librfnm fills for the stream, one usb packet (`RxStream::packet_size` samples) each.
+    /// More of them let the reader stall longer before samples are lost, which also means samples can be older
+    /// by the time they are read. Never less than `MIN_RX_BUFFER_COUNT`, and the buffers of a device are only
+    /// ever added to, a later stream on the same device with fewer keeps the ones it has. `MIN_RX_BUFFER_COUNT` (1000) by default.
+    pub rx_buffer_count: usize,
+    /// Samples per channel `RxStream::suggested_buffer_size` asks for, in usb packets.
+    /// Small blocks hand samples out as soon as they arrive, large ones cost less per sample.
+    /// `None` keeps librfnm's suggestion, and is the default.
+    pub block_packets: Option<usize>,
+}
+
+impl RxStreamSettings {
+    /// Single packet blocks with the fewest buffers librfnm allows. A reader that cannot keep up loses samples early.
+    pub fn low_latency() -> Self {
+        Self {
+            rx_buffer_count: MIN_RX_BUFFER_COUNT,
+            block_packets: Some(1),
+        }
+    }
+
+    /// Large blocks and four times the buffers, for readers with stalls, at the cost of memory and latency.
+    pub fn robust() -> Self {
+        Self {
+            rx_buffer_count: 4 * MIN_RX_BUFFER_COUNT,
+            block_packets: Some(32),
+        }
+    }
+}
+
+impl Default for RxStreamSettings {
+    fn default() -> Self {
+        Self {
+            rx_buffer_count: MIN_RX_BUFFER_COUNT,
+            block_packets: None,
+        }
+    }
+}
+
 /// A synchronized rx stream over one or more device channels
 ///
 /// Takes ownership of the device
 pub struct RxStream<T> {
     _p: PhantomData<T>,
     channel_count: usize,
+    channels: Vec<rfnm_channel>,
+    sample_rate: f64,
     suggested_buffer_size: usize,
+    packet_size: usize,
+    rx_buffer_count: usize,
     device: Option<Device>,
     wrapper: *mut StreamWrapper,
+    // offset tuning shift per channel, with the offset it was set up for
+    offset_mixers: RefCell<Vec<(i64, Nco)>>,
+    // what librfnm writes for formats repacked by `StreamDataFormat::FROM_CS16`
+    cs16_scratch: RefCell<Vec<Vec<Complex<i16>>>>,
+    stats: Arc<StatsCounters>,
+    tags: RefCell<TagState>,
 }
 
 pub struct StreamReadInfo {
     pub elements_read: usize,
     pub timestamp_ns: u64,
+    /// Why the read returned early with fewer samples than asked for, usually `RfnmApiError::Timeout`.
+    /// The `elements_read` samples are valid either way, and the next read continues right after them.
+    pub error: Option<RfnmApiError>,
+}
+
+/// What an `RxStream` delivered so far, see `RxStream::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StreamStats {
+    /// Samples per channel, including the zeros librfnm fills short gaps with.
+    pub samples: u64,
+    pub reads: u64,
+    /// Reads that ran out of time before the board delivered.
+    pub timeouts: u64,
+    /// Reads that failed for any other reason.
+    pub errors: u64,
+    /// Usb buffers that did not follow the one before them on the board's timer, each tagged with
+    /// `crate::tags::TagKind::Discontinuity`.
+    pub discontinuities: u64,
+    /// Samples per second per channel from the first successful read to the last, by the wall clock.
+    /// 0 until two reads returned samples.
+    pub measured_sample_rate: f64,
+    /// Sample rate the dividers are set up for, see `RxStream::sample_rate`.
+    pub expected_sample_rate: f64,
+    /// How old the first sample of the last block was when `read` returned it: the duration of the block,
+    /// plus how far the reader is behind the freshest block it ever got.
+    ///
+    /// The usb transfer itself is not included, so this is a lower bound. Measured against the nominal
+    /// sample rate, so it also drifts with the board clock, by its ppm deviation.
+    pub latency: Duration,
+    /// The highest `latency` since the stream was started.
+    pub max_latency: Duration,
+}
+
+/// Reads the `StreamStats` of an `RxStream` from any thread, without getting in the way of `read`.
+#[derive(Clone)]
+pub struct StreamStatsHandle(Arc<StatsCounters>);
+
+impl StreamStatsHandle {
+    pub fn get(&self) -> StreamStats {
+        self.0.snapshot()
+    }
+}
+
+struct StatsCounters {
+    epoch: Instant,
+    expected_sample_rate: f64,
+    samples: AtomicU64,
+    reads: AtomicU64,
+    timeouts: AtomicU64,
+    errors: AtomicU64,
+    discontinuities: AtomicU64,
+    // wall clock as ns since `epoch`, with the samples counted up to the first read
+    first_read_ns: AtomicU64,
+    first_read_samples: AtomicU64,
+    last_read_ns: AtomicU64,
+    // smallest wall clock minus stream time of a block end so far, what latency is measured against
+    min_block_offset_ns: AtomicI64,
+    latency_ns: AtomicU64,
+    max_latency_ns: AtomicU64,
+}
+
+impl StatsCounters {
+    fn new(expected_sample_rate: f64) -> Self {
+        Self {
+            epoch: Instant::now(),
+            expected_sample_rate,
+            samples: AtomicU64::new(0),
+            reads: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            discontinuities: AtomicU64::new(0),
+            first_read_ns: AtomicU64::new(0),
+            first_read_samples: AtomicU64::new(0),
+            last_read_ns: AtomicU64::new(0),
+            min_block_offset_ns: AtomicI64::new(i64::MAX),
+            latency_ns: AtomicU64::new(0),
+            max_latency_ns: AtomicU64::new(0),
+        }
+    }
+
+    fn now_ns(&self) -> u64 {
+        (self.epoch.elapsed().as_nanos() as u64).max(1)
+    }
+
+    /// `block_end_ns` is the stream timestamp right after the last sample of the block,
+    /// `block_ns` how long the block is.
+    fn record_latency(&self, now_ns: u64, block_end_ns: f64, block_ns: f64) {
+        let offset = now_ns as i64 - block_end_ns as i64;
+        let min_offset = self.min_block_offset_ns.load(Ordering::Relaxed).min(offset);
+        self.min_block_offset_ns
+            .store(min_offset, Ordering::Relaxed);
+        let latency = (offset - min_offset) as u64 + block_ns as u64;
+        self.latency_ns.store(latency, Ordering::Relaxed);
+        if latency > self.max_latency_ns.load(Ordering::Relaxed) {
+            self.max_latency_ns.store(latency, Ordering::Relaxed);
+        }
+    }
+
+    /// Timestamps start over with every start, and so does the latency.
+    fn reset_latency(&self) {
+        self.min_block_offset_ns.store(i64::MAX, Ordering::Relaxed);
+        self.latency_ns.store(0, Ordering::Relaxed);
+        self.max_latency_ns.store(0, Ordering::Relaxed);
+    }
+
+    /// Where wall clock time `at` is in the stream, once a block has been read.
+    fn stream_time_ns(&self, at: Instant) -> Option<f64> {
+        let min_offset = self.min_block_offset_ns.load(Ordering::Relaxed);
+        if min_offset == i64::MAX {
+            return None;
+        }
+        let wall_ns = at.saturating_duration_since(self.epoch).as_nanos() as i64;
+        Some((wall_ns - min_offset) as f64)
+    }
+
+    fn record_samples(&self, elements: usize, now_ns: u64) {
+        // only `read` writes, so loads and stores are enough and nobody ever waits
+        let samples = self.samples.load(Ordering::Relaxed) + elements as u64;
+        if self.first_read_ns.load(Ordering::Relaxed) == 0 {
+            self.first_read_samples.store(samples, Ordering::Relaxed);
+            self.first_read_ns.store(now_ns, Ordering::Relaxed);
+        }
+        self.last_read_ns.store(now_ns, Ordering::Relaxed);
+        self.samples.store(samples, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> StreamStats {
+        let samples = self.samples.load(Ordering::Relaxed);
+        let first_ns = self.first_read_ns.load(Ordering::Relaxed);
+        let last_ns = self.last_read_ns.load(Ordering::Relaxed);
+        let measured_sample_rate = if last_ns > first_ns && first_ns != 0 {
+            let since_first =
+                samples.saturating_sub(self.first_read_samples.load(Ordering::Relaxed));
+            since_first as f64 * 1e9 / (last_ns - first_ns) as f64
+        } else {
+            0.0
+        };
+        StreamStats {
+            samples,
+            reads: self.reads.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            discontinuities: self.discontinuities.load(Ordering::Relaxed),
+            measured_sample_rate,
+            expected_sample_rate: self.expected_sample_rate,
+            latency: Duration::from_nanos(self.latency_ns.load(Ordering::Relaxed)),
+            max_latency: Duration::from_nanos(self.max_latency_ns.load(Ordering::Relaxed)),
+        }
+    }
 }
 
 impl<T: StreamDataFormat> RxStream<T> {
     pub fn new(device: Device, channels: rfnm_channel) -> Result<Self, (RfnmApiError, Device)> {
+        Self::with_settings(device, channels, &RxStreamSettings::default())
+    }
+
+    /// Create a stream with buffering other than the default, see `RxStreamSettings`.
+    pub fn with_settings(
+        device: Device,
+        channels: rfnm_channel,
+        settings: &RxStreamSettings,
+    ) -> Result<Self, (RfnmApiError, Device)> {
+        if let Err(e) = Self::check_channels(&device, channels) {
+            return Err((e, device));
+        }
         let channel_count = channels.0.count_ones() as usize;
         let mut thrown_err = WrappedThrownError::empty();
         let mut suggested_buffer_size = 0;
@@ -70,6 +341,26 @@ impl<T: StreamDataFormat> RxStream<T> {
         }) {
             return Err((e, device));
         }
+        // librfnm's stream format is the size of a sample in bytes, and its suggestion one packet in bytes
+        let packet_size = suggested_buffer_size / T::api_format().0 as usize;
+        if let Some(packets) = settings.block_packets {
+            suggested_buffer_size = packets.max(1) * packet_size;
+        }
+        let rx_buffer_count =
+            unsafe { device_reserve_rx_buffers(device.wrapper(), settings.rx_buffer_count) };
+
+        // librfnm makes sure all channels share the same rate, so the first one is as good as any
+        let channel_list: Vec<rfnm_channel> = split_channel_flags(channels).collect();
+        let sample_rate = match channel_list.first() {
+            Some(first) => match device.get_rx_settings(*first) {
+                Ok(info) => info
+                    .to_settings()
+                    .rate_divider_settings
+                    .sample_rate(device.hwinfo().clock_info.dcs_clk),
+                Err(e) => return Err((e, device)),
+            },
+            None => 0.0,
+        };
 
         let wrapper = unsafe { stream_create(device.wrapper(), channels.0 as u8, &mut thrown_err) };
         if wrapper.is_null() {
@@ -78,7 +369,20 @@ impl<T: StreamDataFormat> RxStream<T> {
             Ok(Self {
                 _p: PhantomData::default(),
                 channel_count,
+                offset_mixers: RefCell::new(
+                    channel_list
+                        .iter()
+                        .map(|_| (0, Nco::new(0.0, sample_rate)))
+                        .collect(),
+                ),
+                cs16_scratch: RefCell::new(vec![Vec::new(); channel_count]),
+                stats: Arc::new(StatsCounters::new(sample_rate)),
+                tags: RefCell::new(TagState::new(channel_count)),
+                channels: channel_list,
+                sample_rate,
                 suggested_buffer_size,
+                packet_size,
+                rx_buffer_count,
                 device: Some(device),
                 wrapper,
             })
@@ -100,15 +404,49 @@ impl<T: StreamDataFormat> RxStream<T> {
         self.channel_count
     }
 
+    /// The channels in this stream, in the order their buffers are passed to `read`.
+    pub fn channels(&self) -> &[rfnm_channel] {
+        &self.channels
+    }
+
+    /// Sample rate of every channel in this stream, in Hz.
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
     pub fn suggested_buffer_size(&self) -> usize {
         self.suggested_buffer_size
     }
 
+    /// Samples per channel in one usb packet, the unit librfnm hands samples over in.
+    pub fn packet_size(&self) -> usize {
+        self.packet_size
+    }
+
+    /// Usb buffers librfnm has for the device, see `RxStreamSettings::rx_buffer_count`.
+    pub fn rx_buffer_count(&self) -> usize {
+        self.rx_buffer_count
+    }
+
+    /// Counters of everything `read` did so far.
+    pub fn stats(&self) -> StreamStats {
+        self.stats.snapshot()
+    }
+
+    /// For polling `stats` from another thread while this one reads.
+    pub fn stats_handle(&self) -> StreamStatsHandle {
+        StreamStatsHandle(self.stats.clone())
+    }
+
+    /// Toggle the DC offset correction built into librfnm.
+    /// Use `crate::dc_offset::DcOffsetCorrector` instead for control over the filter and the offsets.
     pub fn set_auto_dc_offset(&self, auto: bool, channel: rfnm_channel) {
         unsafe { stream_set_auto_dc_offset(self.wrapper, auto, channel.0 as u8) }
     }
 
     pub fn start(&self) -> Result<(), RfnmApiError> {
+        self.stats.reset_latency();
+        self.tags.borrow_mut().reset();
         check_code(unsafe { stream_start(self.wrapper) })
     }
 
@@ -116,9 +454,62 @@ impl<T: StreamDataFormat> RxStream<T> {
         check_code(unsafe { stream_stop(self.wrapper) })
     }
 
-    pub fn read<'a>(
+    /// Read the same number of samples for every channel of the stream.
+    ///
+    /// Channels set up with `RxChannelSettings::tuning_offset` are shifted back here,
+    /// so their requested frequency is at 0 Hz in `dst`.
+    ///
+    /// Fails only if no samples were read at all. When librfnm gives up part way, for example on a timeout,
+    /// the samples it got are returned with the reason in `StreamReadInfo::error`.
+    pub fn read(
         &self,
-        dst: &[&'a mut [T]],
+        dst: &mut [&mut [T]],
+        timeout: Duration,
+    ) -> Result<StreamReadInfo, RfnmApiError> {
+        self.read_tagged(dst, timeout, &mut Vec::new())
+    }
+
+    /// `read`, with the tags of the block in `tags`, see `crate::tags`.
+    /// `tags` is cleared first, a reused `Vec` spares the allocation.
+    pub fn read_tagged(
+        &self,
+        dst: &mut [&mut [T]],
+        timeout: Duration,
+        tags: &mut Vec<StreamTag>,
+    ) -> Result<StreamReadInfo, RfnmApiError> {
+        tags.clear();
+        let result = self.read_samples(dst, timeout);
+        self.stats.reads.fetch_add(1, Ordering::Relaxed);
+        match &result {
+            Ok(info) => {
+                self.record_read(info, tags);
+                if let Some(e) = &info.error {
+                    self.record_error(e);
+                }
+            }
+            Err(e) => self.record_error(e),
+        }
+        result
+    }
+}
+
+impl<T: StreamDataFormat> RxStream<T> {
+    /// What librfnm would otherwise only tell with an exception, or once streaming starts.
+    fn check_channels(device: &Device, channels: rfnm_channel) -> Result<(), RfnmApiError> {
+        device.topology()?.check_rx_combination(channels)?;
+        for channel in split_channel_flags(channels) {
+            if device.get_rx_settings(channel)?.enable() != rfnm_ch_enable::RFNM_CH_OFF {
+                return Err(RfnmApiError::ChannelAlreadyEnabled(
+                    channel.0.trailing_zeros(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_samples(
+        &self,
+        dst: &mut [&mut [T]],
         timeout: Duration,
     ) -> Result<StreamReadInfo, RfnmApiError> {
         if dst.len() != self.channel_count {
@@ -147,11 +538,19 @@ impl<T: StreamDataFormat> RxStream<T> {
         // we *do* know that there cannot be more than 8 channels though, making this array more or less free.
         assert!(dst.len() <= 8);
         let mut raw_buffers = [std::ptr::null_mut(); 8];
-        for i in 0..dst.len() {
-            raw_buffers[i] = dst[i].as_ptr() as *mut c_void;
+        let mut scratch = self.cs16_scratch.borrow_mut();
+        if T::FROM_CS16.is_some() {
+            for (i, buffer) in scratch.iter_mut().enumerate() {
+                buffer.resize(element_count, Complex::new(0, 0));
+                raw_buffers[i] = buffer.as_mut_ptr() as *mut c_void;
+            }
+        } else {
+            for i in 0..dst.len() {
+                raw_buffers[i] = dst[i].as_mut_ptr() as *mut c_void;
+            }
         }
         // perform the magic
-        check_code(unsafe {
+        let result = check_code(unsafe {
             stream_read(
                 self.wrapper,
                 raw_buffers.as_ptr(),
@@ -160,14 +559,129 @@ impl<T: StreamDataFormat> RxStream<T> {
                 &mut timestamp,
                 timeout_us,
             )
-        })?;
-        Ok(StreamReadInfo {
-            elements_read: actually_written,
-            timestamp_ns: timestamp,
-        })
+        });
+        self.record_discontinuities();
+        // librfnm counts what it wrote even when it gives up early, so those samples are part of the stream
+        // and get the same treatment as a full read, or the shift and the timestamps fall out of step
+        self.undo_tuning_offsets(&raw_buffers[..dst.len()], actually_written);
+        if let Some(repack) = T::FROM_CS16 {
+            for (buffer, samples) in dst.iter_mut().zip(scratch.iter()) {
+                repack(
+                    &samples[..actually_written],
+                    &mut buffer[..actually_written],
+                );
+            }
+        }
+        match result {
+            Err(e) if actually_written == 0 => Err(e),
+            result => Ok(StreamReadInfo {
+                elements_read: actually_written,
+                timestamp_ns: timestamp,
+                error: result.err(),
+            }),
+        }
+    }
+
+    /// Count and tag the buffers librfnm found out of step during the last read.
+    fn record_discontinuities(&self) {
+        let mut found = [StreamDiscontinuity::default(); MAX_DISCONTINUITIES];
+        let count =
+            unsafe { stream_get_discontinuities(self.wrapper, found.as_mut_ptr(), found.len()) };
+        if count > 0 {
+            self.stats
+                .discontinuities
+                .fetch_add(count as u64, Ordering::Relaxed);
+            self.tags
+                .borrow_mut()
+                .mark_discontinuities(&found[..count.min(found.len())]);
+        }
+    }
+
+    fn record_error(&self, error: &RfnmApiError) {
+        match error {
+            RfnmApiError::DqbufOverflow => {
+                self.tags.borrow_mut().mark_overflow();
+                self.stats.errors.fetch_add(1, Ordering::Relaxed);
+            }
+            RfnmApiError::Timeout | RfnmApiError::DqbufNoData => {
+                self.stats.timeouts.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                self.stats.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn record_read(&self, info: &StreamReadInfo, tags: &mut Vec<StreamTag>) {
+        if info.elements_read == 0 {
+            return;
+        }
+        let ns_per_sample = 1e9 / self.sample_rate;
+        let timestamp_ns = info.timestamp_ns as f64;
+        let block_ns = info.elements_read as f64 * ns_per_sample;
+        let now_ns = self.stats.now_ns();
+        self.stats
+            .record_latency(now_ns, timestamp_ns + block_ns, block_ns);
+        self.stats.record_samples(info.elements_read, now_ns);
+
+        // librfnm shares its buffers between the channels
+        let buffered_ns = (self.rx_buffer_count / self.channel_count.max(1) * self.packet_size)
+            as f64
+            * ns_per_sample;
+        let latency_ns = self.stats.latency_ns.load(Ordering::Relaxed) as f64;
+        let block = Block {
+            timestamp_ns,
+            elements: info.elements_read,
+            ns_per_sample,
+        };
+        self.tags.borrow_mut().collect(
+            self.device(),
+            &self.channels,
+            &block,
+            latency_ns > buffered_ns,
+            |at| self.stats.stream_time_ns(at),
+            tags,
+        );
+    }
+
+    /// Shift the samples librfnm wrote to `raw_buffers`, before any repacking, so `T` needs no conversions.
+    fn undo_tuning_offsets(&self, raw_buffers: &[*mut c_void], elements: usize) {
+        let mut mixers = self.offset_mixers.borrow_mut();
+        for ((raw, channel), (offset, nco)) in raw_buffers
+            .iter()
+            .zip(&self.channels)
+            .zip(mixers.iter_mut())
+        {
+            let wanted = self.device().tuning_offset(*channel);
+            if wanted != *offset {
+                *offset = wanted;
+                nco.set_frequency(wanted as f64, self.sample_rate);
+            }
+            if *offset == 0 {
+                continue;
+            }
+            // librfnm wrote `elements` samples of its own format there
+            unsafe {
+                match T::api_format() {
+                    rfnm_stream_format::STREAM_FORMAT_CS8 => nco.mix(
+                        std::slice::from_raw_parts_mut(*raw as *mut Complex<i8>, elements),
+                    ),
+                    rfnm_stream_format::STREAM_FORMAT_CS16 => nco.mix(
+                        std::slice::from_raw_parts_mut(*raw as *mut Complex<i16>, elements),
+                    ),
+                    _ => nco.mix(std::slice::from_raw_parts_mut(
+                        *raw as *mut Complex<f32>,
+                        elements,
+                    )),
+                }
+            }
+        }
     }
 }
 
+// same as for `Device`, the stream can be used from any thread, just not from several at once
+unsafe impl<T: Send> Send for RxStream<T> {}
+
 impl<T> Drop for RxStream<T> {
     fn drop(&mut self) {
         unsafe { stream_free(self.wrapper) };
@@ -0,0 +1,382 @@
+//! Streams that survive the board going away.
+//!
+//! A `SupervisedStream` wraps a `RxStream` of a board known by its serial number.
+//! Once reads keep failing with transport errors, it drops the board, opens it again,
+//! reapplies the last known `RxChannelSettings` of every channel and restarts streaming.
+//! The consumer sees the errors while the board is gone, and the length of the gap on the first read after it.
+
+use crate::channel_settings::RxChannelSettings;
+use crate::device::Device;
+use crate::stream::{RxStream, StreamDataFormat};
+use crate::{RfnmApiError, split_channel_flags};
+use rfnm_sys::rfnm_channel;
+use std::time::{Duration, Instant};
+
+/// When to give up on the current connection and how fast to retry.
+#[derive(Debug, Clone)]
+pub struct SupervisorSettings {
+    /// Failed reads in a row after which the board is considered gone and is reconnected, 10 by default.
+    pub max_consecutive_errors: u32,
+    /// Minimum time between two reconnect attempts, 1 s by default.
+    pub reconnect_delay: Duration,
+}
+
+impl Default for SupervisorSettings {
+    fn default() -> Self {
+        Self {
+            max_consecutive_errors: 10,
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+pub struct SupervisedReadInfo {
+    pub elements_read: usize,
+    /// Time of the first sample since the stream was first started.
+    /// Unlike `StreamReadInfo::timestamp_ns` this keeps counting across reconnects, gaps included.
+    pub timestamp_ns: u64,
+    /// Set on the first read after a reconnect: the time from the last sample before the failure
+    /// to the restart of the stream.
+    pub gap: Option<Duration>,
+    /// See `StreamReadInfo::error`. A transport error here counts towards a reconnect like a failed read.
+    pub error: Option<RfnmApiError>,
+}
+
+/// A `RxStream` that is reopened whenever the board stops talking to us.
+pub struct SupervisedStream<T> {
+    serial: String,
+    channels: rfnm_channel,
+    settings: SupervisorSettings,
+    channel_settings: Vec<(rfnm_channel, RxChannelSettings)>,
+    auto_dc_offset: rfnm_channel,
+    stream: Option<RxStream<T>>,
+    running: bool,
+    consecutive_errors: u32,
+    reconnects: u32,
+    last_attempt: Option<Instant>,
+    last_success: Instant,
+    pending_gap: Option<Duration>,
+    sample_rate: f64,
+    // where the timestamps of the current stream start on the continuous time line
+    timestamp_base_ns: u64,
+    timestamp_end_ns: u64,
+}
+
+impl<T: StreamDataFormat> SupervisedStream<T> {
+    /// Connect to the board with the given serial number and set up a stream for `channels`,
+    /// with the settings the channels currently have.
+    pub fn open(
+        serial: &str,
+        channels: rfnm_channel,
+        settings: SupervisorSettings,
+    ) -> Result<Self, RfnmApiError> {
+        let device = Device::connect_usb_serial(serial)?;
+        let stream = RxStream::new(device, channels).map_err(|(e, _)| e)?;
+        Self::from_stream(stream, serial, settings)
+    }
+
+    /// Supervise an existing stream. `serial` must be the one of the board the stream is on.
+    pub fn from_stream(
+        stream: RxStream<T>,
+        serial: &str,
+        settings: SupervisorSettings,
+    ) -> Result<Self, RfnmApiError> {
+        let mut channel_settings = Vec::with_capacity(stream.channel_count());
+        for channel in stream.channels() {
+            let current = stream.device().get_rx_settings(*channel)?.to_settings();
+            channel_settings.push((*channel, current));
+        }
+        Ok(Self {
+            serial: serial.to_string(),
+            channels: rfnm_channel(stream.channels().iter().fold(0, |acc, ch| acc | ch.0)),
+            settings,
+            channel_settings,
+            auto_dc_offset: rfnm_channel(0),
+            sample_rate: stream.sample_rate(),
+            stream: Some(stream),
+            running: false,
+            consecutive_errors: 0,
+            reconnects: 0,
+            last_attempt: None,
+            last_success: Instant::now(),
+            pending_gap: None,
+            timestamp_base_ns: 0,
+            timestamp_end_ns: 0,
+        })
+    }
+
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// The current stream, `None` while the board is gone.
+    pub fn stream(&self) -> Option<&RxStream<T>> {
+        self.stream.as_ref()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// How often the board was reconnected so far.
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects
+    }
+
+    /// Settings every channel gets again after a reconnect.
+    pub fn channel_settings(&self) -> &[(rfnm_channel, RxChannelSettings)] {
+        &self.channel_settings
+    }
+
+    /// Change the settings of one channel of the stream.
+    ///
+    /// Go through here instead of `RxStream::device`, otherwise the change is lost on the next reconnect.
+    /// While the board is gone, the settings are only stored and applied on reconnect.
+    pub fn set_rx_settings(
+        &mut self,
+        channel: rfnm_channel,
+        settings: &RxChannelSettings,
+    ) -> Result<(), RfnmApiError> {
+        if let Some(stream) = &self.stream {
+            stream.device().set_rx_settings(channel, settings)?;
+        }
+        if let Some((_, stored)) = self
+            .channel_settings
+            .iter_mut()
+            .find(|(ch, _)| *ch == channel)
+        {
+            *stored = settings.clone();
+        }
+        Ok(())
+    }
+
+    /// See `RxStream::set_auto_dc_offset`. Kept across reconnects.
+    pub fn set_auto_dc_offset(&mut self, auto: bool, channel: rfnm_channel) {
+        if auto {
+            self.auto_dc_offset.0 |= channel.0;
+        } else {
+            self.auto_dc_offset.0 &= !channel.0;
+        }
+        if let Some(stream) = &self.stream {
+            stream.set_auto_dc_offset(auto, channel);
+        }
+    }
+
+    pub fn start(&mut self) -> Result<(), RfnmApiError> {
+        if let Some(stream) = &self.stream {
+            stream.start()?;
+        }
+        self.running = true;
+        self.last_success = Instant::now();
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<(), RfnmApiError> {
+        self.running = false;
+        match &self.stream {
+            Some(stream) => stream.stop(),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `RxStream::read`, but with the board reconnected as needed.
+    ///
+    /// While the board is gone every call makes one reconnect attempt once `SupervisorSettings::reconnect_delay`
+    /// has passed since the previous one, and returns its error if it fails. If the delay does not run out within
+    /// `timeout`, the call waits for `timeout` and returns `RfnmApiError::Timeout`, so the caller can try again.
+    pub fn read(
+        &mut self,
+        dst: &mut [&mut [T]],
+        timeout: Duration,
+    ) -> Result<SupervisedReadInfo, RfnmApiError> {
+        let started = Instant::now();
+        if self.stream.is_none() {
+            self.reconnect(timeout)?;
+        }
+        // unwrap: reconnect either filled it or returned
+        let stream = self.stream.as_ref().unwrap();
+        match stream.read(dst, timeout.saturating_sub(started.elapsed())) {
+            Ok(info) => {
+                let timestamp_ns = self.timestamp_base_ns + info.timestamp_ns;
+                self.timestamp_end_ns =
+                    timestamp_ns + (info.elements_read as f64 * 1e9 / self.sample_rate) as u64;
+                match &info.error {
+                    Some(e) if e.is_transport_error() => self.count_failure(),
+                    _ => {
+                        self.consecutive_errors = 0;
+                        self.last_success = Instant::now();
+                    }
+                }
+                Ok(SupervisedReadInfo {
+                    elements_read: info.elements_read,
+                    timestamp_ns,
+                    gap: self.pending_gap.take(),
+                    error: info.error,
+                })
+            }
+            Err(e) if e.is_transport_error() => {
+                self.count_failure();
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn count_failure(&mut self) {
+        self.consecutive_errors += 1;
+        if self.consecutive_errors >= self.settings.max_consecutive_errors {
+            // dropping the stream drops the device with it
+            self.stream = None;
+        }
+    }
+
+    fn reconnect(&mut self, timeout: Duration) -> Result<(), RfnmApiError> {
+        if let Some(last) = self.last_attempt {
+            let wait = self.settings.reconnect_delay.saturating_sub(last.elapsed());
+            if wait > timeout {
+                std::thread::sleep(timeout);
+                return Err(RfnmApiError::Timeout);
+            }
+            std::thread::sleep(wait);
+        }
+        self.last_attempt = Some(Instant::now());
+
+        let device = Device::connect_usb_serial(&self.serial)?;
+        for (channel, settings) in &self.channel_settings {
+            device.set_rx_settings(*channel, settings)?;
+        }
+        let stream = RxStream::new(device, self.channels).map_err(|(e, _)| e)?;
+        for channel in split_channel_flags(self.auto_dc_offset) {
+            stream.set_auto_dc_offset(true, channel);
+        }
+        if self.running {
+            stream.start()?;
+        }
+
+        // a stream failing again right after the reconnect adds to the gap that is not reported yet
+        let gap = self.pending_gap.unwrap_or_default() + self.last_success.elapsed();
+        self.timestamp_base_ns = self.timestamp_end_ns + gap.as_nanos() as u64;
+        self.pending_gap = Some(gap);
+        self.last_success = Instant::now();
+        self.sample_rate = stream.sample_rate();
+        self.stream = Some(stream);
+        self.consecutive_errors = 0;
+        self.reconnects += 1;
+        Ok(())
+    }
+}
+
+// the simulated board is the only one that can be unplugged on demand
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use num_complex::Complex;
+
+    const BLOCK: usize = 1000;
+
+    fn supervised() -> SupervisedStream<Complex<f32>> {
+        let mut stream = SupervisedStream::open(
+            "MOCK0001",
+            rfnm_channel::CH0,
+            SupervisorSettings {
+                max_consecutive_errors: 3,
+                reconnect_delay: Duration::from_millis(10),
+            },
+        )
+        .unwrap();
+        stream.start().unwrap();
+        stream
+    }
+
+    fn read(
+        stream: &mut SupervisedStream<Complex<f32>>,
+    ) -> Result<SupervisedReadInfo, RfnmApiError> {
+        let mut buffer = vec![Complex::new(0.0, 0.0); BLOCK];
+        stream.read(&mut [buffer.as_mut_slice()], Duration::from_millis(100))
+    }
+
+    /// Read until the board is back, returning the first block after the reconnect.
+    fn read_reconnected(stream: &mut SupervisedStream<Complex<f32>>) -> SupervisedReadInfo {
+        loop {
+            if let Ok(info) = read(stream) {
+                return info;
+            }
+        }
+    }
+
+    fn unplug(stream: &SupervisedStream<Complex<f32>>) {
+        unsafe { rfnm_sys::mock_unplug(stream.stream().unwrap().device().wrapper()) }
+    }
+
+    fn block_ns(stream: &SupervisedStream<Complex<f32>>) -> u64 {
+        (BLOCK as f64 * 1e9 / stream.stream().unwrap().sample_rate()) as u64
+    }
+
+    #[test]
+    fn the_board_is_reconnected_after_errors_in_a_row() {
+        let mut stream = supervised();
+        read(&mut stream).unwrap();
+        unplug(&stream);
+        for _ in 0..2 {
+            assert!(matches!(read(&mut stream), Err(RfnmApiError::UsbFail)));
+            assert!(stream.is_connected());
+        }
+        assert!(matches!(read(&mut stream), Err(RfnmApiError::UsbFail)));
+        assert!(!stream.is_connected());
+
+        assert_eq!(read(&mut stream).unwrap().elements_read, BLOCK);
+        assert!(stream.is_connected());
+        assert_eq!(stream.reconnects(), 1);
+    }
+
+    #[test]
+    fn the_gap_is_reported_once() {
+        let mut stream = supervised();
+        let before_last_block = Instant::now();
+        assert_eq!(read(&mut stream).unwrap().gap, None);
+        unplug(&stream);
+        std::thread::sleep(Duration::from_millis(20));
+        let gap = read_reconnected(&mut stream).gap.unwrap();
+        // from the last good block to the restart
+        assert!(gap >= Duration::from_millis(20), "{gap:?}");
+        assert!(gap <= before_last_block.elapsed(), "{gap:?}");
+        assert_eq!(read(&mut stream).unwrap().gap, None);
+    }
+
+    #[test]
+    fn timestamps_keep_counting_across_reconnects() {
+        let mut stream = supervised();
+        let first = read(&mut stream).unwrap();
+        let second = read(&mut stream).unwrap();
+        assert_eq!(second.timestamp_ns, first.timestamp_ns + block_ns(&stream));
+
+        unplug(&stream);
+        let after = read_reconnected(&mut stream);
+        let gap = after.gap.unwrap().as_nanos() as u64;
+        // the new stream starts at 0, the base puts it after the old one and the gap
+        assert_eq!(
+            after.timestamp_ns,
+            second.timestamp_ns + block_ns(&stream) + gap
+        );
+        let next = read(&mut stream).unwrap();
+        assert_eq!(next.timestamp_ns, after.timestamp_ns + block_ns(&stream));
+    }
+
+    #[test]
+    fn settings_survive_the_reconnect() {
+        let mut stream = supervised();
+        let mut settings = stream.channel_settings()[0].1.clone();
+        settings.gain = 10;
+        stream
+            .set_rx_settings(rfnm_channel::CH0, &settings)
+            .unwrap();
+        unplug(&stream);
+        read_reconnected(&mut stream);
+        assert_eq!(stream.reconnects(), 1);
+        let device = stream.stream().unwrap().device();
+        assert_eq!(
+            device.get_rx_settings(rfnm_channel::CH0).unwrap().gain(),
+            10
+        );
+    }
+}
@@ -0,0 +1,479 @@
+//! In band events of a `crate::stream::RxStream`, in the spirit of GNU Radio's stream tags.
+//!
+//! `RxStream::read_tagged` hands out the tags that fall into the block it read, each at the sample it applies from.
+//! Settings changes come from `Device::set_rx_settings` and `Device::submit_rx_settings` on the stream's device,
+//! every one of them is tagged, however many fall between two reads.
+//! The board does not say when it switched, so their offset is where the time of the call ends up in the stream,
+//! mapped with the same reference as `StreamStats::latency`. The first read after a start tags the settings every
+//! channel starts out with.
+
+use crate::channel_settings::RfPath;
+use crate::device::Device;
+use rfnm_sys::{StreamDiscontinuity, rfnm_channel};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamTag {
+    /// Sample of the block the tag applies from.
+    pub offset: usize,
+    /// The channel the tag is about, `None` for all of them.
+    pub channel: Option<rfnm_channel>,
+    pub kind: TagKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagKind {
+    /// The frequency at 0 Hz, in Hz, see `crate::channel_settings::RxChannelSettings::frequency`.
+    Frequency(i64),
+    /// Gain in dB.
+    Gain(i8),
+    Path(RfPath),
+    /// A usb buffer of the channel did not follow the one before it on the board's timer, with `missing_samples`
+    /// in between, negative if samples came twice. librfnm fills gaps of up to 16 usb packets with zeros and
+    /// skips repeated samples, so the timestamps stay right. Larger gaps and timer flybacks it passes on as they
+    /// are, and from here on the timestamps are off by `missing_samples`.
+    Discontinuity {
+        missing_samples: i64,
+    },
+    /// Samples were dropped because the reader fell behind: librfnm reported it, or the latency grew past what
+    /// the usb buffers hold.
+    Overflow,
+}
+
+/// Settings the rust side sent for a channel, and when.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ChannelChange {
+    pub frequency: i64,
+    pub gain: i8,
+    pub path: RfPath,
+    pub at: Instant,
+}
+
+/// The block a read returned, in stream time.
+pub(crate) struct Block {
+    pub timestamp_ns: f64,
+    pub elements: usize,
+    pub ns_per_sample: f64,
+}
+
+struct PendingTag {
+    channel: rfnm_channel,
+    kind: TagKind,
+    stream_ns: f64,
+}
+
+/// What an `RxStream` keeps between reads to come up with tags.
+pub(crate) struct TagState {
+    // what was tagged last per channel, `None` until the first read after a start
+    seen: Vec<Option<ChannelChange>>,
+    // the changes per channel taken from the device for the block at hand
+    queued: Vec<Vec<ChannelChange>>,
+    // changes that happened after the end of the blocks read so far
+    pending: Vec<PendingTag>,
+    // what librfnm reported, until the block it is in is tagged
+    discontinuities: Vec<StreamDiscontinuity>,
+    overflow_pending: bool,
+    over_capacity: bool,
+}
+
+impl TagState {
+    pub fn new(channel_count: usize) -> Self {
+        Self {
+            seen: vec![None; channel_count],
+            queued: vec![Vec::new(); channel_count],
+            pending: Vec::new(),
+            discontinuities: Vec::new(),
+            overflow_pending: false,
+            over_capacity: false,
+        }
+    }
+
+    /// Timestamps start over with every start, and everything is tagged again.
+    pub fn reset(&mut self) {
+        self.seen.iter_mut().for_each(|seen| *seen = None);
+        self.pending.clear();
+        self.discontinuities.clear();
+        self.over_capacity = false;
+    }
+
+    /// Buffers librfnm found out of step during the read of the next block.
+    pub fn mark_discontinuities(&mut self, found: &[StreamDiscontinuity]) {
+        self.discontinuities.extend_from_slice(found);
+    }
+
+    /// librfnm reported lost samples on a read that returned nothing.
+    pub fn mark_overflow(&mut self) {
+        self.overflow_pending = true;
+    }
+
+    /// Add the tags of `block` to `tags`, ordered by offset.
+    /// `stream_ns` maps a wall clock time to stream time, if that is known yet.
+    pub fn collect(
+        &mut self,
+        device: &Device,
+        channels: &[rfnm_channel],
+        block: &Block,
+        over_capacity: bool,
+        stream_ns: impl Fn(Instant) -> Option<f64>,
+        tags: &mut Vec<StreamTag>,
+    ) {
+        for ((seen, queued), channel) in self.seen.iter().zip(&mut self.queued).zip(channels) {
+            queued.clear();
+            device.drain_rx_changes(*channel, |change| queued.push(change));
+            if seen.is_none() && queued.is_empty() {
+                queued.extend(initial_settings(device, *channel));
+            }
+        }
+        self.tag_block(channels, block, over_capacity, stream_ns, tags);
+    }
+
+    /// `collect` once the changes are in `queued`.
+    fn tag_block(
+        &mut self,
+        channels: &[rfnm_channel],
+        block: &Block,
+        over_capacity: bool,
+        stream_ns: impl Fn(Instant) -> Option<f64>,
+        tags: &mut Vec<StreamTag>,
+    ) {
+        // only the crossing is news, the latency takes a while to come back down
+        if self.overflow_pending || (over_capacity && !self.over_capacity) {
+            tags.push(StreamTag {
+                offset: 0,
+                channel: None,
+                kind: TagKind::Overflow,
+            });
+        }
+        self.overflow_pending = false;
+        self.over_capacity = over_capacity;
+        for found in self.discontinuities.drain(..) {
+            tags.push(StreamTag {
+                offset: found.offset.min(block.elements.saturating_sub(1)),
+                channel: Some(rfnm_channel(1 << found.channel)),
+                kind: TagKind::Discontinuity {
+                    missing_samples: found.missing_samples,
+                },
+            });
+        }
+
+        for ((seen, queued), channel) in self.seen.iter_mut().zip(&self.queued).zip(channels) {
+            let Some(last) = seen else {
+                // the settings the channel streams with from the start, whatever came before
+                let Some(initial) = queued.last() else {
+                    continue;
+                };
+                for kind in changed_kinds(None, initial) {
+                    tags.push(StreamTag {
+                        offset: 0,
+                        channel: Some(*channel),
+                        kind,
+                    });
+                }
+                *seen = Some(*initial);
+                continue;
+            };
+            for change in queued {
+                let at = stream_ns(change.at).unwrap_or(block.timestamp_ns);
+                for kind in changed_kinds(Some(last), change) {
+                    self.pending.push(PendingTag {
+                        channel: *channel,
+                        kind,
+                        stream_ns: at,
+                    });
+                }
+                *last = *change;
+            }
+        }
+
+        let block_end_ns = block.timestamp_ns + block.elements as f64 * block.ns_per_sample;
+        self.pending.retain(|pending| {
+            if pending.stream_ns >= block_end_ns {
+                return true;
+            }
+            let offset = ((pending.stream_ns - block.timestamp_ns) / block.ns_per_sample).ceil();
+            tags.push(StreamTag {
+                offset: (offset.max(0.0) as usize).min(block.elements.saturating_sub(1)),
+                channel: Some(pending.channel),
+                kind: pending.kind,
+            });
+            false
+        });
+        tags.sort_by_key(|tag| tag.offset);
+    }
+}
+
+fn initial_settings(device: &Device, channel: rfnm_channel) -> Option<ChannelChange> {
+    let settings = device.get_rx_settings(channel).ok()?.to_settings();
+    Some(ChannelChange {
+        frequency: settings.frequency,
+        gain: settings.gain,
+        path: settings.path,
+        at: Instant::now(),
+    })
+}
+
+fn changed_kinds(last: Option<&ChannelChange>, current: &ChannelChange) -> Vec<TagKind> {
+    let mut kinds = Vec::new();
+    if last.is_none_or(|last| last.frequency != current.frequency) {
+        kinds.push(TagKind::Frequency(current.frequency));
+    }
+    if last.is_none_or(|last| last.gain != current.gain) {
+        kinds.push(TagKind::Gain(current.gain));
+    }
+    if last.is_none_or(|last| last.path != current.path) {
+        kinds.push(TagKind::Path(current.path));
+    }
+    kinds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rfnm_sys::rfnm_rf_path;
+    use std::time::Duration;
+
+    const CH0: rfnm_channel = rfnm_channel(1);
+    const CH1: rfnm_channel = rfnm_channel(2);
+
+    // samples 10 ns apart, the first block covers 1000 to 2000 ns of stream time
+    const BLOCK: Block = Block {
+        timestamp_ns: 1000.0,
+        elements: 100,
+        ns_per_sample: 10.0,
+    };
+    const NEXT_BLOCK: Block = Block {
+        timestamp_ns: 2000.0,
+        ..BLOCK
+    };
+
+    struct Clock(Instant);
+
+    impl Clock {
+        fn change(&self, stream_ns: u64, frequency: i64, gain: i8) -> ChannelChange {
+            ChannelChange {
+                frequency,
+                gain,
+                path: RfPath(rfnm_rf_path::RFNM_PATH_SMA_A),
+                at: self.0 + Duration::from_nanos(stream_ns),
+            }
+        }
+
+        fn stream_ns(&self) -> impl Fn(Instant) -> Option<f64> {
+            let start = self.0;
+            move |at| Some(at.duration_since(start).as_nanos() as f64)
+        }
+    }
+
+    fn read(
+        state: &mut TagState,
+        clock: &Clock,
+        block: &Block,
+        changes: &[&[ChannelChange]],
+    ) -> Vec<StreamTag> {
+        for (queued, changes) in state.queued.iter_mut().zip(changes) {
+            queued.clear();
+            queued.extend_from_slice(changes);
+        }
+        let mut tags = Vec::new();
+        state.tag_block(&[CH0, CH1], block, false, clock.stream_ns(), &mut tags);
+        tags
+    }
+
+    /// A state past its first read, with both channels at 100 Hz and 10 dB.
+    fn started(clock: &Clock) -> TagState {
+        let mut state = TagState::new(2);
+        let initial = clock.change(0, 100, 10);
+        read(&mut state, clock, &BLOCK, &[&[initial], &[initial]]);
+        state
+    }
+
+    #[test]
+    fn the_first_read_tags_the_latest_settings_at_the_start() {
+        let clock = Clock(Instant::now());
+        let mut state = TagState::new(2);
+        let tags = read(
+            &mut state,
+            &clock,
+            &BLOCK,
+            &[&[clock.change(0, 50, 0), clock.change(0, 100, 10)], &[]],
+        );
+        assert_eq!(
+            tags,
+            [
+                StreamTag {
+                    offset: 0,
+                    channel: Some(CH0),
+                    kind: TagKind::Frequency(100)
+                },
+                StreamTag {
+                    offset: 0,
+                    channel: Some(CH0),
+                    kind: TagKind::Gain(10)
+                },
+                StreamTag {
+                    offset: 0,
+                    channel: Some(CH0),
+                    kind: TagKind::Path(RfPath(rfnm_rf_path::RFNM_PATH_SMA_A))
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn every_change_between_reads_is_tagged_where_it_happened() {
+        let clock = Clock(Instant::now());
+        let mut state = started(&clock);
+        let tags = read(
+            &mut state,
+            &clock,
+            &BLOCK,
+            &[
+                &[clock.change(1250, 200, 10), clock.change(1555, 300, 10)],
+                &[clock.change(1400, 100, 20)],
+            ],
+        );
+        assert_eq!(
+            tags,
+            [
+                StreamTag {
+                    offset: 25,
+                    channel: Some(CH0),
+                    kind: TagKind::Frequency(200)
+                },
+                StreamTag {
+                    offset: 40,
+                    channel: Some(CH1),
+                    kind: TagKind::Gain(20)
+                },
+                // a change between two samples applies from the next one
+                StreamTag {
+                    offset: 56,
+                    channel: Some(CH0),
+                    kind: TagKind::Frequency(300)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn a_change_past_the_block_waits_for_its_block() {
+        let clock = Clock(Instant::now());
+        let mut state = started(&clock);
+        let tags = read(
+            &mut state,
+            &clock,
+            &BLOCK,
+            &[&[clock.change(2030, 200, 10)], &[]],
+        );
+        assert!(tags.is_empty());
+        let tags = read(&mut state, &clock, &NEXT_BLOCK, &[&[], &[]]);
+        assert_eq!(
+            tags,
+            [StreamTag {
+                offset: 3,
+                channel: Some(CH0),
+                kind: TagKind::Frequency(200)
+            }]
+        );
+    }
+
+    #[test]
+    fn a_change_before_the_block_applies_from_its_start() {
+        let clock = Clock(Instant::now());
+        let mut state = started(&clock);
+        let tags = read(
+            &mut state,
+            &clock,
+            &NEXT_BLOCK,
+            &[&[clock.change(1500, 100, 5)], &[]],
+        );
+        assert_eq!(
+            tags,
+            [StreamTag {
+                offset: 0,
+                channel: Some(CH0),
+                kind: TagKind::Gain(5)
+            }]
+        );
+    }
+
+    #[test]
+    fn an_unchanged_setting_is_not_tagged_again() {
+        let clock = Clock(Instant::now());
+        let mut state = started(&clock);
+        let tags = read(
+            &mut state,
+            &clock,
+            &BLOCK,
+            &[&[clock.change(1100, 100, 10)], &[]],
+        );
+        assert!(tags.is_empty());
+    }
+
+    #[test]
+    fn discontinuities_are_tagged_where_their_buffer_starts() {
+        let clock = Clock(Instant::now());
+        let mut state = started(&clock);
+        state.mark_discontinuities(&[
+            StreamDiscontinuity {
+                offset: 60,
+                channel: 1,
+                missing_samples: 4096,
+            },
+            StreamDiscontinuity {
+                offset: 150,
+                channel: 0,
+                missing_samples: -12,
+            },
+        ]);
+        let tags = read(&mut state, &clock, &BLOCK, &[&[], &[]]);
+        assert_eq!(
+            tags,
+            [
+                StreamTag {
+                    offset: 60,
+                    channel: Some(CH1),
+                    kind: TagKind::Discontinuity {
+                        missing_samples: 4096
+                    }
+                },
+                // past the samples the read returned, so on the last one
+                StreamTag {
+                    offset: 99,
+                    channel: Some(CH0),
+                    kind: TagKind::Discontinuity {
+                        missing_samples: -12
+                    }
+                },
+            ]
+        );
+        assert!(read(&mut state, &clock, &NEXT_BLOCK, &[&[], &[]]).is_empty());
+    }
+
+    #[test]
+    fn overflow_is_tagged_once_per_crossing() {
+        let clock = Clock(Instant::now());
+        let mut state = started(&clock);
+        let mut tags = Vec::new();
+        let overflows = |tags: &[StreamTag]| {
+            tags.iter()
+                .filter(|tag| tag.kind == TagKind::Overflow)
+                .count()
+        };
+        for (over_capacity, expected) in [(true, 1), (true, 0), (false, 0), (true, 1)] {
+            tags.clear();
+            state.tag_block(
+                &[CH0, CH1],
+                &BLOCK,
+                over_capacity,
+                clock.stream_ns(),
+                &mut tags,
+            );
+            assert_eq!(overflows(&tags), expected);
+        }
+        state.mark_overflow();
+        tags.clear();
+        state.tag_block(&[CH0, CH1], &BLOCK, false, clock.stream_ns(), &mut tags);
+        assert_eq!(overflows(&tags), 1);
+    }
+}
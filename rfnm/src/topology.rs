@@ -0,0 +1,305 @@
+//! Which channel sits where on a board.
+//!
+//! A `Topology` is a snapshot of the daughterboards, the channels on each of them and the converters
+//! they are wired to, built from `HwInfo` and the channel infos. It also knows which rx channels
+//! librfnm can stream together: every channel needs an ADC of its own, and all of them the same sample rate.
+
+use crate::channel_settings::{RxChannelInfo, SampleRateDividerSettings, TxChannelInfo};
+use crate::hwinfo::{BoardInfo, HwInfo};
+use crate::{RfnmApiError, split_channel_flags};
+use rfnm_sys::rfnm_channel;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone)]
+pub struct Topology {
+    pub motherboard: BoardInfo,
+    /// The populated daughterboard slots.
+    pub daughterboards: Vec<DaughterboardTopology>,
+    dcs_clk: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DaughterboardTopology {
+    /// Slot on the motherboard, as in `HwInfo::daughterboards`.
+    pub slot: usize,
+    pub board: BoardInfo,
+    pub rx_channels: Vec<RxChannelTopology>,
+    pub tx_channels: Vec<TxChannelTopology>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RxChannelTopology {
+    pub channel: rfnm_channel,
+    /// Number of the channel on its daughterboard.
+    pub dgb_ch_id: i8,
+    pub adc_id: i8,
+    pub available: bool,
+    pub freq_range: RangeInclusive<i64>,
+    /// The divider the channel was set to when the snapshot was taken.
+    pub rate_divider_settings: SampleRateDividerSettings,
+}
+
+#[derive(Debug, Clone)]
+pub struct TxChannelTopology {
+    /// Number of the channel across the board, see `crate::device::Device::get_tx_channel_info`.
+    pub channel_num: u32,
+    /// Number of the channel on its daughterboard.
+    pub dgb_ch_id: i8,
+    pub dac_id: i8,
+    pub available: bool,
+    pub freq_range: RangeInclusive<i64>,
+}
+
+impl Topology {
+    /// `rx` and `tx` are all channels of the board, in order, as `crate::device::Device::topology` collects them.
+    pub fn new(hwinfo: &HwInfo, rx: &[RxChannelInfo], tx: &[TxChannelInfo]) -> Self {
+        let daughterboards = hwinfo
+            .daughterboards
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, board)| {
+                let board = board.as_ref()?;
+                let rx_channels = rx
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, info)| info.dgb_id() as usize == slot)
+                    .map(|(num, info)| RxChannelTopology {
+                        channel: rfnm_channel(1 << num),
+                        dgb_ch_id: info.dgb_ch_id(),
+                        adc_id: info.adc_id(),
+                        available: info.avail(),
+                        freq_range: info.freq_range(),
+                        rate_divider_settings: info.to_settings().rate_divider_settings,
+                    })
+                    .collect();
+                let tx_channels = tx
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, info)| info.dgb_id() as usize == slot)
+                    .map(|(num, info)| TxChannelTopology {
+                        channel_num: num as u32,
+                        dgb_ch_id: info.dgb_ch_id(),
+                        dac_id: info.dac_id(),
+                        available: info.avail(),
+                        freq_range: info.freq_range(),
+                    })
+                    .collect();
+                Some(DaughterboardTopology {
+                    slot,
+                    board: board.clone(),
+                    rx_channels,
+                    tx_channels,
+                })
+            })
+            .collect();
+        Self {
+            motherboard: hwinfo.motherboard.clone(),
+            daughterboards,
+            dcs_clk: hwinfo.clock_info.dcs_clk,
+        }
+    }
+
+    /// Every rx channel of the board, lowest first.
+    pub fn rx_channels(&self) -> impl Iterator<Item = &RxChannelTopology> {
+        let mut channels: Vec<_> = self
+            .daughterboards
+            .iter()
+            .flat_map(|db| &db.rx_channels)
+            .collect();
+        channels.sort_by_key(|ch| ch.channel.0);
+        channels.into_iter()
+    }
+
+    pub fn rx_channel(&self, channel: rfnm_channel) -> Option<&RxChannelTopology> {
+        self.rx_channels().find(|ch| ch.channel == channel)
+    }
+
+    /// The daughterboard `channel` is on.
+    pub fn daughterboard_of(&self, channel: rfnm_channel) -> Option<&DaughterboardTopology> {
+        self.daughterboards
+            .iter()
+            .find(|db| db.rx_channels.iter().any(|ch| ch.channel == channel))
+    }
+
+    /// All rx channels sampled by ADC `adc_id`.
+    pub fn rx_channels_on_adc(&self, adc_id: i8) -> rfnm_channel {
+        rfnm_channel(
+            self.rx_channels()
+                .filter(|ch| ch.adc_id == adc_id)
+                .fold(0, |flags, ch| flags | ch.channel.0),
+        )
+    }
+
+    /// Sample rate of `channel` at the time of the snapshot, in Hz.
+    pub fn sample_rate(&self, channel: rfnm_channel) -> Option<f64> {
+        self.rx_channel(channel)
+            .map(|ch| ch.rate_divider_settings.sample_rate(self.dcs_clk))
+    }
+
+    /// True if librfnm can stream `channels` in one `crate::stream::RxStream`, with the dividers as they were.
+    pub fn can_stream_together(&self, channels: rfnm_channel) -> bool {
+        channels.0 != 0 && self.check_rx_combination(channels).is_ok()
+    }
+
+    /// Every combination of rx channels `can_stream_together` accepts, as channel flags.
+    pub fn valid_rx_combinations(&self) -> Vec<rfnm_channel> {
+        let all = self.rx_channels().fold(0, |flags, ch| flags | ch.channel.0);
+        // every non empty subset of the channels the board has
+        (1..=all)
+            .filter(|flags| flags & !all == 0)
+            .map(rfnm_channel)
+            .filter(|channels| self.can_stream_together(*channels))
+            .collect()
+    }
+
+    /// Like `can_stream_together`, but says which channels are in the way.
+    pub fn check_rx_combination(&self, channels: rfnm_channel) -> Result<(), RfnmApiError> {
+        let mut first: Option<&RxChannelTopology> = None;
+        for flag in split_channel_flags(channels) {
+            let num = flag.0.trailing_zeros();
+            let Some(ch) = self.rx_channel(flag) else {
+                return Err(RfnmApiError::NoSuchChannel(num));
+            };
+            if !ch.available {
+                return Err(RfnmApiError::ChannelUnavailable(num));
+            }
+            let on_adc = self.rx_channels_on_adc(ch.adc_id).0 & channels.0 & !flag.0;
+            if on_adc != 0 {
+                let other = on_adc.trailing_zeros();
+                return Err(RfnmApiError::AdcConflict {
+                    adc_id: ch.adc_id,
+                    first: num.min(other),
+                    other: num.max(other),
+                });
+            }
+            match first {
+                None => first = Some(ch),
+                Some(first) => {
+                    let (a, b) = (first.rate_divider_settings, ch.rate_divider_settings);
+                    if a.m != b.m || a.n != b.n {
+                        return Err(RfnmApiError::SampleRateMismatch {
+                            first: first.channel.0.trailing_zeros(),
+                            first_m: a.m,
+                            first_n: a.n,
+                            other: num,
+                            other_m: b.m,
+                            other_n: b.n,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hwinfo::ChannelCounts;
+
+    fn board(id: u8, rx: u8) -> BoardInfo {
+        BoardInfo {
+            id,
+            revision: 0,
+            serial: [0; 9],
+            name: String::new(),
+            mac_addr: None,
+            channel_counts: ChannelCounts { rx, tx: 0 },
+        }
+    }
+
+    fn rx(num: u32, adc_id: i8, available: bool, m: i16) -> RxChannelTopology {
+        RxChannelTopology {
+            channel: rfnm_channel(1 << num),
+            dgb_ch_id: 0,
+            adc_id,
+            available,
+            freq_range: 0..=1,
+            rate_divider_settings: SampleRateDividerSettings { m, n: 2 },
+        }
+    }
+
+    /// Channels 2 and 3 share an ADC, 4 runs at another rate, 7 is unavailable and 5 and 6 do not exist.
+    fn topology() -> Topology {
+        let daughterboard = |slot: usize, rx_channels| DaughterboardTopology {
+            slot,
+            board: board(slot as u8, 4),
+            rx_channels,
+            tx_channels: Vec::new(),
+        };
+        Topology {
+            motherboard: board(0, 0),
+            daughterboards: vec![
+                daughterboard(
+                    0,
+                    vec![rx(0, 0, true, 1), rx(1, 1, true, 1), rx(7, 4, false, 1)],
+                ),
+                daughterboard(
+                    1,
+                    vec![rx(2, 2, true, 1), rx(3, 2, true, 1), rx(4, 3, true, 2)],
+                ),
+            ],
+            dcs_clk: 122_880_000,
+        }
+    }
+
+    #[test]
+    fn channels_on_own_adcs_with_one_rate_stream_together() {
+        let topology = topology();
+        topology.check_rx_combination(rfnm_channel(0b111)).unwrap();
+        topology.check_rx_combination(rfnm_channel(1 << 4)).unwrap();
+        assert!(topology.can_stream_together(rfnm_channel(0b1011)));
+        assert!(!topology.can_stream_together(rfnm_channel(0)));
+    }
+
+    #[test]
+    fn missing_and_unavailable_channels_are_named() {
+        let topology = topology();
+        assert!(matches!(
+            topology.check_rx_combination(rfnm_channel(0b10_0001)),
+            Err(RfnmApiError::NoSuchChannel(5))
+        ));
+        assert!(matches!(
+            topology.check_rx_combination(rfnm_channel(0b1000_0010)),
+            Err(RfnmApiError::ChannelUnavailable(7))
+        ));
+    }
+
+    #[test]
+    fn channels_sharing_an_adc_conflict() {
+        assert!(matches!(
+            topology().check_rx_combination(rfnm_channel(0b1101)),
+            Err(RfnmApiError::AdcConflict {
+                adc_id: 2,
+                first: 2,
+                other: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn different_rates_mismatch() {
+        assert!(matches!(
+            topology().check_rx_combination(rfnm_channel(0b1_0010)),
+            Err(RfnmApiError::SampleRateMismatch {
+                first: 1,
+                first_m: 1,
+                other: 4,
+                other_m: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn valid_combinations_are_every_accepted_subset() {
+        let combinations = topology().valid_rx_combinations();
+        // the non empty subsets of 0 to 3 without both 2 and 3, and 4 on its own
+        assert_eq!(combinations.len(), 12);
+        assert!(combinations.contains(&rfnm_channel(0b1011)));
+        assert!(combinations.contains(&rfnm_channel(1 << 4)));
+        assert!(!combinations.contains(&rfnm_channel(0b1100)));
+        assert!(!combinations.iter().any(|flags| flags.0 & (1 << 7) != 0));
+    }
+}
@@ -0,0 +1,513 @@
+//! VITA-49 (VRT) packets over UDP.
+//!
+//! `VrtEncoder` turns sample blocks into IF data packets with a stream ID and
+//! integer and fractional timestamps, and `VrtContext` into context packets carrying
+//! the RF frequency, gain, sample rate and payload format. Samples go out as big endian complex
+//! cartesian items of the stream's own size, 8 or 16 bit signed integers or 32 bit floats.
+//!
+//! `VrtUdpSender` puts both on the wire: a context packet before the first data packet,
+//! whenever the context changes and, if asked to, every `VrtSettings::context_interval`.
+
+use crate::channel_settings::RxChannelSettings;
+use crate::stream::StreamDataFormat;
+use num_complex::Complex;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+const PACKET_TYPE_IF_DATA_WITH_ID: u32 = 0b0001;
+const PACKET_TYPE_CONTEXT: u32 = 0b0100;
+
+const TSI_UTC: u32 = 0b01;
+const TSI_OTHER: u32 = 0b11;
+const TSF_REAL_TIME: u32 = 0b10;
+
+// context indicator field bits, the fields follow in descending bit order
+const CIF_CHANGED: u32 = 1 << 31;
+const CIF_RF_REFERENCE_FREQUENCY: u32 = 1 << 27;
+const CIF_GAIN: u32 = 1 << 23;
+const CIF_SAMPLE_RATE: u32 = 1 << 21;
+const CIF_PAYLOAD_FORMAT: u32 = 1 << 15;
+
+// header, stream ID, integer and two fractional timestamp words
+const PREFIX_WORDS: usize = 5;
+const MAX_PACKET_WORDS: usize = u16::MAX as usize;
+
+#[derive(Debug, Error)]
+pub enum VrtError {
+    #[error("Could not send: {0}")]
+    Io(#[from] io::Error),
+    #[error("Packets of {0} bytes cannot hold any samples")]
+    PacketTooSmall(usize),
+}
+
+/// What the integer timestamps count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VrtTimestamps {
+    /// Seconds since 1970, from the host clock at the first block plus the stream's own time after that.
+    Utc,
+    /// Seconds since the stream started, straight from `StreamReadInfo::timestamp_ns`.
+    StreamTime,
+}
+
+/// Samples a VRT payload can carry.
+pub trait VrtSample: StreamDataFormat + Copy {
+    /// Bits per I or Q value.
+    const ITEM_BITS: u32;
+    /// IEEE-754 single precision rather than signed fixed point.
+    const FLOAT: bool;
+    /// Append I and Q in network byte order.
+    fn write_be(self, out: &mut Vec<u8>);
+}
+
+impl VrtSample for Complex<i8> {
+    const ITEM_BITS: u32 = 8;
+    const FLOAT: bool = false;
+    fn write_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.re as u8, self.im as u8]);
+    }
+}
+
+impl VrtSample for Complex<i16> {
+    const ITEM_BITS: u32 = 16;
+    const FLOAT: bool = false;
+    fn write_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.re.to_be_bytes());
+        out.extend_from_slice(&self.im.to_be_bytes());
+    }
+}
+
+impl VrtSample for Complex<f32> {
+    const ITEM_BITS: u32 = 32;
+    const FLOAT: bool = true;
+    fn write_be(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.re.to_be_bytes());
+        out.extend_from_slice(&self.im.to_be_bytes());
+    }
+}
+
+/// Channel state sent in context packets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VrtContext {
+    /// RF frequency at 0 Hz in the samples, in Hz.
+    pub frequency: f64,
+    /// In dB.
+    pub gain: f64,
+    pub sample_rate: f64,
+}
+
+impl VrtContext {
+    /// The context of a channel with `settings`, given the `dcs_clk` from `crate::hwinfo::ClockInfo`.
+    pub fn from_settings(settings: &RxChannelSettings, dcs_clk: u64) -> Self {
+        Self {
+            frequency: settings.frequency as f64,
+            gain: settings.gain as f64,
+            sample_rate: settings.rate_divider_settings.sample_rate(dcs_clk),
+        }
+    }
+}
+
+/// Builds VRT packets for one stream ID.
+#[derive(Debug, Clone)]
+pub struct VrtEncoder {
+    stream_id: u32,
+    timestamps: VrtTimestamps,
+    max_packet_size: usize,
+    // host time of `timestamp_ns == 0`, for `VrtTimestamps::Utc`
+    utc_origin_ns: Option<u128>,
+    data_count: u32,
+    context_count: u32,
+    packet: Vec<u8>,
+}
+
+impl VrtEncoder {
+    /// `max_packet_size` is in bytes, and rounded down to whole words.
+    pub fn new(
+        stream_id: u32,
+        timestamps: VrtTimestamps,
+        max_packet_size: usize,
+    ) -> Result<Self, VrtError> {
+        // room for at least two of the largest samples
+        if max_packet_size / 4 < PREFIX_WORDS + 4 {
+            return Err(VrtError::PacketTooSmall(max_packet_size));
+        }
+        Ok(Self {
+            stream_id,
+            timestamps,
+            max_packet_size: max_packet_size.min(MAX_PACKET_WORDS * 4),
+            utc_origin_ns: None,
+            data_count: 0,
+            context_count: 0,
+            packet: Vec::new(),
+        })
+    }
+
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
+    /// Split `samples` into as few data packets as fit, handing each to `emit`.
+    ///
+    /// `timestamp_ns` is the `StreamReadInfo::timestamp_ns` of the first sample.
+    /// 8 bit samples are packed two to a word, a lone sample at the end of a packet is padded with a zero one.
+    pub fn encode_data<T: VrtSample>(
+        &mut self,
+        samples: &[T],
+        timestamp_ns: u64,
+        sample_rate: f64,
+        mut emit: impl FnMut(&[u8]) -> io::Result<()>,
+    ) -> Result<(), VrtError> {
+        let bytes_per_sample = T::ITEM_BITS as usize / 4;
+        let payload_bytes = (self.max_packet_size / 4 - PREFIX_WORDS) * 4;
+        // keep packets of 8 bit samples at whole words, except for the last one
+        let per_packet = (payload_bytes / bytes_per_sample) & !1;
+        for (i, chunk) in samples.chunks(per_packet).enumerate() {
+            let offset_ns = (i * per_packet) as f64 * 1e9 / sample_rate;
+            let (integer, fractional) = self.timestamp(timestamp_ns + offset_ns.round() as u64);
+            let payload_words = (chunk.len() * bytes_per_sample).div_ceil(4);
+
+            self.packet.clear();
+            self.push_word(self.header(
+                PACKET_TYPE_IF_DATA_WITH_ID,
+                self.data_count,
+                PREFIX_WORDS + payload_words,
+            ));
+            self.data_count = (self.data_count + 1) % 16;
+            self.push_word(self.stream_id);
+            self.push_word(integer);
+            self.packet.extend_from_slice(&fractional.to_be_bytes());
+            for sample in chunk {
+                sample.write_be(&mut self.packet);
+            }
+            self.packet.resize((PREFIX_WORDS + payload_words) * 4, 0);
+            emit(&self.packet)?;
+        }
+        Ok(())
+    }
+
+    /// A context packet for samples of type `T`, valid from `timestamp_ns` on.
+    ///
+    /// `changed` sets the change indicator, telling receivers that something differs from the previous context.
+    pub fn encode_context<T: VrtSample>(
+        &mut self,
+        context: &VrtContext,
+        timestamp_ns: u64,
+        changed: bool,
+    ) -> &[u8] {
+        let (integer, fractional) = self.timestamp(timestamp_ns);
+        let mut indicators =
+            CIF_RF_REFERENCE_FREQUENCY | CIF_GAIN | CIF_SAMPLE_RATE | CIF_PAYLOAD_FORMAT;
+        if changed {
+            indicators |= CIF_CHANGED;
+        }
+        // prefix, indicators, frequency, gain, sample rate, payload format
+        let words = PREFIX_WORDS + 1 + 2 + 1 + 2 + 2;
+
+        self.packet.clear();
+        self.push_word(self.header(PACKET_TYPE_CONTEXT, self.context_count, words));
+        self.context_count = (self.context_count + 1) % 16;
+        self.push_word(self.stream_id);
+        self.push_word(integer);
+        self.packet.extend_from_slice(&fractional.to_be_bytes());
+        self.push_word(indicators);
+        self.packet
+            .extend_from_slice(&radix_20(context.frequency).to_be_bytes());
+        // stage 2 in the upper half, unused
+        self.push_word(radix_7(context.gain) as u16 as u32);
+        self.packet
+            .extend_from_slice(&radix_20(context.sample_rate).to_be_bytes());
+        self.packet
+            .extend_from_slice(&payload_format::<T>().to_be_bytes());
+        &self.packet
+    }
+
+    fn header(&self, packet_type: u32, count: u32, words: usize) -> u32 {
+        let tsi = match self.timestamps {
+            VrtTimestamps::Utc => TSI_UTC,
+            VrtTimestamps::StreamTime => TSI_OTHER,
+        };
+        packet_type << 28 | tsi << 22 | TSF_REAL_TIME << 20 | count << 16 | words as u32
+    }
+
+    /// Full seconds, and picoseconds into the second.
+    fn timestamp(&mut self, timestamp_ns: u64) -> (u32, u64) {
+        let ns = match self.timestamps {
+            VrtTimestamps::StreamTime => timestamp_ns as u128,
+            VrtTimestamps::Utc => {
+                let origin = *self.utc_origin_ns.get_or_insert_with(|| {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    now.as_nanos().saturating_sub(timestamp_ns as u128)
+                });
+                origin + timestamp_ns as u128
+            }
+        };
+        (
+            (ns / 1_000_000_000) as u32,
+            (ns % 1_000_000_000) as u64 * 1000,
+        )
+    }
+
+    fn push_word(&mut self, word: u32) {
+        self.packet.extend_from_slice(&word.to_be_bytes());
+    }
+}
+
+/// Hz as 64 bit fixed point with 20 fractional bits.
+fn radix_20(value: f64) -> i64 {
+    (value * (1u64 << 20) as f64).round() as i64
+}
+
+/// dB as 16 bit fixed point with 7 fractional bits.
+fn radix_7(value: f64) -> i16 {
+    (value * 128.0).round() as i16
+}
+
+/// Data packet payload format field: processing efficient packing of complex cartesian items.
+fn payload_format<T: VrtSample>() -> u64 {
+    let complex_cartesian: u64 = 0b01;
+    let item_format: u64 = if T::FLOAT { 0b01110 } else { 0b00000 };
+    let size = (T::ITEM_BITS - 1) as u64;
+    // the second word, repeat count and vector size, stays 0 for single items
+    (complex_cartesian << 29 | item_format << 24 | size << 6 | size) << 32
+}
+
+/// Where and how to send.
+#[derive(Debug, Clone)]
+pub struct VrtSettings {
+    /// Receiver of the packets, `127.0.0.1:4991` by default.
+    pub destination: String,
+    /// Local address to send from, any interface and port by default.
+    pub bind: String,
+    /// 1 by default.
+    pub stream_id: u32,
+    /// Largest UDP payload to send, in bytes. 1472 by default, which fits into a standard Ethernet frame,
+    /// jumbo frames allow larger packets and so a lot fewer of them.
+    pub max_packet_size: usize,
+    /// `VrtTimestamps::Utc` by default.
+    pub timestamps: VrtTimestamps,
+    /// Repeat an unchanged context this often, so receivers joining late learn it too. `None` only sends changes.
+    /// Every second by default.
+    pub context_interval: Option<Duration>,
+}
+
+impl Default for VrtSettings {
+    fn default() -> Self {
+        Self {
+            destination: "127.0.0.1:4991".to_string(),
+            bind: "0.0.0.0:0".to_string(),
+            stream_id: 1,
+            max_packet_size: 1472,
+            timestamps: VrtTimestamps::Utc,
+            context_interval: Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+/// Sends sample blocks as VRT data packets, with context packets as needed.
+pub struct VrtUdpSender {
+    socket: UdpSocket,
+    encoder: VrtEncoder,
+    settings: VrtSettings,
+    // the payload format is part of the context, so the sample type is too
+    last_context: Option<(VrtContext, u32)>,
+    last_context_sent: Option<Instant>,
+    packets_sent: u64,
+}
+
+impl VrtUdpSender {
+    pub fn connect(settings: VrtSettings) -> Result<Self, VrtError> {
+        let socket = UdpSocket::bind(&settings.bind)?;
+        socket.connect(&settings.destination)?;
+        let encoder = VrtEncoder::new(
+            settings.stream_id,
+            settings.timestamps,
+            settings.max_packet_size,
+        )?;
+        Ok(Self {
+            socket,
+            encoder,
+            settings,
+            last_context: None,
+            last_context_sent: None,
+            packets_sent: 0,
+        })
+    }
+
+    pub fn settings(&self) -> &VrtSettings {
+        &self.settings
+    }
+
+    /// Data and context packets sent so far.
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent
+    }
+
+    /// Send one block, `timestamp_ns` being its `StreamReadInfo::timestamp_ns`.
+    /// `context` describes the samples, a context packet goes out first if it changed since the last block.
+    pub fn send<T: VrtSample>(
+        &mut self,
+        samples: &[T],
+        timestamp_ns: u64,
+        context: &VrtContext,
+    ) -> Result<(), VrtError> {
+        let current = (*context, T::ITEM_BITS);
+        let changed = self.last_context != Some(current);
+        let due = match (self.last_context_sent, self.settings.context_interval) {
+            (Some(sent), Some(interval)) => sent.elapsed() >= interval,
+            _ => false,
+        };
+        if changed || due {
+            let packet = self
+                .encoder
+                .encode_context::<T>(context, timestamp_ns, changed);
+            self.socket.send(packet)?;
+            self.packets_sent += 1;
+            self.last_context = Some(current);
+            self.last_context_sent = Some(Instant::now());
+        }
+
+        let socket = &self.socket;
+        let mut sent = 0;
+        self.encoder
+            .encode_data(samples, timestamp_ns, context.sample_rate, |packet| {
+                sent += 1;
+                socket.send(packet).map(|_| ())
+            })?;
+        self.packets_sent += sent;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(packet: &[u8]) -> Vec<u32> {
+        packet
+            .chunks(4)
+            .map(|w| u32::from_be_bytes(w.try_into().unwrap()))
+            .collect()
+    }
+
+    fn encode<T: VrtSample>(
+        encoder: &mut VrtEncoder,
+        samples: &[T],
+        timestamp_ns: u64,
+    ) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        encoder
+            .encode_data(samples, timestamp_ns, 1e6, |packet| {
+                packets.push(packet.to_vec());
+                Ok(())
+            })
+            .unwrap();
+        packets
+    }
+
+    #[test]
+    fn data_packet_layout() {
+        let mut encoder = VrtEncoder::new(0x1234, VrtTimestamps::StreamTime, 1472).unwrap();
+        let samples = [
+            Complex::new(1i16, -1),
+            Complex::new(0x1234, 0x5678),
+            Complex::new(-2, 3),
+        ];
+        let packets = encode(&mut encoder, &samples, 1_500_000_000);
+        assert_eq!(packets.len(), 1);
+
+        let w = words(&packets[0]);
+        assert_eq!(w.len(), PREFIX_WORDS + 3);
+        assert_eq!(w[0], 0x1 << 28 | TSI_OTHER << 22 | TSF_REAL_TIME << 20 | 8);
+        assert_eq!(w[1], 0x1234);
+        assert_eq!(w[2], 1);
+        // half a second in picoseconds
+        assert_eq!((w[3] as u64) << 32 | w[4] as u64, 500_000_000_000);
+        assert_eq!(&w[5..], [0x0001_ffff, 0x1234_5678, 0xfffe_0003]);
+    }
+
+    #[test]
+    fn long_blocks_are_split() {
+        // room for 4 words of payload, so 4 16 bit samples per packet
+        let mut encoder =
+            VrtEncoder::new(1, VrtTimestamps::StreamTime, (PREFIX_WORDS + 4) * 4).unwrap();
+        let samples = vec![Complex::new(0i16, 0); 10];
+        let packets = encode(&mut encoder, &samples, 0);
+        let lengths: Vec<usize> = packets.iter().map(|p| p.len() / 4 - PREFIX_WORDS).collect();
+        assert_eq!(lengths, [4, 4, 2]);
+        for (count, packet) in packets.iter().enumerate() {
+            let w = words(packet);
+            assert_eq!((w[0] >> 16) & 0xf, count as u32);
+            assert_eq!(w[0] & 0xffff, (packet.len() / 4) as u32);
+            // 4 samples at 1 MHz apart
+            assert_eq!((w[3] as u64) << 32 | w[4] as u64, count as u64 * 4_000_000);
+        }
+        // the packet count wraps at 16
+        for _ in 0..5 {
+            encode(&mut encoder, &samples, 0);
+        }
+        let next = encode(&mut encoder, &samples[..1], 0);
+        assert_eq!((words(&next[0])[0] >> 16) & 0xf, 18 % 16);
+    }
+
+    #[test]
+    fn odd_8_bit_blocks_are_padded() {
+        let mut encoder = VrtEncoder::new(1, VrtTimestamps::StreamTime, 1472).unwrap();
+        let samples = [
+            Complex::new(1i8, 2),
+            Complex::new(3, 4),
+            Complex::new(-1, -2),
+        ];
+        let packets = encode(&mut encoder, &samples, 0);
+        assert_eq!(
+            &packets[0][PREFIX_WORDS * 4..],
+            [1, 2, 3, 4, 0xff, 0xfe, 0, 0]
+        );
+    }
+
+    #[test]
+    fn context_packet_layout() {
+        let mut encoder = VrtEncoder::new(7, VrtTimestamps::StreamTime, 1472).unwrap();
+        let context = VrtContext {
+            frequency: 100e6,
+            gain: 10.5,
+            sample_rate: 61.44e6,
+        };
+        let w = words(encoder.encode_context::<Complex<f32>>(&context, 2_000_000_000, true));
+        assert_eq!(w.len(), PREFIX_WORDS + 8);
+        assert_eq!(w[0], 0x4 << 28 | TSI_OTHER << 22 | TSF_REAL_TIME << 20 | 13);
+        assert_eq!(w[1], 7);
+        assert_eq!(w[2], 2);
+        assert_eq!(
+            w[5],
+            CIF_CHANGED
+                | CIF_RF_REFERENCE_FREQUENCY
+                | CIF_GAIN
+                | CIF_SAMPLE_RATE
+                | CIF_PAYLOAD_FORMAT
+        );
+        let frequency = (w[6] as u64) << 32 | w[7] as u64;
+        assert_eq!(frequency, 100_000_000 << 20);
+        assert_eq!(w[8], 10 * 128 + 64);
+        let sample_rate = (w[9] as u64) << 32 | w[10] as u64;
+        assert_eq!(sample_rate, 61_440_000 << 20);
+        // complex cartesian, IEEE-754 single, 32 bit items
+        assert_eq!(w[11], 1 << 29 | 0b01110 << 24 | 31 << 6 | 31);
+        assert_eq!(w[12], 0);
+
+        let w = words(encoder.encode_context::<Complex<i8>>(&context, 0, false));
+        assert_eq!(w[5] & CIF_CHANGED, 0);
+        assert_eq!((w[0] >> 16) & 0xf, 1);
+        assert_eq!(w[11], 1 << 29 | 7 << 6 | 7);
+    }
+
+    #[test]
+    fn tiny_packets_are_refused() {
+        assert!(matches!(
+            VrtEncoder::new(1, VrtTimestamps::Utc, 32),
+            Err(VrtError::PacketTooSmall(32))
+        ));
+    }
+}
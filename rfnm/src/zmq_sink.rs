@@ -0,0 +1,332 @@
+//! Publishing samples for GNU Radio's ZMQ blocks.
+//!
+//! `ZmqPubSink` sends every block it gets as one message on a ZeroMQ PUB socket,
+//! as raw items like GNU Radio's `ZMQ PUB Sink` does. Subscribe with a `ZMQ SUB Source`
+//! of the matching item type: `gr_complex` for `Complex<f32>`, a vector of 2 `short`s for `Complex<i16>`,
+//! a vector of 2 `byte`s for `Complex<i8>`.
+//!
+//! With `ZmqSinkSettings::pass_tags`, messages start with the tag header the source parses when its
+//! `pass_tags` is set too, carrying `rx_freq`, `rx_rate` and `rx_time` tags like a UHD source would.
+//!
+//! Only available with the `zmq` feature.
+
+use crate::stream::StreamDataFormat;
+use thiserror::Error;
+
+/// Marks a message that starts with a tag header, see gr-zeromq's `tag_headers.cc`.
+const GR_HEADER_MAGIC: u16 = 0x5FF0;
+const GR_HEADER_VERSION: u8 = 0x01;
+
+// pmt serialization type bytes, see pmt's `pmt_serial_tags.h`
+const PST_FALSE: u8 = 0x01;
+const PST_SYMBOL: u8 = 0x02;
+const PST_DOUBLE: u8 = 0x04;
+const PST_UINT64: u8 = 0x0b;
+const PST_TUPLE: u8 = 0x0c;
+
+#[derive(Debug, Error)]
+pub enum ZmqSinkError {
+    #[error("ZeroMQ failed: {0}")]
+    Zmq(#[from] zmq::Error),
+}
+
+/// Where and how to publish.
+#[derive(Debug, Clone)]
+pub struct ZmqSinkSettings {
+    /// ZeroMQ endpoint to bind to, `tcp://*:5555` by default.
+    pub endpoint: String,
+    /// Prefix messages with a tag header, on by default. The SUB source must have `pass_tags` set to match.
+    pub pass_tags: bool,
+    /// Messages queued per subscriber before new ones are dropped for it, 64 by default.
+    pub high_water_mark: i32,
+}
+
+impl Default for ZmqSinkSettings {
+    fn default() -> Self {
+        Self {
+            endpoint: "tcp://*:5555".to_string(),
+            pass_tags: true,
+            high_water_mark: 64,
+        }
+    }
+}
+
+/// What the samples of one block are, for the tags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockInfo {
+    /// `StreamReadInfo::timestamp_ns` of the block.
+    pub timestamp_ns: u64,
+    /// RF frequency at 0 Hz in the samples, in Hz.
+    pub frequency: f64,
+    pub sample_rate: f64,
+}
+
+/// Publishes sample blocks on a ZeroMQ PUB socket, in GNU Radio's format.
+pub struct ZmqPubSink {
+    // the context has to outlive the socket
+    _context: zmq::Context,
+    socket: zmq::Socket,
+    settings: ZmqSinkSettings,
+    items_sent: u64,
+    last_info: Option<BlockInfo>,
+    expected_timestamp_ns: Option<u64>,
+    message: Vec<u8>,
+}
+
+impl ZmqPubSink {
+    pub fn bind(settings: ZmqSinkSettings) -> Result<Self, ZmqSinkError> {
+        let context = zmq::Context::new();
+        let socket = context.socket(zmq::PUB)?;
+        socket.set_sndhwm(settings.high_water_mark)?;
+        socket.bind(&settings.endpoint)?;
+        Ok(Self {
+            _context: context,
+            socket,
+            settings,
+            items_sent: 0,
+            last_info: None,
+            expected_timestamp_ns: None,
+            message: Vec::new(),
+        })
+    }
+
+    pub fn settings(&self) -> &ZmqSinkSettings {
+        &self.settings
+    }
+
+    /// The endpoint the socket is bound to, with the port filled in if the settings left it to the system.
+    pub fn bound_endpoint(&self) -> Result<String, ZmqSinkError> {
+        // only fails for non utf-8 endpoints, which bind would have refused
+        Ok(self.socket.get_last_endpoint()?.unwrap_or_default())
+    }
+
+    /// Items published so far. GNU Radio counts tag offsets in the same items.
+    pub fn items_sent(&self) -> u64 {
+        self.items_sent
+    }
+
+    /// Publish one block. Tags are only sent when something changed:
+    /// frequency and rate on change, the time on the first block and after every gap in the timestamps.
+    pub fn send<T: StreamDataFormat>(
+        &mut self,
+        samples: &[T],
+        info: &BlockInfo,
+    ) -> Result<(), ZmqSinkError> {
+        self.message.clear();
+        if self.settings.pass_tags {
+            let last = self.last_info;
+            let mut tags: Vec<(&str, Vec<u8>)> = Vec::new();
+            if last.is_none_or(|l| l.frequency != info.frequency) {
+                tags.push(("rx_freq", pmt_double(info.frequency)));
+            }
+            if last.is_none_or(|l| l.sample_rate != info.sample_rate) {
+                tags.push(("rx_rate", pmt_double(info.sample_rate)));
+            }
+            if self.expected_timestamp_ns != Some(info.timestamp_ns) {
+                tags.push(("rx_time", pmt_time(info.timestamp_ns)));
+            }
+            self.write_tag_header(&tags);
+        }
+        // every `StreamDataFormat` is a plain pair of numbers, which is exactly GNU Radio's item layout
+        let bytes = unsafe {
+            std::slice::from_raw_parts(samples.as_ptr().cast::<u8>(), size_of_val(samples))
+        };
+        self.message.extend_from_slice(bytes);
+        self.socket.send(&self.message, 0)?;
+
+        self.items_sent += samples.len() as u64;
+        self.last_info = Some(*info);
+        let block_ns = samples.len() as f64 * 1e9 / info.sample_rate;
+        self.expected_timestamp_ns = Some(info.timestamp_ns + block_ns.round() as u64);
+        Ok(())
+    }
+
+    fn write_tag_header(&mut self, tags: &[(&str, Vec<u8>)]) {
+        // the header itself is in host byte order, only the pmts inside are big endian
+        self.message
+            .extend_from_slice(&GR_HEADER_MAGIC.to_ne_bytes());
+        self.message.push(GR_HEADER_VERSION);
+        self.message
+            .extend_from_slice(&self.items_sent.to_ne_bytes());
+        self.message
+            .extend_from_slice(&(tags.len() as u64).to_ne_bytes());
+        for (key, value) in tags {
+            self.message
+                .extend_from_slice(&self.items_sent.to_ne_bytes());
+            self.message.extend_from_slice(&pmt_symbol(key));
+            self.message.extend_from_slice(value);
+            // source id, not set
+            self.message.push(PST_FALSE);
+        }
+    }
+}
+
+fn pmt_symbol(symbol: &str) -> Vec<u8> {
+    let mut out = vec![PST_SYMBOL];
+    out.extend_from_slice(&(symbol.len() as u16).to_be_bytes());
+    out.extend_from_slice(symbol.as_bytes());
+    out
+}
+
+fn pmt_double(value: f64) -> Vec<u8> {
+    let mut out = vec![PST_DOUBLE];
+    out.extend_from_slice(&value.to_be_bytes());
+    out
+}
+
+/// Time as `(full seconds, fractional seconds)`, the way UHD sources tag `rx_time`.
+fn pmt_time(timestamp_ns: u64) -> Vec<u8> {
+    let mut out = vec![PST_TUPLE];
+    out.extend_from_slice(&2u32.to_be_bytes());
+    out.push(PST_UINT64);
+    out.extend_from_slice(&(timestamp_ns / 1_000_000_000).to_be_bytes());
+    out.extend_from_slice(&pmt_double((timestamp_ns % 1_000_000_000) as f64 / 1e9));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_complex::Complex;
+
+    const SAMPLES: [Complex<i16>; 4] = [
+        Complex::new(1, -2),
+        Complex::new(3, 4),
+        Complex::new(-5, 6),
+        Complex::new(7, -8),
+    ];
+
+    fn sample_bytes() -> Vec<u8> {
+        SAMPLES
+            .iter()
+            .flat_map(|s| [s.re.to_ne_bytes(), s.im.to_ne_bytes()])
+            .flatten()
+            .collect()
+    }
+
+    /// A sink on a free local port and a subscriber that already gets its messages.
+    /// PUB drops everything until the subscription arrived, so blocks go out until one makes it.
+    fn connected(pass_tags: bool) -> (ZmqPubSink, zmq::Context, zmq::Socket, BlockInfo) {
+        let mut sink = ZmqPubSink::bind(ZmqSinkSettings {
+            endpoint: "tcp://127.0.0.1:*".to_string(),
+            pass_tags,
+            ..Default::default()
+        })
+        .unwrap();
+        let context = zmq::Context::new();
+        let sub = context.socket(zmq::SUB).unwrap();
+        sub.set_rcvtimeo(50).unwrap();
+        sub.connect(&sink.bound_endpoint().unwrap()).unwrap();
+        sub.set_subscribe(b"").unwrap();
+
+        let mut info = BlockInfo {
+            timestamp_ns: 1_500_000_000,
+            frequency: 100e6,
+            sample_rate: 1e6,
+        };
+        for _ in 0..100 {
+            sink.send(&SAMPLES, &info).unwrap();
+            info.timestamp_ns += 4_000;
+            if sub.recv_bytes(0).is_ok() {
+                return (sink, context, sub, info);
+            }
+        }
+        panic!("the subscriber never got a message");
+    }
+
+    /// Length of the pmt at the start of `bytes`, for the types the sink writes.
+    fn pmt_len(bytes: &[u8]) -> usize {
+        match bytes[0] {
+            PST_FALSE => 1,
+            PST_SYMBOL => 3 + u16::from_be_bytes([bytes[1], bytes[2]]) as usize,
+            PST_DOUBLE | PST_UINT64 => 9,
+            PST_TUPLE => {
+                let count = u32::from_be_bytes(bytes[1..5].try_into().unwrap());
+                let mut len = 5;
+                for _ in 0..count {
+                    len += pmt_len(&bytes[len..]);
+                }
+                len
+            }
+            other => panic!("unexpected pmt type {other:#x}"),
+        }
+    }
+
+    struct Parsed {
+        offset: u64,
+        tags: Vec<(u64, String, Vec<u8>)>,
+        items: Vec<u8>,
+    }
+
+    fn parse(message: &[u8]) -> Parsed {
+        let u64_at = |at: usize| u64::from_ne_bytes(message[at..at + 8].try_into().unwrap());
+        assert_eq!(&message[..2], &GR_HEADER_MAGIC.to_ne_bytes());
+        assert_eq!(message[2], GR_HEADER_VERSION);
+        let offset = u64_at(3);
+        let count = u64_at(11);
+        let mut at = 19;
+        let mut tags = Vec::new();
+        for _ in 0..count {
+            let tag_offset = u64_at(at);
+            at += 8;
+            let key_len = pmt_len(&message[at..]);
+            let key = String::from_utf8(message[at + 3..at + key_len].to_vec()).unwrap();
+            at += key_len;
+            let value_len = pmt_len(&message[at..]);
+            let value = message[at..at + value_len].to_vec();
+            at += value_len;
+            assert_eq!(message[at], PST_FALSE);
+            at += 1;
+            tags.push((tag_offset, key, value));
+        }
+        Parsed {
+            offset,
+            tags,
+            items: message[at..].to_vec(),
+        }
+    }
+
+    #[test]
+    fn tags_precede_the_items() {
+        let (mut sink, _context, sub, mut info) = connected(true);
+
+        // nothing changed, so no tags
+        let before = sink.items_sent();
+        sink.send(&SAMPLES, &info).unwrap();
+        let parsed = parse(&sub.recv_bytes(0).unwrap());
+        assert_eq!(parsed.offset, before);
+        assert!(parsed.tags.is_empty());
+        assert_eq!(parsed.items, sample_bytes());
+
+        // a new frequency and a gap in the timestamps
+        info.timestamp_ns += 4_000 + 2_250_000_000;
+        info.frequency = 101e6;
+        let before = sink.items_sent();
+        sink.send(&SAMPLES, &info).unwrap();
+        let parsed = parse(&sub.recv_bytes(0).unwrap());
+        assert_eq!(parsed.offset, before);
+        assert_eq!(parsed.items, sample_bytes());
+        let keys: Vec<_> = parsed.tags.iter().map(|(_, key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["rx_freq", "rx_time"]);
+        assert!(parsed.tags.iter().all(|(offset, _, _)| *offset == before));
+        assert_eq!(parsed.tags[0].2, pmt_double(101e6));
+        assert_eq!(parsed.tags[1].2, pmt_time(info.timestamp_ns));
+    }
+
+    #[test]
+    fn without_tags_messages_are_items() {
+        let (mut sink, _context, sub, info) = connected(false);
+        sink.send(&SAMPLES, &info).unwrap();
+        assert_eq!(sub.recv_bytes(0).unwrap(), sample_bytes());
+        assert_eq!(sink.items_sent() % 4, 0);
+    }
+
+    #[test]
+    fn time_is_seconds_and_fraction() {
+        let mut expected = vec![PST_TUPLE, 0, 0, 0, 2, PST_UINT64];
+        expected.extend_from_slice(&3u64.to_be_bytes());
+        expected.push(PST_DOUBLE);
+        expected.extend_from_slice(&0.25f64.to_be_bytes());
+        assert_eq!(pmt_time(3_250_000_000), expected);
+    }
+}
@@ -0,0 +1,23 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    // the header goes to OUT_DIR, include/rfnm_capi.h is the checked in copy of it.
+    // That one is only rewritten on request, the header test in src/lib.rs catches when it is stale.
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml"))
+        .expect("Couldn't read cbindgen.toml");
+    let header = cbindgen::generate_with_config(&crate_dir, config)
+        .expect("Couldn't generate the C header");
+    header.write_to_file(out_dir.join("rfnm_capi.h"));
+    if env::var_os("RFNM_CAPI_UPDATE_HEADER").is_some() {
+        header.write_to_file(crate_dir.join("include/rfnm_capi.h"));
+    }
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=../rfnm/src/channel_settings.rs");
+    println!("cargo:rerun-if-env-changed=RFNM_CAPI_UPDATE_HEADER");
+}
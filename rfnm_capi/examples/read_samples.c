@@ -0,0 +1,63 @@
+// Reads a few blocks from channel 0 of the first board and prints the power of each.
+// Needs a connected board, or rfnm_capi built with the mock feature.
+//
+// cargo build -p rfnm_capi --release
+// cc read_samples.c -I ../include -L ../../target/release -lrfnm_capi -o read_samples
+
+#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "rfnm_capi.h"
+
+static int check(RfnmStatus status, const char* what) {
+  if (status != RFNM_STATUS_OK) {
+    fprintf(stderr, "%s failed (%d): %s\n", what, (int)status, rfnm_last_error_message());
+    return 0;
+  }
+  return 1;
+}
+
+int main(int argc, char** argv) {
+  const char* serial = argc > 1 ? argv[1] : NULL;
+  RfnmDevice* device = NULL;
+  if (!check(rfnm_device_open(serial, &device), "rfnm_device_open")) {
+    return 1;
+  }
+
+  RfnmRxChannelSettings settings;
+  rfnm_rx_channel_settings_default(&settings);
+  settings.frequency = 100000000;
+  if (!check(rfnm_device_set_rx_settings(device, 0, &settings), "rfnm_device_set_rx_settings")) {
+    rfnm_device_free(device);
+    return 1;
+  }
+
+  RfnmRxStream* stream = NULL;
+  if (!check(rfnm_rx_stream_create(&device, 1, RFNM_STREAM_FORMAT_CF32, &stream), "rfnm_rx_stream_create")) {
+    rfnm_device_free(device);
+    return 1;
+  }
+
+  const size_t elements = rfnm_rx_stream_suggested_buffer_size(stream);
+  float* samples = malloc(elements * 2 * sizeof(float));
+  void* buffers[1] = {samples};
+  int ok = check(rfnm_rx_stream_start(stream), "rfnm_rx_stream_start");
+  for (int block = 0; ok && block < 10; ++block) {
+    size_t read = 0;
+    uint64_t timestamp_ns = 0;
+    ok = check(rfnm_rx_stream_read(stream, buffers, 1, elements, 100000, &read, &timestamp_ns), "rfnm_rx_stream_read");
+    double power = 0.0;
+    for (size_t i = 0; i < read * 2; ++i) {
+      power += samples[i] * samples[i];
+    }
+    if (ok && read > 0) {
+      printf("%llu ns: %zu samples, %.1f dBFS\n", (unsigned long long)timestamp_ns, read, 10.0 * log10(power / read + 1e-20));
+    }
+  }
+
+  rfnm_rx_stream_stop(stream);
+  rfnm_rx_stream_free(stream);
+  free(samples);
+  return ok ? 0 : 1;
+}
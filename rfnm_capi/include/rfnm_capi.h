@@ -0,0 +1,147 @@
+#ifndef RFNM_CAPI_H
+#define RFNM_CAPI_H
+
+/* Generated by cbindgen from rfnm_capi/src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of every fallible call. `RFNM_STATUS_OK` is 0, the rest map `RfnmApiError`.
+typedef enum RfnmStatus {
+  RFNM_STATUS_OK = 0,
+  RFNM_STATUS_API_EXCEPTION,
+  RFNM_STATUS_BUFFER_COUNT_MISMATCH,
+  RFNM_STATUS_BUFFER_SIZE_MISMATCH,
+  RFNM_STATUS_PROBE_FAIL,
+  RFNM_STATUS_TUNE_FAIL,
+  RFNM_STATUS_GAIN_FAIL,
+  RFNM_STATUS_TIMEOUT,
+  RFNM_STATUS_USB_FAIL,
+  RFNM_STATUS_DQBUF_OVERFLOW,
+  RFNM_STATUS_NOT_SUPPORTED,
+  RFNM_STATUS_SOFTWARE_UPGRADE_REQUIRED,
+  RFNM_STATUS_DQBUF_NO_DATA,
+  RFNM_STATUS_MIN_QBUF_COUNT_NOT_SATISFIED,
+  RFNM_STATUS_MIN_QBUF_QUEUE_FULL,
+  RFNM_STATUS_INVALID_SERIAL,
+  RFNM_STATUS_UNKNOWN,
+  // A handle or output pointer was null, or a handle was already used up.
+  RFNM_STATUS_NULL_POINTER,
+  // An argument is out of range, e.g. a channel number.
+  RFNM_STATUS_INVALID_ARGUMENT,
+  // Later settings were executed before the result of these was read.
+  RFNM_STATUS_SET_RESULT_SUPERSEDED,
+  // A stream was asked for a channel the board does not have.
+  RFNM_STATUS_NO_SUCH_CHANNEL,
+  RFNM_STATUS_CHANNEL_UNAVAILABLE,
+  // A stream was asked for a channel that already streams.
+  RFNM_STATUS_CHANNEL_ALREADY_ENABLED,
+  // The channels of a stream have different sample rate dividers.
+  RFNM_STATUS_SAMPLE_RATE_MISMATCH,
+  // Two channels of a stream are sampled by the same ADC.
+  RFNM_STATUS_ADC_CONFLICT,
+} RfnmStatus;
+
+// Sample layout of a stream's buffers. Complex samples with I first, then Q.
+typedef enum RfnmStreamFormat {
+  // Two floats per sample, full scale is 1.0
+  RFNM_STREAM_FORMAT_CF32,
+  // Two int16_t per sample
+  RFNM_STREAM_FORMAT_CS16,
+  // Two int8_t per sample
+  RFNM_STREAM_FORMAT_CS8,
+} RfnmStreamFormat;
+
+// A connected board.
+typedef struct RfnmDevice RfnmDevice;
+
+// A rx stream over one or more channels of a board.
+typedef struct RfnmRxStream RfnmRxStream;
+
+// `RxChannelSettings` as plain numbers, for bindings and wire formats that cannot carry the nested types.
+typedef struct RfnmRxChannelSettings {
+  int64_t frequency;
+  int8_t gain;
+  // Sample rate is dcs_clk / n, m does not change it
+  int16_t m;
+  int16_t n;
+  // Index of the rf path, 0 is SMA A
+  uint32_t path;
+  float iq_gain;
+  float iq_phase;
+  int64_t tuning_offset;
+} RfnmRxChannelSettings;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Description of the last failure on this thread. Valid until the next failing call on the same thread.
+const char *rfnm_last_error_message(void);
+
+// Fill `settings` with the defaults used on the rust side.
+enum RfnmStatus rfnm_rx_channel_settings_default(struct RfnmRxChannelSettings *settings);
+
+// Connect to the board with the given serial number, or to the first one found if `serial` is null.
+enum RfnmStatus rfnm_device_open(const char *serial, struct RfnmDevice **device);
+
+// Disconnect. Null is ignored.
+void rfnm_device_free(struct RfnmDevice *device);
+
+enum RfnmStatus rfnm_device_get_rx_settings(const struct RfnmDevice *device,
+                                            uint32_t channel,
+                                            struct RfnmRxChannelSettings *settings);
+
+enum RfnmStatus rfnm_device_set_rx_settings(struct RfnmDevice *device,
+                                            uint32_t channel,
+                                            const struct RfnmRxChannelSettings *settings);
+
+// Create a stream over the channels in the `channels` bit mask (bit 0 is channel 0).
+//
+// The stream takes the device over: on success `*device` is set to null and must not be freed anymore,
+// it is freed together with the stream. On failure the device stays usable.
+enum RfnmStatus rfnm_rx_stream_create(struct RfnmDevice **device,
+                                      uint8_t channels,
+                                      enum RfnmStreamFormat format,
+                                      struct RfnmRxStream **stream);
+
+// Stop streaming and free the stream together with its device. Null is ignored.
+void rfnm_rx_stream_free(struct RfnmRxStream *stream);
+
+enum RfnmStatus rfnm_rx_stream_start(struct RfnmRxStream *stream);
+
+enum RfnmStatus rfnm_rx_stream_stop(struct RfnmRxStream *stream);
+
+// Sample rate of every channel of the stream in Hz, 0 for a null stream.
+double rfnm_rx_stream_sample_rate(const struct RfnmRxStream *stream);
+
+// Suggested number of samples per channel to read at once, 0 for a null stream.
+size_t rfnm_rx_stream_suggested_buffer_size(const struct RfnmRxStream *stream);
+
+// Change the settings of one channel of the stream's device.
+enum RfnmStatus rfnm_rx_stream_set_rx_settings(struct RfnmRxStream *stream,
+                                               uint32_t channel,
+                                               const struct RfnmRxChannelSettings *settings);
+
+// Read `elements` samples for every channel of the stream.
+//
+// `buffers` holds one buffer per channel, in channel order, each with room for `elements` samples.
+// `elements_read` and `timestamp_ns` may be null.
+// When the read stops short, the samples it got are still counted in `elements_read`
+// and the status says why, usually `RFNM_STATUS_TIMEOUT`.
+enum RfnmStatus rfnm_rx_stream_read(struct RfnmRxStream *stream,
+                                    void *const *buffers,
+                                    size_t buffer_count,
+                                    size_t elements,
+                                    uint32_t timeout_us,
+                                    size_t *elements_read,
+                                    uint64_t *timestamp_ns);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RFNM_CAPI_H */
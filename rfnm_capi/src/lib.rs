@@ -0,0 +1,422 @@
+//! C API for the rfnm crate.
+//!
+//! Devices and streams are opaque handles. Every function that can fail returns a `RfnmStatus`,
+//! and `rfnm_last_error_message` has the details of the last failure on the calling thread.
+//! `include/rfnm_capi.h` is the header, `FlatRxChannelSettings` is `RfnmRxChannelSettings` in it.
+//! Every build generates it into `OUT_DIR`, and a test fails while the checked in copy differs,
+//! `RFNM_CAPI_UPDATE_HEADER=1 cargo build -p rfnm_capi` refreshes that.
+//!
+//! Handles may be moved between threads, but must not be used from several threads at once.
+//!
+//! Safety is the same for every function: pointers are either null or valid for what they point to,
+//! and handles are ones this API handed out and did not free or take over yet.
+#![allow(clippy::missing_safety_doc)]
+
+use num_complex::Complex;
+use rfnm::channel_settings::FlatRxChannelSettings;
+use rfnm::device::Device;
+use rfnm::stream::{RxStream, StreamDataFormat, StreamReadInfo};
+use rfnm::{MAX_RX_CHANNELS, RfnmApiError, rfnm_channel};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::time::Duration;
+
+/// Result of every fallible call. `RFNM_STATUS_OK` is 0, the rest map `RfnmApiError`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RfnmStatus {
+    Ok = 0,
+    ApiException,
+    BufferCountMismatch,
+    BufferSizeMismatch,
+    ProbeFail,
+    TuneFail,
+    GainFail,
+    Timeout,
+    UsbFail,
+    DqbufOverflow,
+    NotSupported,
+    SoftwareUpgradeRequired,
+    DqbufNoData,
+    MinQbufCountNotSatisfied,
+    MinQbufQueueFull,
+    InvalidSerial,
+    Unknown,
+    /// A handle or output pointer was null, or a handle was already used up.
+    NullPointer,
+    /// An argument is out of range, e.g. a channel number.
+    InvalidArgument,
+    /// Later settings were executed before the result of these was read.
+    SetResultSuperseded,
+    /// A stream was asked for a channel the board does not have.
+    NoSuchChannel,
+    ChannelUnavailable,
+    /// A stream was asked for a channel that already streams.
+    ChannelAlreadyEnabled,
+    /// The channels of a stream have different sample rate dividers.
+    SampleRateMismatch,
+    /// Two channels of a stream are sampled by the same ADC.
+    AdcConflict,
+}
+
+impl From<&RfnmApiError> for RfnmStatus {
+    fn from(value: &RfnmApiError) -> Self {
+        match value {
+            RfnmApiError::ApiException(_) => RfnmStatus::ApiException,
+            RfnmApiError::BufferCountMismatch(..) => RfnmStatus::BufferCountMismatch,
+            RfnmApiError::BufferSizeMismatch => RfnmStatus::BufferSizeMismatch,
+            RfnmApiError::ProbeFail => RfnmStatus::ProbeFail,
+            RfnmApiError::TuneFail => RfnmStatus::TuneFail,
+            RfnmApiError::GainFail => RfnmStatus::GainFail,
+            RfnmApiError::Timeout => RfnmStatus::Timeout,
+            RfnmApiError::UsbFail => RfnmStatus::UsbFail,
+            RfnmApiError::DqbufOverflow => RfnmStatus::DqbufOverflow,
+            RfnmApiError::NotSupported => RfnmStatus::NotSupported,
+            RfnmApiError::SoftwareUpgradeRequred => RfnmStatus::SoftwareUpgradeRequired,
+            RfnmApiError::DqbufNoData => RfnmStatus::DqbufNoData,
+            RfnmApiError::MinQbufCountNotSatisfied => RfnmStatus::MinQbufCountNotSatisfied,
+            RfnmApiError::MinQbufQueueFull => RfnmStatus::MinQbufQueueFull,
+            RfnmApiError::InvalidSerial(_) => RfnmStatus::InvalidSerial,
+            RfnmApiError::SetResultSuperseded => RfnmStatus::SetResultSuperseded,
+            RfnmApiError::NoSuchChannel(_) => RfnmStatus::NoSuchChannel,
+            RfnmApiError::NotSingleChannel(_) => RfnmStatus::InvalidArgument,
+            RfnmApiError::ChannelUnavailable(_) => RfnmStatus::ChannelUnavailable,
+            RfnmApiError::ChannelAlreadyEnabled(_) => RfnmStatus::ChannelAlreadyEnabled,
+            RfnmApiError::SampleRateMismatch { .. } => RfnmStatus::SampleRateMismatch,
+            RfnmApiError::AdcConflict { .. } => RfnmStatus::AdcConflict,
+            RfnmApiError::Unknown(_) => RfnmStatus::Unknown,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn fail(status: RfnmStatus, message: impl ToString) -> RfnmStatus {
+    // interior nul bytes cannot happen in our messages, but better an empty message than a panic
+    let message = CString::new(message.to_string()).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status
+}
+
+fn api_fail(e: RfnmApiError) -> RfnmStatus {
+    fail((&e).into(), e)
+}
+
+fn status(result: Result<(), RfnmApiError>) -> RfnmStatus {
+    match result {
+        Ok(()) => RfnmStatus::Ok,
+        Err(e) => api_fail(e),
+    }
+}
+
+fn channel_flag(channel: u32) -> Result<rfnm_channel, RfnmStatus> {
+    if (channel as usize) < MAX_RX_CHANNELS {
+        Ok(rfnm_channel(1 << channel))
+    } else {
+        Err(fail(
+            RfnmStatus::InvalidArgument,
+            format!("channel {channel} out of range"),
+        ))
+    }
+}
+
+/// Description of the last failure on this thread. Valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn rfnm_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Fill `settings` with the defaults used on the rust side.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_channel_settings_default(
+    settings: *mut FlatRxChannelSettings,
+) -> RfnmStatus {
+    let Some(settings) = (unsafe { settings.as_mut() }) else {
+        return fail(RfnmStatus::NullPointer, "settings is null");
+    };
+    *settings = FlatRxChannelSettings::default();
+    RfnmStatus::Ok
+}
+
+/// A connected board.
+pub struct RfnmDevice {
+    device: Device,
+}
+
+/// Connect to the board with the given serial number, or to the first one found if `serial` is null.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_device_open(
+    serial: *const c_char,
+    device: *mut *mut RfnmDevice,
+) -> RfnmStatus {
+    if device.is_null() {
+        return fail(RfnmStatus::NullPointer, "device is null");
+    }
+    let connected = if serial.is_null() {
+        Device::connect_usb()
+    } else {
+        let serial = unsafe { CStr::from_ptr(serial) }.to_string_lossy();
+        Device::connect_usb_serial(&serial)
+    };
+    match connected {
+        Ok(connected) => {
+            let handle = Box::new(RfnmDevice { device: connected });
+            unsafe { *device = Box::into_raw(handle) };
+            RfnmStatus::Ok
+        }
+        Err(e) => api_fail(e),
+    }
+}
+
+/// Disconnect. Null is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_device_free(device: *mut RfnmDevice) {
+    if !device.is_null() {
+        drop(unsafe { Box::from_raw(device) });
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_device_get_rx_settings(
+    device: *const RfnmDevice,
+    channel: u32,
+    settings: *mut FlatRxChannelSettings,
+) -> RfnmStatus {
+    let (Some(device), Some(settings)) = (unsafe { device.as_ref() }, unsafe { settings.as_mut() })
+    else {
+        return fail(RfnmStatus::NullPointer, "device or settings is null");
+    };
+    let channel = match channel_flag(channel) {
+        Ok(channel) => channel,
+        Err(status) => return status,
+    };
+    match device.device.get_rx_settings(channel) {
+        Ok(info) => {
+            *settings = info.to_settings().into();
+            RfnmStatus::Ok
+        }
+        Err(e) => api_fail(e),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_device_set_rx_settings(
+    device: *mut RfnmDevice,
+    channel: u32,
+    settings: *const FlatRxChannelSettings,
+) -> RfnmStatus {
+    let (Some(device), Some(settings)) = (unsafe { device.as_ref() }, unsafe { settings.as_ref() })
+    else {
+        return fail(RfnmStatus::NullPointer, "device or settings is null");
+    };
+    let channel = match channel_flag(channel) {
+        Ok(channel) => channel,
+        Err(status) => return status,
+    };
+    status(device.device.set_rx_settings(channel, &(*settings).into()))
+}
+
+/// Sample layout of a stream's buffers. Complex samples with I first, then Q.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RfnmStreamFormat {
+    /// Two floats per sample, full scale is 1.0
+    Cf32,
+    /// Two int16_t per sample
+    Cs16,
+    /// Two int8_t per sample
+    Cs8,
+}
+
+enum StreamKind {
+    Cf32(RxStream<Complex<f32>>),
+    Cs16(RxStream<Complex<i16>>),
+    Cs8(RxStream<Complex<i8>>),
+}
+
+/// A rx stream over one or more channels of a board.
+pub struct RfnmRxStream {
+    stream: StreamKind,
+}
+
+macro_rules! with_stream {
+    ($handle:expr, $s:ident => $body:expr) => {
+        match &$handle.stream {
+            StreamKind::Cf32($s) => $body,
+            StreamKind::Cs16($s) => $body,
+            StreamKind::Cs8($s) => $body,
+        }
+    };
+}
+
+/// Create a stream over the channels in the `channels` bit mask (bit 0 is channel 0).
+///
+/// The stream takes the device over: on success `*device` is set to null and must not be freed anymore,
+/// it is freed together with the stream. On failure the device stays usable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_stream_create(
+    device: *mut *mut RfnmDevice,
+    channels: u8,
+    format: RfnmStreamFormat,
+    stream: *mut *mut RfnmRxStream,
+) -> RfnmStatus {
+    if device.is_null() || stream.is_null() || unsafe { *device }.is_null() {
+        return fail(RfnmStatus::NullPointer, "device or stream is null");
+    }
+    let owned = unsafe { Box::from_raw(*device) }.device;
+    let flags = rfnm_channel(channels as u32);
+    let created = match format {
+        RfnmStreamFormat::Cf32 => RxStream::new(owned, flags).map(StreamKind::Cf32),
+        RfnmStreamFormat::Cs16 => RxStream::new(owned, flags).map(StreamKind::Cs16),
+        RfnmStreamFormat::Cs8 => RxStream::new(owned, flags).map(StreamKind::Cs8),
+    };
+    match created {
+        Ok(created) => {
+            unsafe {
+                *device = std::ptr::null_mut();
+                *stream = Box::into_raw(Box::new(RfnmRxStream { stream: created }));
+            }
+            RfnmStatus::Ok
+        }
+        Err((e, owned)) => {
+            // hand the device back in a fresh handle
+            unsafe { *device = Box::into_raw(Box::new(RfnmDevice { device: owned })) };
+            api_fail(e)
+        }
+    }
+}
+
+/// Stop streaming and free the stream together with its device. Null is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_stream_free(stream: *mut RfnmRxStream) {
+    if !stream.is_null() {
+        drop(unsafe { Box::from_raw(stream) });
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_stream_start(stream: *mut RfnmRxStream) -> RfnmStatus {
+    let Some(stream) = (unsafe { stream.as_ref() }) else {
+        return fail(RfnmStatus::NullPointer, "stream is null");
+    };
+    status(with_stream!(stream, s => s.start()))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_stream_stop(stream: *mut RfnmRxStream) -> RfnmStatus {
+    let Some(stream) = (unsafe { stream.as_ref() }) else {
+        return fail(RfnmStatus::NullPointer, "stream is null");
+    };
+    status(with_stream!(stream, s => s.stop()))
+}
+
+/// Sample rate of every channel of the stream in Hz, 0 for a null stream.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_stream_sample_rate(stream: *const RfnmRxStream) -> f64 {
+    unsafe { stream.as_ref() }.map_or(0.0, |stream| with_stream!(stream, s => s.sample_rate()))
+}
+
+/// Suggested number of samples per channel to read at once, 0 for a null stream.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_stream_suggested_buffer_size(
+    stream: *const RfnmRxStream,
+) -> usize {
+    unsafe { stream.as_ref() }.map_or(
+        0,
+        |stream| with_stream!(stream, s => s.suggested_buffer_size()),
+    )
+}
+
+/// Change the settings of one channel of the stream's device.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_stream_set_rx_settings(
+    stream: *mut RfnmRxStream,
+    channel: u32,
+    settings: *const FlatRxChannelSettings,
+) -> RfnmStatus {
+    let (Some(stream), Some(settings)) = (unsafe { stream.as_ref() }, unsafe { settings.as_ref() })
+    else {
+        return fail(RfnmStatus::NullPointer, "stream or settings is null");
+    };
+    let channel = match channel_flag(channel) {
+        Ok(channel) => channel,
+        Err(status) => return status,
+    };
+    let settings = (*settings).into();
+    status(with_stream!(stream, s => s.device().set_rx_settings(channel, &settings)))
+}
+
+/// Read `elements` samples for every channel of the stream.
+///
+/// `buffers` holds one buffer per channel, in channel order, each with room for `elements` samples.
+/// `elements_read` and `timestamp_ns` may be null.
+/// When the read stops short, the samples it got are still counted in `elements_read`
+/// and the status says why, usually `RFNM_STATUS_TIMEOUT`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn rfnm_rx_stream_read(
+    stream: *mut RfnmRxStream,
+    buffers: *const *mut c_void,
+    buffer_count: usize,
+    elements: usize,
+    timeout_us: u32,
+    elements_read: *mut usize,
+    timestamp_ns: *mut u64,
+) -> RfnmStatus {
+    let Some(stream) = (unsafe { stream.as_ref() }) else {
+        return fail(RfnmStatus::NullPointer, "stream is null");
+    };
+    if buffers.is_null() || buffer_count > MAX_RX_CHANNELS {
+        return fail(RfnmStatus::InvalidArgument, "buffers is null or too many");
+    }
+    let buffers = unsafe { std::slice::from_raw_parts(buffers, buffer_count) };
+    if buffers.iter().any(|b| b.is_null()) {
+        return fail(RfnmStatus::NullPointer, "one of the buffers is null");
+    }
+    let timeout = Duration::from_micros(timeout_us as u64);
+    let result = with_stream!(stream, s => unsafe { read_into(s, buffers, elements, timeout) });
+    match result {
+        Ok(info) => {
+            unsafe {
+                if let Some(elements_read) = elements_read.as_mut() {
+                    *elements_read = info.elements_read;
+                }
+                if let Some(timestamp_ns) = timestamp_ns.as_mut() {
+                    *timestamp_ns = info.timestamp_ns;
+                }
+            }
+            match info.error {
+                Some(e) => api_fail(e),
+                None => RfnmStatus::Ok,
+            }
+        }
+        Err(e) => api_fail(e),
+    }
+}
+
+unsafe fn read_into<T: StreamDataFormat>(
+    stream: &RxStream<T>,
+    buffers: &[*mut c_void],
+    elements: usize,
+    timeout: Duration,
+) -> Result<StreamReadInfo, RfnmApiError> {
+    let mut slices: Vec<&mut [T]> = buffers
+        .iter()
+        .map(|b| unsafe { std::slice::from_raw_parts_mut(b.cast::<T>(), elements) })
+        .collect();
+    stream.read(&mut slices, timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn checked_in_header_is_current() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/rfnm_capi.h"));
+        let checked_in = include_str!("../include/rfnm_capi.h");
+        assert!(
+            generated == checked_in,
+            "include/rfnm_capi.h is stale, refresh it with RFNM_CAPI_UPDATE_HEADER=1 cargo build -p rfnm_capi"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// Runs the C API against the simulated board of the mock feature. Exits with 0 if every check passes.
+//
+// cargo build -p rfnm_capi --features mock
+// cc tests/test_capi.c -I include -L ../target/debug -lrfnm_capi -lm -o test_capi
+// LD_LIBRARY_PATH=../target/debug ./test_capi
+//
+// The mock has two rx channels, each carrying a tone of (channel + 1) / 64 of the sample rate,
+// 0.1 of full scale at 0 dB gain and 10 times that at 20 dB.
+
+#include <complex.h>
+#include <math.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "rfnm_capi.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                              \
+  do {                                                                           \
+    if (!(cond)) {                                                               \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+      ++failures;                                                                \
+    }                                                                            \
+  } while (0)
+
+#define CHECK_OK(call)                                                                              \
+  do {                                                                                              \
+    RfnmStatus status_ = (call);                                                                    \
+    if (status_ != RFNM_STATUS_OK) {                                                                \
+      fprintf(stderr, "%s:%d: %s failed (%d): %s\n", __FILE__, __LINE__, #call, (int)status_,        \
+              rfnm_last_error_message());                                                           \
+      ++failures;                                                                                   \
+    }                                                                                               \
+  } while (0)
+
+static void test_errors(void) {
+  RfnmDevice* device = NULL;
+  CHECK(rfnm_device_open("NOTHERE1", &device) != RFNM_STATUS_OK);
+  CHECK(device == NULL);
+  CHECK(strlen(rfnm_last_error_message()) > 0);
+
+  CHECK(rfnm_device_open(NULL, NULL) == RFNM_STATUS_NULL_POINTER);
+
+  CHECK_OK(rfnm_device_open(NULL, &device));
+  RfnmRxChannelSettings settings;
+  CHECK(rfnm_device_get_rx_settings(device, 9, &settings) == RFNM_STATUS_INVALID_ARGUMENT);
+  CHECK(rfnm_device_get_rx_settings(device, 0, NULL) == RFNM_STATUS_NULL_POINTER);
+
+  // channel 2 is not on the mock, the device stays with the caller
+  RfnmRxStream* stream = NULL;
+  CHECK(rfnm_rx_stream_create(&device, 0x4, RFNM_STREAM_FORMAT_CF32, &stream) != RFNM_STATUS_OK);
+  CHECK(stream == NULL);
+  CHECK(device != NULL);
+  rfnm_device_free(device);
+}
+
+static void test_settings(void) {
+  RfnmDevice* device = NULL;
+  CHECK_OK(rfnm_device_open(NULL, &device));
+
+  RfnmRxChannelSettings settings;
+  CHECK_OK(rfnm_device_get_rx_settings(device, 1, &settings));
+  settings.gain = 20;
+  settings.frequency = 433920000;
+  CHECK_OK(rfnm_device_set_rx_settings(device, 1, &settings));
+
+  RfnmRxChannelSettings read_back;
+  CHECK_OK(rfnm_device_get_rx_settings(device, 1, &read_back));
+  CHECK(read_back.gain == 20);
+  CHECK(read_back.frequency == 433920000);
+
+  // out of the mock's gain range
+  settings.gain = 100;
+  CHECK(rfnm_device_set_rx_settings(device, 1, &settings) == RFNM_STATUS_GAIN_FAIL);
+  rfnm_device_free(device);
+}
+
+static void test_read_cs16(void) {
+  RfnmDevice* device = NULL;
+  CHECK_OK(rfnm_device_open(NULL, &device));
+
+  RfnmRxStream* stream = NULL;
+  CHECK_OK(rfnm_rx_stream_create(&device, 0x1, RFNM_STREAM_FORMAT_CS16, &stream));
+  CHECK(device == NULL);
+  if (stream == NULL) {
+    rfnm_device_free(device);
+    return;
+  }
+
+  const size_t elements = rfnm_rx_stream_suggested_buffer_size(stream);
+  const double sample_rate = rfnm_rx_stream_sample_rate(stream);
+  CHECK(elements > 0);
+  CHECK(sample_rate > 0.0);
+
+  int16_t* samples = malloc(elements * 2 * sizeof(int16_t));
+  void* buffers[1] = {samples};
+  CHECK_OK(rfnm_rx_stream_start(stream));
+
+  uint64_t first_ts = 0;
+  for (int block = 0; block < 2; ++block) {
+    size_t read = 0;
+    uint64_t timestamp_ns = 0;
+    CHECK_OK(rfnm_rx_stream_read(stream, buffers, 1, elements, 100000, &read, &timestamp_ns));
+    CHECK(read == elements);
+    if (block == 0) {
+      first_ts = timestamp_ns;
+    } else {
+      const double expected = first_ts + elements * 1e9 / sample_rate;
+      CHECK(fabs(timestamp_ns - expected) <= 1.0);
+    }
+
+    const double complex step = cexp(I * 2.0 * M_PI / 64.0);
+    for (size_t i = 0; i + 1 < read; ++i) {
+      const double complex now = samples[2 * i] + I * samples[2 * i + 1];
+      const double complex next = samples[2 * i + 2] + I * samples[2 * i + 3];
+      if (fabs(cabs(now) - 0.1 * 32767) > 2.0 || cabs(next / now - step) > 1e-3) {
+        fprintf(stderr, "sample %zu of block %d is off the tone\n", i, block);
+        ++failures;
+        break;
+      }
+    }
+  }
+
+  // a second buffer for a one channel stream is a mismatch
+  void* two_buffers[2] = {samples, samples};
+  CHECK(rfnm_rx_stream_read(stream, two_buffers, 2, elements, 100000, NULL, NULL) == RFNM_STATUS_BUFFER_COUNT_MISMATCH);
+
+  CHECK_OK(rfnm_rx_stream_stop(stream));
+  rfnm_rx_stream_free(stream);
+  free(samples);
+}
+
+static void test_read_two_channels_cf32(void) {
+  RfnmDevice* device = NULL;
+  CHECK_OK(rfnm_device_open(NULL, &device));
+
+  RfnmRxStream* stream = NULL;
+  CHECK_OK(rfnm_rx_stream_create(&device, 0x3, RFNM_STREAM_FORMAT_CF32, &stream));
+  if (stream == NULL) {
+    rfnm_device_free(device);
+    return;
+  }
+
+  RfnmRxChannelSettings settings;
+  rfnm_rx_channel_settings_default(&settings);
+  settings.gain = 20;
+  CHECK_OK(rfnm_rx_stream_set_rx_settings(stream, 1, &settings));
+
+  const size_t elements = 4096;
+  float complex* channel0 = malloc(elements * sizeof(float complex));
+  float complex* channel1 = malloc(elements * sizeof(float complex));
+  void* buffers[2] = {channel0, channel1};
+  CHECK_OK(rfnm_rx_stream_start(stream));
+  size_t read = 0;
+  CHECK_OK(rfnm_rx_stream_read(stream, buffers, 2, elements, 100000, &read, NULL));
+  CHECK(read == elements);
+  CHECK(fabs(cabsf(channel0[10]) - 0.1) < 1e-4);
+  CHECK(fabs(cabsf(channel1[10]) - 1.0) < 1e-4);
+  CHECK(cabs(channel0[11] / channel0[10] - cexp(I * 2.0 * M_PI / 64.0)) < 1e-3);
+  CHECK(cabs(channel1[11] / channel1[10] - cexp(I * 4.0 * M_PI / 64.0)) < 1e-3);
+
+  rfnm_rx_stream_free(stream);
+  free(channel0);
+  free(channel1);
+}
+
+int main(void) {
+  test_errors();
+  test_settings();
+  test_read_cs16();
+  test_read_two_channels_cf32();
+  if (failures > 0) {
+    fprintf(stderr, "%d checks failed\n", failures);
+    return 1;
+  }
+  printf("all checks passed\n");
+  return 0;
+}
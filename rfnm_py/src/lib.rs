@@ -0,0 +1,354 @@
+//! Python bindings for the rfnm crate.
+//!
+//! Build with `maturin develop` in this directory, then `import rfnm`.
+//! `RxStream.read` fills NumPy arrays the caller allocates, without copying:
+//! complex64 arrays for the `cf32` format, int16 / int8 arrays with interleaved I and Q for `cs16` / `cs8`.
+//!
+//! Everything here needs a real board, unless built with the `mock` feature, which swaps librfnm for a simulated
+//! one: `maturin develop --features mock`, then `pytest tests` runs the tests against it.
+
+use num_complex::Complex;
+use numpy::{Element, PyArrayDyn, PyArrayMethods};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use rfnm::channel_settings::FlatRxChannelSettings;
+use rfnm::device::Device;
+use rfnm::hwinfo::HwInfo;
+use rfnm::stream::{RxStream, StreamDataFormat};
+use rfnm::{MAX_RX_CHANNELS, RfnmApiError, rfnm_channel};
+use std::time::Duration;
+
+create_exception!(rfnm, RfnmError, PyException);
+
+fn to_py_err(e: RfnmApiError) -> PyErr {
+    RfnmError::new_err(e.to_string())
+}
+
+fn channel_flag(channel: u32) -> PyResult<rfnm_channel> {
+    if (channel as usize) < MAX_RX_CHANNELS {
+        Ok(rfnm_channel(1 << channel))
+    } else {
+        Err(PyValueError::new_err(format!(
+            "channel {channel} out of range, there are {MAX_RX_CHANNELS} at most"
+        )))
+    }
+}
+
+#[pyclass(name = "HwInfo", module = "rfnm", get_all)]
+#[derive(Clone)]
+struct PyHwInfo {
+    protocol_version: u32,
+    serial: String,
+    name: String,
+    revision: u8,
+    daughterboards: Vec<String>,
+    rx_channels: u8,
+    tx_channels: u8,
+    dcs_clk: u64,
+}
+
+impl From<HwInfo> for PyHwInfo {
+    fn from(value: HwInfo) -> Self {
+        let daughterboards: Vec<_> = value.daughterboards.iter().flatten().collect();
+        Self {
+            protocol_version: value.protocol_version,
+            serial: value.motherboard.serial_string(),
+            name: value.motherboard.name.clone(),
+            revision: value.motherboard.revision,
+            daughterboards: daughterboards.iter().map(|db| db.name.clone()).collect(),
+            rx_channels: daughterboards.iter().map(|db| db.channel_counts.rx).sum(),
+            tx_channels: daughterboards.iter().map(|db| db.channel_counts.tx).sum(),
+            dcs_clk: value.clock_info.dcs_clk,
+        }
+    }
+}
+
+#[pymethods]
+impl PyHwInfo {
+    fn __repr__(&self) -> String {
+        format!(
+            "HwInfo(serial={:?}, name={:?}, daughterboards={:?})",
+            self.serial, self.name, self.daughterboards
+        )
+    }
+}
+
+#[pyfunction]
+fn discover_usb_boards() -> Vec<PyHwInfo> {
+    rfnm::discover_usb_boards()
+        .into_iter()
+        .map(Into::into)
+        .collect()
+}
+
+/// `FlatRxChannelSettings`, with the path as its index (0 is SMA A) and the IQ balance split up.
+#[pyclass(name = "RxChannelSettings", module = "rfnm")]
+#[derive(Clone)]
+struct PyRxChannelSettings(FlatRxChannelSettings);
+
+/// A python property for every field of `FlatRxChannelSettings`, next to the constructor and repr.
+macro_rules! settings_properties {
+    ($($field:ident, $setter:ident: $ty:ty;)*) => {
+        #[pymethods]
+        impl PyRxChannelSettings {
+            #[new]
+            fn new() -> Self {
+                Self(FlatRxChannelSettings::default())
+            }
+
+            fn __repr__(&self) -> String {
+                format!(
+                    "RxChannelSettings(frequency={}, gain={}, m={}, n={}, path={}, tuning_offset={})",
+                    self.0.frequency, self.0.gain, self.0.m, self.0.n, self.0.path, self.0.tuning_offset
+                )
+            }
+
+            $(
+                #[getter]
+                fn $field(&self) -> $ty {
+                    self.0.$field
+                }
+
+                #[setter]
+                fn $setter(&mut self, value: $ty) {
+                    self.0.$field = value;
+                }
+            )*
+        }
+    };
+}
+
+settings_properties! {
+    frequency, set_frequency: i64;
+    gain, set_gain: i8;
+    m, set_m: i16;
+    n, set_n: i16;
+    path, set_path: u32;
+    iq_gain, set_iq_gain: f32;
+    iq_phase, set_iq_phase: f32;
+    tuning_offset, set_tuning_offset: i64;
+}
+
+/// A connected board. Creating a `RxStream` takes the board over, after that only the stream can use it.
+#[pyclass(name = "Device", module = "rfnm", unsendable)]
+struct PyDevice {
+    device: Option<Device>,
+}
+
+impl PyDevice {
+    fn device(&self) -> PyResult<&Device> {
+        self.device
+            .as_ref()
+            .ok_or_else(|| RfnmError::new_err("the device is owned by a stream"))
+    }
+}
+
+#[pymethods]
+impl PyDevice {
+    /// Connect to the board with the given serial number, or to the first one found.
+    #[new]
+    #[pyo3(signature = (serial=None))]
+    fn new(serial: Option<&str>) -> PyResult<Self> {
+        let device = match serial {
+            Some(serial) => Device::connect_usb_serial(serial),
+            None => Device::connect_usb(),
+        }
+        .map_err(to_py_err)?;
+        Ok(Self {
+            device: Some(device),
+        })
+    }
+
+    fn hwinfo(&self) -> PyResult<PyHwInfo> {
+        Ok(self.device()?.hwinfo().into())
+    }
+
+    fn get_rx_settings(&self, channel: u32) -> PyResult<PyRxChannelSettings> {
+        let info = self
+            .device()?
+            .get_rx_settings(channel_flag(channel)?)
+            .map_err(to_py_err)?;
+        Ok(PyRxChannelSettings(info.to_settings().into()))
+    }
+
+    fn set_rx_settings(&self, channel: u32, settings: &PyRxChannelSettings) -> PyResult<()> {
+        self.device()?
+            .set_rx_settings(channel_flag(channel)?, &settings.0.into())
+            .map_err(to_py_err)
+    }
+}
+
+enum StreamKind {
+    Cf32(RxStream<Complex<f32>>),
+    Cs16(RxStream<Complex<i16>>),
+    Cs8(RxStream<Complex<i8>>),
+}
+
+/// A rx stream over one or more channels of a board, see `rfnm::stream::RxStream`.
+#[pyclass(name = "RxStream", module = "rfnm", unsendable)]
+struct PyRxStream {
+    stream: StreamKind,
+}
+
+#[pymethods]
+impl PyRxStream {
+    /// `channels` are channel numbers, `format` is one of `cf32`, `cs16` and `cs8`.
+    #[new]
+    #[pyo3(signature = (device, channels, format="cf32"))]
+    fn new(device: &mut PyDevice, channels: Vec<u32>, format: &str) -> PyResult<Self> {
+        let mut flags = rfnm_channel(0);
+        for channel in channels {
+            flags.0 |= channel_flag(channel)?.0;
+        }
+        let owned = device
+            .device
+            .take()
+            .ok_or_else(|| RfnmError::new_err("the device is owned by a stream"))?;
+        let stream = match format {
+            "cf32" => RxStream::new(owned, flags).map(StreamKind::Cf32),
+            "cs16" => RxStream::new(owned, flags).map(StreamKind::Cs16),
+            "cs8" => RxStream::new(owned, flags).map(StreamKind::Cs8),
+            _ => {
+                device.device = Some(owned);
+                return Err(PyValueError::new_err(format!(
+                    "unknown format {format:?}, use cf32, cs16 or cs8"
+                )));
+            }
+        };
+        match stream {
+            Ok(stream) => Ok(Self { stream }),
+            Err((e, owned)) => {
+                device.device = Some(owned);
+                Err(to_py_err(e))
+            }
+        }
+    }
+
+    #[getter]
+    fn channel_count(&self) -> usize {
+        match &self.stream {
+            StreamKind::Cf32(s) => s.channel_count(),
+            StreamKind::Cs16(s) => s.channel_count(),
+            StreamKind::Cs8(s) => s.channel_count(),
+        }
+    }
+
+    #[getter]
+    fn sample_rate(&self) -> f64 {
+        match &self.stream {
+            StreamKind::Cf32(s) => s.sample_rate(),
+            StreamKind::Cs16(s) => s.sample_rate(),
+            StreamKind::Cs8(s) => s.sample_rate(),
+        }
+    }
+
+    /// Suggested number of samples per channel to read at once.
+    #[getter]
+    fn suggested_buffer_size(&self) -> usize {
+        match &self.stream {
+            StreamKind::Cf32(s) => s.suggested_buffer_size(),
+            StreamKind::Cs16(s) => s.suggested_buffer_size(),
+            StreamKind::Cs8(s) => s.suggested_buffer_size(),
+        }
+    }
+
+    fn set_auto_dc_offset(&self, auto: bool, channel: u32) -> PyResult<()> {
+        let channel = channel_flag(channel)?;
+        match &self.stream {
+            StreamKind::Cf32(s) => s.set_auto_dc_offset(auto, channel),
+            StreamKind::Cs16(s) => s.set_auto_dc_offset(auto, channel),
+            StreamKind::Cs8(s) => s.set_auto_dc_offset(auto, channel),
+        }
+        Ok(())
+    }
+
+    fn start(&self) -> PyResult<()> {
+        match &self.stream {
+            StreamKind::Cf32(s) => s.start(),
+            StreamKind::Cs16(s) => s.start(),
+            StreamKind::Cs8(s) => s.start(),
+        }
+        .map_err(to_py_err)
+    }
+
+    fn stop(&self) -> PyResult<()> {
+        match &self.stream {
+            StreamKind::Cf32(s) => s.stop(),
+            StreamKind::Cs16(s) => s.stop(),
+            StreamKind::Cs8(s) => s.stop(),
+        }
+        .map_err(to_py_err)
+    }
+
+    /// Fill one contiguous array per channel, all of the same size.
+    /// Returns the number of samples read per channel, the timestamp of the first one in ns
+    /// and, if the read stopped short, why, otherwise `None`.
+    /// Other python threads keep running while this waits for samples.
+    #[pyo3(signature = (buffers, timeout_ms=100))]
+    fn read(
+        &mut self,
+        py: Python<'_>,
+        buffers: Vec<Bound<'_, PyAny>>,
+        timeout_ms: u64,
+    ) -> PyResult<(usize, u64, Option<String>)> {
+        let timeout = Duration::from_millis(timeout_ms);
+        match &mut self.stream {
+            StreamKind::Cf32(s) => read_arrays::<_, Complex<f32>>(py, s, &buffers, timeout),
+            StreamKind::Cs16(s) => read_arrays::<_, i16>(py, s, &buffers, timeout),
+            StreamKind::Cs8(s) => read_arrays::<_, i8>(py, s, &buffers, timeout),
+        }
+    }
+}
+
+/// Read straight into the memory of the numpy arrays, which hold `E`s that make up the stream's `T`s.
+fn read_arrays<T: StreamDataFormat + Send, E: Element>(
+    py: Python<'_>,
+    stream: &mut RxStream<T>,
+    buffers: &[Bound<'_, PyAny>],
+    timeout: Duration,
+) -> PyResult<(usize, u64, Option<String>)> {
+    let per_sample = size_of::<T>() / size_of::<E>();
+    let mut arrays = Vec::with_capacity(buffers.len());
+    for buffer in buffers {
+        arrays.push(buffer.cast::<PyArrayDyn<E>>()?.try_readwrite()?);
+    }
+    let mut slices = Vec::with_capacity(arrays.len());
+    for array in &mut arrays {
+        let elements = array.as_slice_mut()?;
+        if elements.len() % per_sample != 0 {
+            return Err(PyValueError::new_err(
+                "integer buffers need an even length, I and Q are interleaved",
+            ));
+        }
+        // Complex<T> is repr(C), so interleaved I and Q are exactly that
+        let samples = unsafe {
+            std::slice::from_raw_parts_mut(
+                elements.as_mut_ptr().cast::<T>(),
+                elements.len() / per_sample,
+            )
+        };
+        slices.push(samples);
+    }
+    // buffers keeps the arrays alive while other python threads run
+    let info = py
+        .detach(move || stream.read(&mut slices, timeout))
+        .map_err(to_py_err)?;
+    Ok((
+        info.elements_read,
+        info.timestamp_ns,
+        info.error.map(|e| e.to_string()),
+    ))
+}
+
+#[pymodule]
+#[pyo3(name = "rfnm")]
+fn rfnm_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("RfnmError", m.py().get_type::<RfnmError>())?;
+    m.add_function(wrap_pyfunction!(discover_usb_boards, m)?)?;
+    m.add_class::<PyHwInfo>()?;
+    m.add_class::<PyRxChannelSettings>()?;
+    m.add_class::<PyDevice>()?;
+    m.add_class::<PyRxStream>()?;
+    Ok(())
+}
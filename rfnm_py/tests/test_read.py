@@ -0,0 +1,100 @@
+"""Reads from the simulated board of the `mock` feature.
+
+Build with `maturin develop --features mock`, then run `pytest tests`. The mock has two rx channels, each carrying
+a tone of 1/64 (channel 0) or 2/64 (channel 1) of the sample rate, 0.1 of full scale at 0 dB gain.
+"""
+
+import numpy as np
+import pytest
+
+import rfnm
+
+TONE_LEVEL = 0.1
+
+
+def tone_step(channel):
+    return np.exp(2j * np.pi * (channel + 1) / 64)
+
+
+@pytest.fixture
+def device():
+    return rfnm.Device()
+
+
+def test_discovers_the_mock():
+    boards = rfnm.discover_usb_boards()
+    assert [board.serial for board in boards] == ["MOCK0001"]
+    assert boards[0].rx_channels == 2
+
+
+def test_read_complex64(device):
+    stream = rfnm.RxStream(device, [0], "cf32")
+    stream.start()
+    first = np.zeros(stream.suggested_buffer_size, dtype=np.complex64)
+    second = np.zeros_like(first)
+
+    read, first_ts, error = stream.read([first])
+    assert read == len(first)
+    assert error is None
+    read, second_ts, _ = stream.read([second])
+    assert read == len(second)
+    stream.stop()
+
+    assert np.allclose(np.abs(first), TONE_LEVEL, atol=1e-4)
+    # one tone without a gap between the reads
+    assert np.allclose(first[1:] / first[:-1], tone_step(0), atol=1e-3)
+    assert np.isclose(second[0] / first[-1], tone_step(0), atol=1e-3)
+    assert second_ts == pytest.approx(first_ts + len(first) * 1e9 / stream.sample_rate, abs=1)
+
+
+def test_read_int16(device):
+    stream = rfnm.RxStream(device, [0], "cs16")
+    stream.start()
+    buffer = np.zeros(2 * stream.suggested_buffer_size, dtype=np.int16)
+    read, _, _ = stream.read([buffer])
+    stream.stop()
+
+    assert read == len(buffer) // 2
+    iq = buffer[0::2] + 1j * buffer[1::2]
+    assert np.allclose(np.abs(iq), TONE_LEVEL * 32767, atol=2)
+    assert np.allclose(iq[1:] / iq[:-1], tone_step(0), atol=1e-3)
+
+
+def test_int16_needs_whole_samples(device):
+    stream = rfnm.RxStream(device, [0], "cs16")
+    stream.start()
+    with pytest.raises(ValueError):
+        stream.read([np.zeros(101, dtype=np.int16)])
+
+
+def test_read_two_channels(device):
+    stream = rfnm.RxStream(device, [0, 1], "cf32")
+    assert stream.channel_count == 2
+    stream.start()
+    buffers = [np.zeros(4096, dtype=np.complex64) for _ in range(2)]
+    read, _, _ = stream.read(buffers)
+    stream.stop()
+
+    assert read == 4096
+    for channel, buffer in enumerate(buffers):
+        assert np.allclose(buffer[1:] / buffer[:-1], tone_step(channel), atol=1e-3)
+
+
+def test_gain_changes_the_level(device):
+    settings = device.get_rx_settings(0)
+    settings.gain = 20
+    device.set_rx_settings(0, settings)
+    assert device.get_rx_settings(0).gain == 20
+
+    stream = rfnm.RxStream(device, [0], "cf32")
+    stream.start()
+    buffer = np.zeros(4096, dtype=np.complex64)
+    stream.read([buffer])
+    stream.stop()
+    assert np.allclose(np.abs(buffer), TONE_LEVEL * 10, atol=1e-3)
+
+
+def test_stream_takes_the_device(device):
+    rfnm.RxStream(device, [0])
+    with pytest.raises(rfnm.RfnmError):
+        device.hwinfo()
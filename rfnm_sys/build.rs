@@ -2,12 +2,33 @@ use bindgen::{Bindings, EnumVariation};
 use cmake;
 use std::env;
 use std::path::PathBuf;
+use std::process::Command;
 
 fn main() {
     // We need the rfnm lib and our wrapper
     // then we run bindgen on the wrapper
     // simple, right?
 
+    if env::var_os("CARGO_FEATURE_MOCK").is_some() {
+        build_mock();
+    } else {
+        build_librfnm();
+    }
+
+    // we have everything build except the bindgen
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    gen_bindings()
+        .write_to_file(out_path.join("librfnm_wrap.rs"))
+        .expect("Couldn't write bindings!");
+
+    // rerun rules
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=librfnm_wrap");
+    println!("cargo:rerun-if-changed=librfnm");
+}
+
+fn build_librfnm() {
     let libdst = cmake::Config::new("librfnm")
         .profile("RelWithDebInfo")
         .build();
@@ -19,28 +40,77 @@ fn main() {
     println!("cargo:rustc-link-lib=fmt");
     println!("cargo:rustc-link-lib=usb-1.0");
 
-    cc::Build::new()
-        .cpp(true)
-        .include("librfnm/include/")
+    let mut build = cc::Build::new();
+    build.cpp(true).include("librfnm/include/");
+    // match the spdlog librfnm links against, the wrapper logs into the same registry
+    for flag in spdlog_cflags() {
+        if let Some(define) = flag.strip_prefix("-D") {
+            match define.split_once('=') {
+                Some((name, value)) => build.define(name, value),
+                None => build.define(define, None),
+            };
+        } else if let Some(dir) = flag.strip_prefix("-I") {
+            build.include(dir);
+        } else {
+            build.flag(&flag);
+        }
+    }
+    build
         .file("librfnm_wrap/librfnm_wrap.cpp")
         .cargo_metadata(true)
         .compile("librfnm_wrap");
+}
 
-    // we have everything build except the bindgen
-
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-    gen_bindings()
-        .write_to_file(out_path.join("librfnm_wrap.rs"))
-        .expect("Couldn't write bindings!");
+/// What code using spdlog has to be compiled with, from spdlog's pkg-config file.
+/// The defines depend on how spdlog was built: compiled or header only, shared or static, bundled or external fmt.
+/// Without pkg-config the build goes on with none, which works for a header only spdlog on the default include path.
+fn spdlog_cflags() -> Vec<String> {
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG");
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_PATH");
+    let pkg_config = env::var("PKG_CONFIG").unwrap_or_else(|_| "pkg-config".to_string());
+    let output = Command::new(&pkg_config)
+        .args(["--cflags", "spdlog"])
+        .output();
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .map(String::from)
+            .collect(),
+        Ok(output) => {
+            println!(
+                "cargo:warning={pkg_config} does not know spdlog, building without its flags. Install its development files or set PKG_CONFIG_PATH if the build fails: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            Vec::new()
+        }
+        Err(e) => {
+            println!(
+                "cargo:warning=Could not run {pkg_config} to find spdlog's flags, building without them: {e}"
+            );
+            Vec::new()
+        }
+    }
+}
 
-    // rerun rules
-    println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rerun-if-changed=librfnm_wrap");
-    println!("cargo:rerun-if-changed=librfnm");
+/// The wrapper api without librfnm, libusb or spdlog, for tests on machines without a board
+fn build_mock() {
+    cc::Build::new()
+        .cpp(true)
+        .std("c++17")
+        .include("librfnm/include/")
+        .file("librfnm_wrap/mock_wrap.cpp")
+        .cargo_metadata(true)
+        .compile("librfnm_wrap");
 }
 
 pub fn gen_bindings() -> Bindings {
-    bindgen::Builder::default()
+    let mut builder = bindgen::Builder::default();
+    if env::var_os("CARGO_FEATURE_MOCK").is_some() {
+        builder = builder
+            .header("librfnm_wrap/mock_wrap.hpp")
+            .allowlist_file("librfnm_wrap/mock_wrap.hpp");
+    }
+    builder
         .clang_args(["-I", "librfnm/include/"])
         .header("librfnm_wrap/librfnm_wrap.hpp")
         .allowlist_file("librfnm_wrap/librfnm_wrap.hpp")
@@ -51,6 +121,7 @@ pub fn gen_bindings() -> Bindings {
         .bitfield_enum("rfnm::channel")
         .allowlist_item("rfnm::channel")
         .allowlist_item("rfnm::stream_format")
+        .allowlist_item("rfnm::debug_level")
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
         .derive_default(true)
         .generate()
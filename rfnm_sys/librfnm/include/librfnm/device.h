@@ -125,6 +125,11 @@ namespace rfnm {
 
         MSDLL rfnm_api_failcode set(uint16_t applies, bool confirm_execution = true, uint32_t timeout_us = 1000000);
 
+        // Ask once how far the board is with the settings sent so far, without waiting
+        MSDLL rfnm_api_failcode get_set_result(struct rfnm_dev_get_set_result *result);
+        // The change counters of the last set that applied tx and rx channels, as get_set_result reports them once executed
+        MSDLL void get_change_counters(uint32_t *tx, uint32_t *rx);
+
         // Getters
         MSDLL const struct rfnm_dev_hwinfo * get_hwinfo();
         MSDLL const struct rfnm_dev_status * get_dev_status();
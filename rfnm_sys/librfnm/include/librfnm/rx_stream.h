@@ -22,6 +22,17 @@ namespace rfnm {
         MSDLL rfnm_api_failcode read(void * const * buffs, size_t elems_to_read,
             size_t &elems_read, uint64_t &timestamp_ns, uint32_t timeout_us = 20000);
 
+        struct discontinuity {
+            // sample of the block read where the buffer starts
+            size_t offset;
+            uint32_t channel;
+            // samples missing before the buffer by the phytimer, negative for repeated ones
+            int64_t missing_samples;
+        };
+
+        // Buffers of the last read that did not follow the one before them on the phytimer
+        MSDLL const std::vector<discontinuity> & get_discontinuities();
+
     private:
         rfnm_api_failcode rx_dqbuf_multi(uint32_t timeout_us, bool first = false);
         void rx_qbuf_multi();
@@ -35,6 +46,10 @@ namespace rfnm {
         struct rx_buf * pending_rx_buf[MAX_RX_CHANNELS] = {};
         uint32_t samples_left[MAX_RX_CHANNELS] = {};
 
+        std::vector<discontinuity> discontinuities;
+        // where in the block of the current read the buffers being dequeued start
+        size_t dqbuf_offset = 0;
+
         int64_t sample_counter = 0;
         uint32_t last_phytimer[MAX_RX_CHANNELS] = {};
         double ns_per_sample;
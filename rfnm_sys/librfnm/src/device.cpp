@@ -1158,6 +1158,22 @@ MSDLL rfnm_api_failcode device::set(uint16_t applies, bool confirm_execution, ui
     return RFNM_API_OK;
 }
 
+MSDLL rfnm_api_failcode device::get_set_result(struct rfnm_dev_get_set_result *result) {
+    int r = libusb_control_transfer(usb_handle->primary, uint8_t(LIBUSB_ENDPOINT_IN) | uint8_t(LIBUSB_REQUEST_TYPE_VENDOR), RFNM_B_REQUEST,
+            RFNM_GET_SET_RESULT, 0, (unsigned char*)result, sizeof(struct rfnm_dev_get_set_result), 50);
+    if (r < 0) {
+        spdlog::error("libusb_control_transfer for GET_SET_RESULT failed");
+        return RFNM_API_USB_FAIL;
+    }
+
+    return RFNM_API_OK;
+}
+
+MSDLL void device::get_change_counters(uint32_t *tx, uint32_t *rx) {
+    *tx = cc_tx;
+    *rx = cc_rx;
+}
+
 MSDLL const struct rfnm_dev_hwinfo * device::get_hwinfo() {
     return &(s->hwinfo);
 }
@@ -155,6 +155,7 @@ MSDLL void rx_stream::set_auto_dc_offset(bool enabled, uint8_t channel_mask) {
 MSDLL rfnm_api_failcode rx_stream::read(void * const * buffs, size_t elems_to_read,
         size_t &elems_read, uint64_t &timestamp_ns, uint32_t timeout_us) {
     rfnm_api_failcode ret = RFNM_API_OK;
+    discontinuities.clear();
 
     auto timeout = std::chrono::system_clock::now() + std::chrono::microseconds(timeout_us);
     size_t bytes_per_ele = dev.get_transport_status()->rx_stream_format;
@@ -213,6 +214,7 @@ MSDLL rfnm_api_failcode rx_stream::read(void * const * buffs, size_t elems_to_re
                 wait_us = std::chrono::duration_cast<std::chrono::microseconds>(time_remaining).count();
             }
 
+            dqbuf_offset = read_elems;
             ret = rx_dqbuf_multi(wait_us);
             if (ret) break;
         }
@@ -225,6 +227,10 @@ MSDLL rfnm_api_failcode rx_stream::read(void * const * buffs, size_t elems_to_re
     return ret;
 }
 
+MSDLL const std::vector<rx_stream::discontinuity> & rx_stream::get_discontinuities() {
+    return discontinuities;
+}
+
 rfnm_api_failcode rx_stream::rx_dqbuf_multi(uint32_t timeout_us, bool first) {
     rfnm_api_failcode ret = RFNM_API_OK;
     auto timeout = std::chrono::system_clock::now() + std::chrono::microseconds(timeout_us);
@@ -306,6 +312,10 @@ rfnm_api_failcode rx_stream::rx_dqbuf_multi(uint32_t timeout_us, bool first) {
             // tolerance of +- 64 samples to deal with phytimer jitter
             // note that phytimer is dequeue time, subject to interrupt servicing time
             // phytimer timestamp is at a variable offset from buffer start time
+            if (samp_delta < RFNM_USB_RX_PACKET_ELEM_CNT - 64 || samp_delta > RFNM_USB_RX_PACKET_ELEM_CNT + 64) {
+                discontinuities.push_back({dqbuf_offset, channel, int64_t(samp_delta) - int64_t(RFNM_USB_RX_PACKET_ELEM_CNT)});
+            }
+
             if (samp_delta < RFNM_USB_RX_PACKET_ELEM_CNT - 64) {
                 // samples were repeated (strange, shouldn't happen)
                 shift_samples = samp_delta - RFNM_USB_RX_PACKET_ELEM_CNT;
@@ -2,13 +2,21 @@
 // Created by mkalte on 10/13/24.
 //
 #include "librfnm_wrap.hpp"
+#include <algorithm>
+#include <array>
 #include <memory>
+#include <mutex>
 #include <stdexcept>
 #include <cstring>
+#include <vector>
 
 
 #include <librfnm/device.h>
 #include <librfnm/rx_stream.h>
+#include <libusb-1.0/libusb.h>
+#include <spdlog/spdlog.h>
+#include <spdlog/sinks/base_sink.h>
+#include <spdlog/sinks/stdout_color_sinks.h>
 
 using namespace rfnm;
 
@@ -26,9 +34,46 @@ void err_msg(WrappedThrownError* err, const std::runtime_error& exception) {
   err->message[to_copy] = '\0';
 }
 
+/// Hands spdlog records to a callback, librfnm logs through the default logger
+class CallbackSink : public spdlog::sinks::base_sink<std::mutex> {
+public:
+  explicit CallbackSink(LogCallback callback) : callback(callback) {}
+
+protected:
+  void sink_it_(const spdlog::details::log_msg& msg) override {
+    callback(static_cast<int>(msg.level), msg.payload.data(), msg.payload.size());
+  }
+  void flush_() override {}
+
+private:
+  LogCallback callback;
+};
+
 extern "C" {
 
+  void log_set_callback(LogCallback callback) {
+    const auto level = spdlog::default_logger()->level();
+    // the name has to be free before making a logger of that name
+    spdlog::drop("librfnm");
+    std::shared_ptr<spdlog::logger> logger;
+    if (callback == nullptr) {
+      logger = spdlog::stdout_color_mt("librfnm");
+    } else {
+      logger = std::make_shared<spdlog::logger>("librfnm", std::make_shared<CallbackSink>(callback));
+    }
+    // keep the level, whoever set it
+    logger->set_level(level);
+    spdlog::set_default_logger(logger);
+  }
+
+  void log_set_level(int level) {
+    spdlog::set_level(static_cast<spdlog::level::level_enum>(level));
+  }
+
   struct DeviceWrapper {
+    // rx buffers handed to librfnm, declared first so they outlive the device using them
+    std::vector<std::unique_ptr<uint8_t[]>> rx_buffer_data;
+    std::vector<std::unique_ptr<rx_buf>> rx_buffers;
     std::unique_ptr<device> dev;
   };
 
@@ -54,12 +99,74 @@ extern "C" {
     return to_copy;
   }
 
+  /// List the serial numbers of all connected usb rfnm devices, 9 chars each including the terminating 0
+  /// Only opens the devices, without claiming them, so boards that are in use are listed as well
+  /// Same return value as find_usb_devices
+  size_t list_usb_serials(char* dst_serials, size_t max_count)
+  {
+    std::vector<std::array<char, 9>> serials;
+    libusb_context* ctx = nullptr;
+#if LIBUSB_API_VERSION >= 0x0100010A
+    if (libusb_init_context(&ctx, nullptr, 0) < 0) {
+#else
+    if (libusb_init(&ctx) < 0) {
+#endif
+      return 0;
+    }
+
+    libusb_device** devs = nullptr;
+    const ssize_t dev_cnt = libusb_get_device_list(ctx, &devs);
+    for (ssize_t d = 0; d < dev_cnt; ++d) {
+      libusb_device_descriptor desc{};
+      if (libusb_get_device_descriptor(devs[d], &desc) < 0) {
+        continue;
+      }
+      if (desc.idVendor != RFNM_USB_VID || desc.idProduct != RFNM_USB_PID) {
+        continue;
+      }
+      libusb_device_handle* handle = nullptr;
+      if (libusb_open(devs[d], &handle) != 0) {
+        continue;
+      }
+      std::array<char, 9> serial{};
+      if (libusb_get_string_descriptor_ascii(handle, desc.iSerialNumber, reinterpret_cast<unsigned char*>(serial.data()), serial.size()) >= 0) {
+        serial[8] = '\0';
+        serials.push_back(serial);
+      }
+      libusb_close(handle);
+    }
+    if (dev_cnt >= 0) {
+      libusb_free_device_list(devs, 1);
+    }
+    libusb_exit(ctx);
 
- DeviceWrapper* device_connect_usb(WrappedThrownError* err)
+    if (max_count==0 || dst_serials == nullptr) {
+      return serials.size();
+    }
+    size_t to_copy = std::min(max_count,serials.size());
+    for (size_t i = 0; i < to_copy; ++i) {
+      std::memcpy(dst_serials + i * 9, serials[i].data(), 9);
+    }
+    return to_copy;
+  }
+
+  bool find_usb_device_by_serial(const char* serial, rfnm_dev_hwinfo* dst_info)
+  {
+    const auto infos = device::find(transport::TRANSPORT_USB, serial);
+    if (infos.empty()) {
+      return false;
+    }
+    *dst_info = infos[0];
+    return true;
+  }
+
+
+ DeviceWrapper* device_connect_usb(const char* serial, debug_level dbg, WrappedThrownError* err)
  {
    clear_thrown_err_wrapper(err);
     try {
-      auto new_device = std::make_unique<device>(transport::TRANSPORT_USB,"",DEBUG_NONE);
+      const std::string address = serial == nullptr ? "" : serial;
+      auto new_device = std::make_unique<device>(transport::TRANSPORT_USB,address,dbg);
       DeviceWrapper* wrapper = new DeviceWrapper();
       wrapper->dev = std::move(new_device);
       return wrapper;
@@ -88,6 +195,17 @@ rfnm_api_failcode device_get_rx_channel(DeviceWrapper* dev, uint32_t num, rfnm_a
   }
 }
 
+rfnm_api_failcode device_get_tx_channel(DeviceWrapper* dev, uint32_t num, rfnm_api_tx_ch* dst)
+{
+  const auto info = dev->dev->get_tx_channel(num);
+  if (info == nullptr) {
+    return rfnm_api_failcode::RFNM_API_NOT_SUPPORTED;
+  } else {
+    memcpy(dst,info,sizeof(rfnm_api_tx_ch));
+    return RFNM_API_OK;
+  }
+}
+
 rfnm_api_failcode device_set_stream_format(DeviceWrapper* dev, stream_format format, size_t* bufsize)
   {
   return dev->dev->set_stream_format(format,bufsize);
@@ -105,6 +223,33 @@ rfnm_api_failcode device_set(DeviceWrapper* dev, uint16_t applies, bool confirm_
   return dev->dev->set(applies,confirm_execution, timeout_us);
 }
 
+rfnm_api_failcode device_get_set_result(DeviceWrapper* dev, rfnm_dev_get_set_result* dst) {
+  return dev->dev->get_set_result(dst);
+}
+
+rfnm_api_failcode device_submit(DeviceWrapper* dev, uint16_t applies, uint32_t* cc_tx, uint32_t* cc_rx) {
+  const rfnm_api_failcode r = dev->dev->set(applies, false);
+  // whatever went out, the counters are librfnm's own
+  dev->dev->get_change_counters(cc_tx, cc_rx);
+  return r;
+}
+
+size_t device_reserve_rx_buffers(DeviceWrapper* dev, size_t count)
+{
+  const size_t target = std::max(count, MIN_RX_BUFCNT);
+  // big enough for the widest format, as the format may still change until a stream is created
+  const size_t bufsize = RFNM_USB_RX_PACKET_ELEM_CNT * STREAM_FORMAT_CF32;
+  while (dev->rx_buffers.size() < target) {
+    auto data = std::make_unique<uint8_t[]>(bufsize);
+    auto rxbuf = std::make_unique<rx_buf>();
+    rxbuf->buf = data.get();
+    dev->dev->rx_qbuf(rxbuf.get(), true);
+    dev->rx_buffer_data.push_back(std::move(data));
+    dev->rx_buffers.push_back(std::move(rxbuf));
+  }
+  return dev->rx_buffers.size();
+}
+
 uint32_t device_get_rx_channel_count(DeviceWrapper* dev)
 {
   return dev->dev->get_rx_channel_count();
@@ -191,4 +336,12 @@ void stream_set_auto_dc_offset(StreamWrapper* stream, bool enabled, uint8_t ch_i
 rfnm_api_failcode stream_read(StreamWrapper* stream, void* const* buffs, size_t elements_to_read, size_t& elements_read, uint64_t& timestamp_ns, uint32_t timeout_us){
   return stream->stream->read(buffs, elements_to_read, elements_read, timestamp_ns, timeout_us);
 }
+size_t stream_get_discontinuities(StreamWrapper* stream, StreamDiscontinuity* dst, size_t max_count){
+  const auto& discontinuities = stream->stream->get_discontinuities();
+  const size_t to_copy = std::min(max_count, discontinuities.size());
+  for (size_t i = 0; i < to_copy; ++i) {
+    dst[i] = {discontinuities[i].offset, discontinuities[i].channel, discontinuities[i].missing_samples};
+  }
+  return discontinuities.size();
+}
 }
\ No newline at end of file
@@ -19,18 +19,39 @@ struct WrappedThrownError
 };
 
 size_t find_usb_devices(rfnm_dev_hwinfo* dst_infos, size_t max_info_count);
+size_t list_usb_serials(char* dst_serials, size_t max_count);
+/// Hardware info of the board with the given usb serial number. False if it is not there or in use
+bool find_usb_device_by_serial(const char* serial, rfnm_dev_hwinfo* dst_info);
+
+/// Receives librfnm's log records. level is spdlog's, 0 is trace up to 5 for critical. msg is not 0 terminated
+typedef void (*LogCallback)(int level, const char* msg, size_t msg_len);
+/// Send everything librfnm logs to callback instead of stdout. nullptr goes back to stdout
+void log_set_callback(LogCallback callback);
+/// Drop records below level, spdlog's levels with 6 for off
+void log_set_level(int level);
 
 struct DeviceWrapper;
-DeviceWrapper* device_connect_usb(WrappedThrownError* err);
+/// Connect to the board with the given usb serial number, or to the first one found if serial is nullptr or empty
+DeviceWrapper* device_connect_usb(const char* serial, rfnm::debug_level debug_level, WrappedThrownError* err);
 void device_free(DeviceWrapper* dev);
 void device_get_hwinfo(DeviceWrapper* dev, rfnm_dev_hwinfo* dst);
 rfnm_api_failcode device_get_rx_channel(DeviceWrapper* dev, uint32_t num, rfnm_api_rx_ch* dst);
+rfnm_api_failcode device_get_tx_channel(DeviceWrapper* dev, uint32_t num, rfnm_api_tx_ch* dst);
 rfnm_api_failcode device_set_stream_format(DeviceWrapper* dev, rfnm::stream_format format, size_t* bufsize);
+/// Make librfnm stream through at least count usb buffers, allocated here instead of by librfnm on the first start.
+/// Less than MIN_RX_BUFCNT is raised to it, librfnm will not stream with fewer. Buffers are only freed with the device.
+/// Returns how many there are now
+size_t device_reserve_rx_buffers(DeviceWrapper* dev, size_t count);
 uint32_t device_get_rx_channel_count(DeviceWrapper* dev);
 uint32_t device_get_tx_channel_count(DeviceWrapper* dev);
 rfnm_api_failcode device_rx_work_stop(DeviceWrapper* dev);
 rfnm_api_failcode device_tx_work_stop(DeviceWrapper* dev);
 rfnm_api_failcode device_set(DeviceWrapper* dev, uint16_t applies, bool confirm_execution, uint32_t timeout_us);
+/// Send the staged settings for applies without waiting for them.
+/// The change counters the board will report once it has executed them go to cc_tx and cc_rx
+rfnm_api_failcode device_submit(DeviceWrapper* dev, uint16_t applies, uint32_t* cc_tx, uint32_t* cc_rx);
+/// What the board last executed, read with one control transfer and without waiting
+rfnm_api_failcode device_get_set_result(DeviceWrapper* dev, rfnm_dev_get_set_result* dst);
 rfnm_api_failcode device_set_rx_channel_active(DeviceWrapper* dev, uint32_t channel, rfnm_ch_enable enable, rfnm_ch_stream stream, bool apply);
 rfnm_api_failcode device_set_tx_channel_active(DeviceWrapper* dev, uint32_t channel, rfnm_ch_enable enable, rfnm_ch_stream stream, bool apply);
 rfnm_api_failcode device_set_rx_channel_samp_freq_div(DeviceWrapper* dev, uint32_t channel, int16_t m, int16_t n, bool apply);
@@ -48,6 +69,17 @@ rfnm_api_failcode stream_start(StreamWrapper* stream);
 rfnm_api_failcode stream_stop(StreamWrapper* stream);
 void stream_set_auto_dc_offset(StreamWrapper* stream, bool enabled, uint8_t ch_ids);
 rfnm_api_failcode stream_read(StreamWrapper* stream, void* const* buffs, size_t elements_to_read, size_t& elements_read, uint64_t& timestamp_ns, uint32_t timeout_us);
+/// A usb buffer of a read that did not follow the one before it on the board's timer
+struct StreamDiscontinuity
+{
+  /// Sample of the block read where the buffer starts
+  size_t offset;
+  uint32_t channel;
+  /// Samples missing before the buffer by the timer, negative for repeated ones
+  int64_t missing_samples;
+};
+/// The discontinuities of the last read, up to max_count of them go to dst. Returns how many there were
+size_t stream_get_discontinuities(StreamWrapper* stream, StreamDiscontinuity* dst, size_t max_count);
 
 
 #ifdef __cplusplus
@@ -0,0 +1,514 @@
+//
+// librfnm_wrap.hpp without a board, built instead of librfnm_wrap.cpp with the mock feature.
+// There is one board with the serial MOCK0001 and two rx channels. Settings are executed as soon as they are sent,
+// and every streamed channel carries a tone whose level follows the gain, -20 dBFS at 0 dB.
+//
+#include "mock_wrap.hpp"
+#include <algorithm>
+#include <cmath>
+#include <complex>
+#include <cstring>
+#include <memory>
+#include <stdexcept>
+#include <string>
+#include <vector>
+
+using namespace rfnm;
+
+namespace {
+
+const char MOCK_SERIAL[] = "MOCK0001";
+const uint32_t MOCK_RX_CHANNELS = 2;
+const uint32_t MOCK_TX_CHANNELS = 1;
+const uint64_t MOCK_DCS_CLK = 122880000;
+
+LogCallback log_callback = nullptr;
+int log_level = 2;
+
+void log_info(const std::string& msg) {
+  if (log_callback != nullptr && log_level <= 2) {
+    log_callback(2, msg.data(), msg.size());
+  }
+}
+
+void clear_thrown_err_wrapper(WrappedThrownError* err) {
+  for (auto &c : err->message) {
+    c = 0;
+  }
+}
+
+void err_msg(WrappedThrownError* err, const std::runtime_error& exception) {
+  const size_t err_chars = strlen(exception.what());
+  const size_t to_copy = std::min(err_chars,sizeof(err->message)-1);
+  memcpy(err->message,exception.what(),to_copy);
+  err->message[to_copy] = '\0';
+}
+
+rfnm_dev_hwinfo mock_hwinfo() {
+  rfnm_dev_hwinfo info{};
+  info.protocol_version = 1;
+  info.motherboard.board_id = 1;
+  info.motherboard.board_revision_id = 1;
+  memcpy(info.motherboard.serial_number, MOCK_SERIAL, sizeof(MOCK_SERIAL));
+  strncpy(info.motherboard.user_readable_name, "Mock Motherboard", sizeof(info.motherboard.user_readable_name) - 1);
+  info.motherboard.tx_ch_cnt = MOCK_TX_CHANNELS;
+  info.motherboard.rx_ch_cnt = MOCK_RX_CHANNELS;
+  info.daughterboard[0].board_id = 2;
+  info.daughterboard[0].board_revision_id = 1;
+  memcpy(info.daughterboard[0].serial_number, MOCK_SERIAL, sizeof(MOCK_SERIAL));
+  strncpy(info.daughterboard[0].user_readable_name, "Mock Daughterboard", sizeof(info.daughterboard[0].user_readable_name) - 1);
+  info.daughterboard[0].tx_ch_cnt = MOCK_TX_CHANNELS;
+  info.daughterboard[0].rx_ch_cnt = MOCK_RX_CHANNELS;
+  info.clock.dcs_clk = MOCK_DCS_CLK;
+  return info;
+}
+
+rfnm_api_rx_ch mock_rx_channel(uint32_t num) {
+  rfnm_api_rx_ch ch{};
+  ch.abs_id = int8_t(num);
+  ch.dgb_ch_id = int8_t(num);
+  ch.dgb_id = 0;
+  ch.adc_id = int8_t(num);
+  ch.freq_min = 10000000;
+  ch.freq_max = 7200000000;
+  ch.freq = 2450000000;
+  ch.rfic_lpf_bw = 100;
+  ch.samp_freq_div_m = 1;
+  ch.samp_freq_div_n = 2;
+  ch.avail = 1;
+  ch.gain = 0;
+  ch.gain_range.min = -12;
+  ch.gain_range.max = 30;
+  ch.enable = RFNM_CH_OFF;
+  ch.stream = RFNM_CH_STREAM_AUTO;
+  ch.agc = RFNM_AGC_OFF;
+  ch.bias_tee = RFNM_BIAS_TEE_OFF;
+  ch.fm_notch = RFNM_FM_NOTCH_AUTO;
+  ch.path = RFNM_PATH_SMA_A;
+  ch.path_preferred = RFNM_PATH_SMA_A;
+  // packed, so no references into it
+  for (size_t i = 0; i < 10; ++i) {
+    ch.path_possible[i] = RFNM_PATH_NULL;
+  }
+  ch.path_possible[0] = RFNM_PATH_SMA_A;
+  ch.path_possible[1] = RFNM_PATH_SMA_B;
+  ch.data_type = RFNM_CH_DATA_TYPE_COMPLEX;
+  return ch;
+}
+
+rfnm_api_tx_ch mock_tx_channel(uint32_t num) {
+  rfnm_api_tx_ch ch{};
+  ch.abs_id = int8_t(num);
+  ch.freq_min = 10000000;
+  ch.freq_max = 7200000000;
+  ch.freq = 2450000000;
+  ch.samp_freq_div_m = 1;
+  ch.samp_freq_div_n = 2;
+  ch.avail = 1;
+  ch.power_range.max = 30;
+  ch.enable = RFNM_CH_OFF;
+  ch.stream = RFNM_CH_STREAM_AUTO;
+  ch.path = RFNM_PATH_SMA_A;
+  ch.path_preferred = RFNM_PATH_SMA_A;
+  // packed, so no references into it
+  for (size_t i = 0; i < 10; ++i) {
+    ch.path_possible[i] = RFNM_PATH_NULL;
+  }
+  ch.path_possible[0] = RFNM_PATH_SMA_A;
+  ch.data_type = RFNM_CH_DATA_TYPE_COMPLEX;
+  return ch;
+}
+
+}
+
+extern "C" {
+
+  void log_set_callback(LogCallback callback) {
+    log_callback = callback;
+  }
+
+  void log_set_level(int level) {
+    log_level = level;
+  }
+
+  struct DeviceWrapper {
+    rfnm_dev_hwinfo hwinfo = mock_hwinfo();
+    // staged settings, and what the board runs with once they are applied
+    std::vector<rfnm_api_rx_ch> rx_staged;
+    std::vector<rfnm_api_rx_ch> rx_applied;
+    std::vector<rfnm_api_tx_ch> tx_staged;
+    std::vector<rfnm_api_tx_ch> tx_applied;
+    stream_format format = STREAM_FORMAT_CS16;
+    size_t rx_buffer_count = 0;
+    uint32_t cc_tx = 0;
+    uint32_t cc_rx = 0;
+    size_t streams = 0;
+    bool unplugged = false;
+  };
+
+  struct StreamWrapper {
+    DeviceWrapper* dev;
+    std::vector<uint32_t> channels;
+    bool running = false;
+    uint64_t sample_counter = 0;
+  };
+
+  size_t find_usb_devices(rfnm_dev_hwinfo* dst_infos, size_t max_info_count)
+  {
+    if (max_info_count==0 || dst_infos == nullptr) {
+      return 1;
+    }
+    dst_infos[0] = mock_hwinfo();
+    return 1;
+  }
+
+  size_t list_usb_serials(char* dst_serials, size_t max_count)
+  {
+    if (max_count==0 || dst_serials == nullptr) {
+      return 1;
+    }
+    memcpy(dst_serials, MOCK_SERIAL, 9);
+    return 1;
+  }
+
+  bool find_usb_device_by_serial(const char* serial, rfnm_dev_hwinfo* dst_info)
+  {
+    if (serial != nullptr && strcmp(serial, MOCK_SERIAL) != 0) {
+      return false;
+    }
+    *dst_info = mock_hwinfo();
+    return true;
+  }
+
+  DeviceWrapper* device_connect_usb(const char* serial, debug_level, WrappedThrownError* err)
+  {
+    clear_thrown_err_wrapper(err);
+    if (serial != nullptr && serial[0] != '\0' && strcmp(serial, MOCK_SERIAL) != 0) {
+      err_msg(err, std::runtime_error("RFNM Device not found"));
+      return nullptr;
+    }
+    auto wrapper = new DeviceWrapper();
+    for (uint32_t i = 0; i < MOCK_RX_CHANNELS; ++i) {
+      wrapper->rx_staged.push_back(mock_rx_channel(i));
+    }
+    for (uint32_t i = 0; i < MOCK_TX_CHANNELS; ++i) {
+      wrapper->tx_staged.push_back(mock_tx_channel(i));
+    }
+    wrapper->rx_applied = wrapper->rx_staged;
+    wrapper->tx_applied = wrapper->tx_staged;
+    log_info(std::string("Connected to mock board ") + MOCK_SERIAL);
+    return wrapper;
+  }
+
+  void device_free(DeviceWrapper* dev) {
+    delete dev;
+  }
+
+  void device_get_hwinfo(DeviceWrapper* dev, rfnm_dev_hwinfo* dst) {
+    *dst = dev->hwinfo;
+  }
+
+  rfnm_api_failcode device_get_rx_channel(DeviceWrapper* dev, uint32_t num, rfnm_api_rx_ch* dst)
+  {
+    if (num >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    *dst = dev->rx_staged[num];
+    return RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_get_tx_channel(DeviceWrapper* dev, uint32_t num, rfnm_api_tx_ch* dst)
+  {
+    if (num >= dev->tx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    *dst = dev->tx_staged[num];
+    return RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_stream_format(DeviceWrapper* dev, stream_format format, size_t* bufsize)
+  {
+    // like librfnm, the format is locked once a stream exists
+    if (dev->streams > 0 && format != dev->format) {
+      if (bufsize) {
+        *bufsize = RFNM_USB_RX_PACKET_ELEM_CNT * dev->format;
+      }
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    switch (format) {
+    case STREAM_FORMAT_CS8:
+    case STREAM_FORMAT_CS16:
+    case STREAM_FORMAT_CF32:
+      dev->format = format;
+      if (bufsize) {
+        *bufsize = RFNM_USB_RX_PACKET_ELEM_CNT * format;
+      }
+      return RFNM_API_OK;
+    default:
+      if (bufsize) {
+        *bufsize = 0;
+      }
+      return RFNM_API_NOT_SUPPORTED;
+    }
+  }
+
+  size_t device_reserve_rx_buffers(DeviceWrapper* dev, size_t count)
+  {
+    dev->rx_buffer_count = std::max({dev->rx_buffer_count, count, MIN_RX_BUFCNT});
+    return dev->rx_buffer_count;
+  }
+
+  uint32_t device_get_rx_channel_count(DeviceWrapper* dev)
+  {
+    return dev->rx_staged.size();
+  }
+
+  uint32_t device_get_tx_channel_count(DeviceWrapper* dev)
+  {
+    return dev->tx_staged.size();
+  }
+
+  rfnm_api_failcode device_rx_work_stop(DeviceWrapper*) {
+    return RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_tx_work_stop(DeviceWrapper*) {
+    return RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set(DeviceWrapper* dev, uint16_t applies, bool, uint32_t) {
+    for (uint32_t i = 0; i < dev->tx_staged.size(); ++i) {
+      if (applies & tx_channel_apply_flags[i]) {
+        dev->tx_applied[i] = dev->tx_staged[i];
+      }
+    }
+    for (uint32_t i = 0; i < dev->rx_staged.size(); ++i) {
+      if (applies & rx_channel_apply_flags[i]) {
+        dev->rx_applied[i] = dev->rx_staged[i];
+      }
+    }
+    // librfnm counts a set for tx and for rx only when it applies channels of that kind
+    if (applies & 0xff) {
+      ++dev->cc_tx;
+    }
+    if (applies & 0xff00) {
+      ++dev->cc_rx;
+    }
+    return RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_submit(DeviceWrapper* dev, uint16_t applies, uint32_t* cc_tx, uint32_t* cc_rx) {
+    const rfnm_api_failcode r = device_set(dev, applies, false, 0);
+    *cc_tx = dev->cc_tx;
+    *cc_rx = dev->cc_rx;
+    return r;
+  }
+
+  rfnm_api_failcode device_get_set_result(DeviceWrapper* dev, rfnm_dev_get_set_result* dst) {
+    *dst = rfnm_dev_get_set_result{};
+    dst->cc_tx = dev->cc_tx;
+    dst->cc_rx = dev->cc_rx;
+    return RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_rx_channel_active(DeviceWrapper* dev, uint32_t channel, rfnm_ch_enable enable, rfnm_ch_stream stream, bool apply)
+  {
+    if (channel >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    dev->rx_staged[channel].enable = enable;
+    dev->rx_staged[channel].stream = stream;
+    return apply ? device_set(dev, rx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_tx_channel_active(DeviceWrapper* dev, uint32_t channel, rfnm_ch_enable enable, rfnm_ch_stream stream, bool apply)
+  {
+    if (channel >= dev->tx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    dev->tx_staged[channel].enable = enable;
+    dev->tx_staged[channel].stream = stream;
+    return apply ? device_set(dev, tx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_rx_channel_samp_freq_div(DeviceWrapper* dev, uint32_t channel, int16_t m, int16_t n, bool apply)
+  {
+    if (channel >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    dev->rx_staged[channel].samp_freq_div_m = m;
+    dev->rx_staged[channel].samp_freq_div_n = n;
+    return apply ? device_set(dev, rx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_rx_channel_gain(DeviceWrapper* dev, uint32_t channel, int8_t gain, bool apply)
+  {
+    if (channel >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    const rfnm_range_8b range = dev->rx_staged[channel].gain_range;
+    if (gain < range.min || gain > int(range.max)) {
+      return RFNM_API_GAIN_FAIL;
+    }
+    dev->rx_staged[channel].gain = gain;
+    return apply ? device_set(dev, rx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_rx_channel_freq(DeviceWrapper* dev, uint32_t channel, int64_t freq, bool apply)
+  {
+    if (channel >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    if (freq < dev->rx_staged[channel].freq_min || freq > dev->rx_staged[channel].freq_max) {
+      return RFNM_API_TUNE_FAIL;
+    }
+    dev->rx_staged[channel].freq = freq;
+    return apply ? device_set(dev, rx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_rx_channel_agc(DeviceWrapper* dev, uint32_t channel, rfnm_agc_type agc, bool apply)
+  {
+    if (channel >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    dev->rx_staged[channel].agc = agc;
+    return apply ? device_set(dev, rx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_rx_channel_fm_notch(DeviceWrapper* dev, uint32_t channel, rfnm_fm_notch fm_notch, bool apply)
+  {
+    if (channel >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    dev->rx_staged[channel].fm_notch = fm_notch;
+    return apply ? device_set(dev, rx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_rx_channel_bias_tee(DeviceWrapper* dev, uint32_t channel, rfnm_bias_tee bias_tee, bool apply)
+  {
+    if (channel >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    dev->rx_staged[channel].bias_tee = bias_tee;
+    return apply ? device_set(dev, rx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  rfnm_api_failcode device_set_rx_channel_path(DeviceWrapper* dev, uint32_t channel, rfnm_rf_path path, bool apply)
+  {
+    if (channel >= dev->rx_staged.size()) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    bool possible = false;
+    for (size_t i = 0; i < 10; ++i) {
+      possible |= path != RFNM_PATH_NULL && dev->rx_staged[channel].path_possible[i] == path;
+    }
+    if (!possible) {
+      return RFNM_API_NOT_SUPPORTED;
+    }
+    dev->rx_staged[channel].path = path;
+    return apply ? device_set(dev, rx_channel_apply_flags[channel], true, 1000000) : RFNM_API_OK;
+  }
+
+  StreamWrapper* stream_create(DeviceWrapper* dev, uint8_t ch_ids, WrappedThrownError* err)
+  {
+    clear_thrown_err_wrapper(err);
+    auto wrapper = std::make_unique<StreamWrapper>();
+    wrapper->dev = dev;
+    for (uint32_t i = 0; i < MAX_RX_CHANNELS; ++i) {
+      if (!(ch_ids & channel_flags[i])) {
+        continue;
+      }
+      if (i >= dev->rx_staged.size()) {
+        err_msg(err, std::runtime_error("Channel not available"));
+        return nullptr;
+      }
+      wrapper->channels.push_back(i);
+    }
+    ++dev->streams;
+    return wrapper.release();
+  }
+
+  void stream_free(StreamWrapper* stream) {
+    --stream->dev->streams;
+    delete stream;
+  }
+
+  rfnm_api_failcode stream_start(StreamWrapper* stream) {
+    uint16_t apply = 0;
+    for (const uint32_t channel : stream->channels) {
+      stream->dev->rx_staged[channel].enable = RFNM_CH_ON;
+      stream->dev->rx_staged[channel].stream = RFNM_CH_STREAM_ON;
+      apply |= rx_channel_apply_flags[channel];
+    }
+    stream->running = true;
+    return device_set(stream->dev, apply, true, 1000000);
+  }
+
+  rfnm_api_failcode stream_stop(StreamWrapper* stream) {
+    uint16_t apply = 0;
+    for (const uint32_t channel : stream->channels) {
+      stream->dev->rx_staged[channel].stream = RFNM_CH_STREAM_OFF;
+      apply |= rx_channel_apply_flags[channel];
+    }
+    stream->running = false;
+    return device_set(stream->dev, apply, true, 1000000);
+  }
+
+  void stream_set_auto_dc_offset(StreamWrapper*, bool, uint8_t) {}
+
+  rfnm_api_failcode stream_read(StreamWrapper* stream, void* const* buffs, size_t elements_to_read, size_t& elements_read, uint64_t& timestamp_ns, uint32_t)
+  {
+    const DeviceWrapper* dev = stream->dev;
+    elements_read = 0;
+    if (dev->unplugged) {
+      return RFNM_API_USB_FAIL;
+    }
+    if (!stream->running || stream->channels.empty()) {
+      return RFNM_API_DQBUF_NO_DATA;
+    }
+    const double ns_per_sample = dev->rx_applied[stream->channels[0]].samp_freq_div_n * 1e9 / dev->hwinfo.clock.dcs_clk;
+    timestamp_ns = uint64_t(stream->sample_counter * ns_per_sample);
+
+    for (size_t buf_idx = 0; buf_idx < stream->channels.size(); ++buf_idx) {
+      const auto &ch = dev->rx_applied[stream->channels[buf_idx]];
+      const double amplitude = std::min(1.0, 0.1 * std::pow(10.0, ch.gain / 20.0));
+      // a different tone on every channel, so they can be told apart
+      const double step = 2.0 * M_PI * (ch.abs_id + 1) / 64.0;
+      for (size_t i = 0; i < elements_to_read; ++i) {
+        const auto sample = std::polar(amplitude, step * double(stream->sample_counter + i));
+        switch (dev->format) {
+        case STREAM_FORMAT_CS8: {
+          auto dst = reinterpret_cast<int8_t*>(buffs[buf_idx]) + 2 * i;
+          dst[0] = int8_t(std::lround(sample.real() * 127.0));
+          dst[1] = int8_t(std::lround(sample.imag() * 127.0));
+          break;
+        }
+        case STREAM_FORMAT_CS16: {
+          auto dst = reinterpret_cast<int16_t*>(buffs[buf_idx]) + 2 * i;
+          dst[0] = int16_t(std::lround(sample.real() * 32767.0));
+          dst[1] = int16_t(std::lround(sample.imag() * 32767.0));
+          break;
+        }
+        case STREAM_FORMAT_CF32: {
+          auto dst = reinterpret_cast<float*>(buffs[buf_idx]) + 2 * i;
+          dst[0] = float(sample.real());
+          dst[1] = float(sample.imag());
+          break;
+        }
+        }
+      }
+    }
+    elements_read = elements_to_read;
+    stream->sample_counter += elements_to_read;
+    return RFNM_API_OK;
+  }
+
+  void mock_unplug(DeviceWrapper* dev) {
+    dev->unplugged = true;
+  }
+
+  // the mock board never loses or repeats a buffer
+  size_t stream_get_discontinuities(StreamWrapper*, StreamDiscontinuity*, size_t) {
+    return 0;
+  }
+}
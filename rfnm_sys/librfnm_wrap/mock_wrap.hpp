@@ -0,0 +1,22 @@
+//
+// Controls of the simulated board, only there with the mock feature.
+//
+
+#ifndef RFNM_RS_MOCK_WRAP_H
+#define RFNM_RS_MOCK_WRAP_H
+
+#include "librfnm_wrap.hpp"
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/// Pull the cable of this connection: every read of its streams fails with RFNM_API_USB_FAIL from now on.
+/// Connecting again gets a working board
+void mock_unplug(DeviceWrapper* dev);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif //RFNM_RS_MOCK_WRAP_H
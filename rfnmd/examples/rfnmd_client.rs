@@ -0,0 +1,49 @@
+//! List the channels of the board behind a running `rfnmd` and measure the sample rate it delivers.
+//!
+//! Usage: rfnmd_client [socket]
+
+use num_complex::Complex;
+use rfnm::rfnm_channel;
+use rfnmd::client::{RemoteDevice, RemoteRxStream};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let device = match std::env::args().nth(1) {
+        Some(path) => RemoteDevice::connect(path)?,
+        None => RemoteDevice::connect_default()?,
+    };
+    let info = device.info()?;
+    println!(
+        "Board {}, streaming channels {:?}",
+        info.serial, info.channels
+    );
+    for channel in device.list_channels()? {
+        println!(
+            "  channel {}: {} Hz, gain {} dB{}",
+            channel.channel,
+            channel.settings.frequency,
+            channel.settings.gain,
+            if channel.streamed { ", streamed" } else { "" }
+        );
+    }
+
+    let first = rfnm_channel(1 << info.channels.first().ok_or("the daemon streams nothing")?);
+    let stream = RemoteRxStream::<Complex<f32>>::new(device, first).map_err(|(e, _)| e)?;
+    let mut scratch = vec![Complex::new(0.0, 0.0); stream.suggested_buffer_size()];
+    stream.start()?;
+    let started = Instant::now();
+    let mut samples = 0;
+    while started.elapsed() < Duration::from_secs(5) {
+        let info = stream.read(&mut [scratch.as_mut_slice()], Duration::from_millis(100))?;
+        samples += info.elements_read;
+    }
+    stream.stop()?;
+    println!(
+        "{:.0} samples/s, expected {:.0}, {} blocks dropped",
+        samples as f64 / started.elapsed().as_secs_f64(),
+        stream.sample_rate(),
+        stream.frames_dropped()
+    );
+    Ok(())
+}
@@ -0,0 +1,489 @@
+//! The client side, shaped like `Device` and `RxStream`.
+//!
+//! `RemoteDevice` stands in for a `Device`, and `RemoteRxStream` for a `RxStream` over any of the channels
+//! the daemon streams. Porting a tool mostly means swapping the types.
+//! Unlike a local stream, starting and stopping only say whether this client wants samples:
+//! the board keeps running as long as any client does.
+
+use crate::protocol::{
+    ChannelInfo, ChannelParams, DaemonInfo, FrameHeader, JSONRPC_VERSION, Request, Response,
+    SetRxSettingsParams,
+};
+use num_complex::Complex;
+use rfnm::channel_settings::{FlatRxChannelSettings, RxChannelSettings};
+use rfnm::stream::{ComplexSample, StreamReadInfo};
+use rfnm::{channel_flag_to_number, rfnm_channel, split_channel_flags};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Socket error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid message from the daemon: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Daemon error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("The daemon closed the connection")]
+    Closed,
+    #[error("Timeout")]
+    Timeout,
+    #[error("The daemon's stream failed: {0}")]
+    StreamFailed(String),
+    #[error("Expected exactly one channel, got {0:#x}")]
+    InvalidChannel(u32),
+    #[error("Channel {0:#x} is not streamed by the daemon")]
+    ChannelNotStreamed(u32),
+    #[error("Read buffer count {0} does not match stream channel count of {1}")]
+    BufferCountMismatch(usize, usize),
+    #[error("Buffer sizes in stream buffers do not match. They must all be the same")]
+    BufferSizeMismatch,
+}
+
+/// A JSON-RPC connection to the daemon.
+struct Connection {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl Connection {
+    fn open(path: &Path) -> Result<Self, ClientError> {
+        let writer = UnixStream::connect(path)?;
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            next_id: 0,
+        })
+    }
+
+    fn call<P: Serialize, R: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: P,
+    ) -> Result<R, ClientError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = Request {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id: Some(id),
+            method: method.to_string(),
+            params: serde_json::to_value(params)?,
+        };
+        serde_json::to_writer(&mut self.writer, &request)?;
+        self.writer.write_all(b"\n")?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(ClientError::Closed);
+        }
+        let response: Response = serde_json::from_str(&line)?;
+        if let Some(error) = response.error {
+            return Err(ClientError::Rpc {
+                code: error.code,
+                message: error.message,
+            });
+        }
+        Ok(serde_json::from_value(response.result.unwrap_or_default())?)
+    }
+}
+
+/// Open a connection and turn it into a subscription.
+///
+/// The response is read byte by byte, as the frames follow right behind it and must not end up in a buffer.
+fn subscribe(path: &Path) -> Result<UnixStream, ClientError> {
+    let mut data = UnixStream::connect(path)?;
+    let request = Request {
+        jsonrpc: JSONRPC_VERSION.to_string(),
+        id: Some(0),
+        method: "subscribe".to_string(),
+        params: Value::Null,
+    };
+    serde_json::to_writer(&mut data, &request)?;
+    data.write_all(b"\n")?;
+
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while byte[0] != b'\n' {
+        if data.read(&mut byte)? == 0 {
+            return Err(ClientError::Closed);
+        }
+        line.push(byte[0]);
+    }
+    let response: Response = serde_json::from_slice(&line)?;
+    match response.error {
+        Some(error) => Err(ClientError::Rpc {
+            code: error.code,
+            message: error.message,
+        }),
+        None => Ok(data),
+    }
+}
+
+/// A board owned by `rfnmd`, used like a `Device`.
+pub struct RemoteDevice {
+    path: PathBuf,
+    // boxed, the device travels in `RemoteRxStream::new`'s error
+    control: RefCell<Box<Connection>>,
+}
+
+impl RemoteDevice {
+    /// Connect to the daemon listening on `path`.
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Self {
+            control: RefCell::new(Box::new(Connection::open(&path)?)),
+            path,
+        })
+    }
+
+    /// Connect to the daemon at `crate::default_socket_path`.
+    pub fn connect_default() -> Result<Self, ClientError> {
+        Self::connect(crate::default_socket_path())
+    }
+
+    pub fn info(&self) -> Result<DaemonInfo, ClientError> {
+        self.call("info", Value::Null)
+    }
+
+    /// Every rx channel of the board, streamed by the daemon or not.
+    pub fn list_channels(&self) -> Result<Vec<ChannelInfo>, ClientError> {
+        self.call("list_channels", Value::Null)
+    }
+
+    /// Unlike `Device::get_rx_settings`, this only gets the settable part.
+    /// `list_channels` has the ranges.
+    pub fn get_rx_settings(&self, channel: rfnm_channel) -> Result<RxChannelSettings, ClientError> {
+        let settings: FlatRxChannelSettings = self.call(
+            "get_rx_settings",
+            ChannelParams {
+                channel: channel_number(channel)?,
+            },
+        )?;
+        Ok(settings.into())
+    }
+
+    /// Changes are seen by every client. The sample rate of streamed channels cannot change.
+    pub fn set_rx_settings(
+        &self,
+        channel: rfnm_channel,
+        settings: &RxChannelSettings,
+    ) -> Result<(), ClientError> {
+        self.call(
+            "set_rx_settings",
+            SetRxSettingsParams {
+                channel: channel_number(channel)?,
+                settings: settings.clone().into(),
+            },
+        )
+    }
+
+    fn call<P: Serialize, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: P,
+    ) -> Result<R, ClientError> {
+        self.control.borrow_mut().call(method, params)
+    }
+}
+
+fn channel_number(channel: rfnm_channel) -> Result<u32, ClientError> {
+    channel_flag_to_number(channel).ok_or(ClientError::InvalidChannel(channel.0))
+}
+
+/// Samples of the daemon's stream, used like a `RxStream`.
+///
+/// Takes ownership of the device
+pub struct RemoteRxStream<T> {
+    _p: PhantomData<T>,
+    device: RemoteDevice,
+    channels: Vec<rfnm_channel>,
+    // where each of our channels is in the daemon's frames
+    frame_indices: Vec<usize>,
+    sample_rate: f64,
+    suggested_buffer_size: usize,
+    frames: RefCell<FrameReader>,
+}
+
+/// The subscription, and what is left of the last frame.
+#[derive(Default)]
+struct FrameReader {
+    data: Option<UnixStream>,
+    // the frame coming in, which can take more than one read when they time out
+    incoming: Vec<u8>,
+    received: usize,
+    samples: Vec<Complex<i16>>,
+    header: Option<FrameHeader>,
+    offset: usize,
+    dropped: u64,
+}
+
+impl<T: ComplexSample> RemoteRxStream<T> {
+    /// Stream `channels`, which have to be part of the daemon's stream.
+    pub fn new(
+        device: RemoteDevice,
+        channels: rfnm_channel,
+    ) -> Result<Self, (ClientError, RemoteDevice)> {
+        let info = match device.info() {
+            Ok(info) => info,
+            Err(e) => return Err((e, device)),
+        };
+        let channel_list: Vec<rfnm_channel> = split_channel_flags(channels).collect();
+        let mut frame_indices = Vec::with_capacity(channel_list.len());
+        for channel in &channel_list {
+            let number = channel_flag_to_number(*channel);
+            match info.channels.iter().position(|ch| Some(*ch) == number) {
+                Some(index) => frame_indices.push(index),
+                None => return Err((ClientError::ChannelNotStreamed(channel.0), device)),
+            }
+        }
+        Ok(Self {
+            _p: PhantomData,
+            device,
+            channels: channel_list,
+            frame_indices,
+            sample_rate: info.sample_rate,
+            suggested_buffer_size: info.suggested_buffer_size,
+            frames: RefCell::new(FrameReader::default()),
+        })
+    }
+
+    pub fn into_device(self) -> RemoteDevice {
+        self.device
+    }
+
+    pub fn device(&self) -> &RemoteDevice {
+        &self.device
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn channels(&self) -> &[rfnm_channel] {
+        &self.channels
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn suggested_buffer_size(&self) -> usize {
+        self.suggested_buffer_size
+    }
+
+    /// Blocks the daemon dropped for us so far because we did not read fast enough.
+    pub fn frames_dropped(&self) -> u64 {
+        self.frames.borrow().dropped
+    }
+
+    /// Ask for samples. The board starts streaming unless another client already has it running.
+    pub fn start(&self) -> Result<(), ClientError> {
+        let mut frames = self.frames.borrow_mut();
+        if frames.data.is_none() {
+            frames.data = Some(subscribe(&self.device.path)?);
+        }
+        self.device.call("start", Value::Null)
+    }
+
+    /// Stop asking for samples, and drop what is still queued for us.
+    pub fn stop(&self) -> Result<(), ClientError> {
+        let mut frames = self.frames.borrow_mut();
+        frames.data = None;
+        frames.header = None;
+        frames.received = 0;
+        self.device.call("stop", Value::Null)
+    }
+
+    /// Same as `RxStream::read`. Also returns fewer samples than fit when the current block runs out.
+    pub fn read(
+        &self,
+        dst: &mut [&mut [T]],
+        timeout: Duration,
+    ) -> Result<StreamReadInfo, ClientError> {
+        if dst.is_empty() || dst.len() != self.channels.len() {
+            return Err(ClientError::BufferCountMismatch(
+                dst.len(),
+                self.channels.len(),
+            ));
+        }
+        if dst.iter().any(|buf| buf.len() != dst[0].len()) {
+            return Err(ClientError::BufferSizeMismatch);
+        }
+        let mut frames = self.frames.borrow_mut();
+        let header = match frames.header {
+            Some(header) if frames.offset < header.elements as usize => header,
+            _ => frames.next(timeout)?,
+        };
+
+        let elements = header.elements as usize;
+        let count = dst[0].len().min(elements - frames.offset);
+        for (buf, index) in dst.iter_mut().zip(&self.frame_indices) {
+            let start = index * elements + frames.offset;
+            for (out, sample) in buf.iter_mut().zip(&frames.samples[start..start + count]) {
+                *out = T::from_complex_f32(Complex::new(
+                    sample.re as f32 / i16::MAX as f32,
+                    sample.im as f32 / i16::MAX as f32,
+                ));
+            }
+        }
+        let offset_ns = frames.offset as f64 * 1e9 / self.sample_rate;
+        frames.offset += count;
+        Ok(StreamReadInfo {
+            elements_read: count,
+            timestamp_ns: header.timestamp_ns + offset_ns.round() as u64,
+            error: None,
+        })
+    }
+}
+
+impl FrameReader {
+    /// Wait at most `timeout` for the next frame. A frame that is not complete by then is
+    /// picked up where it stopped by the next call.
+    fn next(&mut self, timeout: Duration) -> Result<FrameHeader, ClientError> {
+        let Some(data) = &mut self.data else {
+            return Err(ClientError::Timeout);
+        };
+        let deadline = Instant::now() + timeout;
+        let size = FrameHeader::SIZE.max(self.incoming.len());
+        self.incoming.resize(size, 0);
+        fill(
+            data,
+            &mut self.incoming[..FrameHeader::SIZE],
+            &mut self.received,
+            deadline,
+        )?;
+        let header = FrameHeader::from_bytes(
+            self.incoming[..FrameHeader::SIZE]
+                .try_into()
+                .expect("header sized slice"),
+        );
+        self.incoming
+            .resize(FrameHeader::SIZE + header.payload_size(), 0);
+        fill(data, &mut self.incoming, &mut self.received, deadline)?;
+        self.received = 0;
+
+        let payload = &self.incoming[FrameHeader::SIZE..];
+        if header.is_error() {
+            // the daemon ends the subscription after this
+            let message = String::from_utf8_lossy(payload).into_owned();
+            self.data = None;
+            self.header = None;
+            return Err(ClientError::StreamFailed(message));
+        }
+        self.samples.clear();
+        self.samples.extend(payload.chunks_exact(4).map(|b| {
+            Complex::new(
+                i16::from_le_bytes([b[0], b[1]]),
+                i16::from_le_bytes([b[2], b[3]]),
+            )
+        }));
+        self.dropped += header.dropped as u64;
+        self.header = Some(header);
+        self.offset = 0;
+        Ok(header)
+    }
+}
+
+/// Read until `buf` is full from `*received` on, or `deadline` passes.
+fn fill(
+    data: &mut UnixStream,
+    buf: &mut [u8],
+    received: &mut usize,
+    deadline: Instant,
+) -> Result<(), ClientError> {
+    while *received < buf.len() {
+        // a zero timeout would mean none at all
+        let left = deadline.saturating_duration_since(Instant::now());
+        data.set_read_timeout(Some(left.max(Duration::from_micros(1))))?;
+        match data.read(&mut buf[*received..]) {
+            Ok(0) => return Err(ClientError::Closed),
+            Ok(n) => *received += n,
+            // which of the two depends on the platform
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Err(ClientError::Timeout);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader() -> (FrameReader, UnixStream) {
+        let (data, daemon) = UnixStream::pair().unwrap();
+        let reader = FrameReader {
+            data: Some(data),
+            ..Default::default()
+        };
+        (reader, daemon)
+    }
+
+    const WAIT: Duration = Duration::from_millis(20);
+
+    #[test]
+    fn a_slow_frame_is_picked_up_where_it_stopped() {
+        let (mut reader, mut daemon) = reader();
+        let header = FrameHeader {
+            timestamp_ns: 1234,
+            elements: 2,
+            channels: 1,
+            dropped: 5,
+        };
+        let bytes = header.to_bytes();
+        assert!(matches!(reader.next(WAIT), Err(ClientError::Timeout)));
+
+        daemon.write_all(&bytes[..7]).unwrap();
+        assert!(matches!(reader.next(WAIT), Err(ClientError::Timeout)));
+        daemon.write_all(&bytes[7..]).unwrap();
+        daemon.write_all(&[1, 0, 2, 0, 3]).unwrap();
+        assert!(matches!(reader.next(WAIT), Err(ClientError::Timeout)));
+
+        daemon.write_all(&[0, 0xff, 0xff]).unwrap();
+        assert_eq!(reader.next(WAIT).unwrap(), header);
+        assert_eq!(reader.samples, [Complex::new(1, 2), Complex::new(3, -1)]);
+        assert_eq!(reader.dropped, 5);
+
+        // the next frame starts from scratch
+        daemon.write_all(&header.to_bytes()).unwrap();
+        daemon.write_all(&[0; 8]).unwrap();
+        assert_eq!(reader.next(WAIT).unwrap(), header);
+        assert_eq!(reader.dropped, 10);
+    }
+
+    #[test]
+    fn a_failed_stream_ends_the_subscription() {
+        let (mut reader, mut daemon) = reader();
+        let message = "Device error: USB transfer failed";
+        daemon
+            .write_all(&FrameHeader::error(message.len() as u32).to_bytes())
+            .unwrap();
+        daemon.write_all(message.as_bytes()).unwrap();
+        match reader.next(WAIT) {
+            Err(ClientError::StreamFailed(m)) => assert_eq!(m, message),
+            other => panic!("expected the failure, got {:?}", other.map(|_| ())),
+        }
+        assert!(reader.data.is_none());
+    }
+
+    #[test]
+    fn a_closed_daemon_is_reported() {
+        let (mut reader, daemon) = reader();
+        drop(daemon);
+        assert!(matches!(reader.next(WAIT), Err(ClientError::Closed)));
+    }
+}
@@ -0,0 +1,488 @@
+//! The daemon side: owns the board and serves the socket.
+//!
+//! Every connection gets a thread answering its requests. One more thread reads the stream
+//! while anybody wants it running and hands each block to all subscribers, dropping blocks
+//! for those that fall behind instead of holding up everybody.
+
+use crate::protocol::{
+    ChannelInfo, ChannelParams, DEVICE_ERROR, DaemonInfo, FrameHeader, INVALID_PARAMS,
+    JSONRPC_VERSION, METHOD_NOT_FOUND, NO_SUCH_CHANNEL, PARSE_ERROR, Request, Response, RpcError,
+    SAMPLE_RATE_LOCKED, SetRxSettingsParams,
+};
+use num_complex::Complex;
+use rfnm::channel_settings::{FlatRxChannelSettings, RxChannelSettings};
+use rfnm::device::Device;
+use rfnm::stream::RxStream;
+use rfnm::{RfnmApiError, channel_flag_to_number, rfnm_channel, split_channel_flags};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(20);
+const IDLE_POLL: Duration = Duration::from_millis(20);
+// how often an idle subscriber checks whether its client is still there
+const CLOSED_POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Error)]
+pub enum DaemonError {
+    #[error("Device error: {0}")]
+    Api(#[from] RfnmApiError),
+    #[error("Socket error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// What to open and where to listen.
+#[derive(Debug, Clone)]
+pub struct DaemonSettings {
+    /// `crate::default_socket_path` by default.
+    pub socket_path: PathBuf,
+    /// Board to open, the first one found if not given, which is the default.
+    pub serial: Option<String>,
+    /// Channels in the stream. They have to share a sample rate, like for any `RxStream`.
+    /// CH0 by default.
+    pub channels: rfnm_channel,
+    /// Frames queued per subscriber before new ones are dropped for it, 32 by default.
+    pub client_queue_len: usize,
+}
+
+impl Default for DaemonSettings {
+    fn default() -> Self {
+        Self {
+            socket_path: crate::default_socket_path(),
+            serial: None,
+            channels: rfnm_channel::CH0,
+            client_queue_len: 32,
+        }
+    }
+}
+
+struct Frame {
+    header: FrameHeader,
+    payload: Vec<u8>,
+}
+
+struct Subscriber {
+    frames: SyncSender<Arc<Frame>>,
+    dropped: Arc<AtomicU32>,
+    // why the stream stopped, sent once the queued frames are out
+    failure: Arc<OnceLock<String>>,
+}
+
+struct State {
+    stream: RxStream<Complex<i16>>,
+    // connections that called `start` and not `stop` yet
+    started: HashSet<u64>,
+    running: bool,
+    subscribers: Vec<Subscriber>,
+}
+
+impl State {
+    fn update_running(&mut self) -> Result<(), RfnmApiError> {
+        let wanted = !self.started.is_empty();
+        if wanted && !self.running {
+            self.stream.start()?;
+        } else if !wanted && self.running {
+            self.stream.stop()?;
+        }
+        self.running = wanted;
+        Ok(())
+    }
+}
+
+/// Owns one board and shares it through a Unix socket.
+pub struct Daemon {
+    state: Arc<Mutex<State>>,
+    listener: UnixListener,
+    settings: DaemonSettings,
+    serial: String,
+    channel_count: u32,
+}
+
+impl Daemon {
+    /// Open the board and bind the socket. A socket file left over from an earlier run is replaced.
+    pub fn open(settings: DaemonSettings) -> Result<Self, DaemonError> {
+        let device = match &settings.serial {
+            Some(serial) => Device::connect_usb_serial(serial)?,
+            None => Device::connect_usb()?,
+        };
+        let hwinfo = device.hwinfo();
+        let serial = hwinfo.motherboard.serial_string();
+        let channel_count = hwinfo
+            .daughterboards
+            .iter()
+            .flatten()
+            .map(|board| board.channel_counts.rx as u32)
+            .sum();
+        let stream = RxStream::new(device, settings.channels).map_err(|(e, _)| e)?;
+
+        if settings.socket_path.exists() {
+            std::fs::remove_file(&settings.socket_path)?;
+        }
+        let listener = UnixListener::bind(&settings.socket_path)?;
+        Ok(Self {
+            state: Arc::new(Mutex::new(State {
+                stream,
+                started: HashSet::new(),
+                running: false,
+                subscribers: Vec::new(),
+            })),
+            listener,
+            settings,
+            serial,
+            channel_count,
+        })
+    }
+
+    pub fn settings(&self) -> &DaemonSettings {
+        &self.settings
+    }
+
+    /// Serve until the socket fails.
+    pub fn run(&self) -> Result<(), DaemonError> {
+        let state = self.state.clone();
+        thread::spawn(move || pump_samples(state));
+
+        for (id, connection) in (0u64..).zip(self.listener.incoming()) {
+            let connection = connection?;
+            let handler = Handler {
+                id,
+                state: self.state.clone(),
+                serial: self.serial.clone(),
+                channel_count: self.channel_count,
+                queue_len: self.settings.client_queue_len,
+            };
+            thread::spawn(move || handler.serve(connection));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.settings.socket_path);
+    }
+}
+
+/// Read the stream while it runs and hand the blocks out.
+fn pump_samples(state: Arc<Mutex<State>>) {
+    let mut buffers: Vec<Vec<Complex<i16>>> = Vec::new();
+    loop {
+        let mut guard = lock(&state);
+        if !guard.running || guard.subscribers.is_empty() {
+            drop(guard);
+            thread::sleep(IDLE_POLL);
+            continue;
+        }
+        let size = guard.stream.suggested_buffer_size();
+        buffers.resize_with(guard.stream.channel_count(), Vec::new);
+        for buffer in &mut buffers {
+            buffer.resize(size, Complex::new(0, 0));
+        }
+        let mut dst: Vec<&mut [Complex<i16>]> =
+            buffers.iter_mut().map(|b| b.as_mut_slice()).collect();
+        let info = match guard.stream.read(&mut dst, READ_TIMEOUT) {
+            Ok(mut info) => match info.error.take() {
+                // a block cut short by the timeout still goes out
+                Some(e) if !matches!(e, RfnmApiError::Timeout) => {
+                    fail_stream(&mut guard, e);
+                    continue;
+                }
+                _ => info,
+            },
+            // no block yet, the read already waited
+            Err(RfnmApiError::Timeout) => continue,
+            Err(e) => {
+                fail_stream(&mut guard, e);
+                continue;
+            }
+        };
+        // let the control requests in while the frame is put together
+        drop(guard);
+
+        let mut payload = Vec::with_capacity(info.elements_read * buffers.len() * 4);
+        for buffer in &buffers {
+            for sample in &buffer[..info.elements_read] {
+                payload.extend_from_slice(&sample.re.to_le_bytes());
+                payload.extend_from_slice(&sample.im.to_le_bytes());
+            }
+        }
+        let frame = Arc::new(Frame {
+            header: FrameHeader {
+                timestamp_ns: info.timestamp_ns,
+                elements: info.elements_read as u32,
+                channels: buffers.len() as u32,
+                dropped: 0,
+            },
+            payload,
+        });
+        lock(&state).subscribers.retain(|subscriber| {
+            match subscriber.frames.try_send(frame.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+/// Stop the stream, tell every subscriber why and end their subscriptions.
+/// Clients have to `start` again to try once more.
+fn fail_stream(state: &mut State, e: RfnmApiError) {
+    let _ = state.stream.stop();
+    state.running = false;
+    state.started.clear();
+    let message = e.to_string();
+    // dropping the senders ends the subscriptions once their queues are empty
+    for subscriber in state.subscribers.drain(..) {
+        let _ = subscriber.failure.set(message.clone());
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    // a panicking handler leaves nothing half done that matters here
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+struct Handler {
+    id: u64,
+    state: Arc<Mutex<State>>,
+    serial: String,
+    channel_count: u32,
+    queue_len: usize,
+}
+
+impl Handler {
+    fn serve(&self, connection: UnixStream) {
+        let _ = self.answer_requests(connection);
+        let mut state = lock(&self.state);
+        state.started.remove(&self.id);
+        let _ = state.update_running();
+    }
+
+    fn answer_requests(&self, connection: UnixStream) -> io::Result<()> {
+        let mut writer = connection.try_clone()?;
+        for line in BufReader::new(connection).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (response, subscribe) = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => {
+                    let subscribe = request.method == "subscribe";
+                    let result = self.call(&request.method, request.params);
+                    let Some(id) = request.id else {
+                        // a notification, carried out without an answer
+                        if subscribe && result.is_ok() {
+                            return self.send_frames(writer);
+                        }
+                        continue;
+                    };
+                    (respond(Some(id), result), subscribe)
+                }
+                Err(e) => (respond(None, Err(error(PARSE_ERROR, e))), false),
+            };
+            serde_json::to_writer(&mut writer, &response)?;
+            writer.write_all(b"\n")?;
+            if subscribe && response.error.is_none() {
+                return self.send_frames(writer);
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "info" | "subscribe" => to_value(self.info()),
+            "list_channels" => to_value(self.list_channels()?),
+            "get_rx_settings" => {
+                let params: ChannelParams = from_params(params)?;
+                let channel = self.channel(params.channel)?;
+                let state = lock(&self.state);
+                let info = state.stream.device().get_rx_settings(channel);
+                to_value(FlatRxChannelSettings::from(
+                    info.map_err(device_error)?.to_settings(),
+                ))
+            }
+            "set_rx_settings" => {
+                let params: SetRxSettingsParams = from_params(params)?;
+                let channel = self.channel(params.channel)?;
+                let settings = RxChannelSettings::from(params.settings);
+                let state = lock(&self.state);
+                let device = state.stream.device();
+                let current = device.get_rx_settings(channel).map_err(device_error)?;
+                let current = current.to_settings().rate_divider_settings;
+                let streamed = state.stream.channels().contains(&channel);
+                if streamed
+                    && (current.m != settings.rate_divider_settings.m
+                        || current.n != settings.rate_divider_settings.n)
+                {
+                    return Err(error(
+                        SAMPLE_RATE_LOCKED,
+                        "the sample rate of streamed channels is fixed while the daemon runs",
+                    ));
+                }
+                device
+                    .set_rx_settings(channel, &settings)
+                    .map_err(device_error)?;
+                Ok(Value::Null)
+            }
+            "start" | "stop" => {
+                let mut state = lock(&self.state);
+                if method == "start" {
+                    state.started.insert(self.id);
+                } else {
+                    state.started.remove(&self.id);
+                }
+                state.update_running().map_err(device_error)?;
+                Ok(Value::Null)
+            }
+            _ => Err(error(METHOD_NOT_FOUND, format!("unknown method {method}"))),
+        }
+    }
+
+    fn info(&self) -> DaemonInfo {
+        let state = lock(&self.state);
+        DaemonInfo {
+            serial: self.serial.clone(),
+            sample_rate: state.stream.sample_rate(),
+            suggested_buffer_size: state.stream.suggested_buffer_size(),
+            channels: state
+                .stream
+                .channels()
+                .iter()
+                .filter_map(|ch| channel_flag_to_number(*ch))
+                .collect(),
+            streaming: state.running,
+        }
+    }
+
+    fn list_channels(&self) -> Result<Vec<ChannelInfo>, RpcError> {
+        let state = lock(&self.state);
+        let all = rfnm_channel((1 << self.channel_count) - 1);
+        split_channel_flags(all)
+            .map(|channel| {
+                let info = state
+                    .stream
+                    .device()
+                    .get_rx_settings(channel)
+                    .map_err(device_error)?;
+                Ok(ChannelInfo {
+                    channel: channel_flag_to_number(channel).unwrap_or(0),
+                    streamed: state.stream.channels().contains(&channel),
+                    settings: info.to_settings().into(),
+                    freq_min: *info.freq_range().start(),
+                    freq_max: *info.freq_range().end(),
+                    gain_min: *info.gain_range().start(),
+                    gain_max: *info.gain_range().end(),
+                })
+            })
+            .collect()
+    }
+
+    fn channel(&self, number: u32) -> Result<rfnm_channel, RpcError> {
+        if number < self.channel_count {
+            Ok(rfnm_channel(1 << number))
+        } else {
+            Err(error(
+                NO_SUCH_CHANNEL,
+                format!("the board has no rx channel {number}"),
+            ))
+        }
+    }
+
+    /// Turn the connection into a subscriber, until it goes away or the stream fails.
+    fn send_frames(&self, mut writer: UnixStream) -> io::Result<()> {
+        let (frames, queue) = mpsc::sync_channel(self.queue_len);
+        let dropped = Arc::new(AtomicU32::new(0));
+        let failure = Arc::new(OnceLock::new());
+        lock(&self.state).subscribers.push(Subscriber {
+            frames,
+            dropped: dropped.clone(),
+            failure: failure.clone(),
+        });
+        writer.set_read_timeout(Some(Duration::from_micros(1)))?;
+        loop {
+            match queue.recv_timeout(CLOSED_POLL) {
+                Ok(frame) => {
+                    let header = FrameHeader {
+                        dropped: dropped.swap(0, Ordering::Relaxed),
+                        ..frame.header
+                    };
+                    writer.write_all(&header.to_bytes())?;
+                    writer.write_all(&frame.payload)?;
+                }
+                // without frames, a client that went away is only noticed by looking
+                Err(RecvTimeoutError::Timeout) => {
+                    if client_closed(&writer)? {
+                        return Ok(());
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        if let Some(message) = failure.get() {
+            writer.write_all(&FrameHeader::error(message.len() as u32).to_bytes())?;
+            writer.write_all(message.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Subscribed clients send nothing, so the only thing to read is the end of the connection.
+fn client_closed(mut connection: &UnixStream) -> io::Result<bool> {
+    match connection.read(&mut [0; 64]) {
+        Ok(n) => Ok(n == 0),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+            Ok(false)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn respond(id: Option<u64>, result: Result<Value, RpcError>) -> Response {
+    let (result, error) = match result {
+        Ok(value) => (Some(value), None),
+        Err(e) => (None, Some(e)),
+    };
+    Response {
+        jsonrpc: JSONRPC_VERSION.to_string(),
+        id,
+        result,
+        error,
+    }
+}
+
+fn error(code: i64, message: impl ToString) -> RpcError {
+    RpcError {
+        code,
+        message: message.to_string(),
+    }
+}
+
+fn device_error(e: RfnmApiError) -> RpcError {
+    error(DEVICE_ERROR, e)
+}
+
+fn from_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| error(INVALID_PARAMS, e))
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    // our own types always serialize
+    Ok(serde_json::to_value(value).unwrap_or_default())
+}
@@ -0,0 +1,20 @@
+//! Sharing one board between processes.
+//!
+//! Only one process can own a `Device`. `rfnmd` is that process: it opens the board, streams a fixed
+//! set of channels and lets any number of local clients look at the samples and change settings,
+//! through a Unix socket. See `protocol` for what goes over the socket, and `client` for the library
+//! side that mimics `Device` and `RxStream`.
+
+pub mod client;
+pub mod daemon;
+pub mod protocol;
+
+use std::path::PathBuf;
+
+/// `$XDG_RUNTIME_DIR/rfnmd.sock`, or `/tmp/rfnmd.sock` without a runtime dir.
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("rfnmd.sock")
+}
@@ -0,0 +1,40 @@
+//! Usage: rfnmd [--socket <path>] [--serial <serial>] [--channels <n,n,...>]
+//!
+//! Opens the board with the given serial, or the first one found, streams the given rx channels
+//! (0 if not given) and serves them on the socket until killed.
+
+use rfnm::rfnm_channel;
+use rfnmd::daemon::{Daemon, DaemonSettings};
+use std::error::Error;
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut settings = DaemonSettings::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or_else(|| format!("{arg} needs a value"))?;
+        match arg.as_str() {
+            "--socket" => settings.socket_path = PathBuf::from(value),
+            "--serial" => settings.serial = Some(value),
+            "--channels" => {
+                let mut channels = 0;
+                for number in value.split(',') {
+                    let number: u32 = number.trim().parse()?;
+                    channels |= 1 << number;
+                }
+                settings.channels = rfnm_channel(channels);
+            }
+            _ => {
+                eprintln!(
+                    "Usage: rfnmd [--socket <path>] [--serial <serial>] [--channels <n,n,...>]"
+                );
+                return Err(format!("unknown argument {arg}").into());
+            }
+        }
+    }
+
+    let daemon = Daemon::open(settings)?;
+    eprintln!("Serving on {}", daemon.settings().socket_path.display());
+    daemon.run()?;
+    Ok(())
+}
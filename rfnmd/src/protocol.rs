@@ -0,0 +1,213 @@
+//! What daemon and clients say to each other.
+//!
+//! Control is JSON-RPC 2.0, one JSON object per line in both directions. Methods:
+//!
+//! | method            | params                    | result                  |
+//! |-------------------|---------------------------|-------------------------|
+//! | `info`            |                           | `DaemonInfo`            |
+//! | `list_channels`   |                           | `[ChannelInfo]`         |
+//! | `get_rx_settings` | `{channel}`               | `FlatRxChannelSettings` |
+//! | `set_rx_settings` | `{channel, settings}`     | `null`                  |
+//! | `start`           |                           | `null`                  |
+//! | `stop`            |                           | `null`                  |
+//! | `subscribe`       |                           | `DaemonInfo`            |
+//!
+//! Requests without an `id` are notifications: the daemon carries them out and does not answer.
+//! The board streams while at least one connection has called `start` and not `stop`.
+//! After the response to `subscribe`, the connection carries nothing but sample frames:
+//! a `FrameHeader`, then `elements` CS16 samples for every streamed channel in turn, all little endian.
+//! If reading the board fails, the daemon stops the stream, sends a frame with `channels` 0 whose
+//! `elements` bytes are the error message, and closes every subscription. A new `start` tries again.
+
+use rfnm::channel_settings::FlatRxChannelSettings;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+// error codes, the negative ones are JSON-RPC's own
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The board refused, the message has the `RfnmApiError`.
+pub const DEVICE_ERROR: i64 = 1;
+/// The sample rate cannot change, every client's stream depends on it.
+pub const SAMPLE_RATE_LOCKED: i64 = 2;
+/// The channel is not one of the board's.
+pub const NO_SUCH_CHANNEL: i64 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// `None` for a notification.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelParams {
+    pub channel: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetRxSettingsParams {
+    pub channel: u32,
+    pub settings: FlatRxChannelSettings,
+}
+
+/// The board the daemon owns and what it streams.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonInfo {
+    pub serial: String,
+    pub sample_rate: f64,
+    pub suggested_buffer_size: usize,
+    /// Channel numbers in every frame, in order.
+    pub channels: Vec<u32>,
+    pub streaming: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    pub channel: u32,
+    /// Part of the daemon's stream, so present in the frames.
+    pub streamed: bool,
+    pub settings: FlatRxChannelSettings,
+    pub freq_min: i64,
+    pub freq_max: i64,
+    pub gain_min: i8,
+    pub gain_max: i8,
+}
+
+/// Starts every sample frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// `StreamReadInfo::timestamp_ns` of the first sample.
+    pub timestamp_ns: u64,
+    /// Samples per channel.
+    pub elements: u32,
+    pub channels: u32,
+    /// Frames this subscriber missed since the previous one, because it did not keep up.
+    pub dropped: u32,
+}
+
+impl FrameHeader {
+    pub const SIZE: usize = 20;
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut out = [0; Self::SIZE];
+        out[0..8].copy_from_slice(&self.timestamp_ns.to_le_bytes());
+        out[8..12].copy_from_slice(&self.elements.to_le_bytes());
+        out[12..16].copy_from_slice(&self.channels.to_le_bytes());
+        out[16..20].copy_from_slice(&self.dropped.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Self {
+            timestamp_ns: word(0) as u64 | (word(4) as u64) << 32,
+            elements: word(8),
+            channels: word(12),
+            dropped: word(16),
+        }
+    }
+
+    /// The frame that reports a failed stream, followed by `message_len` bytes of message.
+    pub fn error(message_len: u32) -> Self {
+        Self {
+            timestamp_ns: 0,
+            elements: message_len,
+            channels: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.channels == 0
+    }
+
+    /// Bytes of samples, or of the message, following the header.
+    pub fn payload_size(&self) -> usize {
+        if self.is_error() {
+            self.elements as usize
+        } else {
+            self.elements as usize * self.channels as usize * 4
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_without_id_are_notifications() {
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc": "2.0", "method": "start"}"#).unwrap();
+        assert_eq!(request.id, None);
+        assert_eq!(request.params, Value::Null);
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(!json.contains("id"));
+
+        let request: Request =
+            serde_json::from_str(r#"{"jsonrpc": "2.0", "id": 7, "method": "info"}"#).unwrap();
+        assert_eq!(request.id, Some(7));
+    }
+
+    #[test]
+    fn settings_params_are_flat() {
+        let params: SetRxSettingsParams = serde_json::from_value(serde_json::json!({
+            "channel": 1,
+            "settings": {
+                "frequency": 433920000,
+                "gain": 10,
+                "m": 1,
+                "n": 2,
+                "path": 0,
+                "iq_gain": 1.0,
+                "iq_phase": 0.0,
+                "tuning_offset": 0,
+            },
+        }))
+        .unwrap();
+        assert_eq!(params.settings.frequency, 433_920_000);
+        assert_eq!(params.settings.n, 2);
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = FrameHeader {
+            timestamp_ns: 0x0102_0304_0506_0708,
+            elements: 1000,
+            channels: 2,
+            dropped: 3,
+        };
+        assert_eq!(FrameHeader::from_bytes(&header.to_bytes()), header);
+        assert_eq!(header.payload_size(), 8000);
+
+        let error = FrameHeader::error(12);
+        assert!(error.is_error());
+        assert!(!header.is_error());
+        assert_eq!(error.payload_size(), 12);
+    }
+}
@@ -0,0 +1,168 @@
+//! The daemon serving the simulated board of the `mock` feature, driven through the client.
+#![cfg(feature = "mock")]
+
+use num_complex::Complex;
+use rfnm::rfnm_channel;
+use rfnmd::client::{ClientError, RemoteDevice, RemoteRxStream};
+use rfnmd::daemon::{Daemon, DaemonSettings};
+use rfnmd::protocol::{METHOD_NOT_FOUND, NO_SUCH_CHANNEL, SAMPLE_RATE_LOCKED};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Run a daemon on a socket of its own and wait until it answers.
+fn serve(name: &str, client_queue_len: usize) -> PathBuf {
+    let socket_path =
+        std::env::temp_dir().join(format!("rfnmd-test-{}-{name}.sock", std::process::id()));
+    let settings = DaemonSettings {
+        socket_path: socket_path.clone(),
+        client_queue_len,
+        ..Default::default()
+    };
+    // the daemon runs until the test process ends
+    thread::spawn(move || Daemon::open(settings).unwrap().run());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while RemoteDevice::connect(&socket_path).is_err() {
+        assert!(Instant::now() < deadline, "the daemon did not come up");
+        thread::sleep(Duration::from_millis(10));
+    }
+    socket_path
+}
+
+fn stream(socket_path: &PathBuf) -> RemoteRxStream<Complex<f32>> {
+    let device = RemoteDevice::connect(socket_path).unwrap();
+    RemoteRxStream::new(device, rfnm_channel::CH0)
+        .map_err(|(e, _)| e)
+        .unwrap()
+}
+
+fn rpc_code<T>(result: Result<T, ClientError>) -> i64 {
+    match result {
+        Err(ClientError::Rpc { code, .. }) => code,
+        Err(e) => panic!("expected an rpc error, got {e}"),
+        Ok(_) => panic!("expected an rpc error"),
+    }
+}
+
+/// Whether the board streams, once it settled on `expected`.
+fn wait_for_streaming(device: &RemoteDevice, expected: bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let streaming = device.info().unwrap().streaming;
+        if streaming == expected || Instant::now() > deadline {
+            return streaming;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn calls_are_dispatched() {
+    let socket_path = serve("calls", 32);
+    let device = RemoteDevice::connect(&socket_path).unwrap();
+
+    let info = device.info().unwrap();
+    assert_eq!(info.serial, "MOCK0001");
+    assert_eq!(info.channels, [0]);
+    assert!(!info.streaming);
+
+    let channels = device.list_channels().unwrap();
+    assert_eq!(channels.len(), 2);
+    assert!(channels[0].streamed);
+    assert!(!channels[1].streamed);
+
+    let mut settings = device.get_rx_settings(rfnm_channel::CH1).unwrap();
+    settings.gain = 12;
+    device
+        .set_rx_settings(rfnm_channel::CH1, &settings)
+        .unwrap();
+    assert_eq!(device.get_rx_settings(rfnm_channel::CH1).unwrap().gain, 12);
+
+    assert_eq!(
+        rpc_code(device.get_rx_settings(rfnm_channel(1 << 5))),
+        NO_SUCH_CHANNEL
+    );
+
+    let mut raw = UnixStream::connect(&socket_path).unwrap();
+    raw.write_all(b"{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"reboot\"}\n")
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(raw).read_line(&mut line).unwrap();
+    let response: rfnmd::protocol::Response = serde_json::from_str(&line).unwrap();
+    assert_eq!(response.id, Some(7));
+    assert_eq!(response.error.unwrap().code, METHOD_NOT_FOUND);
+}
+
+#[test]
+fn the_sample_rate_of_streamed_channels_is_locked() {
+    let socket_path = serve("rate", 32);
+    let device = RemoteDevice::connect(&socket_path).unwrap();
+    for channel in [rfnm_channel::CH0, rfnm_channel::CH1] {
+        let mut settings = device.get_rx_settings(channel).unwrap();
+        settings.rate_divider_settings.n *= 2;
+        let result = device.set_rx_settings(channel, &settings);
+        if channel == rfnm_channel::CH0 {
+            assert_eq!(rpc_code(result), SAMPLE_RATE_LOCKED);
+        } else {
+            result.unwrap();
+        }
+    }
+}
+
+#[test]
+fn the_board_streams_while_any_client_wants_it() {
+    let socket_path = serve("refcount", 32);
+    let first = stream(&socket_path);
+    let second = stream(&socket_path);
+    let watcher = RemoteDevice::connect(&socket_path).unwrap();
+
+    first.start().unwrap();
+    second.start().unwrap();
+    assert!(watcher.info().unwrap().streaming);
+    first.stop().unwrap();
+    assert!(watcher.info().unwrap().streaming);
+    let mut buffer = vec![Complex::new(0.0, 0.0); 1024];
+    assert!(
+        second
+            .read(&mut [buffer.as_mut_slice()], TIMEOUT)
+            .unwrap()
+            .elements_read
+            > 0
+    );
+    second.stop().unwrap();
+    assert!(!watcher.info().unwrap().streaming);
+
+    // a client that goes away without stopping counts as stopped
+    first.start().unwrap();
+    assert!(watcher.info().unwrap().streaming);
+    drop(first);
+    assert!(!wait_for_streaming(&watcher, false));
+}
+
+#[test]
+fn slow_subscribers_lose_frames() {
+    let socket_path = serve("slow", 2);
+    let fast = stream(&socket_path);
+    let slow = stream(&socket_path);
+    fast.start().unwrap();
+    slow.start().unwrap();
+
+    let mut buffer = vec![Complex::new(0.0, 0.0); fast.suggested_buffer_size()];
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_millis(200) {
+        fast.read(&mut [buffer.as_mut_slice()], TIMEOUT).unwrap();
+    }
+    // the slow one gets what was queued and in the socket for it, then the count of those it missed
+    for _ in 0..64 {
+        if slow.frames_dropped() > 0 {
+            break;
+        }
+        slow.read(&mut [buffer.as_mut_slice()], TIMEOUT).unwrap();
+    }
+    assert!(slow.frames_dropped() > 0);
+    fast.read(&mut [buffer.as_mut_slice()], TIMEOUT).unwrap();
+}